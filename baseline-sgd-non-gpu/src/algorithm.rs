@@ -3,30 +3,58 @@
 //! This module is intended to be used as a namespace (no stateful struct).
 
 use crate::graph;
-use rand::Rng;
 use rand::seq::SliceRandom;
 
-fn norm2(v: [f64; 2]) -> f64 {
-    (v[0] * v[0] + v[1] * v[1]).sqrt()
+fn norm2<const D: usize>(v: [f64; D]) -> f64 {
+    v.iter().map(|c| c * c).sum::<f64>().sqrt()
 }
 
-fn sub(a: [f64; 2], b: [f64; 2]) -> [f64; 2] {
-    [a[0] - b[0], a[1] - b[1]]
+fn sub<const D: usize>(a: [f64; D], b: [f64; D]) -> [f64; D] {
+    std::array::from_fn(|k| a[k] - b[k])
 }
 
-fn center_inplace(positions: &mut [[f64; 2]]) {
+/// Move the reference point chosen by `mode` to the origin.
+///
+/// A weighted barycenter whose weights sum to zero (or do not match the node
+/// count) falls back to the plain centroid.
+fn center_inplace<const D: usize>(positions: &mut [[f64; D]], mode: &graph::CenterMode) {
     if positions.is_empty() {
         return;
     }
-    let n = positions.len() as f64;
-    let mean_x = positions.iter().map(|p| p[0]).sum::<f64>() / n;
-    let mean_y = positions.iter().map(|p| p[1]).sum::<f64>() / n;
+    let mean: [f64; D] = match mode {
+        graph::CenterMode::WeightedBarycenter(weights)
+            if weights.len() == positions.len() && weights.iter().sum::<f64>() > 0.0 =>
+        {
+            let total: f64 = weights.iter().sum();
+            std::array::from_fn(|k| positions.iter().zip(weights).map(|(p, w)| w * p[k]).sum::<f64>() / total)
+        }
+        _ => {
+            let n = positions.len() as f64;
+            std::array::from_fn(|k| positions.iter().map(|p| p[k]).sum::<f64>() / n)
+        }
+    };
     for p in positions {
-        p[0] -= mean_x;
-        p[1] -= mean_y;
+        for k in 0..D {
+            p[k] -= mean[k];
+        }
     }
 }
 
+/// Deterministic angle in `[0, 2π)` for the pair `(u, v)`.
+///
+/// Used as the nudge direction when two nodes coincide, so the degenerate
+/// branch does not consume the RNG (splitmix64 finaliser on the packed pair).
+/// vram-lock-native's `cpu::pair_angle` is a copy of this function; change
+/// both together (the tests pin the same values). The GPU shaders of both
+/// vram-lock crates port it too.
+fn pair_angle(u: usize, v: usize) -> f64 {
+    let mut z = ((u as u64) << 32 ^ v as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64 * std::f64::consts::TAU
+}
+
 /// Execute SGD for stress minimization.
 ///
 /// This follows the same update rule as `sgd_stress_nongpu.py`:
@@ -35,12 +63,51 @@ fn center_inplace(positions: &mut [[f64; 2]]) {
 ///   - `r = ((||xv-xu|| - dij)/2) * (diff / ||diff||)`
 ///   - `mu = min(wij * eta, 1)`
 ///   - `xu += mu * r`, `xv -= mu * r`
-pub fn execute_sgd(sgd_params: graph::SgdParams) -> Vec<[f64; 2]> {
+///
+/// Works in any dimension `D`; a coincident pair is nudged apart in the
+/// `x`/`y` plane. A node marked in `fixed` never moves: the other node of
+/// its pairs moves by `2 * mu * r` instead, and the result is not centered.
+pub fn execute_sgd<const D: usize>(sgd_params: graph::SgdParams<D>) -> Vec<[f64; D]> {
+    execute_sgd_with_transform(sgd_params, |_, _| {})
+}
+
+/// [`execute_sgd`] calling `on_iteration(iteration, positions)` after every
+/// iteration (before the final centering), e.g. to record animation frames
+/// or a convergence curve. It only borrows the positions; copying or
+/// evaluating them is the caller's cost.
+pub fn execute_sgd_with_callback<const D: usize>(
+    sgd_params: graph::SgdParams<D>,
+    mut on_iteration: impl FnMut(usize, &[[f64; D]]),
+) -> Vec<[f64; D]> {
+    execute_sgd_with_transform(sgd_params, |positions, iteration| on_iteration(iteration, positions))
+}
+
+/// Stress `Σ wij (||xi - xj|| - dij)²` of `positions` over `pairs`.
+pub fn stress<const D: usize>(positions: &[[f64; D]], pairs: &[graph::EdgeInfo]) -> f64 {
+    pairs
+        .iter()
+        .map(|p| {
+            let d = norm2(sub(positions[p.v], positions[p.u]));
+            p.wij * (d - p.dij) * (d - p.dij)
+        })
+        .sum()
+}
+
+/// [`execute_sgd`] with a hook called as `transform(positions, iteration)`
+/// after every iteration's updates.
+///
+/// The hook may move nodes freely (snap to a line, apply a field, ...); the
+/// next iteration continues from whatever it leaves behind.
+pub fn execute_sgd_with_transform<const D: usize>(
+    sgd_params: graph::SgdParams<D>,
+    mut transform: impl FnMut(&mut [[f64; D]], usize),
+) -> Vec<[f64; D]> {
     let mut rng = rand::rng();
     let mut positions = sgd_params.positions.clone();
     let mut pairs = sgd_params.pairs.clone();
 
     let tiny = 1e-12_f64;
+    let is_fixed = |node: usize| sgd_params.fixed.get(node).copied().unwrap_or(false);
 
     for (iteration, &eta) in sgd_params.etas.iter().enumerate() {
         pairs.shuffle(&mut rng);
@@ -50,35 +117,106 @@ pub fn execute_sgd(sgd_params: graph::SgdParams) -> Vec<[f64; 2]> {
             let v = pair.v;
             let dij = pair.dij;
             let wij = pair.wij;
+            // A fixed node stays put and the other node takes the whole displacement
+            let (share_u, share_v) = match (is_fixed(u), is_fixed(v)) {
+                (false, false) => (1.0, 1.0),
+                (true, false) => (0.0, 2.0),
+                (false, true) => (2.0, 0.0),
+                (true, true) => continue,
+            };
 
             let mut diff = sub(positions[v], positions[u]);
             let mut nrm = norm2(diff);
 
             if nrm < tiny {
-                // avoid 0-division; pick a tiny random direction
-                let angle = rng.random::<f64>() * std::f64::consts::TAU;
-                diff = [angle.cos() * 1e-6, angle.sin() * 1e-6];
+                // avoid 0-division; pick a tiny direction derived from the pair
+                let angle = pair_angle(u, v);
+                diff = [0.0; D];
+                diff[0] = angle.cos() * 1e-6;
+                diff[1] = angle.sin() * 1e-6;
                 nrm = norm2(diff);
             }
 
             // NOTE: i から 勾配方向に ずれ*学習率*(1/2) ずつ移動
-            let r = [
-                ((nrm - dij) / 2.0) * (diff[0] / nrm),
-                ((nrm - dij) / 2.0) * (diff[1] / nrm),
-            ];
+            let r: [f64; D] = std::array::from_fn(|k| ((nrm - dij) / 2.0) * (diff[k] / nrm));
             let mu = (wij * eta).min(1.0);
-            positions[u][0] += mu * r[0];
-            positions[u][1] += mu * r[1];
-            positions[v][0] -= mu * r[0];
-            positions[v][1] -= mu * r[1];
+            for k in 0..D {
+                positions[u][k] += share_u * mu * r[k];
+                positions[v][k] -= share_v * mu * r[k];
+            }
         }
 
+        transform(&mut positions, iteration);
+
         println!("Iteration: {}", iteration + 1);
     }
 
-    if sgd_params.center {
-        center_inplace(&mut positions);
+    if sgd_params.center && !sgd_params.fixed.contains(&true) {
+        center_inplace(&mut positions, &sgd_params.center_mode);
     }
 
     positions
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn pair_angle_is_fixed_per_ordered_pair() {
+        // Same values as the copy in vram-lock-native's cpu.rs
+        assert_eq!(pair_angle(0, 1), 3.559811364734998);
+        assert_eq!(pair_angle(1, 0), 4.814815942578842);
+        assert_eq!(pair_angle(3, 7), 3.658356198943096);
+        for (u, v) in [(0, 1), (5, 2), (100_000, 3)] {
+            assert!((0.0..std::f64::consts::TAU).contains(&pair_angle(u, v)));
+        }
+    }
+
+    /// `side` x `side` grid graph; node `y * side + x` sits at `(x, y)`.
+    fn grid(side: usize) -> graph::Graph {
+        let (mut edge_src, mut edge_dst) = (Vec::new(), Vec::new());
+        for v in 0..side * side {
+            if v % side + 1 < side {
+                edge_src.push(v);
+                edge_dst.push(v + 1);
+            }
+            if v + side < side * side {
+                edge_src.push(v);
+                edge_dst.push(v + side);
+            }
+        }
+        graph::Graph { node_size: side * side, edge_size: edge_src.len(), edge_src, edge_dst }
+    }
+
+    #[test]
+    fn pinned_grid_corners_stay_while_the_rest_relaxes() {
+        // Corners (0, 0) and (k, k) of a 6 x 6 grid, k = 5 hops apart per axis
+        let k = 5;
+        let graph = grid(k + 1);
+        let mut params = graph.prepare_sgd_params::<2>(30, 0.1, true);
+        let last = graph.node_size - 1;
+        params.pin(&HashMap::from([(0, [0.0, 0.0]), (last, [k as f64, k as f64])])).unwrap();
+        let initial = params.positions.clone();
+        let positions = execute_sgd(params);
+        assert_eq!(positions[0], [0.0, 0.0]);
+        assert_eq!(positions[last], [k as f64, k as f64]);
+        for v in 1..last {
+            assert_ne!(positions[v], initial[v], "node {} never moved", v);
+        }
+    }
+
+    #[test]
+    fn callback_fires_once_per_iteration() {
+        let path = std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../data/bcspwr01.mtx"));
+        let graph = graph::Graph::from_mtx(path).unwrap();
+        let params = graph.prepare_sgd_params::<2>(12, 0.1, true);
+        let mut seen = Vec::new();
+        execute_sgd_with_callback(params, |iteration, positions| {
+            assert_eq!(positions.len(), graph.node_size);
+            seen.push(iteration);
+        });
+        assert_eq!(seen, (0..12).collect::<Vec<_>>());
+    }
+}
@@ -2,7 +2,7 @@ use anyhow::Result;
 use rand::Rng;
 use sprs::io::read_matrix_market;
 use sprs::num_kinds::Pattern;
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::path::Path;
 
 #[derive(Debug)]
@@ -13,12 +13,50 @@ pub struct Graph {
     pub edge_dst: Vec<usize>,
 }
 
+/// Precomputed SGD inputs for a layout in `D` dimensions (2 unless asked
+/// otherwise; 3 for a 3D layout).
 #[derive(Debug)]
-pub struct SgdParams {
+pub struct SgdParams<const D: usize = 2> {
     pub etas: Vec<f64>,
-    pub positions: Vec<[f64; 2]>,
+    pub positions: Vec<[f64; D]>,
     pub pairs: Vec<EdgeInfo>,
     pub center: bool,
+    /// Which point `center` moves to the origin.
+    pub center_mode: CenterMode,
+    /// Nodes that SGD leaves in place (see [`SgdParams::pin`]); empty means
+    /// none.
+    pub fixed: Vec<bool>,
+}
+
+impl<const D: usize> SgdParams<D> {
+    /// Move the nodes in `pins` to the given coordinates and keep them there.
+    /// A pinned layout is not centered at the end, since that would move
+    /// the pins.
+    pub fn pin(&mut self, pins: &HashMap<usize, [f64; D]>) -> Result<()> {
+        let n = self.positions.len();
+        if self.fixed.is_empty() {
+            self.fixed = vec![false; n];
+        }
+        for (&node, &p) in pins {
+            if node >= n {
+                anyhow::bail!("pinned node {} out of range for {} nodes", node, n);
+            }
+            self.positions[node] = p;
+            self.fixed[node] = true;
+        }
+        Ok(())
+    }
+}
+
+/// Reference point used when centering a layout.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum CenterMode {
+    /// Unweighted mean of all positions.
+    #[default]
+    Centroid,
+    /// Mean weighted by one non-negative weight per node (e.g. importance or
+    /// [`Graph::degrees`]), so heavy nodes define the frame's center.
+    WeightedBarycenter(Vec<f64>),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -35,14 +73,15 @@ impl Graph {
 
         let node_size: usize = matrix.rows();
         
-        // Filter out self-loops
+        // Filter out self-loops and duplicate edges
         let mut edge_src = Vec::new();
         let mut edge_dst = Vec::new();
+        let mut seen = HashSet::new();
         
-        for (row, col) in matrix.row_inds().iter().zip(matrix.col_inds().iter()) {
-            if row != col {
-                edge_src.push(*row);
-                edge_dst.push(*col);
+        for (&row, &col) in matrix.row_inds().iter().zip(matrix.col_inds().iter()) {
+            if row != col && seen.insert((row.min(col), row.max(col))) {
+                edge_src.push(row);
+                edge_dst.push(col);
             }
         }
         
@@ -98,18 +137,14 @@ impl Graph {
         let mut dmin: f64 = f64::INFINITY;
         let mut dmax: f64 = 0.0;
 
-        for u in 0..dist.len() {
-            for v in 0..dist[u].len() {
-                if u >= v {
-                    continue;
-                }
-
+        for (u, row) in dist.iter().enumerate() {
+            for (v, &d) in row.iter().enumerate().skip(u + 1) {
                 // Skip unreachable nodes (distance == usize::MAX)
-                if dist[u][v] == usize::MAX {
+                if d == usize::MAX {
                     continue;
                 }
 
-                let dij = dist[u][v] as f64;
+                let dij = d as f64;
                 if dij <= 0.0 {
                     continue;
                 }
@@ -128,13 +163,13 @@ impl Graph {
         (pairs, wmin, wmax)
     }
 
-    /// Precompute SGD parameters
-    pub fn prepare_sgd_params(
+    /// Precompute SGD parameters for a `D`-dimensional layout
+    pub fn prepare_sgd_params<const D: usize>(
         &self,
         iterations: usize,
         epsilon: f64,
         center: bool,
-    ) -> SgdParams {
+    ) -> SgdParams<D> {
         let dist = self.calc_dist_matrix();
         let (pairs, wmin, wmax) = self.calc_edge_info(&dist);
 
@@ -147,13 +182,36 @@ impl Graph {
             positions,
             pairs,
             center,
+            center_mode: CenterMode::Centroid,
+            fixed: Vec::new(),
+        }
+    }
+
+    /// Number of edge endpoints at each node (self-loops count twice).
+    pub fn degrees(&self) -> Vec<f64> {
+        let mut degrees = vec![0.0; self.node_size];
+        for (&u, &v) in self.edge_src.iter().zip(&self.edge_dst) {
+            degrees[u] += 1.0;
+            degrees[v] += 1.0;
         }
+        degrees
     }
 }
 
 pub fn calc_learning_rate(tmax: usize, wmin: f64, wmax: f64, eps: f64) -> Vec<f64> {
+    // Replace unusable weight bounds so every step stays finite
+    let usable = |w: f64| w.is_finite() && w > 0.0;
+    let (wmin, wmax) = match (usable(wmin), usable(wmax)) {
+        (true, true) => (wmin, wmax),
+        (true, false) => (wmin, wmin),
+        (false, true) => (wmax, wmax),
+        (false, false) => (1.0, 1.0),
+    };
     let eta_max = 1.0 / wmin;
     let eta_min = eps / wmax;
+    if tmax < 2 {
+        return vec![eta_max; tmax];
+    }
     let lamb = (eta_max / eta_min).ln() / (tmax - 1) as f64;
 
     let etas: Vec<f64> = (0..tmax)
@@ -163,28 +221,73 @@ pub fn calc_learning_rate(tmax: usize, wmin: f64, wmax: f64, eps: f64) -> Vec<f6
     etas
 }
 
-pub fn init_positions_random(n_nodes: usize, center: bool) -> Vec<[f64; 2]> {
+pub fn init_positions_random<const D: usize>(n_nodes: usize, center: bool) -> Vec<[f64; D]> {
     let mut rng = rand::rng();
 
     // Random coordinates in the range [0, 1)
-    let mut positions: Vec<[f64; 2]> = (0..n_nodes)
-        .map(|_| [rng.random::<f64>(), rng.random::<f64>()])
+    let mut positions: Vec<[f64; D]> = (0..n_nodes)
+        .map(|_| std::array::from_fn(|_| rng.random::<f64>()))
         .collect();
 
     // centering if center is true
     if center {
         // Calc the center of the positions
-        let sum_x: f64 = positions.iter().map(|p| p[0]).sum();
-        let sum_y: f64 = positions.iter().map(|p| p[1]).sum();
-        let mean_x = sum_x / n_nodes as f64;
-        let mean_y = sum_y / n_nodes as f64;
+        let mean: [f64; D] =
+            std::array::from_fn(|k| positions.iter().map(|p| p[k]).sum::<f64>() / n_nodes as f64);
 
         // move to the center
         for pos in &mut positions {
-            pos[0] -= mean_x;
-            pos[1] -= mean_y;
+            for k in 0..D {
+                pos[k] -= mean[k];
+            }
         }
     }
 
     positions
 }
+
+/// Read pinned node positions, one `node c1 .. cD` line each (`#` comments
+/// and blank lines ignored).
+pub fn read_pins<const D: usize>(path: &Path) -> Result<HashMap<usize, [f64; D]>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut pins = HashMap::new();
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < D + 1 {
+            anyhow::bail!("{}:{}: expected a node and {} coordinates", path.display(), lineno + 1, D);
+        }
+        let mut p = [0.0; D];
+        for (k, c) in p.iter_mut().enumerate() {
+            *c = parts[k + 1].parse()?;
+        }
+        pins.insert(parts[0].parse()?, p);
+    }
+    Ok(pins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn learning_rates_follow_the_pair_weights() {
+        let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../data/bcspwr01.mtx"));
+        let graph = Graph::from_mtx(path).unwrap();
+        let (_, wmin, wmax) = graph.calc_edge_info(&graph.calc_dist_matrix());
+        let etas = graph.prepare_sgd_params::<2>(30, 0.1, true).etas;
+        assert!((etas[0] - 1.0 / wmin).abs() < 1e-9);
+        assert!((etas[29] - 0.1 / wmax).abs() < 1e-12);
+        assert!(etas.windows(2).all(|w| w[0] > w[1]));
+        assert_eq!(graph.prepare_sgd_params::<3>(1, 0.1, true).etas, vec![1.0 / wmin]);
+
+        // Isolated nodes have no pairs, so neither weight bound is usable
+        let isolated = Graph { node_size: 3, edge_size: 0, edge_src: Vec::new(), edge_dst: Vec::new() };
+        let etas = isolated.prepare_sgd_params::<2>(3, 0.1, true).etas;
+        assert!((etas[0] - 1.0).abs() < 1e-12);
+        assert!((etas[2] - 0.1).abs() < 1e-12);
+    }
+}
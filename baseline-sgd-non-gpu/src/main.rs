@@ -26,13 +26,45 @@ fn main() -> Result<()> {
     // LOG: Print graph information
     // println!("{:?}",graph);
 
+    // --dim 2|3: lay out in the plane (default) or in 3D
+    let args: Vec<String> = std::env::args().collect();
+    match args.iter().position(|a| a == "--dim").and_then(|i| args.get(i + 1)).map(String::as_str) {
+        None | Some("2") => run::<2>(&graph, mtx_path, &args),
+        Some("3") => run::<3>(&graph, mtx_path, &args),
+        Some(other) => anyhow::bail!("unknown --dim {:?} (expected 2 or 3)", other),
+    }
+}
+
+/// Lay out `graph` in `D` dimensions and write the initial and final
+/// positions (`D` columns) to `../output`.
+fn run<const D: usize>(graph: &graph::Graph, mtx_path: &Path, args: &[String]) -> Result<()> {
     // CPU precompute
-    let sgd_params = graph.prepare_sgd_params(15, 0.1, true);
+    let mut sgd_params = graph.prepare_sgd_params::<D>(15, 0.1, true);
+    // --center-by-degree: center the result on the degree-weighted barycenter
+    if args.iter().any(|a| a == "--center-by-degree") {
+        sgd_params.center_mode = graph::CenterMode::WeightedBarycenter(graph.degrees());
+    }
+    // --pin FILE: `node c1 .. cD` lines; those nodes start and stay there
+    if let Some(path) = args.iter().position(|a| a == "--pin").and_then(|i| args.get(i + 1)) {
+        let pins = graph::read_pins::<D>(Path::new(path))?;
+        sgd_params.pin(&pins)?;
+        println!("Pinned {} nodes from {}", pins.len(), path);
+    }
     // println!("{:?}", sgd_params);
     let initial_positions = sgd_params.positions.clone();
 
+    // --convergence PATH: write `iteration stress` after every iteration
+    let convergence_path = args.iter().position(|a| a == "--convergence").and_then(|i| args.get(i + 1));
+    let pairs = convergence_path.map(|_| sgd_params.pairs.clone()).unwrap_or_default();
+    let mut convergence: Vec<(usize, f64)> = Vec::new();
+
     let start = Instant::now();
-    let result = algorithm::execute_sgd(sgd_params);
+    let result = match convergence_path {
+        Some(_) => algorithm::execute_sgd_with_callback(sgd_params, |iteration, positions| {
+            convergence.push((iteration, algorithm::stress(positions, &pairs)));
+        }),
+        None => algorithm::execute_sgd(sgd_params),
+    };
 
     let duration = start.elapsed();
     println!("Time taken: {:?}", duration);
@@ -49,15 +81,15 @@ fn main() -> Result<()> {
     writeln!(file, "# Timestamp: {}", Local::now().format("%Y-%m-%d %H:%M:%S"))?;
     writeln!(file, "# Node count: {}", graph.node_size)?;
     writeln!(file, "# Edge count: {}", graph.edge_size)?;
-    writeln!(file, "")?;
+    writeln!(file)?;
     writeln!(file, "# Edges (source target)")?;
     for i in 0..graph.edge_size {
         writeln!(file, "{} {}", graph.edge_src[i], graph.edge_dst[i])?;
     }
-    writeln!(file, "")?;
-    writeln!(file, "# Positions (x y)")?;
+    writeln!(file)?;
+    writeln!(file, "# Positions ({})", ["x", "y", "z"][..D].join(" "))?;
     for pos in &initial_positions {
-        writeln!(file, "{} {}", pos[0], pos[1])?;
+        writeln!(file, "{}", pos.map(|c| c.to_string()).join(" "))?;
     }
     println!("Initial result saved to {}", filename_init);
     
@@ -68,17 +100,26 @@ fn main() -> Result<()> {
     writeln!(file, "# Timestamp: {}", Local::now().format("%Y-%m-%d %H:%M:%S"))?;
     writeln!(file, "# Node count: {}", graph.node_size)?;
     writeln!(file, "# Edge count: {}", graph.edge_size)?;
-    writeln!(file, "")?;
+    writeln!(file)?;
     writeln!(file, "# Edges (source target)")?;
     for i in 0..graph.edge_size {
         writeln!(file, "{} {}", graph.edge_src[i], graph.edge_dst[i])?;
     }
-    writeln!(file, "")?;
-    writeln!(file, "# Positions (x y)")?;
+    writeln!(file)?;
+    writeln!(file, "# Positions ({})", ["x", "y", "z"][..D].join(" "))?;
     for pos in &result {
-        writeln!(file, "{} {}", pos[0], pos[1])?;
+        writeln!(file, "{}", pos.map(|c| c.to_string()).join(" "))?;
     }
     println!("Processed result saved to {}", filename_processed);
 
+    if let Some(path) = convergence_path {
+        let mut file = File::create(path)?;
+        writeln!(file, "# iteration stress")?;
+        for (iteration, stress) in &convergence {
+            writeln!(file, "{} {}", iteration, stress)?;
+        }
+        println!("Convergence saved to {}", path);
+    }
+
     Ok(())
 }
@@ -32,6 +32,7 @@ fn main() -> Result<()> {
     let (width, height)    = parse_size(&args).unwrap_or((2048, 2048));
     let node_radius_px     = parse_node_radius(&args)
         .unwrap_or(renderer::DEFAULT_NODE_RADIUS_PX);
+    let color_by_stress    = args.iter().any(|a| a == "--color-by-stress");
 
     // ── Load ─────────────────────────────────────────────────────────────────
     println!("Reading: {}", input_path.display());
@@ -66,6 +67,17 @@ fn main() -> Result<()> {
         }
     };
 
+    let edge_stress = stress::calc_edge_stress(&graph.positions, &graph.edges);
+    println!("  edge stress = {edge_stress:.2}");
+
+    // Per-node stress colouring (blue = low, red = high)
+    let node_colors = if color_by_stress {
+        let node_stress = stress::calc_node_stress(&graph.positions, &graph.edges);
+        Some(renderer::value_colors(&node_stress))
+    } else {
+        None
+    };
+
     // ── GPU render ────────────────────────────────────────────────────────────
     println!("Initialising GPU renderer ...");
     let renderer = renderer::GpuRenderer::new()?;
@@ -78,6 +90,7 @@ fn main() -> Result<()> {
         width,
         height,
         node_radius_px,
+        node_colors.as_deref(),
     )?;
     println!("  GPU render: {:.1}ms", t1.elapsed().as_secs_f64() * 1000.0);
 
@@ -88,6 +101,7 @@ fn main() -> Result<()> {
 
     println!("Saved:  {}", output_path.display());
     println!("Stress: {stress_str}");
+    println!("Edge stress: {edge_stress:.2}");
     println!("Total:  {:.1}ms", t0.elapsed().as_secs_f64() * 1000.0);
 
     Ok(())
@@ -5,6 +5,9 @@ use wgpu::util::DeviceExt;
 /// Default node radius in pixels for the output image.
 pub const DEFAULT_NODE_RADIUS_PX: f32 = 4.0;
 
+/// Default node fill colour (matplotlib blue #1f77b4).
+pub const DEFAULT_NODE_COLOR: [f32; 4] = [0.122, 0.467, 0.706, 1.0];
+
 /// Uniform buffer sent to both shaders.
 #[repr(C)]
 #[derive(Clone, Copy, Pod, Zeroable)]
@@ -61,6 +64,9 @@ impl GpuRenderer {
     }
 
     /// Render graph to a tightly-packed RGBA8 pixel buffer (top-to-bottom).
+    ///
+    /// `node_colors` gives one RGBA fill per node; `None` draws every node in
+    /// `DEFAULT_NODE_COLOR`.
     pub fn render(
         &self,
         positions:      &[[f32; 2]],
@@ -68,7 +74,17 @@ impl GpuRenderer {
         width:          u32,
         height:         u32,
         node_radius_px: f32,
+        node_colors:    Option<&[[f32; 4]]>,
     ) -> Result<Vec<u8>> {
+        if let Some(colors) = node_colors {
+            anyhow::ensure!(
+                colors.len() == positions.len(),
+                "{} node colours given for {} nodes",
+                colors.len(),
+                positions.len()
+            );
+        }
+
         // ── Bounds + padding ──────────────────────────────────────────────────
         let (min_x, max_x, min_y, max_y) = bounds(positions);
         let pad_x = (max_x - min_x).max(1e-6) * 0.05;
@@ -141,6 +157,11 @@ impl GpuRenderer {
             offset:           0,
             shader_location:  1,
         }];
+        let vec4_attr_slot2 = [wgpu::VertexAttribute {
+            format:           wgpu::VertexFormat::Float32x4,
+            offset:           0,
+            shader_location:  2,
+        }];
 
         // ── Edge pipeline: LINE_LIST + alpha blending ─────────────────────────
         let edge_pipeline =
@@ -180,6 +201,7 @@ impl GpuRenderer {
         // ── Node pipeline: instanced quads + alpha blending ───────────────────
         // slot 0 = quad corner offsets (Vertex step)
         // slot 1 = node center positions (Instance step)
+        // slot 2 = node fill colours (Instance step)
         let node_pipeline =
             self.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
                 label:  Some("node_pipeline"),
@@ -198,6 +220,11 @@ impl GpuRenderer {
                             step_mode:    wgpu::VertexStepMode::Instance,
                             attributes:   &vec2_attr_slot1,
                         },
+                        wgpu::VertexBufferLayout {
+                            array_stride: 16,
+                            step_mode:    wgpu::VertexStepMode::Instance,
+                            attributes:   &vec4_attr_slot2,
+                        },
                     ],
                     compilation_options: wgpu::PipelineCompilationOptions::default(),
                 },
@@ -248,6 +275,21 @@ impl GpuRenderer {
             usage:    wgpu::BufferUsages::VERTEX,
         });
 
+        // Node colour buffer
+        let default_colors;
+        let colors = match node_colors {
+            Some(c) => c,
+            None => {
+                default_colors = vec![DEFAULT_NODE_COLOR; positions.len()];
+                &default_colors
+            }
+        };
+        let color_buf = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label:    Some("node_colors"),
+            contents: bytemuck::cast_slice(colors),
+            usage:    wgpu::BufferUsages::VERTEX,
+        });
+
         // ── Off-screen render texture ──────────────────────────────────────────
         let texture = self.device.create_texture(&wgpu::TextureDescriptor {
             label:             Some("render_texture"),
@@ -309,6 +351,7 @@ impl GpuRenderer {
             pass.set_pipeline(&node_pipeline);
             pass.set_vertex_buffer(0, quad_buf.slice(..));
             pass.set_vertex_buffer(1, node_buf.slice(..));
+            pass.set_vertex_buffer(2, color_buf.slice(..));
             pass.draw(0..QUAD_VERTS.len() as u32, 0..positions.len() as u32);
         }
 
@@ -346,6 +389,26 @@ impl GpuRenderer {
     }
 }
 
+/// Map per-node values to colours on a blue → red ramp, normalised by the
+/// largest value (blue = lowest, red = highest).
+pub fn value_colors(values: &[f64]) -> Vec<[f32; 4]> {
+    const LOW:  [f32; 3] = [0.122, 0.467, 0.706]; // #1f77b4
+    const HIGH: [f32; 3] = [0.839, 0.153, 0.157]; // #d62728
+    let max = values.iter().cloned().fold(0.0f64, f64::max);
+    values
+        .iter()
+        .map(|&v| {
+            let t = if max > 0.0 { (v / max) as f32 } else { 0.0 };
+            [
+                LOW[0] + (HIGH[0] - LOW[0]) * t,
+                LOW[1] + (HIGH[1] - LOW[1]) * t,
+                LOW[2] + (HIGH[2] - LOW[2]) * t,
+                1.0,
+            ]
+        })
+        .collect()
+}
+
 fn bounds(positions: &[[f32; 2]]) -> (f32, f32, f32, f32) {
     let mut min_x = f32::INFINITY;
     let mut max_x = f32::NEG_INFINITY;
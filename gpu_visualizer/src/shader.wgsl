@@ -4,7 +4,8 @@
 // Y is negated so larger Y values appear at the top, matching matplotlib.
 //
 // Edge pass  : vs_edge / fs_edge   – LINE_LIST, semi-transparent blue
-// Node pass  : vs_node / fs_node   – instanced unit quads, circular clip
+// Node pass  : vs_node / fs_node   – instanced unit quads, circular clip,
+//              per-node fill colour
 
 struct Uniforms {
     min_x:    f32,
@@ -43,18 +44,21 @@ fn fs_edge() -> @location(0) vec4<f32> {
 struct NodeOut {
     @builtin(position) pos:    vec4<f32>,
     @location(0)       offset: vec2<f32>,  // quad corner in [-1,1]²
+    @location(1)       color:  vec4<f32>,
 }
 
 // @location(0) offset : per-vertex quad corner   (VertexStepMode::Vertex)
 // @location(1) center : per-node graph position  (VertexStepMode::Instance)
+// @location(2) color  : per-node fill colour     (VertexStepMode::Instance)
 @vertex
 fn vs_node(
     @location(0) offset: vec2<f32>,
     @location(1) center: vec2<f32>,
+    @location(2) color:  vec4<f32>,
 ) -> NodeOut {
     let ndc_center = graph_to_ndc(center);
     let ndc_pos    = ndc_center + offset * vec2<f32>(u.node_r_x, u.node_r_y);
-    return NodeOut(vec4<f32>(ndc_pos, 0.0, 1.0), offset);
+    return NodeOut(vec4<f32>(ndc_pos, 0.0, 1.0), offset, color);
 }
 
 @fragment
@@ -63,5 +67,5 @@ fn fs_node(in: NodeOut) -> @location(0) vec4<f32> {
     if length(in.offset) > 1.0 {
         discard;
     }
-    return vec4<f32>(0.122, 0.467, 0.706, 1.0);
+    return in.color;
 }
@@ -1,4 +1,4 @@
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 
 /// Threshold above which stress computation is skipped (O(n²) memory/time).
 const MAX_N_FOR_STRESS: usize = usize::MAX;
@@ -21,6 +21,63 @@ pub fn calc_stress(
         return StressResult::TooLarge;
     }
 
+    let mut stress = 0.0f64;
+    for_each_pair_term(positions, edges, |_, _, term| stress += term);
+
+    // If every off-diagonal BFS entry was MAX the graph is fully disconnected
+    // (stress would be 0.0 — report Disconnected instead)
+    if stress == 0.0 && n > 1 && edges.is_empty() {
+        return StressResult::Disconnected;
+    }
+
+    StressResult::Value(stress)
+}
+
+/// Edge stress: Σ_{(i,j) ∈ E} (||p_i - p_j|| - 1)²
+///
+/// Only the graph edges (d_ij = w_ij = 1) are evaluated, so this is cheap
+/// even when the full stress is not. Each undirected edge counts once.
+pub fn calc_edge_stress(
+    positions: &[[f32; 2]],
+    edges: &[(usize, usize)],
+) -> f64 {
+    let n = positions.len();
+    let mut seen = HashSet::new();
+    let mut stress = 0.0f64;
+    for &(u, v) in edges {
+        if u == v || u >= n || v >= n || !seen.insert((u.min(v), u.max(v))) {
+            continue;
+        }
+        let dx = positions[u][0] as f64 - positions[v][0] as f64;
+        let dy = positions[u][1] as f64 - positions[v][1] as f64;
+        let euc = (dx * dx + dy * dy).sqrt();
+        stress += (euc - 1.0) * (euc - 1.0);
+    }
+    stress
+}
+
+/// Per-node stress: each pair term w_ij (||p_i - p_j|| - d_ij)² is credited
+/// to both endpoints, so the values sum to twice the total stress.
+pub fn calc_node_stress(
+    positions: &[[f32; 2]],
+    edges: &[(usize, usize)],
+) -> Vec<f64> {
+    let mut stress = vec![0.0f64; positions.len()];
+    for_each_pair_term(positions, edges, |src, dst, term| {
+        stress[src] += term;
+        stress[dst] += term;
+    });
+    stress
+}
+
+/// Visit every connected pair i<j once with its weighted stress term.
+fn for_each_pair_term(
+    positions: &[[f32; 2]],
+    edges: &[(usize, usize)],
+    mut f: impl FnMut(usize, usize, f64),
+) {
+    let n = positions.len();
+
     // Build adjacency list
     let mut adj: Vec<Vec<usize>> = vec![Vec::new(); n];
     for &(u, v) in edges {
@@ -30,33 +87,21 @@ pub fn calc_stress(
         }
     }
 
-    let stress: f64 = (0..n)
-        .map(|src| {
-            let dist = bfs(&adj, n, src);
-            let mut s = 0.0f64;
-            for dst in (src + 1)..n {
-                let d = dist[dst];
-                if d == u32::MAX {
-                    continue; // unreachable pair – skip
-                }
-                let d = d as f64;
-                let dx = positions[src][0] as f64 - positions[dst][0] as f64;
-                let dy = positions[src][1] as f64 - positions[dst][1] as f64;
-                let euc = (dx * dx + dy * dy).sqrt();
-                let w = 1.0 / (d * d);
-                s += w * (euc - d) * (euc - d);
+    for src in 0..n {
+        let dist = bfs(&adj, n, src);
+        for dst in (src + 1)..n {
+            let d = dist[dst];
+            if d == u32::MAX {
+                continue; // unreachable pair – skip
             }
-            s
-        })
-        .sum();
-
-    // If every off-diagonal BFS entry was MAX the graph is fully disconnected
-    // (stress would be 0.0 — report Disconnected instead)
-    if stress == 0.0 && n > 1 && edges.is_empty() {
-        return StressResult::Disconnected;
+            let d = d as f64;
+            let dx = positions[src][0] as f64 - positions[dst][0] as f64;
+            let dy = positions[src][1] as f64 - positions[dst][1] as f64;
+            let euc = (dx * dx + dy * dy).sqrt();
+            let w = 1.0 / (d * d);
+            f(src, dst, w * (euc - d) * (euc - d));
+        }
     }
-
-    StressResult::Value(stress)
 }
 
 fn bfs(adj: &[Vec<usize>], n: usize, src: usize) -> Vec<u32> {
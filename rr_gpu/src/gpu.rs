@@ -7,6 +7,8 @@ use wgpu::util::DeviceExt;
 
 const T: u32 = 1024; // ブロックサイズ
 
+type Positions = Vec<[f32; 2]>;
+
 /// カーネルに渡す uniform バッファ
 /// r_outer / big_b は廃止。タイル割り当ては tiles バッファで渡す。
 #[repr(C)]
@@ -52,7 +54,7 @@ pub fn build_schedule(big_b: u32) -> Vec<Vec<(u32, u32)>> {
     // ── サイクルアルゴリズム ──────────────────────────────
     // B が奇数のときダミーブロック B を追加して偶数化。
     // ダミーを含むペアは skip する。
-    let b_eff = if b % 2 == 0 { b } else { b + 1 };
+    let b_eff = if b.is_multiple_of(2) { b } else { b + 1 };
     let fixed = (b_eff - 1) as u32; // 固定ノード（実ブロック or ダミー）
 
     for r in 0..(b_eff - 1) {
@@ -92,6 +94,9 @@ pub fn build_schedule(big_b: u32) -> Vec<Vec<(u32, u32)>> {
 
 // ── GPU コンテキスト ──────────────────────────────────────────────────────────
 
+/// パイプラインが使うストレージバッファ数
+const STORAGE_BUFFERS_USED: u32 = 5;
+
 impl GpuContext {
     pub fn new() -> Result<Self> {
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
@@ -104,6 +109,25 @@ impl GpuContext {
         let limits = adapter.limits();
         println!("max_compute_invocations_per_workgroup: {}", limits.max_compute_invocations_per_workgroup);
 
+        // コンピュートシェーダ非対応のアダプタはパイプライン作成前に弾く
+        let info = adapter.get_info();
+        if !adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS) {
+            anyhow::bail!(
+                "selected adapter {} ({:?}) does not support compute shaders",
+                info.name,
+                info.device_type
+            );
+        }
+        if limits.max_storage_buffers_per_shader_stage < STORAGE_BUFFERS_USED {
+            anyhow::bail!(
+                "selected adapter {} ({:?}) supports only {} storage buffers per shader stage, {} are needed",
+                info.name,
+                info.device_type,
+                limits.max_storage_buffers_per_shader_stage,
+                STORAGE_BUFFERS_USED
+            );
+        }
+
         let (device, queue) = pollster::block_on(adapter.request_device(
             &wgpu::DeviceDescriptor {
                 label:                None,
@@ -125,7 +149,7 @@ impl GpuContext {
     pub fn execute_sgd(
         &self,
         params: graph::SgdParams,
-    ) -> Result<(Vec<[f32; 2]>, Vec<[f32; 2]>)> {
+    ) -> Result<(Positions, Positions)> {
         let n      = params.positions.len() as u32;
         let big_b  = n.div_ceil(T);
 
@@ -329,8 +353,7 @@ impl GpuContext {
         // 外側シャッフル用にスケジュールのインデックス列を用意
         let mut round_order: Vec<usize> = (0..schedule.len()).collect();
 
-        for iter in 0..num_iterations {
-            let eta = etas[iter];
+        for (iter, &eta) in etas.iter().enumerate() {
             let uni = Uniforms { n, eta, _pad: [0; 2] };
             self.queue.write_buffer(&uniforms_buffer, 0, bytemuck::bytes_of(&uni));
 
@@ -2,7 +2,7 @@ use anyhow::Result;
 use rand::Rng;
 use sprs::io::read_matrix_market;
 use sprs::num_kinds::Pattern;
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::path::Path;
 
 #[derive(Debug)]
@@ -18,6 +18,7 @@ pub struct SgdParams {
     pub etas: Vec<f64>,
     pub positions: Vec<[f64; 2]>,
     pub pairs: Vec<EdgeInfo>,
+    #[allow(dead_code)]
     pub center: bool,
 }
 
@@ -26,6 +27,7 @@ pub struct EdgeInfo {
     pub u: usize,
     pub v: usize,
     pub dij: f64,
+    #[allow(dead_code)]
     pub wij: f64,
 }
 
@@ -35,14 +37,15 @@ impl Graph {
 
         let node_size: usize = matrix.rows();
         
-        // Filter out self-loops
+        // Filter out self-loops and duplicate edges
         let mut edge_src = Vec::new();
         let mut edge_dst = Vec::new();
+        let mut seen = HashSet::new();
         
-        for (row, col) in matrix.row_inds().iter().zip(matrix.col_inds().iter()) {
-            if row != col {
-                edge_src.push(*row);
-                edge_dst.push(*col);
+        for (&row, &col) in matrix.row_inds().iter().zip(matrix.col_inds().iter()) {
+            if row != col && seen.insert((row.min(col), row.max(col))) {
+                edge_src.push(row);
+                edge_dst.push(col);
             }
         }
         
@@ -103,11 +106,11 @@ impl Graph {
                 let mut dmin = f64::INFINITY;
                 let mut dmax = 0.0_f64;
 
-                for v in (u + 1)..n {
-                    if dist[u][v] == usize::MAX {
+                for (v, &d) in dist[u].iter().enumerate().skip(u + 1) {
+                    if d == usize::MAX {
                         continue;
                     }
-                    let dij = dist[u][v] as f64;
+                    let dij = d as f64;
                     if dij <= 0.0 {
                         continue;
                     }
@@ -160,8 +163,19 @@ impl Graph {
 }
 
 pub fn calc_learning_rate(tmax: usize, wmin: f64, wmax: f64, eps: f64) -> Vec<f64> {
+    // Fall back to the other bound (or 1) when one is zero or infinite
+    let usable = |w: f64| w.is_finite() && w > 0.0;
+    let (wmin, wmax) = match (usable(wmin), usable(wmax)) {
+        (true, true) => (wmin, wmax),
+        (true, false) => (wmin, wmin),
+        (false, true) => (wmax, wmax),
+        (false, false) => (1.0, 1.0),
+    };
     let eta_max = 1.0 / wmin;
     let eta_min = eps / wmax;
+    if tmax < 2 {
+        return vec![eta_max; tmax];
+    }
     let lamb = (eta_max / eta_min).ln() / (tmax - 1) as f64;
 
     let etas: Vec<f64> = (0..tmax)
@@ -196,3 +210,21 @@ pub fn init_positions_random(n_nodes: usize, center: bool) -> Vec<[f64; 2]> {
 
     positions
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn learning_rates_stay_finite_for_tiny_graphs() {
+        // Path 0 - 1 - 2: weights 1/4 (the ends) to 1
+        let path = Graph { node_size: 3, edge_size: 2, edge_src: vec![0, 1], edge_dst: vec![1, 2] };
+        assert_eq!(path.prepare_sgd_params(1, 0.1, true).etas, vec![4.0]);
+        let etas = path.prepare_sgd_params(10, 0.1, true).etas;
+        assert!((etas[0] - 4.0).abs() < 1e-12);
+        assert!((etas[9] - 0.1).abs() < 1e-12);
+
+        let single_node = Graph { node_size: 1, edge_size: 0, edge_src: Vec::new(), edge_dst: Vec::new() };
+        assert_eq!(single_node.prepare_sgd_params(1, 0.1, true).etas, vec![1.0]);
+    }
+}
@@ -0,0 +1,133 @@
+//! Single-file `.tar.gz` bundle of a finished run.
+//!
+//! Archive layout (all paths at the top level):
+//!
+//! - `edges.txt`     one `source target layer` line per graph edge
+//! - `positions.txt` one `x y` line per node (final layout)
+//! - `params.bin`    the run's `SgdParams` in the `SgdParams::save` format
+//! - `metrics.txt`   one `name value` line per stress metric
+//!
+//! Lines starting with `#` are comments. The node count is taken from
+//! `positions.txt`.
+
+use crate::graph::{Graph, SgdParams};
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Contents of an archive written by [`write_archive`].
+pub struct LayoutArchive {
+    pub graph: Graph,
+    pub positions: Vec<[f32; 2]>,
+    pub params: SgdParams,
+    pub metrics: Vec<(String, f64)>,
+}
+
+/// Bundle `graph`, the final `positions`, the `params` the run started from
+/// and named `metrics` into a gzip-compressed tar file.
+pub fn write_archive(
+    path: &Path,
+    graph: &Graph,
+    positions: &[[f32; 2]],
+    params: &SgdParams,
+    metrics: &[(&str, f64)],
+) -> Result<()> {
+    let mut edges = String::from("# source target layer\n");
+    for i in 0..graph.edge_size {
+        writeln!(edges, "{} {} {}", graph.edge_src[i], graph.edge_dst[i], graph.edge_layer[i])?;
+    }
+    let mut positions_txt = String::from("# x y\n");
+    for pos in positions {
+        writeln!(positions_txt, "{} {}", pos[0], pos[1])?;
+    }
+    let mut params_bin = Vec::new();
+    params.write_to(&mut params_bin)?;
+    let mut metrics_txt = String::new();
+    for (name, value) in metrics {
+        writeln!(metrics_txt, "{} {}", name, value)?;
+    }
+
+    let mtime = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut builder = tar::Builder::new(GzEncoder::new(File::create(path)?, Compression::default()));
+    for (name, data) in [
+        ("edges.txt", edges.as_bytes()),
+        ("positions.txt", positions_txt.as_bytes()),
+        ("params.bin", &params_bin),
+        ("metrics.txt", metrics_txt.as_bytes()),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        builder.append_data(&mut header, name, data)?;
+    }
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Load an archive written by [`write_archive`].
+pub fn read_archive(path: &Path) -> Result<LayoutArchive> {
+    let mut edges = None;
+    let mut positions = None;
+    let mut params = None;
+    let mut metrics = None;
+
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(path)?));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        match name.as_str() {
+            "edges.txt" => edges = Some(String::from_utf8(data)?),
+            "positions.txt" => positions = Some(String::from_utf8(data)?),
+            "params.bin" => params = Some(SgdParams::from_bytes(&data, &format!("{}:params.bin", path.display()))?),
+            "metrics.txt" => metrics = Some(String::from_utf8(data)?),
+            _ => println!("Warning: {}: ignoring unknown entry {}", path.display(), name),
+        }
+    }
+    let missing = |entry: &str| anyhow::anyhow!("{}: missing {}", path.display(), entry);
+
+    let positions = parse_lines(&positions.ok_or_else(|| missing("positions.txt"))?, 2, |fields| {
+        Ok([fields[0].parse()?, fields[1].parse()?])
+    })?;
+    let edges: Vec<(usize, usize, usize)> = parse_lines(&edges.ok_or_else(|| missing("edges.txt"))?, 3, |fields| {
+        Ok((fields[0].parse()?, fields[1].parse()?, fields[2].parse()?))
+    })?;
+    let metrics = parse_lines(&metrics.ok_or_else(|| missing("metrics.txt"))?, 2, |fields| {
+        Ok((fields[0].to_string(), fields[1].parse()?))
+    })?;
+    let params = params.ok_or_else(|| missing("params.bin"))?;
+
+    let mut graph = Graph::from_edges(
+        positions.len(),
+        edges.iter().map(|e| e.0).collect(),
+        edges.iter().map(|e| e.1).collect(),
+    )
+    .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    graph.edge_layer = edges.iter().map(|e| e.2).collect();
+
+    Ok(LayoutArchive { graph, positions, params, metrics })
+}
+
+/// Parse whitespace-separated records of at least `width` fields, skipping
+/// blank and `#` lines.
+fn parse_lines<T>(text: &str, width: usize, parse: impl Fn(&[&str]) -> Result<T>) -> Result<Vec<T>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            anyhow::ensure!(fields.len() >= width, "malformed line `{}`", line);
+            parse(&fields).map_err(|e| anyhow::anyhow!("malformed line `{}`: {}", line, e))
+        })
+        .collect()
+}
@@ -0,0 +1,363 @@
+//! Barnes-Hut approximation of the full stress model (CPU).
+//!
+//! Full stress needs a term for every node pair. Here only the `params.pairs`
+//! (the pairs within `radius` hops, see
+//! `Graph::prepare_sgd_params_neighborhood`) get the usual SGD update. The
+//! terms of all farther pairs are approximated with a quadtree rebuilt from
+//! the current positions each iteration: seen from node `i`, a cell whose
+//! `size / distance` is below `theta` stands in for all of its nodes, placed
+//! at its centre of mass and `d(i, r)` hops away from `i`, where `r` is the
+//! cell's representative node. An iteration then costs about
+//! `O(m + n log n)` instead of `O(n²)`. `theta = 0` visits every leaf (exact
+//! far terms); 0.5 to 1.0 is the usual range. The hop distances of the far
+//! terms are estimated through `k` pivots (see [`PivotDistances`]), so
+//! neither memory nor precompute is `O(n²)`.
+
+use crate::{cpu, graph};
+use rand::seq::SliceRandom;
+
+/// Pivots of [`PivotDistances`] when `--pivots` is not given.
+pub const DEFAULT_PIVOTS: usize = 50;
+
+/// No child in [`Cell::children`].
+const EMPTY: usize = usize::MAX;
+
+/// Cells smaller than this are not split further, so coincident nodes share
+/// a leaf instead of recursing forever.
+const MIN_CELL_SIZE: f64 = 1e-9;
+
+/// Quadtree cell; `mass` is the number of nodes below it.
+struct Cell {
+    min: [f64; 2],
+    size: f64,
+    mass: f64,
+    center_of_mass: [f64; 2],
+    children: [usize; 4],
+    /// The node stored here while the cell is an unsplit leaf.
+    node: Option<usize>,
+    /// First node inserted below the cell; its hop distance stands in for
+    /// the whole cell.
+    rep: usize,
+}
+
+/// Point quadtree over node positions, stored as an arena of cells.
+struct QuadTree {
+    cells: Vec<Cell>,
+}
+
+impl QuadTree {
+    fn build(positions: &[[f64; 2]]) -> Self {
+        let (mut min, mut max) = ([f64::INFINITY; 2], [f64::NEG_INFINITY; 2]);
+        for p in positions {
+            for k in 0..2 {
+                min[k] = min[k].min(p[k]);
+                max[k] = max[k].max(p[k]);
+            }
+        }
+        let size = (max[0] - min[0]).max(max[1] - min[1]).max(MIN_CELL_SIZE);
+        let mut tree = QuadTree { cells: Vec::with_capacity(2 * positions.len()) };
+        tree.cells.push(Cell::new(min, size));
+        for (v, &p) in positions.iter().enumerate() {
+            tree.insert(0, v, p, positions);
+        }
+        tree
+    }
+
+    fn insert(&mut self, mut cell: usize, v: usize, p: [f64; 2], positions: &[[f64; 2]]) {
+        loop {
+            let c = &mut self.cells[cell];
+            let mass = c.mass + 1.0;
+            c.center_of_mass = [
+                (c.center_of_mass[0] * c.mass + p[0]) / mass,
+                (c.center_of_mass[1] * c.mass + p[1]) / mass,
+            ];
+            c.mass = mass;
+
+            if c.mass == 1.0 {
+                c.node = Some(v);
+                c.rep = v;
+                return;
+            }
+            if c.size < MIN_CELL_SIZE {
+                // Collapsed leaf: the nodes only live on in `mass`
+                c.node = None;
+                return;
+            }
+            // Push the leaf's own node one level down before descending
+            if let Some(existing) = c.node.take() {
+                let child = self.child_for(cell, positions[existing]);
+                self.cells[child].mass = 1.0;
+                self.cells[child].center_of_mass = positions[existing];
+                self.cells[child].node = Some(existing);
+                self.cells[child].rep = existing;
+            }
+            cell = self.child_for(cell, p);
+        }
+    }
+
+    /// Index of the quadrant of `cell` containing `p`, creating it if needed.
+    fn child_for(&mut self, cell: usize, p: [f64; 2]) -> usize {
+        let c = &self.cells[cell];
+        let half = c.size / 2.0;
+        let right = p[0] >= c.min[0] + half;
+        let top = p[1] >= c.min[1] + half;
+        let quadrant = right as usize + 2 * top as usize;
+        if c.children[quadrant] == EMPTY {
+            let min = [
+                c.min[0] + if right { half } else { 0.0 },
+                c.min[1] + if top { half } else { 0.0 },
+            ];
+            self.cells.push(Cell::new(min, half));
+            let index = self.cells.len() - 1;
+            self.cells[cell].children[quadrant] = index;
+        }
+        self.cells[cell].children[quadrant]
+    }
+
+    /// Far terms of node `v` at `p`, one per accepted cell (or leaf). Nodes
+    /// within `radius` hops are left to the exact pairs, and a cell whose
+    /// representative is that close is opened instead of accepted.
+    /// `hops(u)` is the hop distance from `v` to `u`.
+    fn far_terms(
+        &self,
+        v: usize,
+        p: [f64; 2],
+        hops: impl Fn(usize) -> usize,
+        radius: usize,
+        theta: f64,
+        boundary: &graph::BoundaryMode,
+    ) -> Vec<FarTerm> {
+        let mut terms = Vec::new();
+        let mut stack = vec![0];
+        while let Some(cell) = stack.pop() {
+            let c = &self.cells[cell];
+            if c.mass == 0.0 || c.node == Some(v) {
+                continue;
+            }
+            let mut diff = [p[0] - c.center_of_mass[0], p[1] - c.center_of_mass[1]];
+            // Minimum-image convention on a torus
+            if let graph::BoundaryMode::Toroidal { width, height } = *boundary {
+                diff[0] -= width * (diff[0] / width).round();
+                diff[1] -= height * (diff[1] / height).round();
+            }
+            let dist2 = diff[0] * diff[0] + diff[1] * diff[1];
+            let hops = hops(c.rep);
+            let is_leaf = c.children.iter().all(|&child| child == EMPTY);
+            if !is_leaf && (hops <= radius || c.size * c.size >= theta * theta * dist2) {
+                stack.extend(c.children.iter().copied().filter(|&child| child != EMPTY));
+                continue;
+            }
+            // Unreachable, near or coincident: no far term
+            if hops == usize::MAX || hops <= radius || dist2 < MIN_CELL_SIZE * MIN_CELL_SIZE {
+                continue;
+            }
+            terms.push(FarTerm { mass: c.mass, point: [p[0] - diff[0], p[1] - diff[1]], dij: hops as f64 });
+        }
+        terms
+    }
+}
+
+/// A cell seen from one node: `mass` nodes at `point`, `dij` hops away.
+struct FarTerm {
+    mass: f64,
+    point: [f64; 2],
+    dij: f64,
+}
+
+/// Hop distances for the far terms in `O(k n)` memory, from the BFS rows
+/// of `k` pivots (see `Graph::pivot_rows`).
+///
+/// [`PivotDistances::hops`] is the landmark bound `min_p d(v, p) + d(p, u)`:
+/// never below the true distance, exact whenever a pivot lies on a shortest
+/// path (so always when every node is a pivot) and `usize::MAX` across
+/// components. A lookup costs `O(k)`.
+pub struct PivotDistances {
+    rows: Vec<Vec<usize>>,
+}
+
+impl PivotDistances {
+    pub fn new(graph: &graph::Graph, num_pivots: usize) -> Self {
+        PivotDistances { rows: graph.pivot_rows(num_pivots).into_iter().map(|(_, row)| row).collect() }
+    }
+
+    pub fn hops(&self, v: usize, u: usize) -> usize {
+        self.rows.iter().map(|row| row[v].saturating_add(row[u])).min().unwrap_or(usize::MAX)
+    }
+}
+
+impl Cell {
+    fn new(min: [f64; 2], size: f64) -> Self {
+        Cell { min, size, mass: 0.0, center_of_mass: [0.0; 2], children: [EMPTY; 4], node: None, rep: 0 }
+    }
+}
+
+/// Run the Barnes-Hut approximated stress layout and return the initial
+/// positions and the final layout.
+///
+/// `distances` estimates the hop distances of the far terms and `radius` is
+/// the neighbourhood the exact `params.pairs` were built from; the nodes of
+/// those pairs never get a far term, whatever the estimate. Each iteration
+/// first applies the
+/// SGD update to every exact pair, then visits the nodes in random order and
+/// applies it to each of their far terms, a cell of mass `m` weighing as
+/// much as `m` pairs (`m / d²`). Only the visited node moves, by its half of
+/// the step; the cell's nodes take the other half on their own turn. Pinned
+/// nodes stay put and the boundary is applied after every iteration, as on
+/// the CPU backend.
+pub fn execute_sgd(
+    params: graph::SgdParams,
+    distances: &PivotDistances,
+    radius: usize,
+    theta: f64,
+) -> graph::LayoutPair {
+    let to_f32 = |p: &[[f64; 2]]| p.iter().map(|p| [p[0] as f32, p[1] as f32]).collect::<Vec<_>>();
+    let initial_positions = to_f32(&params.positions);
+
+    let mut rng = params.rng();
+    let levels = params.node_levels();
+    let mut positions = params.positions;
+    let mut pairs = params.pairs;
+    let boundary = params.boundary;
+    let mut nodes: Vec<usize> = (0..positions.len()).collect();
+    // Partners of every node among the exact pairs, sorted for lookup
+    let mut near = vec![Vec::new(); positions.len()];
+    for pair in &pairs {
+        near[pair.u].push(pair.v);
+        near[pair.v].push(pair.u);
+    }
+    near.iter_mut().for_each(|partners| partners.sort_unstable());
+
+    println!("Executing Barnes-Hut stress iterations on the CPU (theta = {})...", theta);
+    let iteration_start = std::time::Instant::now();
+    let mut cells = 0;
+    for &eta in &params.etas {
+        pairs.shuffle(&mut rng);
+        for pair in &pairs {
+            cpu::apply_pair(&mut positions, pair, eta, &boundary, &levels);
+        }
+
+        let tree = QuadTree::build(&positions);
+        cells = tree.cells.len();
+        nodes.shuffle(&mut rng);
+        for &v in &nodes {
+            if levels[v] == graph::LEVEL_FIXED {
+                continue;
+            }
+            let hops = |u: usize| if near[v].binary_search(&u).is_ok() { 0 } else { distances.hops(v, u) };
+            for term in tree.far_terms(v, positions[v], hops, radius, theta, &boundary) {
+                let p = &mut positions[v];
+                let diff = [p[0] - term.point[0], p[1] - term.point[1]];
+                let nrm = (diff[0] * diff[0] + diff[1] * diff[1]).sqrt().max(1e-12);
+                let mu = (eta * term.mass / (term.dij * term.dij)).min(1.0) / 2.0;
+                p[0] += mu * (term.dij - nrm) * diff[0] / nrm;
+                p[1] += mu * (term.dij - nrm) * diff[1] / nrm;
+            }
+        }
+
+        if boundary != graph::BoundaryMode::Open {
+            for p in positions.iter_mut() {
+                *p = boundary.constrain(*p);
+            }
+        }
+    }
+
+    let iteration_duration = iteration_start.elapsed();
+    let per_iteration = iteration_duration.as_secs_f64() / params.etas.len().max(1) as f64;
+    println!("\n=== Performance Summary ===");
+    println!("Quadtree cells:   {}", cells);
+    println!("Iterations total: {:.3}s", iteration_duration.as_secs_f64());
+    println!("Per iteration:    {:.3}s ({:.1}ms)", per_iteration, per_iteration * 1000.0);
+
+    let layout = crate::layout::Layout::new(to_f32(&positions), params.etas.len(), iteration_duration);
+    (initial_positions, layout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    fn bcspwr01() -> graph::Graph {
+        let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../data/bcspwr01.mtx"));
+        graph::Graph::from_mtx(path).unwrap()
+    }
+
+    #[test]
+    fn theta_zero_visits_every_far_pair() {
+        let graph = bcspwr01();
+        let dist = graph.calc_dist_matrix();
+        let positions = graph.prepare_sgd_params(1, 0.1, true).positions;
+        let tree = QuadTree::build(&positions);
+        let radius = 2;
+        for (v, &p) in positions.iter().enumerate() {
+            let terms = tree.far_terms(v, p, |u| dist[v][u], radius, 0.0, &graph::BoundaryMode::Open);
+            let far: Vec<usize> = (0..positions.len()).filter(|&u| dist[v][u] > radius).collect();
+            assert_eq!(terms.len(), far.len());
+            for u in far {
+                let q = positions[u];
+                assert!(terms.iter().any(|t| {
+                    t.mass == 1.0
+                        && t.dij == dist[v][u] as f64
+                        && (t.point[0] - q[0]).abs() < 1e-9
+                        && (t.point[1] - q[1]).abs() < 1e-9
+                }));
+            }
+        }
+    }
+
+    #[test]
+    fn pivot_distances_bound_the_hop_distances() {
+        let graph = bcspwr01();
+        let dist = graph.calc_dist_matrix();
+        let exact = PivotDistances::new(&graph, graph.node_size);
+        let estimated = PivotDistances::new(&graph, 4);
+        let pivots: Vec<usize> = graph.pivot_rows(4).into_iter().map(|(p, _)| p).collect();
+        for (v, row) in dist.iter().enumerate() {
+            for (u, &d) in row.iter().enumerate() {
+                assert_eq!(exact.hops(v, u), d);
+                assert!(estimated.hops(v, u) >= d);
+                if pivots.contains(&v) {
+                    assert_eq!(estimated.hops(v, u), d);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn approximates_full_stress() {
+        let graph = bcspwr01();
+        let exact_hops = PivotDistances::new(&graph, graph.node_size);
+        let estimated_hops = PivotDistances::new(&graph, 16);
+        let mut full = graph.prepare_sgd_params(30, 0.1, true);
+        full.seed = Some(1);
+        let (_, exact) = cpu::execute_sgd(full.clone(), cpu::CpuSgdOptions::default());
+
+        // Refine the exact layout with the last steps of its schedule: with
+        // theta = 0 and every node a pivot the far terms are the exact
+        // pairs, so the stress holds, and theta = 0.5 may only lose a little
+        // against that, on exact or pivot-estimated hops alike. All runs
+        // share the seed and hence the shuffles.
+        let mut near = graph.prepare_sgd_params_neighborhood(10, 0.1, true, 2);
+        near.positions = exact.positions.iter().map(|p| [p[0] as f64, p[1] as f64]).collect();
+        near.etas = full.etas[20..].to_vec();
+        near.seed = Some(1);
+        let stress = |(_, layout): graph::LayoutPair| crate::metrics::stress(&layout.positions, &full.pairs);
+        let far_exact = stress(execute_sgd(near.clone(), &exact_hops, 2, 0.0));
+        let exact = crate::metrics::stress(&exact.positions, &full.pairs);
+        assert!(far_exact < 1.01 * exact, "{} vs {}", far_exact, exact);
+        for hops in [&exact_hops, &estimated_hops] {
+            let approximated = stress(execute_sgd(near.clone(), hops, 2, 0.5));
+            assert!(approximated < 1.15 * far_exact, "{} vs {}", approximated, far_exact);
+        }
+    }
+
+    #[test]
+    fn pinned_nodes_stay_put() {
+        let graph = bcspwr01();
+        let mut params = graph.prepare_sgd_params_neighborhood(10, 0.1, true, 2);
+        params.pin(&HashMap::from([(0, [3.0, 3.0])])).unwrap();
+        let (_, layout) = execute_sgd(params, &PivotDistances::new(&graph, DEFAULT_PIVOTS), 2, 0.5);
+        assert_eq!(layout.positions[0], [3.0, 3.0]);
+    }
+}
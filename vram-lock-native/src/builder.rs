@@ -0,0 +1,524 @@
+//! Backend selection and a builder for configuring a layout run.
+
+use crate::graph::{self, Graph, SgdParams};
+#[cfg(target_os = "macos")]
+use crate::metal;
+use crate::{cpu, gpu, layout};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// A layout backend chosen at runtime (see [`create_backend`]).
+///
+/// Code that only runs layouts (the multilevel driver, [`crate::execute_sgd`])
+/// takes a `&dyn SgdBackend`, so a new backend only needs this impl. The
+/// per-run options (`with_time_budget`, snapshots, ...) stay on the concrete
+/// context types.
+pub trait SgdBackend {
+    /// Name as accepted by `--backend`.
+    fn name(&self) -> &'static str;
+
+    /// Run `params`; returns the initial positions and the final layout.
+    fn execute(&self, params: SgdParams) -> Result<graph::LayoutPair>;
+
+    /// Run 3D `params` (`positions_z` set).
+    fn execute_3d(&self, _params: SgdParams) -> Result<graph::LayoutPair3> {
+        anyhow::bail!("the {} backend does not support 3D layouts", self.name())
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl SgdBackend for metal::MetalContext {
+    fn name(&self) -> &'static str {
+        "metal"
+    }
+
+    fn execute(&self, params: SgdParams) -> Result<graph::LayoutPair> {
+        self.execute_sgd(params)
+    }
+
+    fn execute_3d(&self, params: SgdParams) -> Result<graph::LayoutPair3> {
+        self.execute_sgd_3d(params)
+    }
+}
+
+impl SgdBackend for gpu::GpuContext {
+    fn name(&self) -> &'static str {
+        "wgpu"
+    }
+
+    fn execute(&self, params: SgdParams) -> Result<graph::LayoutPair> {
+        self.execute_sgd(params)
+    }
+
+    fn execute_3d(&self, params: SgdParams) -> Result<graph::LayoutPair3> {
+        self.execute_sgd_3d(params)
+    }
+}
+
+impl SgdBackend for cpu::CpuBackend {
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    fn execute(&self, params: SgdParams) -> Result<graph::LayoutPair> {
+        Ok(cpu::execute_sgd(params, cpu::CpuSgdOptions::default()))
+    }
+
+    fn execute_3d(&self, params: SgdParams) -> Result<graph::LayoutPair3> {
+        cpu::execute_sgd_3d(params, None, None)
+    }
+}
+
+/// An initialised execution backend, for callers that configure the
+/// concrete context before running.
+pub enum Backend {
+    #[cfg(target_os = "macos")]
+    Metal(metal::MetalContext),
+    Wgpu(gpu::GpuContext),
+    Cpu,
+}
+
+impl Backend {
+    /// This backend as a trait object.
+    pub fn as_dyn(&self) -> &dyn SgdBackend {
+        match self {
+            #[cfg(target_os = "macos")]
+            Backend::Metal(ctx) => ctx,
+            Backend::Wgpu(ctx) => ctx,
+            Backend::Cpu => &cpu::CpuBackend,
+        }
+    }
+
+    /// Box the backend as a trait object.
+    pub fn into_dyn(self) -> Box<dyn SgdBackend> {
+        match self {
+            #[cfg(target_os = "macos")]
+            Backend::Metal(ctx) => Box::new(ctx),
+            Backend::Wgpu(ctx) => Box::new(ctx),
+            Backend::Cpu => Box::new(cpu::CpuBackend),
+        }
+    }
+}
+
+/// Backends usable in this build; Metal only exists on macOS.
+pub fn available_backends() -> &'static [&'static str] {
+    if cfg!(target_os = "macos") {
+        &["metal", "wgpu", "cpu", "auto"]
+    } else {
+        &["wgpu", "cpu", "auto"]
+    }
+}
+
+/// Fail with the list of [`available_backends`] unless `name` is one of
+/// them, so a bad `--backend` is reported before any precompute work.
+pub fn check_backend(name: &str) -> Result<()> {
+    if available_backends().contains(&name) {
+        return Ok(());
+    }
+    if name == "metal" {
+        anyhow::bail!(
+            "Metal backend not compiled on this platform; available backends: {}",
+            available_backends().join(", ")
+        );
+    }
+    anyhow::bail!("Unsupported backend: {}. Available backends: {}", name, available_backends().join(", "))
+}
+
+/// Initialise backend `name` (checked with [`check_backend`]).
+///
+/// `auto` tries Metal (macOS only), then wgpu, then the CPU, and uses the
+/// first one that initialises.
+pub fn init_backend(name: &str) -> Result<Backend> {
+    check_backend(name)?;
+    match name {
+        #[cfg(target_os = "macos")]
+        "metal" => Ok(Backend::Metal(metal::MetalContext::new()?)),
+        "wgpu" => Ok(Backend::Wgpu(gpu::GpuContext::new()?)),
+        "cpu" => Ok(Backend::Cpu),
+        "auto" => {
+            #[cfg(target_os = "macos")]
+            match metal::MetalContext::new() {
+                Ok(ctx) => {
+                    println!("Backend auto: selected metal");
+                    return Ok(Backend::Metal(ctx));
+                }
+                Err(e) => println!("Backend auto: metal unavailable ({})", e),
+            }
+            match gpu::GpuContext::new() {
+                Ok(ctx) => {
+                    println!("Backend auto: selected wgpu");
+                    return Ok(Backend::Wgpu(ctx));
+                }
+                Err(e) => println!("Backend auto: wgpu unavailable ({})", e),
+            }
+            println!("Backend auto: selected cpu");
+            Ok(Backend::Cpu)
+        }
+        other => unreachable!("backend {} passed check_backend", other),
+    }
+}
+
+/// [`init_backend`] as a trait object.
+pub fn create_backend(name: &str) -> Result<Box<dyn SgdBackend>> {
+    Ok(init_backend(name)?.into_dyn())
+}
+
+/// Chainable configuration for one stress layout run.
+///
+/// Unset options keep these defaults:
+///
+/// | option               | default                                   |
+/// |----------------------|-------------------------------------------|
+/// | `iterations`         | 15                                        |
+/// | `epsilon`            | 0.1                                       |
+/// | `center`             | `true`                                    |
+/// | `initial_positions`  | none (uniform in the unit square)         |
+/// | `seed`               | none (fresh randomness each run)          |
+/// | `pair_weights`       | none                                      |
+/// | `edge_only`          | off (all-pairs BFS)                       |
+/// | `neighborhood`       | off (all-pairs BFS)                       |
+/// | `sparse`             | off (all-pairs BFS)                       |
+/// | `dist_cache`         | none                                      |
+/// | `backend`            | `"auto"`                                  |
+/// | `schedule`           | `graph::exponential_schedule`             |
+/// | `distance_transform` | `DistanceTransform::Identity`             |
+/// | `target_width`       | none (scale set by the target distances)  |
+/// | `connect_components` | `false`                                   |
+/// | `dimensions`         | 2                                         |
+/// | `pair_coloring`      | `false` (lock-based kernels)              |
+/// | `pins`               | none                                      |
+///
+/// ```ignore
+/// let (initial, result) = LayoutBuilder::new()
+///     .iterations(30)
+///     .backend("wgpu")
+///     .run(&graph)?;
+/// ```
+#[derive(Clone)]
+pub struct LayoutBuilder {
+    iterations: usize,
+    epsilon: f64,
+    center: bool,
+    initial_positions: Option<Vec<[f64; 2]>>,
+    seed: Option<u64>,
+    pair_weights: HashMap<(usize, usize), f64>,
+    edge_only: Option<usize>,
+    neighborhood: Option<usize>,
+    sparse: Option<usize>,
+    dist_cache: Option<PathBuf>,
+    backend: String,
+    schedule: Option<graph::ScheduleFn>,
+    distance_transform: graph::DistanceTransform,
+    target_width: Option<f64>,
+    connect_components: bool,
+    dimensions: usize,
+    pair_coloring: bool,
+    pins: HashMap<usize, [f64; 2]>,
+}
+
+impl Default for LayoutBuilder {
+    fn default() -> Self {
+        LayoutBuilder {
+            iterations: 15,
+            epsilon: 0.1,
+            center: true,
+            initial_positions: None,
+            seed: None,
+            pair_weights: HashMap::new(),
+            edge_only: None,
+            neighborhood: None,
+            sparse: None,
+            dist_cache: None,
+            backend: "auto".to_string(),
+            schedule: None,
+            distance_transform: graph::DistanceTransform::Identity,
+            target_width: None,
+            connect_components: false,
+            dimensions: 2,
+            pair_coloring: false,
+            pins: HashMap::new(),
+        }
+    }
+}
+
+impl LayoutBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of SGD iterations (length of the learning-rate schedule).
+    pub fn iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Final step size factor of the schedule (`eta_min = epsilon / wmax`).
+    pub fn epsilon(mut self, epsilon: f64) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    /// Center the random initial positions on the origin.
+    pub fn center(mut self, center: bool) -> Self {
+        self.center = center;
+        self
+    }
+
+    /// Start from these positions instead of random ones.
+    pub fn initial_positions(mut self, positions: Vec<[f64; 2]>) -> Self {
+        self.initial_positions = Some(positions);
+        self
+    }
+
+    /// Draw the random initial positions, and the pair order of the CPU
+    /// backend, from generators seeded with `seed`.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Multipliers for `wij` of selected unordered pairs.
+    pub fn pair_weights(mut self, weights: HashMap<(usize, usize), f64>) -> Self {
+        self.pair_weights = weights;
+        self
+    }
+
+    /// Use only graph edges plus `repulsion_samples` random pairs per node
+    /// (see `Graph::prepare_sgd_params_edge_only`).
+    pub fn edge_only(mut self, repulsion_samples: usize) -> Self {
+        self.edge_only = Some(repulsion_samples);
+        self
+    }
+
+    /// Use only the pairs at most `radius` hops apart (see
+    /// `Graph::prepare_sgd_params_neighborhood`).
+    pub fn neighborhood(mut self, radius: usize) -> Self {
+        self.neighborhood = Some(radius);
+        self
+    }
+
+    /// Use sparse stress with `num_pivots` pivots (see
+    /// `Graph::prepare_sgd_params_sparse`).
+    pub fn sparse(mut self, num_pivots: usize) -> Self {
+        self.sparse = Some(num_pivots);
+        self
+    }
+
+    /// Reuse distance matrices stored in `dir` (see
+    /// `Graph::calc_dist_matrix_cached`).
+    pub fn dist_cache(mut self, dir: PathBuf) -> Self {
+        self.dist_cache = Some(dir);
+        self
+    }
+
+    /// `metal`, `wgpu`, `cpu` or `auto`.
+    pub fn backend(mut self, name: &str) -> Self {
+        self.backend = name.to_string();
+        self
+    }
+
+    /// Generate each step size as `schedule(iteration, wmin, wmax)` instead of
+    /// the default exponential decay. `wmin`/`wmax` are the smallest and
+    /// largest `wij` of the final pair set (after pair weights).
+    pub fn schedule(mut self, schedule: impl Fn(usize, f64, f64) -> f64 + 'static) -> Self {
+        self.schedule = Some(Rc::new(schedule));
+        self
+    }
+
+    /// Transform hop distances before they become targets and weights (see
+    /// `graph::DistanceTransform`). Only applies to all-pairs runs; the
+    /// edge-only and neighbourhood pair sets keep raw hop distances.
+    pub fn distance_transform(mut self, transform: graph::DistanceTransform) -> Self {
+        self.distance_transform = transform;
+        self
+    }
+
+    /// Rescale the layout after every iteration so its x-extent is `width`,
+    /// the y axis scaled alike (see `layout::fit_width_transform`).
+    pub fn target_width(mut self, width: f64) -> Self {
+        self.target_width = Some(width);
+        self
+    }
+
+    /// Tie the connected components of a disconnected graph together with
+    /// weak pairs (see `SgdParams::connect_components`). Only applies to
+    /// all-pairs runs; the edge-only, neighbourhood and sparse pair sets are
+    /// meant to stay far below `n²` pairs.
+    pub fn connect_components(mut self, connect: bool) -> Self {
+        self.connect_components = connect;
+        self
+    }
+
+    /// Lay out in 2 or 3 dimensions. A 3D run draws random `z` coordinates
+    /// (seeded alike) next to the usual start and must use
+    /// [`run_3d`](Self::run_3d).
+    pub fn dimensions(mut self, dimensions: usize) -> Self {
+        self.dimensions = dimensions;
+        self
+    }
+
+    /// Color the pairs (see `SgdParams::apply_pair_coloring`) so the GPU
+    /// backends run one lock-free pass per color instead of the lock-based
+    /// kernel.
+    pub fn pair_coloring(mut self, coloring: bool) -> Self {
+        self.pair_coloring = coloring;
+        self
+    }
+
+    /// Keep these nodes at the given x/y coordinates (see `SgdParams::pin`).
+    /// Cannot be combined with `target_width`, which rescales every node.
+    pub fn pins(mut self, pins: HashMap<usize, [f64; 2]>) -> Self {
+        self.pins = pins;
+        self
+    }
+
+    /// Precompute the SGD parameters for `graph` without running them.
+    pub fn params(&self, graph: &Graph) -> Result<SgdParams> {
+        let (iterations, epsilon, center) = (self.iterations, self.epsilon, self.center);
+        let transform = self.distance_transform;
+        let mut params = match (self.sparse, self.neighborhood, self.edge_only, &self.dist_cache) {
+            (Some(num_pivots), ..) => graph.prepare_sgd_params_sparse(iterations, epsilon, center, num_pivots),
+            (None, Some(radius), _, _) => graph.prepare_sgd_params_neighborhood(iterations, epsilon, center, radius),
+            (None, None, Some(samples), _) => {
+                graph.prepare_sgd_params_edge_only(iterations, epsilon, center, samples, &self.pair_weights, self.seed)
+            }
+            // The distance cache only holds hop counts
+            (None, None, None, _) if graph.has_edge_weights() => {
+                println!("Target distances: weighted shortest paths over the edge values");
+                graph.prepare_sgd_params_streamed(iterations, epsilon, center, &self.pair_weights, transform)
+            }
+            (None, None, None, Some(dir)) => {
+                let dist = graph.calc_dist_matrix_cached(dir)?;
+                graph.prepare_sgd_params_from_dist(&dist, iterations, epsilon, center, &self.pair_weights, transform)
+            }
+            (None, None, None, None) => {
+                graph.prepare_sgd_params_streamed(iterations, epsilon, center, &self.pair_weights, transform)
+            }
+        };
+
+        let all_pairs = self.neighborhood.is_none() && self.edge_only.is_none() && self.sparse.is_none();
+        if self.connect_components && all_pairs {
+            let components = graph.components();
+            if components.iter().any(|&c| c > 0) {
+                let added = params.connect_components(&components);
+                let wmin = params.pairs.iter().map(|p| p.wij).fold(f64::INFINITY, f64::min);
+                let wmax = params.pairs.iter().map(|p| p.wij).fold(0.0, f64::max);
+                params.etas = graph::calc_learning_rate(iterations, wmin, wmax, epsilon);
+                println!("Connected components: added {} inter-component pairs", added);
+            }
+        }
+
+        if let Some(positions) = &self.initial_positions {
+            anyhow::ensure!(
+                positions.len() == graph.node_size,
+                "{} initial positions given for {} nodes",
+                positions.len(),
+                graph.node_size
+            );
+            params.positions = positions.clone();
+        } else if let Some(seed) = self.seed {
+            params.positions = graph::init_positions_seeded(graph.node_size, center, Default::default(), seed);
+        }
+        params.seed = self.seed;
+        match self.dimensions {
+            2 => {}
+            3 => params.positions_z = graph::init_positions_z(graph.node_size, center, self.seed),
+            d => anyhow::bail!("layouts have 2 or 3 dimensions, got {}", d),
+        }
+        if let Some(schedule) = &self.schedule {
+            let wmin = params.pairs.iter().map(|p| p.wij).fold(f64::INFINITY, f64::min);
+            let wmax = params.pairs.iter().map(|p| p.wij).fold(0.0, f64::max);
+            params.etas = graph::calc_learning_rate_with(iterations, wmin, wmax, schedule.as_ref());
+        }
+        if !self.pins.is_empty() {
+            params.pin(&self.pins)?;
+        }
+        if self.pair_coloring {
+            params.apply_pair_coloring();
+        }
+        Ok(params)
+    }
+
+    /// Precompute, initialise the backend and run; returns the initial
+    /// positions and the final layout.
+    pub fn run(&self, graph: &Graph) -> Result<graph::LayoutPair> {
+        anyhow::ensure!(self.dimensions == 2, "{}D layout configured: use run_3d", self.dimensions);
+        let params = self.params(graph)?;
+        params.validate()?;
+        let backend = init_backend(&self.backend)?;
+        let Some(width) = self.target_width else {
+            return backend.as_dyn().execute(params);
+        };
+        anyhow::ensure!(width > 0.0 && width.is_finite(), "target width must be positive, got {}", width);
+        anyhow::ensure!(self.pins.is_empty(), "pinned nodes cannot be combined with a target width");
+        let fit = layout::fit_width_transform(width);
+        match backend {
+            #[cfg(target_os = "macos")]
+            Backend::Metal(ctx) => ctx.with_transform(fit).execute_sgd(params),
+            Backend::Wgpu(ctx) => ctx.with_transform(fit).execute_sgd(params),
+            Backend::Cpu => {
+                let mut fit = fit;
+                let options = cpu::CpuSgdOptions { transform: Some(&mut fit), ..Default::default() };
+                Ok(cpu::execute_sgd(params, options))
+            }
+        }
+    }
+
+    /// [`run`](Self::run) for a layout configured with `dimensions(3)`.
+    /// `target_width` is 2D only and is rejected.
+    pub fn run_3d(&self, graph: &Graph) -> Result<graph::LayoutPair3> {
+        anyhow::ensure!(self.dimensions == 3, "run_3d needs dimensions(3), got {}", self.dimensions);
+        anyhow::ensure!(self.target_width.is_none(), "target width is not supported for 3D layouts");
+        let params = self.params(graph)?;
+        params.validate()?;
+        create_backend(&self.backend)?.execute_3d(params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_backend_is_always_available() {
+        assert!(available_backends().contains(&"cpu"));
+        check_backend("cpu").unwrap();
+        assert!(matches!(init_backend("cpu").unwrap(), Backend::Cpu));
+        assert_eq!(create_backend("cpu").unwrap().name(), "cpu");
+    }
+
+    #[test]
+    fn unknown_backend_is_an_error() {
+        let err = check_backend("cuda").unwrap_err().to_string();
+        assert!(err.contains("Available backends: "), "{}", err);
+        assert!(init_backend("cuda").is_err());
+        assert!(create_backend("cuda").is_err());
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn metal_is_reported_as_not_compiled() {
+        assert!(!available_backends().contains(&"metal"));
+        let err = init_backend("metal").err().expect("metal must be rejected").to_string();
+        assert!(err.contains("not compiled on this platform"), "{}", err);
+        assert!(err.contains("wgpu, cpu, auto"), "{}", err);
+    }
+
+    #[test]
+    fn cpu_layout_reports_its_run() -> Result<()> {
+        let graph = Graph::from_edges(6, (0..6).collect(), (1..6).chain([0]).collect())?;
+        let params = graph.prepare_sgd_params(15, 0.1, true);
+        let pairs = params.pairs.clone();
+        let backend = create_backend("cpu")?;
+        assert_eq!(backend.name(), "cpu");
+        let (_, layout) = backend.execute(params)?;
+        assert_eq!(layout.iterations, 15);
+        assert_eq!(layout.node_size, 6);
+        assert_eq!(layout.stress, None);
+        let layout = layout.with_stress(&pairs);
+        assert_eq!(layout.stress, Some(crate::metrics::stress(&layout.positions, &pairs)));
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+//! CPU backend (serial SGD, same update rule as `baseline-sgd-non-gpu`).
+//!
+//! Used when no GPU backend can be initialised.
+
+use crate::graph;
+use crate::layout::Layout;
+use rand::seq::SliceRandom;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// The CPU as an [`SgdBackend`](crate::builder::SgdBackend); runs
+/// [`execute_sgd`] without any of its optional hooks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuBackend;
+
+/// Deterministic angle in `[0, 2π)` for the pair `(u, v)`, used as the nudge
+/// direction when two nodes coincide. Copied from `pair_angle` in
+/// baseline-sgd-non-gpu's algorithm.rs so both CPU paths nudge alike; the
+/// tests in both crates pin the same values. shader.wgsl and shader.metal
+/// port it to f32, and a wgpu test checks the kernel against this one.
+fn pair_angle(u: usize, v: usize) -> f64 {
+    let mut z = ((u as u64) << 32 ^ v as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64 * std::f64::consts::TAU
+}
+
+/// Move the endpoints of `pair` towards their target distance, for points
+/// with `D` coordinates. A torus only wraps the first two. Of two nodes at
+/// different `levels` (see [`graph::SgdParams::node_levels`]) the higher one
+/// stays put and the other takes the whole displacement.
+pub(crate) fn apply_pair<const D: usize>(
+    positions: &mut [[f64; D]],
+    pair: &graph::EdgeInfo,
+    eta: f64,
+    boundary: &graph::BoundaryMode,
+    levels: &[u32],
+) {
+    let tiny = 1e-12_f64;
+    let (u, v) = (pair.u, pair.v);
+    let (level_u, level_v) = (levels[u], levels[v]);
+    let (share_u, share_v) = match level_u.cmp(&level_v) {
+        std::cmp::Ordering::Equal if level_u == graph::LEVEL_FIXED => return,
+        std::cmp::Ordering::Equal => (1.0, 1.0),
+        std::cmp::Ordering::Greater => (0.0, 2.0),
+        std::cmp::Ordering::Less => (2.0, 0.0),
+    };
+    let mut diff: [f64; D] = std::array::from_fn(|k| positions[v][k] - positions[u][k]);
+    // Minimum-image convention on a torus
+    if let graph::BoundaryMode::Toroidal { width, height } = *boundary {
+        diff[0] -= width * (diff[0] / width).round();
+        diff[1] -= height * (diff[1] / height).round();
+    }
+    let mut nrm = diff.iter().map(|d| d * d).sum::<f64>().sqrt();
+    if nrm < tiny {
+        let angle = pair_angle(u, v);
+        diff = [0.0; D];
+        diff[0] = angle.cos() * 1e-6;
+        diff[1] = angle.sin() * 1e-6;
+        nrm = 1e-6;
+    }
+
+    let mu = (pair.wij * eta).min(1.0);
+    for k in 0..D {
+        let r = ((nrm - pair.dij) / 2.0) * (diff[k] / nrm);
+        positions[u][k] += share_u * mu * r;
+        positions[v][k] -= share_v * mu * r;
+    }
+}
+
+/// Optional stops and hooks of [`execute_sgd`]; the default runs every
+/// iteration with none of them.
+#[derive(Default)]
+pub struct CpuSgdOptions<'a> {
+    /// Stop after the current iteration once the flag is set.
+    pub interrupt: Option<&'a AtomicBool>,
+    /// Stop once the iteration loop has run this long.
+    pub time_budget: Option<Duration>,
+    /// `(every, f)`: `f` receives the positions after every `every`-th
+    /// iteration and the last.
+    pub snapshots: Option<(usize, &'a graph::SnapshotFn)>,
+    /// Runs on the positions after each iteration's boundary step.
+    pub transform: Option<&'a mut graph::TransformFn>,
+    /// Receives the positions after every iteration.
+    pub live: Option<&'a graph::LivePositions>,
+    /// Replaces the per-iteration shuffle of the pair order.
+    pub pair_order: Option<&'a mut graph::PairOrderFn>,
+}
+
+/// Run SGD on the CPU and return the initial positions and the final
+/// [`Layout`]. An early stop (see [`CpuSgdOptions`]) keeps the positions
+/// of the last completed iteration.
+pub fn execute_sgd(params: graph::SgdParams, options: CpuSgdOptions) -> graph::LayoutPair {
+    let CpuSgdOptions { interrupt, time_budget, snapshots, mut transform, live, mut pair_order } = options;
+    let to_f32 = |p: &[[f64; 2]]| p.iter().map(|p| [p[0] as f32, p[1] as f32]).collect::<Vec<_>>();
+    let initial_positions = to_f32(&params.positions);
+
+    let mut rng = params.rng();
+    let levels = params.node_levels();
+    let mut positions = params.positions;
+    let pairs = params.pairs;
+    let active_pairs = params.active_pairs;
+    let mut order: Vec<usize> = (0..pairs.len()).collect();
+    let boundary = params.boundary;
+
+    println!("Executing SGD iterations on the CPU...");
+    let iteration_start = std::time::Instant::now();
+    let mut completed_iterations = 0usize;
+    for (iteration, &eta) in params.etas.iter().enumerate() {
+        match pair_order.as_mut() {
+            Some(pair_order) => pair_order(iteration, &mut order),
+            None => order.shuffle(&mut rng),
+        }
+
+        // With a pair growth schedule only the first `active` pairs take part
+        let active = active_pairs.get(iteration).copied().unwrap_or(pairs.len());
+        for pair in order.iter().filter(|&&i| i < active).map(|&i| &pairs[i]) {
+            apply_pair(&mut positions, pair, eta, &boundary, &levels);
+        }
+
+        if boundary != graph::BoundaryMode::Open {
+            for p in positions.iter_mut() {
+                *p = boundary.constrain(*p);
+            }
+        }
+        if let Some(transform) = transform.as_mut() {
+            transform(&mut positions, iteration);
+        }
+        if let Some(live) = live {
+            live.publish(to_f32(&positions));
+        }
+
+        completed_iterations += 1;
+        let interrupted = interrupt.is_some_and(|f| f.load(Ordering::SeqCst));
+        let out_of_time = time_budget.is_some_and(|budget| iteration_start.elapsed() >= budget);
+
+        // An early stop still captures the last completed iteration
+        if let Some((every, snapshot)) = snapshots {
+            if graph::snapshot_due(iteration, params.etas.len(), every) || interrupted || out_of_time {
+                snapshot(iteration, &to_f32(&positions));
+            }
+        }
+
+        if interrupted {
+            println!("Interrupted after iteration {}", iteration);
+            break;
+        }
+        if out_of_time {
+            println!("Time budget exhausted after iteration {}", iteration);
+            break;
+        }
+    }
+
+    let iteration_duration = iteration_start.elapsed();
+    let per_iteration = iteration_duration.as_secs_f64() / completed_iterations.max(1) as f64;
+    println!("\n=== Performance Summary ===");
+    println!("Iterations run:   {}/{}", completed_iterations, params.etas.len());
+    println!("Iterations total: {:.3}s", iteration_duration.as_secs_f64());
+    println!("Per iteration:    {:.3}s ({:.1}ms)", per_iteration, per_iteration * 1000.0);
+
+    (initial_positions, Layout::new(to_f32(&positions), completed_iterations, iteration_duration))
+}
+
+/// [`execute_sgd`] for a 3D layout (`positions_z` set), with the same
+/// `interrupt` and `time_budget` stops as [`CpuSgdOptions`]. Only the open
+/// plane is supported.
+pub fn execute_sgd_3d(
+    params: graph::SgdParams,
+    interrupt: Option<&AtomicBool>,
+    time_budget: Option<Duration>,
+) -> anyhow::Result<graph::LayoutPair3> {
+    graph::check_3d(&params, false)?;
+    let to_f32 = |p: &[[f64; 3]]| p.iter().map(|p| p.map(|c| c as f32)).collect::<Vec<_>>();
+    let mut positions = params.positions_3d();
+    let initial_positions = to_f32(&positions);
+
+    let mut rng = params.rng();
+    let levels = params.node_levels();
+    let pairs = params.pairs;
+    let active_pairs = params.active_pairs;
+    let mut order: Vec<usize> = (0..pairs.len()).collect();
+
+    println!("Executing 3D SGD iterations on the CPU...");
+    let iteration_start = std::time::Instant::now();
+    let mut completed_iterations = 0usize;
+    for (iteration, &eta) in params.etas.iter().enumerate() {
+        order.shuffle(&mut rng);
+        let active = active_pairs.get(iteration).copied().unwrap_or(pairs.len());
+        for pair in order.iter().filter(|&&i| i < active).map(|&i| &pairs[i]) {
+            apply_pair(&mut positions, pair, eta, &graph::BoundaryMode::Open, &levels);
+        }
+
+        completed_iterations += 1;
+        if interrupt.is_some_and(|f| f.load(Ordering::SeqCst)) {
+            println!("Interrupted after iteration {}", iteration);
+            break;
+        }
+        if time_budget.is_some_and(|budget| iteration_start.elapsed() >= budget) {
+            println!("Time budget exhausted after iteration {}", iteration);
+            break;
+        }
+    }
+
+    let iteration_duration = iteration_start.elapsed();
+    let per_iteration = iteration_duration.as_secs_f64() / completed_iterations.max(1) as f64;
+    println!("\n=== Performance Summary ===");
+    println!("Iterations run:   {}/{}", completed_iterations, params.etas.len());
+    println!("Iterations total: {:.3}s", iteration_duration.as_secs_f64());
+    println!("Per iteration:    {:.3}s ({:.1}ms)", per_iteration, per_iteration * 1000.0);
+
+    Ok((initial_positions, to_f32(&positions)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn cycle_params(iterations: usize) -> graph::SgdParams {
+        let graph = graph::Graph::from_edges(6, (0..6).collect(), (1..6).chain([0]).collect()).unwrap();
+        graph.prepare_sgd_params(iterations, 0.1, true)
+    }
+
+    #[test]
+    fn default_options_run_every_iteration() {
+        let (_, layout) = execute_sgd(cycle_params(10), CpuSgdOptions::default());
+        assert_eq!(layout.iterations, 10);
+    }
+
+    #[test]
+    fn interrupt_stops_after_the_current_iteration() {
+        let interrupt = AtomicBool::new(true);
+        let calls = Rc::new(Cell::new(0));
+        let seen = calls.clone();
+        let mut transform: graph::TransformFn = Box::new(move |_, _| seen.set(seen.get() + 1));
+        let options = CpuSgdOptions {
+            interrupt: Some(&interrupt),
+            transform: Some(&mut transform),
+            ..Default::default()
+        };
+        let (_, layout) = execute_sgd(cycle_params(10), options);
+        assert_eq!(layout.iterations, 1);
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn seeded_runs_are_bit_identical() {
+        let mut params = cycle_params(10);
+        params.seed = Some(42);
+        let (_, first) = execute_sgd(params.clone(), CpuSgdOptions::default());
+        let (_, second) = execute_sgd(params.clone(), CpuSgdOptions::default());
+        assert_eq!(first.positions, second.positions);
+
+        params.seed = Some(43);
+        let (_, other) = execute_sgd(params, CpuSgdOptions::default());
+        assert_ne!(first.positions, other.positions);
+    }
+
+    #[test]
+    fn pair_angle_is_fixed_per_ordered_pair() {
+        // Same values as the copy in baseline-sgd-non-gpu's algorithm.rs
+        assert_eq!(pair_angle(0, 1), 3.559811364734998);
+        assert_eq!(pair_angle(1, 0), 4.814815942578842);
+        assert_eq!(pair_angle(3, 7), 3.658356198943096);
+        for (u, v) in [(0, 1), (5, 2), (100_000, 3)] {
+            assert!((0.0..std::f64::consts::TAU).contains(&pair_angle(u, v)));
+        }
+    }
+
+    #[test]
+    fn k4_becomes_a_regular_tetrahedron_in_3d() {
+        let graph = graph::Graph::from_edges(4, vec![0, 0, 0, 1, 1, 2], vec![1, 2, 3, 2, 3, 3]).unwrap();
+        let mut params = graph.prepare_sgd_params(100, 0.01, true);
+        params.positions_z = graph::init_positions_z(4, true, Some(7));
+        let (_, positions) = execute_sgd_3d(params, None, None).unwrap();
+        for i in 0..4 {
+            for j in i + 1..4 {
+                let d: f32 = (0..3).map(|k| (positions[i][k] - positions[j][k]).powi(2)).sum::<f32>().sqrt();
+                assert!((d - 1.0).abs() < 1e-3, "distance {} between {} and {}", d, i, j);
+            }
+        }
+    }
+}
@@ -0,0 +1,865 @@
+//! wgpu backend (Vulkan / DX12 / Metal through wgpu).
+//!
+//! Runs the same lock-based SGD kernel as the Metal backend (`shader.wgsl`
+//! mirrors `shader.metal`), for machines without native Metal.
+
+use crate::graph;
+use crate::layout::Layout;
+use anyhow::Result;
+use bytemuck::{Pod, Zeroable};
+use std::cell::RefCell;
+use std::num::NonZeroU64;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wgpu::util::DeviceExt;
+
+/// One pair as the kernels read it (`EdgeInfo` in shader.wgsl and
+/// shader.metal).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct GpuEdgeInfo {
+    pub u: u32,
+    pub v: u32,
+    pub dij: f32,
+    pub wij: f32,
+}
+
+// Must match the layout of `EdgeInfo` in both shaders.
+const _: () = assert!(std::mem::size_of::<GpuEdgeInfo>() == 16);
+const _: () = assert!(std::mem::align_of::<GpuEdgeInfo>() == 4);
+
+/// Result of a `map_async`, filled in by its callback.
+type MapStatus = Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>;
+
+/// Boundary description passed to the shader (`Boundary` in shader.wgsl).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct GpuBoundary {
+    toroidal: u32,
+    width: f32,
+    height: f32,
+    _pad: u32,
+}
+
+/// Default threads per workgroup on Metal, and the subgroup width assumed on
+/// wgpu when the adapter reports none: one warp.
+pub const DEFAULT_WORKGROUP_SIZE: u32 = 32;
+
+/// Largest default workgroup size on wgpu. The kernels share nothing
+/// within a workgroup, so larger ones gain nothing.
+const MAX_DEFAULT_WORKGROUP_SIZE: u32 = 256;
+
+/// Number of storage buffers bound by the SGD pipeline (bindings 0-2, 4-7, 10).
+const STORAGE_BUFFERS_USED: u32 = 8;
+
+pub struct GpuContext {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    module: wgpu::ShaderModule,
+    interrupt: Option<Arc<AtomicBool>>,
+    time_budget: Option<Duration>,
+    /// `(threshold, abort)` for the per-iteration stall check.
+    slow_iteration: Option<(Duration, bool)>,
+    snapshots: Option<(usize, graph::SnapshotFn)>,
+    transform: Option<RefCell<graph::TransformFn>>,
+    live: Option<graph::LivePositions>,
+    timings: Option<graph::PhaseTimings>,
+    workgroup_size: u32,
+}
+
+impl GpuContext {
+    pub fn new() -> Result<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+
+        let adapter =
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+                .map_err(|e| anyhow::anyhow!("No wgpu adapter found: {:?}", e))?;
+
+        let info = adapter.get_info();
+        println!("Using wgpu adapter: {} ({:?})", info.name, info.backend);
+
+        if !adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        {
+            anyhow::bail!(
+                "selected adapter {} ({:?}) does not support compute shaders",
+                info.name,
+                info.device_type
+            );
+        }
+        let storage_buffers = adapter.limits().max_storage_buffers_per_shader_stage;
+        if storage_buffers < STORAGE_BUFFERS_USED {
+            anyhow::bail!(
+                "selected adapter {} ({:?}) supports only {} storage buffers per shader stage, {} are needed",
+                info.name,
+                info.device_type,
+                storage_buffers,
+                STORAGE_BUFFERS_USED
+            );
+        }
+
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: None,
+            required_features: wgpu::Features::empty(),
+            required_limits: adapter.limits(),
+            experimental_features: wgpu::ExperimentalFeatures::disabled(),
+            memory_hints: wgpu::MemoryHints::MemoryUsage,
+            trace: wgpu::Trace::Off,
+        }))
+        .map_err(|e| anyhow::anyhow!("Failed to create device: {}", e))?;
+
+        let module = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
+
+        Ok(GpuContext {
+            device,
+            queue,
+            module,
+            interrupt: None,
+            time_budget: None,
+            slow_iteration: None,
+            snapshots: None,
+            transform: None,
+            live: None,
+            timings: None,
+            workgroup_size: default_workgroup_size(&adapter.limits()),
+        })
+    }
+
+    /// Threads per workgroup, passed to the shader's `wg_size` override at
+    /// pipeline creation. Every thread updates its own pairs, so the grid
+    /// shrinks as the workgroups grow. Fails if the device cannot run
+    /// workgroups of that size.
+    pub fn with_workgroup_size(mut self, size: u32) -> Result<Self> {
+        let limits = self.device.limits();
+        let max = limits.max_compute_workgroup_size_x.min(limits.max_compute_invocations_per_workgroup);
+        if size == 0 || size > max {
+            anyhow::bail!("workgroup size {} is not supported by this device (1 to {})", size, max);
+        }
+        self.workgroup_size = size;
+        Ok(self)
+    }
+
+    /// Stop iterating (after the current iteration) once `flag` is set.
+    pub fn with_interrupt(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.interrupt = Some(flag);
+        self
+    }
+
+    /// Stop iterating once the iteration loop has run for `budget`; the
+    /// positions reached so far are returned as the result.
+    pub fn with_time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    /// Warn when a single iteration takes longer than `threshold` (e.g. a
+    /// driver hitch or thermal throttling); with `abort` the run also stops
+    /// there and returns the positions reached so far.
+    pub fn with_slow_iteration_threshold(mut self, threshold: Duration, abort: bool) -> Self {
+        self.slow_iteration = Some((threshold, abort));
+        self
+    }
+
+    /// Call `f` with the positions after every `every`-th iteration and after
+    /// the last one, instead of keeping per-iteration copies.
+    ///
+    /// Each snapshot is a readback: a copy into the mappable download buffer,
+    /// a submit, and a blocking map of `node_size * dims` floats, so it costs
+    /// a CPU/GPU round trip that grows with the node count. `f` also runs
+    /// before the next iteration is encoded. Keep `every` large for big
+    /// graphs; `every = 1` can dominate the run time.
+    pub fn with_snapshots(mut self, every: usize, f: graph::SnapshotFn) -> Self {
+        self.snapshots = Some((every, f));
+        self
+    }
+
+    /// Apply `f` to the downloaded positions after every iteration (after
+    /// the boundary constraint) and upload the result before the next one.
+    /// Costs a full position round trip per iteration.
+    pub fn with_transform(mut self, f: graph::TransformFn) -> Self {
+        self.transform = Some(RefCell::new(f));
+        self
+    }
+
+    /// Publish the positions to `live` as the run progresses. The positions
+    /// buffer is copied to a separate staging buffer in the same submission
+    /// as the iteration and mapped asynchronously; a new copy is only queued
+    /// once the previous one has been published, so `live` may lag the GPU
+    /// by an iteration and readers never wait for it.
+    pub fn with_live_positions(mut self, live: graph::LivePositions) -> Self {
+        self.live = Some(live);
+        self
+    }
+
+    /// Record the upload, iteration and download times of each run into
+    /// `timings`.
+    pub fn with_timings(mut self, timings: graph::PhaseTimings) -> Self {
+        self.timings = Some(timings);
+        self
+    }
+
+    /// Run SGD and return the initial positions and the final [`Layout`].
+    pub fn execute_sgd(
+        &self,
+        params: graph::SgdParams,
+    ) -> Result<graph::LayoutPair> {
+        anyhow::ensure!(params.positions_z.is_empty(), "3D parameters given: use execute_sgd_3d");
+        let start = std::time::Instant::now();
+        let (initial, result, iterations) = self.run(params)?;
+        let layout = Layout::new(graph::unflatten(&result), iterations, start.elapsed());
+        Ok((graph::unflatten(&initial), layout))
+    }
+
+    /// [`execute_sgd`](Self::execute_sgd) for a 3D layout (`positions_z`
+    /// set). Only the open plane is supported and no transform may be set;
+    /// snapshots and live positions see the `x`/`y` projection.
+    pub fn execute_sgd_3d(
+        &self,
+        params: graph::SgdParams,
+    ) -> Result<graph::LayoutPair3> {
+        graph::check_3d(&params, self.transform.is_some())?;
+        let (initial, result, _) = self.run(params)?;
+        Ok((graph::unflatten(&initial), graph::unflatten(&result)))
+    }
+
+    /// Run the iterations on positions flattened to `dims` floats per node;
+    /// returns the flattened `(initial, final)` positions and the number of
+    /// iterations run.
+    fn run(&self, params: graph::SgdParams) -> Result<(Vec<f32>, Vec<f32>, usize)> {
+        let setup_start = std::time::Instant::now();
+        let dims = params.dims();
+        let gpu_positions: Vec<f32> = params
+            .positions_3d()
+            .iter()
+            .flat_map(|p| p[..dims].iter().map(|&c| c as f32))
+            .collect();
+        let gpu_levels = params.node_levels();
+        let boundary = params.boundary;
+        let active_pairs = params.active_pairs;
+        let color_offsets = params.color_offsets;
+        let gpu_etas: Vec<f32> = params.etas.into_iter().map(|e| e as f32).collect();
+        let initial_positions = gpu_positions.clone();
+        let gpu_pairs: Vec<GpuEdgeInfo> = params
+            .pairs
+            .into_iter()
+            .map(|p| GpuEdgeInfo {
+                u: p.u as u32,
+                v: p.v as u32,
+                dij: p.dij as f32,
+                wij: p.wij as f32,
+            })
+            .collect();
+
+        let node_size = gpu_positions.len() / dims;
+        let num_iterations = gpu_etas.len();
+        let num_pairs = gpu_pairs.len();
+        println!("Setting up wgpu buffers...");
+        println!("  Nodes: {}, Pairs: {}, Iterations: {}", node_size, num_pairs, num_iterations);
+        // Same check as `SgdParams::validate`, for callers that skip it
+        anyhow::ensure!(num_iterations > 0, "no learning rates given: etas is empty, nothing to iterate");
+        if num_pairs == 0 {
+            return Ok((initial_positions.clone(), initial_positions, 0));
+        }
+
+        let storage = |label: &str, contents: &[u8], extra: wgpu::BufferUsages| {
+            self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage: wgpu::BufferUsages::STORAGE | extra,
+            })
+        };
+        let etas_buffer = storage("Etas Buffer", bytemuck::cast_slice(&gpu_etas), wgpu::BufferUsages::empty());
+        let positions_buffer = storage(
+            "Positions Buffer",
+            bytemuck::cast_slice(&gpu_positions),
+            wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+        );
+        let pairs_buffer = storage("Pairs Buffer", bytemuck::cast_slice(&gpu_pairs), wgpu::BufferUsages::empty());
+        let levels_buffer = storage("Levels Buffer", bytemuck::cast_slice(&gpu_levels), wgpu::BufferUsages::empty());
+        let lock_buffer = storage(
+            "Lock Buffer",
+            bytemuck::cast_slice(&vec![0u32; node_size]),
+            wgpu::BufferUsages::empty(),
+        );
+        // One storage binding may be smaller than the pairs buffer (128 MB
+        // on some adapters, bcspwr10 has 225 MB of pairs), so the pairs are
+        // bound in chunks of at most `chunk` pairs, one bind group and one
+        // dispatch each. Each chunk records its updates from the start of
+        // the record buffers, with its own slot of the counter buffer.
+        let limits = self.device.limits();
+        let chunk = pairs_per_binding(&limits).min(num_pairs);
+        let num_chunks = num_pairs.div_ceil(chunk);
+        let count_stride = limits.min_storage_buffer_offset_alignment as usize;
+        let updated_pairs_buffer = storage(
+            "Updated Pairs Buffer",
+            bytemuck::cast_slice(&vec![0u32; chunk]),
+            wgpu::BufferUsages::empty(),
+        );
+        let zero_counts = vec![0u8; num_chunks * count_stride];
+        let updated_count_buffer = storage("Updated Count Buffer", &zero_counts, wgpu::BufferUsages::COPY_DST);
+        let positions_before_buffer = storage(
+            "Positions Before Buffer",
+            bytemuck::cast_slice(&vec![[0.0f32; 4]; chunk]),
+            wgpu::BufferUsages::empty(),
+        );
+        let iteration_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Iteration Buffer"),
+            contents: bytemuck::cast_slice(&[0u32]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        // Polygons only constrain positions between iterations, on the CPU.
+        let gpu_boundary = match boundary {
+            graph::BoundaryMode::Open | graph::BoundaryMode::Polygon(_) => {
+                GpuBoundary { toroidal: 0, width: 0.0, height: 0.0, _pad: 0 }
+            }
+            graph::BoundaryMode::Toroidal { width, height } => GpuBoundary {
+                toroidal: 1,
+                width: width as f32,
+                height: height as f32,
+                _pad: 0,
+            },
+        };
+        let boundary_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Boundary Buffer"),
+            contents: bytemuck::bytes_of(&gpu_boundary),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        // (chunk, [first pair within it, count]) per dispatch: the chunks of
+        // the pairs `first..first + count`
+        let chunk_passes = |first: usize, count: usize| -> Vec<(usize, [u32; 2])> {
+            let mut passes = Vec::new();
+            let mut start = first;
+            while start < first + count {
+                let (c, offset) = (start / chunk, start % chunk);
+                let len = (chunk - offset).min(first + count - start);
+                passes.push((c, [offset as u32, len as u32]));
+                start += len;
+            }
+            passes
+        };
+        // The colored kernel dispatches each color once per chunk it spans,
+        // reading its range at a dynamic offset aligned for uniform
+        // bindings; the lock-based kernel ignores it
+        let range_stride = limits.min_uniform_buffer_offset_alignment as usize;
+        let color_passes: Vec<(usize, [u32; 2])> = color_offsets
+            .windows(2)
+            .flat_map(|w| chunk_passes(w[0], w[1] - w[0]))
+            .collect();
+        let mut range_bytes = vec![0u8; color_passes.len().max(1) * range_stride];
+        for (k, (_, range)) in color_passes.iter().enumerate() {
+            range_bytes[k * range_stride..][..8].copy_from_slice(bytemuck::cast_slice(range));
+        }
+        let color_range_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Color Range Buffer"),
+            contents: &range_bytes,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let download_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Download Buffer"),
+            size: positions_buffer.size(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let live_buffer = self.live.as_ref().map(|_| {
+            self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Live Positions Buffer"),
+                size: positions_buffer.size(),
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            })
+        });
+
+        let buffers = [
+            (&etas_buffer, true, 4),
+            (&positions_buffer, false, 8),
+            (&pairs_buffer, true, 16),
+            (&iteration_buffer, true, 4),
+            (&lock_buffer, false, 4),
+            (&updated_pairs_buffer, false, 4),
+            (&updated_count_buffer, false, 4),
+            (&positions_before_buffer, false, 16),
+            (&boundary_buffer, true, 16),
+            (&color_range_buffer, true, 8),
+            (&levels_buffer, true, 4),
+        ];
+        let layout_entries: Vec<wgpu::BindGroupLayoutEntry> = buffers
+            .iter()
+            .enumerate()
+            .map(|(binding, &(buffer, read_only, min_size))| wgpu::BindGroupLayoutEntry {
+                binding: binding as u32,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: if buffer.usage().contains(wgpu::BufferUsages::UNIFORM) {
+                        wgpu::BufferBindingType::Uniform
+                    } else {
+                        wgpu::BufferBindingType::Storage { read_only }
+                    },
+                    has_dynamic_offset: std::ptr::eq(buffer, &color_range_buffer),
+                    min_binding_size: NonZeroU64::new(min_size),
+                },
+                count: None,
+            })
+            .collect();
+        let bind_group_layout = self.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &layout_entries,
+        });
+        // The kernel takes arrayLength(&pairs) as its pair count, so a pair
+        // growth schedule binds only the active prefix of the pairs buffer:
+        // the full chunks before it and part of the last one
+        let make_bind_group = |c: usize, len: usize| {
+            let pair_size = std::mem::size_of::<GpuEdgeInfo>();
+            let bind_entries: Vec<wgpu::BindGroupEntry> = buffers
+                .iter()
+                .enumerate()
+                .map(|(binding, &(buffer, _, _))| wgpu::BindGroupEntry {
+                    binding: binding as u32,
+                    resource: if std::ptr::eq(buffer, &pairs_buffer) {
+                        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer,
+                            offset: (c * chunk * pair_size) as u64,
+                            size: NonZeroU64::new((len * pair_size) as u64),
+                        })
+                    } else if std::ptr::eq(buffer, &updated_count_buffer) {
+                        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer,
+                            offset: (c * count_stride) as u64,
+                            size: NonZeroU64::new(4),
+                        })
+                    } else if std::ptr::eq(buffer, &color_range_buffer) {
+                        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer,
+                            offset: 0,
+                            size: NonZeroU64::new(8),
+                        })
+                    } else {
+                        buffer.as_entire_binding()
+                    },
+                })
+                .collect();
+            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &bind_group_layout,
+                entries: &bind_entries,
+            })
+        };
+        let make_bind_groups = |active: usize| -> Vec<wgpu::BindGroup> {
+            chunk_passes(0, active).iter().map(|&(c, [_, len])| make_bind_group(c, len as usize)).collect()
+        };
+        let mut bound_pairs = num_pairs;
+        let mut bind_groups = make_bind_groups(num_pairs);
+        let pipeline_layout = self.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = self.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &self.module,
+            entry_point: Some(if color_offsets.is_empty() { "sgd" } else { "sgd_colored" }),
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &[("wg_size", self.workgroup_size as f64), ("dims", dims as f64)],
+                ..Default::default()
+            },
+            cache: None,
+        });
+
+        // @workgroup_size(wg_size,1,1): one thread per pair, so
+        // ceil(pairs / wg_size) workgroups, 2D once they exceed the
+        // per-dimension limit
+        let max_per_dim = limits.max_compute_workgroups_per_dimension;
+        let workgroups_for = |pairs: usize| (pairs as u32).div_ceil(self.workgroup_size);
+        if num_chunks > 1 {
+            println!("  Pairs bound in {} chunks of up to {} pairs", num_chunks, chunk);
+        }
+        if color_offsets.is_empty() {
+            let (grid_x, grid_y) = dispatch_grid(workgroups_for(chunk), max_per_dim);
+            println!(
+                "  Dispatch grid: {} x {} workgroups of {} threads ({} idle threads)",
+                grid_x,
+                grid_y,
+                self.workgroup_size,
+                (grid_x * grid_y * self.workgroup_size) as usize - chunk
+            );
+        } else {
+            println!(
+                "  Pair coloring: {} lock-free passes per iteration, up to {} pairs each",
+                color_passes.len(),
+                color_passes.iter().map(|(_, r)| r[1]).max().unwrap_or(0)
+            );
+        }
+
+        println!("Executing SGD iterations...");
+        let iteration_start = std::time::Instant::now();
+        let mut completed_iterations = 0usize;
+        // Status of the live buffer's pending map, set by its callback
+        let mut live_map: Option<MapStatus> = None;
+        for iteration in 0..num_iterations {
+            let this_iteration = std::time::Instant::now();
+            self.queue.write_buffer(&updated_count_buffer, 0, &zero_counts);
+            self.queue.write_buffer(&iteration_buffer, 0, bytemuck::cast_slice(&[iteration as u32]));
+            let active = active_pairs.get(iteration).copied().unwrap_or(num_pairs);
+            if active != bound_pairs {
+                bind_groups = make_bind_groups(active);
+                bound_pairs = active;
+            }
+
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some(&format!("SGD Iteration {}", iteration)),
+            });
+            {
+                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some(&format!("SGD Pass {}", iteration)),
+                    timestamp_writes: None,
+                });
+                compute_pass.set_pipeline(&pipeline);
+                // Dispatches within a pass are ordered, so each chunk (and
+                // each color) sees the positions written by the previous one
+                if color_offsets.is_empty() {
+                    for (c, bind_group) in bind_groups.iter().enumerate() {
+                        let len = chunk.min(active - c * chunk);
+                        let (x, y) = dispatch_grid(workgroups_for(len), max_per_dim);
+                        compute_pass.set_bind_group(0, bind_group, &[0]);
+                        compute_pass.dispatch_workgroups(x, y, 1);
+                    }
+                } else {
+                    for (k, &(c, range)) in color_passes.iter().enumerate() {
+                        let (x, y) = dispatch_grid(workgroups_for(range[1] as usize), max_per_dim);
+                        compute_pass.set_bind_group(0, &bind_groups[c], &[(k * range_stride) as u32]);
+                        compute_pass.dispatch_workgroups(x, y, 1);
+                    }
+                }
+            }
+            // Only copy into the live buffer while it is not mapped
+            let live_copy = live_buffer.as_ref().filter(|_| live_map.is_none());
+            if let Some(live_buffer) = live_copy {
+                encoder.copy_buffer_to_buffer(&positions_buffer, 0, live_buffer, 0, positions_buffer.size());
+            }
+            self.queue.submit([encoder.finish()]);
+            if let Some(live_buffer) = live_copy {
+                let status = Arc::new(Mutex::new(None));
+                let callback_status = status.clone();
+                live_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+                    if let Ok(mut status) = callback_status.lock() {
+                        *status = Some(result);
+                    }
+                });
+                live_map = Some(status);
+            }
+            self.device.poll(wgpu::PollType::wait_indefinitely())?;
+            if let (Some(live), Some(live_buffer)) = (&self.live, &live_buffer) {
+                let result = live_map.as_ref().and_then(|status| status.lock().ok()?.take());
+                match result {
+                    Some(Ok(())) => {
+                        let data = live_buffer.slice(..).get_mapped_range();
+                        let positions = positions_from_bytes(&data, node_size * dims);
+                        drop(data);
+                        live_buffer.unmap();
+                        live.publish(graph::project_xy(&positions?, dims));
+                        live_map = None;
+                    }
+                    Some(Err(e)) => {
+                        println!("Warning: could not map the live positions buffer: {}", e);
+                        live_map = None;
+                    }
+                    // Still mapping; publish on a later iteration
+                    None => {}
+                }
+            }
+
+            // Wrap/project positions back into the domain, then apply the hook
+            if boundary != graph::BoundaryMode::Open || self.transform.is_some() {
+                let mut current: Vec<[f64; 2]> = self
+                    .download(&positions_buffer, &download_buffer, node_size * dims)?
+                    .chunks_exact(2)
+                    .map(|p| boundary.constrain([p[0] as f64, p[1] as f64]))
+                    .collect();
+                if let Some(transform) = &self.transform {
+                    (transform.borrow_mut())(&mut current, iteration);
+                }
+                let updated: Vec<[f32; 2]> = current.iter().map(|p| [p[0] as f32, p[1] as f32]).collect();
+                self.queue.write_buffer(&positions_buffer, 0, bytemuck::cast_slice(&updated));
+                if let Some(live) = &self.live {
+                    live.publish(updated);
+                }
+            }
+
+            completed_iterations += 1;
+            let interrupted = self.interrupt.as_ref().is_some_and(|f| f.load(Ordering::SeqCst));
+            let out_of_time = self
+                .time_budget
+                .is_some_and(|budget| iteration_start.elapsed() >= budget);
+            let took = this_iteration.elapsed();
+            let too_slow = match self.slow_iteration {
+                Some((threshold, abort)) if took > threshold => {
+                    println!(
+                        "Warning: iteration {} took {:.1}ms (threshold {:.1}ms)",
+                        iteration,
+                        took.as_secs_f64() * 1000.0,
+                        threshold.as_secs_f64() * 1000.0
+                    );
+                    abort
+                }
+                _ => false,
+            };
+
+            // An early stop still captures the last completed iteration
+            if let Some((every, snapshot)) = &self.snapshots {
+                if graph::snapshot_due(iteration, num_iterations, *every) || interrupted || out_of_time || too_slow {
+                    let positions = self.download(&positions_buffer, &download_buffer, node_size * dims)?;
+                    snapshot(iteration, &graph::project_xy(&positions, dims));
+                }
+            }
+
+            if interrupted {
+                println!("Interrupted after iteration {}", iteration);
+                break;
+            }
+            if out_of_time {
+                println!("Time budget exhausted after iteration {}", iteration);
+                break;
+            }
+            if too_slow {
+                println!("Aborting after slow iteration {}", iteration);
+                break;
+            }
+        }
+
+        let iteration_duration = iteration_start.elapsed();
+        let per_iteration = iteration_duration.as_secs_f64() / completed_iterations.max(1) as f64;
+        println!("\n=== Performance Summary ===");
+        println!("Iterations run:   {}/{}", completed_iterations, num_iterations);
+        println!("Iterations total: {:.3}s", iteration_duration.as_secs_f64());
+        println!("Per iteration:    {:.3}s ({:.1}ms)", per_iteration, per_iteration * 1000.0);
+
+        let download_start = std::time::Instant::now();
+        let final_positions = self.download(&positions_buffer, &download_buffer, node_size * dims)?;
+        if let Some(timings) = &self.timings {
+            timings.record(graph::Timings {
+                upload: iteration_start - setup_start,
+                iterations: iteration_duration,
+                download: download_start.elapsed(),
+                iterations_run: completed_iterations,
+            });
+        }
+        Ok((initial_positions, final_positions, completed_iterations))
+    }
+
+    /// Copy `buffer` into the mappable `staging` buffer and read back its
+    /// first `count` floats.
+    fn download(&self, buffer: &wgpu::Buffer, staging: &wgpu::Buffer, count: usize) -> Result<Vec<f32>> {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(buffer, 0, staging, 0, buffer.size());
+        self.queue.submit([encoder.finish()]);
+
+        let slice = staging.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+
+        let data = slice.get_mapped_range();
+        let positions = positions_from_bytes(&data, count);
+        drop(data);
+        staging.unmap();
+        positions
+    }
+}
+
+/// Most pairs one storage binding of the pairs buffer can hold: the largest
+/// count within `max_storage_buffer_binding_size` whose bytes keep the
+/// offset of the next chunk aligned.
+fn pairs_per_binding(limits: &wgpu::Limits) -> usize {
+    let pair_size = std::mem::size_of::<GpuEdgeInfo>() as u64;
+    let align = (limits.min_storage_buffer_offset_alignment as u64).max(pair_size);
+    let bytes = limits.max_storage_buffer_binding_size as u64 / align * align;
+    (bytes / pair_size).max(1) as usize
+}
+
+/// Most balanced `(x, y)` workgroup grid covering `count` workgroups with
+/// both dimensions at most `max_per_dim`.
+///
+/// Uses the fewest rows that fit and spreads `count` evenly over them, so at
+/// most `y - 1` workgroups fall past the end (and only return at the bounds
+/// check). Filling rows up to the limit instead would leave e.g. 65536
+/// workgroups with a second row of 65534 idle ones. The shaders recover the
+/// workgroup index as `y * x_dim + x`.
+pub fn dispatch_grid(count: u32, max_per_dim: u32) -> (u32, u32) {
+    let rows = count.div_ceil(max_per_dim.max(1)).max(1);
+    (count.div_ceil(rows), rows)
+}
+
+/// As many threads as the device runs per workgroup
+/// (`max_compute_invocations_per_workgroup` and the x dimension limit), at
+/// most [`MAX_DEFAULT_WORKGROUP_SIZE`], rounded down to whole subgroups of
+/// `max_subgroup_size` ([`DEFAULT_WORKGROUP_SIZE`] where the adapter does not
+/// report it). A device below one subgroup gets all it allows.
+fn default_workgroup_size(limits: &wgpu::Limits) -> u32 {
+    let subgroup = if limits.max_subgroup_size > 0 { limits.max_subgroup_size } else { DEFAULT_WORKGROUP_SIZE };
+    let max = limits
+        .max_compute_invocations_per_workgroup
+        .min(limits.max_compute_workgroup_size_x)
+        .min(MAX_DEFAULT_WORKGROUP_SIZE);
+    if max < subgroup {
+        max.max(1)
+    } else {
+        max / subgroup * subgroup
+    }
+}
+
+/// Decode `count` little-endian `f32` coordinates (the flattened positions)
+/// from a mapped readback buffer.
+///
+/// Reads byte-wise rather than with `bytemuck::cast_slice`, so neither the
+/// mapping's alignment nor a buffer size that is not a multiple of 4 (e.g.
+/// padding added to the buffer) can make it panic. Bytes past the last
+/// coordinate are ignored; a buffer too short for `count` coordinates is an
+/// error. vram-lock's gpu.rs has an identical copy with the same tests.
+fn positions_from_bytes(data: &[u8], count: usize) -> Result<Vec<f32>> {
+    let needed = count * 4;
+    if data.len() < needed {
+        anyhow::bail!(
+            "position readback has {} bytes, {} needed for {} coordinates",
+            data.len(),
+            needed,
+            count
+        );
+    }
+    Ok(data[..needed]
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Nodes 0 and 2 four apart on the x axis (node 1 far away) and one pair
+    /// `(0, 2)` with `dij = 2`, `wij = 0.5`, one iteration at `eta = 1`.
+    pub(crate) fn single_pair_params() -> graph::SgdParams {
+        graph::SgdParams {
+            etas: vec![1.0],
+            positions: vec![[0.0, 0.0], [100.0, 100.0], [4.0, 0.0]],
+            pairs: vec![graph::EdgeInfo { u: 0, v: 2, dij: 2.0, wij: 0.5 }],
+            boundary: graph::BoundaryMode::Open,
+            active_pairs: Vec::new(),
+            positions_z: Vec::new(),
+            color_offsets: Vec::new(),
+            fixed: Vec::new(),
+            anchors: Vec::new(),
+            seed: None,
+        }
+    }
+
+    /// `mu = 0.5` and `r = 1`, so both nodes move 0.5 towards each other.
+    /// Reading `dij` and `wij` swapped would move them 1.75; swapped `u`/`v`
+    /// would skip the pair (the kernels only take `u < v`) and a wrong index
+    /// would move node 1.
+    pub(crate) fn assert_single_pair_result(positions: &[[f32; 2]]) {
+        let expected = [[0.5, 0.0], [100.0, 100.0], [3.5, 0.0]];
+        for (p, e) in positions.iter().zip(expected) {
+            assert!((p[0] - e[0]).abs() < 1e-5 && (p[1] - e[1]).abs() < 1e-5, "{:?}", positions);
+        }
+    }
+
+    #[test]
+    fn kernel_reads_the_uploaded_pair() {
+        let Ok(ctx) = GpuContext::new() else {
+            eprintln!("skipping: no wgpu adapter");
+            return;
+        };
+        for coloring in [false, true] {
+            let mut params = single_pair_params();
+            if coloring {
+                params.apply_pair_coloring();
+            }
+            let (_, layout) = ctx.execute_sgd(params).unwrap();
+            assert_single_pair_result(&layout.positions);
+        }
+    }
+
+    #[test]
+    fn default_workgroup_size_is_whole_subgroups_up_to_the_cap() {
+        let limits = |invocations: u32, subgroup: u32| wgpu::Limits {
+            max_compute_invocations_per_workgroup: invocations,
+            max_compute_workgroup_size_x: invocations,
+            max_subgroup_size: subgroup,
+            ..wgpu::Limits::default()
+        };
+        assert_eq!(default_workgroup_size(&limits(1024, 32)), 256);
+        assert_eq!(default_workgroup_size(&limits(1024, 64)), 256);
+        assert_eq!(default_workgroup_size(&limits(200, 64)), 192);
+        assert_eq!(default_workgroup_size(&limits(200, 0)), 192);
+        assert_eq!(default_workgroup_size(&limits(16, 32)), 16);
+    }
+
+    #[test]
+    fn pair_chunks_fit_a_binding_and_stay_aligned() {
+        let limits = |max_binding: u32, align: u32| wgpu::Limits {
+            max_storage_buffer_binding_size: max_binding,
+            min_storage_buffer_offset_alignment: align,
+            ..wgpu::Limits::default()
+        };
+        // 128 MB: 8M pairs, where bcspwr10 has about 14M
+        assert_eq!(pairs_per_binding(&limits(128 << 20, 256)), 8 << 20);
+        // 100 bytes hold 6 pairs, but chunk offsets must be multiples of 64
+        assert_eq!(pairs_per_binding(&limits(100, 64)), 4);
+        assert_eq!(pairs_per_binding(&limits(100, 4)), 6);
+    }
+
+    /// Both endpoints of the pair start at (1, 1), so the update takes the
+    /// nudge direction `pair_angle(0, 2)` and must match the CPU backend.
+    #[test]
+    fn coincident_nodes_separate_along_the_cpu_direction() {
+        let Ok(ctx) = GpuContext::new() else {
+            eprintln!("skipping: no wgpu adapter");
+            return;
+        };
+        let mut params = single_pair_params();
+        params.positions = vec![[1.0, 1.0], [100.0, 100.0], [1.0, 1.0]];
+        params.pairs[0].wij = 1.0;
+        let (_, cpu) = crate::cpu::execute_sgd(params.clone(), Default::default());
+        let (_, gpu) = ctx.execute_sgd(params).unwrap();
+        for (c, g) in cpu.positions.iter().zip(&gpu.positions) {
+            assert!((c[0] - g[0]).abs() < 1e-4 && (c[1] - g[1]).abs() < 1e-4, "{:?} vs {:?}", cpu, gpu);
+        }
+    }
+
+    const COORDS: [f32; 6] = [0.5, -1.0, 2.0, 3.5, -0.25, 7.0];
+
+    fn coord_bytes() -> Vec<u8> {
+        COORDS.iter().flat_map(|c| c.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn decodes_an_odd_node_count() {
+        // 3 nodes: 24 bytes, not a multiple of 16
+        assert_eq!(positions_from_bytes(&coord_bytes(), 6).unwrap(), COORDS);
+    }
+
+    #[test]
+    fn ignores_a_misaligned_tail() {
+        let mut bytes = coord_bytes();
+        bytes.extend([0xaa, 0xbb, 0xcc]);
+        assert_eq!(positions_from_bytes(&bytes, 6).unwrap(), COORDS);
+    }
+
+    #[test]
+    fn decodes_from_an_unaligned_start() {
+        let mut bytes = vec![0];
+        bytes.extend(coord_bytes());
+        assert_eq!(positions_from_bytes(&bytes[1..], 6).unwrap(), COORDS);
+    }
+
+    #[test]
+    fn rejects_a_short_buffer() {
+        let bytes = coord_bytes();
+        let err = positions_from_bytes(&bytes[..22], 6).unwrap_err().to_string();
+        assert!(err.contains("22 bytes, 24 needed"), "{}", err);
+    }
+}
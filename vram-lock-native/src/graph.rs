@@ -1,26 +1,298 @@
 use anyhow::Result;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use sprs::io::read_matrix_market;
 use sprs::num_kinds::Pattern;
-use std::collections::VecDeque;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::Path;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Graph {
     pub node_size: usize,
     pub edge_size: usize,
     pub edge_src: Vec<usize>,
     pub edge_dst: Vec<usize>,
+    /// Layer id of every edge (all 0 unless built by `from_layers`).
+    pub edge_layer: Vec<usize>,
+    /// Stored value of every edge of a `real` or `integer` Matrix Market
+    /// file, meant as that edge's target distance; 1.0 for pattern files and
+    /// graphs from any other source. Hop-count distances ignore it.
+    pub edge_weight: Vec<f64>,
 }
 
-#[derive(Debug)]
+/// Initial positions and the final [`Layout`](crate::layout::Layout)
+/// returned by the SGD backends.
+pub type LayoutPair = (Vec<[f32; 2]>, crate::layout::Layout);
+
+/// `(initial, final)` positions of a 3D run (`execute_sgd_3d`).
+pub type LayoutPair3 = (Vec<[f32; 3]>, Vec<[f32; 3]>);
+
+/// Hook applied to the positions after every iteration, e.g. to impose a
+/// custom constraint. Receives `(positions, iteration)`.
+pub type TransformFn = Box<dyn FnMut(&mut [[f64; 2]], usize)>;
+
+/// Learning-rate schedule called as `schedule(iteration, wmin, wmax)`; see
+/// [`calc_learning_rate_with`].
+pub type ScheduleFn = Rc<dyn Fn(usize, f64, f64) -> f64>;
+
+/// Sets the order in which the CPU backend processes pairs: called as
+/// `f(iteration, order)` with the pair indices of the previous iteration and
+/// must leave a permutation of them in `order`.
+pub type PairOrderFn = Box<dyn FnMut(usize, &mut [usize])>;
+
+/// Callback receiving `(iteration, positions)` for sampled intermediate layouts.
+pub type SnapshotFn = Box<dyn Fn(usize, &[[f32; 2]])>;
+
+/// Whether a run of `total` iterations snapshotting every `every` iterations
+/// captures `iteration`: every `every`-th iteration and always the last one,
+/// so 1000 iterations at `every = 25` give 40 frames.
+pub fn snapshot_due(iteration: usize, total: usize, every: usize) -> bool {
+    (iteration + 1).is_multiple_of(every.max(1)) || iteration + 1 == total
+}
+
+/// Split positions flattened to `D` floats per node back into points.
+pub fn unflatten<const D: usize>(flat: &[f32]) -> Vec<[f32; D]> {
+    flat.chunks_exact(D).map(|c| std::array::from_fn(|k| c[k])).collect()
+}
+
+/// `x`/`y` projection of positions flattened to `dims` floats per node.
+pub fn project_xy(flat: &[f32], dims: usize) -> Vec<[f32; 2]> {
+    flat.chunks_exact(dims).map(|c| [c[0], c[1]]).collect()
+}
+
+/// Largest distance any node moved between two flattened position sets.
+pub fn max_displacement(after: &[f32], before: &[f32], dims: usize) -> f32 {
+    after
+        .chunks_exact(dims)
+        .zip(before.chunks_exact(dims))
+        .map(|(a, b)| a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum::<f32>().sqrt())
+        .fold(0.0f32, f32::max)
+}
+
+/// Reject what a 3D run does not support: a missing third coordinate, a
+/// boundary other than the open plane, or a 2D per-iteration transform.
+pub fn check_3d(params: &SgdParams, has_transform: bool) -> Result<()> {
+    anyhow::ensure!(params.dims() == 3, "3D run without z coordinates: set positions_z");
+    anyhow::ensure!(
+        params.boundary == BoundaryMode::Open,
+        "3D layouts only support the open plane, got {:?}",
+        params.boundary
+    );
+    anyhow::ensure!(!has_transform, "per-iteration transforms are 2D only and cannot be used with a 3D layout");
+    Ok(())
+}
+
+/// Positions shared with other threads while a run is in progress.
+///
+/// The backends publish the positions after every iteration; a clone held by
+/// e.g. a render thread can read them with [`current_positions`] at any time
+/// without stopping the run. Empty until the first iteration completes.
+///
+/// [`current_positions`]: LivePositions::current_positions
+#[derive(Debug, Clone, Default)]
+pub struct LivePositions {
+    positions: Arc<Mutex<Vec<[f32; 2]>>>,
+}
+
+impl LivePositions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copy of the positions after the most recently completed iteration.
+    pub fn current_positions(&self) -> Vec<[f32; 2]> {
+        self.positions.lock().map(|p| p.clone()).unwrap_or_default()
+    }
+
+    /// Replace the shared positions (called by the backends).
+    pub fn publish(&self, positions: Vec<[f32; 2]>) {
+        if let Ok(mut shared) = self.positions.lock() {
+            *shared = positions;
+        }
+    }
+}
+
+/// Wall-clock time of the phases of one backend run.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    /// Buffer creation and upload up to the first iteration.
+    pub upload: Duration,
+    /// The iteration loop.
+    pub iterations: Duration,
+    /// Reading back the final positions.
+    pub download: Duration,
+    /// Iterations completed (fewer than scheduled after an early stop).
+    pub iterations_run: usize,
+}
+
+/// [`Timings`] handle shared with a backend, which records into it when the
+/// run finishes. Stays at the default (all zero) for backends that do not
+/// record timings.
+#[derive(Debug, Clone, Default)]
+pub struct PhaseTimings {
+    timings: Arc<Mutex<Timings>>,
+}
+
+impl PhaseTimings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self) -> Timings {
+        self.timings.lock().map(|t| *t).unwrap_or_default()
+    }
+
+    /// Replace the recorded timings (called by the backends).
+    pub fn record(&self, timings: Timings) {
+        if let Ok(mut shared) = self.timings.lock() {
+            *shared = timings;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct SgdParams {
     pub etas: Vec<f64>,
     pub positions: Vec<[f64; 2]>,
     pub pairs: Vec<EdgeInfo>,
+    pub boundary: BoundaryMode,
+    /// Number of leading `pairs` processed in each iteration; empty means
+    /// all of them every time. Set by [`SgdParams::apply_pair_growth`] and
+    /// not written by [`SgdParams::save`].
+    pub active_pairs: Vec<usize>,
+    /// Third coordinate of every node for a 3D layout (see
+    /// [`init_positions_z`]); empty for the usual 2D layout. Only the
+    /// `execute_sgd_3d` backend entry points use it.
+    pub positions_z: Vec<f64>,
+    /// Color boundaries set by [`SgdParams::apply_pair_coloring`]: color `c`
+    /// is `pairs[color_offsets[c]..color_offsets[c + 1]]`, and no two of its
+    /// pairs share a node. Empty for the lock-based kernels. Not written by
+    /// [`SgdParams::save`].
+    pub color_offsets: Vec<usize>,
+    /// Nodes whose positions SGD leaves untouched (see [`SgdParams::pin`]);
+    /// empty means none. The other node of a pair with one fixed node takes
+    /// the whole displacement.
+    pub fixed: Vec<bool>,
+    /// Pivot nodes of sparse stress (see [`Graph::prepare_sgd_params_sparse`]);
+    /// empty means none. In a pair of an anchor and a free node only the
+    /// free node moves, while pairs of two anchors move both.
+    pub anchors: Vec<bool>,
+    /// Seed of the pair and node shuffles of the CPU backends (see
+    /// [`SgdParams::rng`]); `None` shuffles differently on every run. Not
+    /// written by [`SgdParams::save`].
+    pub seed: Option<u64>,
+}
+
+/// Level of a fixed node in [`SgdParams::node_levels`]; a pair of two fixed
+/// nodes is skipped.
+pub const LEVEL_FIXED: u32 = 2;
+
+/// Weight factor of the pairs added by [`SgdParams::connect_components`],
+/// relative to `1 / dij²`.
+pub const INTER_COMPONENT_WEIGHT: f64 = 0.1;
+
+/// Schedule that grows the active pair set over the iterations, from the
+/// longest target distances down to the shortest.
+///
+/// Iteration 0 processes the `start_fraction` of pairs with the largest
+/// `dij`; the count then grows linearly until every pair is active from
+/// `full_at` (a fraction of the iterations) on. Early iterations cost that
+/// much less and only see the long-range pairs that fix the global shape,
+/// the way a coarse level of a multilevel layout would; short-range pairs
+/// join for the refinement. The price is that local structure gets fewer
+/// iterations to settle, so a too small `full_at` or `start_fraction` on a
+/// short run can leave local stress higher than a plain run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PairGrowth {
+    pub start_fraction: f64,
+    pub full_at: f64,
+}
+
+impl Default for PairGrowth {
+    fn default() -> Self {
+        PairGrowth { start_fraction: 0.1, full_at: 0.5 }
+    }
+}
+
+/// Domain the layout lives in.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum BoundaryMode {
+    /// Unbounded plane (default).
+    #[default]
+    Open,
+    /// Periodic domain `[0, width) x [0, height)`. Pair displacements use the
+    /// minimum-image convention and positions are wrapped back into the
+    /// domain after every iteration.
+    Toroidal { width: f64, height: f64 },
+    /// Convex polygon (vertices in order, either orientation). Nodes that
+    /// leave it are projected back onto the nearest boundary point after
+    /// every iteration.
+    Polygon(Vec<[f64; 2]>),
+}
+
+impl BoundaryMode {
+    /// Map a position back into the domain (identity for `Open`).
+    pub fn constrain(&self, p: [f64; 2]) -> [f64; 2] {
+        match self {
+            BoundaryMode::Open => p,
+            BoundaryMode::Toroidal { width, height } => {
+                [p[0].rem_euclid(*width), p[1].rem_euclid(*height)]
+            }
+            BoundaryMode::Polygon(vertices) => project_into_convex_polygon(p, vertices),
+        }
+    }
+}
+
+/// Return `p` if it lies inside the convex polygon, otherwise the closest
+/// point on the polygon boundary.
+fn project_into_convex_polygon(p: [f64; 2], vertices: &[[f64; 2]]) -> [f64; 2] {
+    let n = vertices.len();
+    if n < 3 {
+        return p;
+    }
+
+    let mut has_pos = false;
+    let mut has_neg = false;
+    for i in 0..n {
+        let a = vertices[i];
+        let b = vertices[(i + 1) % n];
+        let cross = (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0]);
+        has_pos |= cross > 0.0;
+        has_neg |= cross < 0.0;
+    }
+    if !(has_pos && has_neg) {
+        return p;
+    }
+
+    let mut best = p;
+    let mut best_d2 = f64::INFINITY;
+    for i in 0..n {
+        let a = vertices[i];
+        let b = vertices[(i + 1) % n];
+        let ab = [b[0] - a[0], b[1] - a[1]];
+        let len2 = ab[0] * ab[0] + ab[1] * ab[1];
+        let t = if len2 > 0.0 {
+            (((p[0] - a[0]) * ab[0] + (p[1] - a[1]) * ab[1]) / len2).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let q = [a[0] + t * ab[0], a[1] + t * ab[1]];
+        let d2 = (p[0] - q[0]).powi(2) + (p[1] - q[1]).powi(2);
+        if d2 < best_d2 {
+            best_d2 = d2;
+            best = q;
+        }
+    }
+    best
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct EdgeInfo {
     pub u: usize,
     pub v: usize,
@@ -28,161 +300,2564 @@ pub struct EdgeInfo {
     pub wij: f64,
 }
 
-impl Graph {
-    pub fn from_mtx(path: &Path) -> Result<Self> {
-        let matrix: sprs::TriMat<Pattern> = read_matrix_market(path)?;
+/// Layout model to optimise.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Algorithm {
+    /// Stress minimisation by SGD over node pairs (default).
+    #[default]
+    StressSgd,
+    /// Fruchterman-Reingold forces with ideal edge length `k` and repulsion
+    /// strength `repulsion` (see `force.rs`).
+    ForceDirected { k: f64, repulsion: f64 },
+    /// Stress with exact pairs up to `radius` hops and a Barnes-Hut
+    /// approximated far field with accuracy `theta` (see `barnes_hut.rs`).
+    BarnesHut { theta: f64, radius: usize },
+}
 
-        let node_size: usize = matrix.rows();
-        
-        // Filter out self-loops
-        let mut edge_src = Vec::new();
-        let mut edge_dst = Vec::new();
-        
-        for (row, col) in matrix.row_inds().iter().zip(matrix.col_inds().iter()) {
-            if row != col {
-                edge_src.push(*row);
-                edge_dst.push(*col);
-            }
-        }
-        
-        let edge_size = edge_src.len();
+/// Transform applied to graph distances before they become SGD targets.
+///
+/// Weights follow the transformed distance (`wij = 1 / dij²`), so long paths
+/// are compressed in both target and influence.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DistanceTransform {
+    /// Hop distance as is (default).
+    #[default]
+    Identity,
+    /// `sqrt(d)`.
+    Sqrt,
+    /// `1 + ln(d)`, so adjacent nodes keep target distance 1.
+    Log,
+}
 
-        Ok(Graph {
-            node_size,
-            edge_size,
-            edge_src,
-            edge_dst,
-        })
+impl DistanceTransform {
+    pub fn apply(self, d: f64) -> f64 {
+        match self {
+            DistanceTransform::Identity => d,
+            DistanceTransform::Sqrt => d.sqrt(),
+            DistanceTransform::Log => 1.0 + d.ln(),
+        }
     }
+}
 
-    fn calc_adj_matrix(&self) -> Vec<Vec<usize>> {
-        let mut adj = vec![Vec::new(); self.node_size];
-        for i in 0..self.edge_size {
-            adj[self.edge_src[i]].push(self.edge_dst[i]);
-            adj[self.edge_dst[i]].push(self.edge_src[i]);
+/// What to do when an input matrix is not structurally symmetric.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AsymmetryPolicy {
+    /// Print a warning and symmetrize (default).
+    #[default]
+    Warn,
+    /// Refuse to load the matrix.
+    Error,
+}
+
+/// Which stored entries of a Matrix Market file become edges.
+///
+/// Symmetric files list each edge once, but the reader mirrors every entry,
+/// and general files often store both triangles; either way each edge
+/// would otherwise appear twice, doubling `edge_size` and the adjacency.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Triangle {
+    /// One edge per unordered node pair: the first of `(r, c)` and `(c, r)`
+    /// is kept, so entries without a transposed counterpart still count
+    /// once (default).
+    #[default]
+    Auto,
+    /// Only entries with `row < col`.
+    Upper,
+    /// Only entries with `row > col`.
+    Lower,
+    /// Every entry, i.e. both directions of each symmetric edge.
+    Both,
+}
+
+/// Read a Matrix Market file with its values as `f64`. `real` and
+/// `integer` files keep their values; pattern (and complex) files get 1.0
+/// for every entry.
+fn read_mtx_weighted(path: &Path) -> Result<sprs::TriMat<f64>> {
+    let mut header = String::new();
+    BufReader::new(File::open(path)?).read_line(&mut header)?;
+    let field = header.to_lowercase().split_whitespace().nth(3).unwrap_or_default().to_string();
+    let (shape, rows, cols, values) = match field.as_str() {
+        "real" => return Ok(read_matrix_market(path)?),
+        "integer" => {
+            let matrix: sprs::TriMat<i64> = read_matrix_market(path)?;
+            let values = matrix.data().iter().map(|&v| v as f64).collect();
+            (matrix.shape(), matrix.row_inds().to_vec(), matrix.col_inds().to_vec(), values)
         }
-        adj
+        _ => {
+            let matrix: sprs::TriMat<Pattern> = read_matrix_market(path)?;
+            (matrix.shape(), matrix.row_inds().to_vec(), matrix.col_inds().to_vec(), vec![1.0; matrix.nnz()])
+        }
+    };
+    Ok(sprs::TriMat::from_triplets(shape, rows, cols, values))
+}
+
+/// Count off-diagonal entries `(r, c)` whose transpose `(c, r)` is absent.
+fn count_unmatched_entries(rows: &[usize], cols: &[usize]) -> usize {
+    let entries: HashSet<(usize, usize)> = rows
+        .iter()
+        .zip(cols.iter())
+        .filter(|(r, c)| r != c)
+        .map(|(&r, &c)| (r, c))
+        .collect();
+    entries
+        .iter()
+        .filter(|&&(r, c)| !entries.contains(&(c, r)))
+        .count()
+}
+
+/// Start tags of `text` as `(name, attributes)`, where `attributes` is the
+/// raw text between the name and the closing `>`. Comments, processing
+/// instructions, declarations and end tags are skipped; element content is
+/// never looked at.
+fn xml_start_tags(text: &str) -> impl Iterator<Item = (&str, &str)> {
+    let mut rest = text;
+    std::iter::from_fn(move || loop {
+        let open = rest.find('<')?;
+        rest = &rest[open + 1..];
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let close = rest.find('>')?;
+        let tag = &rest[..close];
+        rest = &rest[close + 1..];
+        if tag.starts_with(['/', '?', '!']) {
+            continue;
+        }
+        let tag = tag.strip_suffix('/').unwrap_or(tag);
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        return Some((&tag[..name_end], &tag[name_end..]));
+    })
+}
+
+/// Value of attribute `name` in the attribute text of a start tag, with the
+/// predefined XML entities decoded.
+fn xml_attribute(attributes: &str, name: &str) -> Option<String> {
+    let mut rest = attributes;
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim();
+        let value = rest[eq + 1..].trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let end = value[1..].find(quote)? + 1;
+        if key == name {
+            return Some(
+                value[1..end]
+                    .replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&quot;", "\"")
+                    .replace("&apos;", "'")
+                    .replace("&amp;", "&"),
+            );
+        }
+        rest = &value[end + 1..];
     }
+    None
+}
 
-    pub fn calc_dist_matrix(&self) -> Vec<Vec<usize>> {
-        let adj = Self::calc_adj_matrix(self);
-        let n = adj.len();
-        let mut dist_matrix = vec![vec![usize::MAX; n]; n];
+/// Magic bytes at the start of a serialized `SgdParams` file.
+const PARAMS_MAGIC: &[u8; 4] = b"SGDP";
+const PARAMS_VERSION: u32 = 4;
 
-        // bfs
-        for i in 0..n {
-            let mut deq = VecDeque::new();
-            let mut seen = vec![false; n];
+impl SgdParams {
+    /// Serialize the precomputed parameters to a little-endian binary file.
+    ///
+    /// Layout: magic `SGDP`, version `u32`, then `u64` counts of etas, nodes
+    /// and pairs, the boundary (`u32` mode, `f64` width, `f64` height, and for
+    /// polygons a `u64` vertex count followed by the vertices), then the
+    /// etas, positions and pairs as `f64`/`u64` values. Version 2 appends
+    /// `positions_z` as a `u64` count (0 or the node count) and its values;
+    /// version 3 appends `fixed` the same way with one byte per node, and
+    /// version 4 `anchors` after it.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_to(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
 
-            deq.push_back(i);
-            seen[i] = true;
-            dist_matrix[i][i] = 0;
+    /// Serialize in the [`SgdParams::save`] format to any writer.
+    pub fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        w.write_all(PARAMS_MAGIC)?;
+        w.write_all(&PARAMS_VERSION.to_le_bytes())?;
+        w.write_all(&(self.etas.len() as u64).to_le_bytes())?;
+        w.write_all(&(self.positions.len() as u64).to_le_bytes())?;
+        w.write_all(&(self.pairs.len() as u64).to_le_bytes())?;
 
-            while let Some(v) = deq.pop_front() {
-                for &u in &adj[v] {
-                    if seen[u] {
-                        continue;
-                    }
-                    deq.push_back(u);
-                    seen[u] = true;
-                    dist_matrix[i][u] = dist_matrix[i][v] + 1;
+        let (mode, width, height) = match self.boundary {
+            BoundaryMode::Open => (0u32, 0.0, 0.0),
+            BoundaryMode::Toroidal { width, height } => (1u32, width, height),
+            BoundaryMode::Polygon(_) => (2u32, 0.0, 0.0),
+        };
+        w.write_all(&mode.to_le_bytes())?;
+        w.write_all(&width.to_le_bytes())?;
+        w.write_all(&height.to_le_bytes())?;
+        if let BoundaryMode::Polygon(vertices) = &self.boundary {
+            w.write_all(&(vertices.len() as u64).to_le_bytes())?;
+            for v in vertices {
+                w.write_all(&v[0].to_le_bytes())?;
+                w.write_all(&v[1].to_le_bytes())?;
+            }
+        }
+
+        for eta in &self.etas {
+            w.write_all(&eta.to_le_bytes())?;
+        }
+        for p in &self.positions {
+            w.write_all(&p[0].to_le_bytes())?;
+            w.write_all(&p[1].to_le_bytes())?;
+        }
+        for pair in &self.pairs {
+            w.write_all(&(pair.u as u64).to_le_bytes())?;
+            w.write_all(&(pair.v as u64).to_le_bytes())?;
+            w.write_all(&pair.dij.to_le_bytes())?;
+            w.write_all(&pair.wij.to_le_bytes())?;
+        }
+        w.write_all(&(self.positions_z.len() as u64).to_le_bytes())?;
+        for z in &self.positions_z {
+            w.write_all(&z.to_le_bytes())?;
+        }
+        w.write_all(&(self.fixed.len() as u64).to_le_bytes())?;
+        w.write_all(&self.fixed.iter().map(|&f| f as u8).collect::<Vec<_>>())?;
+        w.write_all(&(self.anchors.len() as u64).to_le_bytes())?;
+        w.write_all(&self.anchors.iter().map(|&a| a as u8).collect::<Vec<_>>())?;
+        Ok(())
+    }
+
+    /// Add a weak pair between every two nodes in different connected
+    /// components (`components` as returned by [`Graph::components`]).
+    ///
+    /// Unreachable pairs get no constraint from `calc_edge_info`, so the
+    /// components of a disconnected graph drift apart freely. The added pairs
+    /// use one more than the largest existing `dij` (the diameter of the
+    /// widest component, after any distance transform) as a surrogate
+    /// target, so no two components are asked to sit closer than nodes of
+    /// the same component. Their weight is [`INTER_COMPONENT_WEIGHT`] times
+    /// `1 / dij²`: a component cannot match one fixed distance to every node
+    /// of another, and at full weight these pairs visibly bend the components
+    /// themselves. Pairs are re-sorted by `(u, v)`. The new weights lie below
+    /// the old `wmin`, so recompute `etas` from the new weight range
+    /// afterwards or the added pairs barely move. Returns the number of pairs
+    /// added.
+    pub fn connect_components(&mut self, components: &[usize]) -> usize {
+        let dij = self.pairs.iter().map(|p| p.dij).fold(0.0, f64::max) + 1.0;
+        let wij = INTER_COMPONENT_WEIGHT / (dij * dij);
+        let before = self.pairs.len();
+        for u in 0..components.len() {
+            for v in u + 1..components.len() {
+                if components[u] != components[v] {
+                    self.pairs.push(EdgeInfo { u, v, dij, wij });
                 }
             }
         }
-        dist_matrix
+        self.pairs.sort_by_key(|p| (p.u, p.v));
+        self.pairs.len() - before
     }
 
-    pub fn calc_edge_info(&self, dist: &[Vec<usize>]) -> (Vec<EdgeInfo>, f64, f64) {
-        let mut pairs = Vec::new();
-        let mut dmin: f64 = f64::INFINITY;
-        let mut dmax: f64 = 0.0;
+    /// Sort the pairs by decreasing `dij` (ties keep their order) and fill
+    /// `active_pairs` according to `growth`.
+    pub fn apply_pair_growth(&mut self, growth: PairGrowth) {
+        self.pairs.sort_by(|a, b| b.dij.total_cmp(&a.dij));
+        let (total, iterations) = (self.pairs.len(), self.etas.len());
+        let start = growth.start_fraction.clamp(0.0, 1.0);
+        let full_at = growth.full_at.max(0.0) * iterations as f64;
+        self.active_pairs = (0..iterations)
+            .map(|t| {
+                let progress = if full_at > 0.0 { (t as f64 / full_at).min(1.0) } else { 1.0 };
+                let fraction = start + (1.0 - start) * progress;
+                ((fraction * total as f64).ceil() as usize).clamp(total.min(1), total)
+            })
+            .collect();
+    }
 
-        for u in 0..dist.len() {
-            for v in 0..dist[u].len() {
-                if u >= v {
-                    continue;
-                }
+    /// Reorder the pairs by their [`color_pairs`] color and fill
+    /// `color_offsets`, so the GPU backends can update one color per pass
+    /// without locks. Returns the number of colors.
+    pub fn apply_pair_coloring(&mut self) -> usize {
+        let colors = color_pairs(&self.pairs, self.positions.len());
+        let mut pairs = Vec::with_capacity(self.pairs.len());
+        self.color_offsets = vec![0];
+        for color in &colors {
+            pairs.extend(color.iter().map(|&k| self.pairs[k]));
+            self.color_offsets.push(pairs.len());
+        }
+        self.pairs = pairs;
+        colors.len()
+    }
 
-                // Skip unreachable nodes (distance == usize::MAX)
-                if dist[u][v] == usize::MAX {
-                    continue;
-                }
+    /// Move the nodes in `pins` to the given x/y coordinates and keep them
+    /// there: they are marked in `fixed` and no pair moves them.
+    pub fn pin(&mut self, pins: &HashMap<usize, [f64; 2]>) -> Result<()> {
+        let n = self.positions.len();
+        if self.fixed.is_empty() {
+            self.fixed = vec![false; n];
+        }
+        for (&node, &p) in pins {
+            if node >= n {
+                anyhow::bail!("pinned node {} out of range for {} nodes", node, n);
+            }
+            if !(p[0].is_finite() && p[1].is_finite()) {
+                anyhow::bail!("pinned node {} has non-finite position {:?}", node, p);
+            }
+            self.positions[node] = p;
+            self.fixed[node] = true;
+        }
+        Ok(())
+    }
 
-                let dij = dist[u][v] as f64;
-                if dij <= 0.0 {
-                    continue;
+    /// Per-node level for the kernels: [`LEVEL_FIXED`] for `fixed` nodes, 1
+    /// for `anchors`, 0 otherwise. Of the two nodes of a pair at different
+    /// levels only the lower one moves.
+    pub fn node_levels(&self) -> Vec<u32> {
+        (0..self.positions.len())
+            .map(|k| {
+                if self.fixed.get(k).copied().unwrap_or(false) {
+                    LEVEL_FIXED
+                } else {
+                    self.anchors.get(k).copied().unwrap_or(false) as u32
                 }
+            })
+            .collect()
+    }
+
+    /// Coordinates per node: 3 once `positions_z` is set, 2 otherwise.
+    pub fn dims(&self) -> usize {
+        if self.positions_z.is_empty() { 2 } else { 3 }
+    }
+
+    /// Positions as `[x, y, z]` (`z = 0` for a 2D parameter set).
+    pub fn positions_3d(&self) -> Vec<[f64; 3]> {
+        self.positions
+            .iter()
+            .enumerate()
+            .map(|(i, p)| [p[0], p[1], self.positions_z.get(i).copied().unwrap_or(0.0)])
+            .collect()
+    }
 
-                let wij = 1.0 / (dij * dij);
-                pairs.push(EdgeInfo { u, v, dij, wij });
+    /// Generator for the CPU-side shuffles: seeded from [`SgdParams::seed`]
+    /// when set, so two runs of the same params take the same pair order.
+    pub fn rng(&self) -> StdRng {
+        seeded_rng(self.seed)
+    }
 
-                dmin = dmin.min(dij);
-                dmax = dmax.max(dij);
+    /// Check the parameters before they are uploaded.
+    ///
+    /// Degenerate inputs (tiny graphs, one pair, extreme weights) are where a
+    /// NaN or infinite step size or distance would first appear; on the GPU it
+    /// silently spreads to every position, so refuse to run instead.
+    pub fn validate(&self) -> Result<()> {
+        let n = self.positions.len();
+        // Without any eta a backend would return the initial positions as if
+        // the layout had converged
+        anyhow::ensure!(!self.etas.is_empty(), "no learning rates given: etas is empty, nothing to iterate");
+        if let Some((t, eta)) = self.etas.iter().enumerate().find(|(_, e)| !(e.is_finite() && **e > 0.0)) {
+            anyhow::bail!("learning rate {} at iteration {} is not a positive finite number", eta, t);
+        }
+        if let Some(p) = self.positions.iter().find(|p| !(p[0].is_finite() && p[1].is_finite())) {
+            anyhow::bail!("initial position {:?} is not finite", p);
+        }
+        if !self.positions_z.is_empty() {
+            if self.positions_z.len() != n {
+                anyhow::bail!("{} z coordinates for {} nodes", self.positions_z.len(), n);
+            }
+            if let Some(z) = self.positions_z.iter().find(|z| !z.is_finite()) {
+                anyhow::bail!("initial z coordinate {} is not finite", z);
             }
         }
+        if !self.active_pairs.is_empty() {
+            if self.active_pairs.len() != self.etas.len() {
+                anyhow::bail!(
+                    "{} active pair counts for {} iterations",
+                    self.active_pairs.len(),
+                    self.etas.len()
+                );
+            }
+            if let Some(&k) = self.active_pairs.iter().find(|&&k| k > self.pairs.len()) {
+                anyhow::bail!("{} active pairs requested but only {} exist", k, self.pairs.len());
+            }
+        }
+        if !self.fixed.is_empty() && self.fixed.len() != n {
+            anyhow::bail!("{} fixed flags for {} nodes", self.fixed.len(), n);
+        }
+        if !self.anchors.is_empty() && self.anchors.len() != n {
+            anyhow::bail!("{} anchor flags for {} nodes", self.anchors.len(), n);
+        }
+        if !self.color_offsets.is_empty() {
+            if !self.active_pairs.is_empty() {
+                anyhow::bail!("pair coloring cannot be combined with pair growth");
+            }
+            let bounds_ok = self.color_offsets.first() == Some(&0)
+                && self.color_offsets.last() == Some(&self.pairs.len())
+                && self.color_offsets.windows(2).all(|w| w[0] <= w[1]);
+            if !bounds_ok {
+                anyhow::bail!("color offsets do not cover the {} pairs", self.pairs.len());
+            }
+        }
+        for pair in &self.pairs {
+            if pair.u >= n || pair.v >= n {
+                anyhow::bail!("pair ({}, {}) out of range for {} nodes", pair.u, pair.v, n);
+            }
+            if !(pair.dij.is_finite() && pair.dij > 0.0 && pair.wij.is_finite() && pair.wij >= 0.0) {
+                anyhow::bail!(
+                    "pair ({}, {}) has invalid dij {} or wij {}",
+                    pair.u,
+                    pair.v,
+                    pair.dij,
+                    pair.wij
+                );
+            }
+        }
+        Ok(())
+    }
 
-        let wmin = 1.0 / (dmax * dmax);
-        let wmax = 1.0 / (dmin * dmin);
-
-        (pairs, wmin, wmax)
+    /// Load parameters written by [`SgdParams::save`].
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_bytes(&fs::read(path)?, &path.display().to_string())
     }
 
-    /// Precompute SGD parameters
-    pub fn prepare_sgd_params(
-        &self,
-        iterations: usize,
-        epsilon: f64,
-        center: bool,
-    ) -> SgdParams {
-        let dist = self.calc_dist_matrix();
-        let (pairs, wmin, wmax) = self.calc_edge_info(&dist);
+    /// Parse the [`SgdParams::save`] format; `source` names the input in errors.
+    pub fn from_bytes(bytes: &[u8], source: &str) -> Result<Self> {
+        let mut r = ByteReader::new(bytes);
 
-        let etas = calc_learning_rate(iterations, wmin, wmax, epsilon);
+        if r.take(4)? != PARAMS_MAGIC {
+            anyhow::bail!("{}: not an SGD params file", source);
+        }
+        let version = r.u32()?;
+        if !(1..=PARAMS_VERSION).contains(&version) {
+            anyhow::bail!("{}: unsupported params version {}", source, version);
+        }
+        let num_etas = r.u64()? as usize;
+        let num_nodes = r.u64()? as usize;
+        let num_pairs = r.u64()? as usize;
 
-        let positions = init_positions_random(self.node_size, center);
+        let mode = r.u32()?;
+        let width = r.f64()?;
+        let height = r.f64()?;
+        let boundary = match mode {
+            0 => BoundaryMode::Open,
+            1 => BoundaryMode::Toroidal { width, height },
+            2 => {
+                let count = r.u64()? as usize;
+                let vertices = (0..count)
+                    .map(|_| Ok([r.f64()?, r.f64()?]))
+                    .collect::<Result<Vec<_>>>()?;
+                BoundaryMode::Polygon(vertices)
+            }
+            _ => anyhow::bail!("{}: unknown boundary mode {}", source, mode),
+        };
 
-        SgdParams {
+        let etas = (0..num_etas).map(|_| r.f64()).collect::<Result<Vec<_>>>()?;
+        let positions = (0..num_nodes)
+            .map(|_| Ok([r.f64()?, r.f64()?]))
+            .collect::<Result<Vec<_>>>()?;
+        let pairs = (0..num_pairs)
+            .map(|_| {
+                let u = r.u64()? as usize;
+                let v = r.u64()? as usize;
+                let dij = r.f64()?;
+                let wij = r.f64()?;
+                if u >= num_nodes || v >= num_nodes {
+                    anyhow::bail!("{}: pair ({}, {}) out of range", source, u, v);
+                }
+                Ok(EdgeInfo { u, v, dij, wij })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let positions_z = if version >= 2 {
+            let count = r.u64()? as usize;
+            if count != 0 && count != num_nodes {
+                anyhow::bail!("{}: {} z coordinates for {} nodes", source, count, num_nodes);
+            }
+            (0..count).map(|_| r.f64()).collect::<Result<Vec<_>>>()?
+        } else {
+            Vec::new()
+        };
+        let fixed = if version >= 3 {
+            let count = r.u64()? as usize;
+            if count != 0 && count != num_nodes {
+                anyhow::bail!("{}: {} fixed flags for {} nodes", source, count, num_nodes);
+            }
+            r.take(count)?.iter().map(|&b| b != 0).collect()
+        } else {
+            Vec::new()
+        };
+        let anchors = if version >= 4 {
+            let count = r.u64()? as usize;
+            if count != 0 && count != num_nodes {
+                anyhow::bail!("{}: {} anchor flags for {} nodes", source, count, num_nodes);
+            }
+            r.take(count)?.iter().map(|&b| b != 0).collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok(SgdParams {
             etas,
             positions,
             pairs,
-        }
+            boundary,
+            active_pairs: Vec::new(),
+            positions_z,
+            color_offsets: Vec::new(),
+            fixed,
+            anchors,
+            seed: None,
+        })
     }
 }
 
-pub fn calc_learning_rate(tmax: usize, wmin: f64, wmax: f64, eps: f64) -> Vec<f64> {
-    let eta_max = 1.0 / wmin;
-    let eta_min = eps / wmax;
-    let lamb = (eta_max / eta_min).ln() / (tmax - 1) as f64;
+/// Magic bytes at the start of a cached distance matrix file.
+const DIST_CACHE_MAGIC: &[u8; 4] = b"SGDD";
+const DIST_CACHE_VERSION: u32 = 1;
 
-    let etas: Vec<f64> = (0..tmax)
-        .map(|t| eta_max * (-lamb * t as f64).exp())
-        .collect();
+/// Little-endian cursor over a byte buffer.
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        ByteReader { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let chunk = self
+            .bytes
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| anyhow::anyhow!("unexpected end of file"))?;
+        self.pos += n;
+        Ok(chunk)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
 
-    etas
+    fn f64(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into()?))
+    }
 }
 
-pub fn init_positions_random(n_nodes: usize, center: bool) -> Vec<[f64; 2]> {
-    let mut rng = rand::rng();
+impl Graph {
+    pub fn from_mtx(path: &Path) -> Result<Self> {
+        Self::from_mtx_with_policy(path, AsymmetryPolicy::Warn)
+    }
 
-    // Random coordinates in the range [0, 1)
-    let mut positions: Vec<[f64; 2]> = (0..n_nodes)
-        .map(|_| [rng.random::<f64>(), rng.random::<f64>()])
-        .collect();
+    /// Load a Matrix Market file, checking that its structure is symmetric.
+    ///
+    /// The layout treats every entry as an undirected edge, so a structurally
+    /// non-symmetric matrix is implicitly symmetrized. `policy` decides whether
+    /// that is only reported or rejected.
+    pub fn from_mtx_with_policy(path: &Path, policy: AsymmetryPolicy) -> Result<Self> {
+        Self::from_mtx_with_options(path, policy, Triangle::default())
+    }
 
-    // centering if center is true
-    if center {
-        // Calc the center of the positions
-        let sum_x: f64 = positions.iter().map(|p| p[0]).sum();
-        let sum_y: f64 = positions.iter().map(|p| p[1]).sum();
-        let mean_x = sum_x / n_nodes as f64;
-        let mean_y = sum_y / n_nodes as f64;
+    /// [`from_mtx_with_policy`](Self::from_mtx_with_policy), keeping only the
+    /// entries selected by `triangle`.
+    pub fn from_mtx_with_options(path: &Path, policy: AsymmetryPolicy, triangle: Triangle) -> Result<Self> {
+        let matrix = read_mtx_weighted(path)?;
+
+        let unmatched = count_unmatched_entries(matrix.row_inds(), matrix.col_inds());
+        if unmatched > 0 {
+            let message = format!(
+                "{}: matrix is not structurally symmetric ({} off-diagonal entries have no transposed counterpart); edges are symmetrized and treated as undirected",
+                path.display(),
+                unmatched
+            );
+            match policy {
+                AsymmetryPolicy::Warn => println!("Warning: {}", message),
+                AsymmetryPolicy::Error => anyhow::bail!(message),
+            }
+        }
 
-        // move to the center
-        for pos in &mut positions {
-            pos[0] -= mean_x;
-            pos[1] -= mean_y;
+        let node_size: usize = matrix.rows();
+        
+        // Filter out self-loops and the entries `triangle` drops
+        let mut edge_src = Vec::new();
+        let mut edge_dst = Vec::new();
+        let mut edge_weight = Vec::new();
+        let mut seen = HashSet::new();
+        
+        let entries = matrix.row_inds().iter().zip(matrix.col_inds()).zip(matrix.data());
+        for ((&row, &col), &value) in entries {
+            let keep = match triangle {
+                Triangle::Auto => seen.insert((row.min(col), row.max(col))),
+                Triangle::Upper => row < col,
+                Triangle::Lower => row > col,
+                Triangle::Both => true,
+            };
+            if row != col && keep {
+                edge_src.push(row);
+                edge_dst.push(col);
+                edge_weight.push(value);
+            }
+        }
+        
+        let edge_size = edge_src.len();
+
+        // The header may declare more rows than the edges actually reference.
+        // Those trailing nodes have no constraints and would only float at
+        // their random initial positions, so drop them here.
+        let referenced = edge_src
+            .iter()
+            .chain(edge_dst.iter())
+            .max()
+            .map_or(node_size, |&m| m + 1);
+        let node_size = if referenced < node_size {
+            println!(
+                "Warning: matrix declares {} nodes but edges only reference {}; ignoring {} trailing unreferenced nodes",
+                node_size,
+                referenced,
+                node_size - referenced
+            );
+            referenced
+        } else {
+            node_size
+        };
+
+        let graph = Graph {
+            node_size,
+            edge_size,
+            edge_src,
+            edge_dst,
+            edge_layer: vec![0; edge_size],
+            edge_weight,
+        };
+        let invalid = graph.invalid_edge_weights();
+        if invalid > 0 {
+            println!(
+                "Warning: {}: {} of {} edge weights are not positive and finite; distances use hop counts instead",
+                path.display(),
+                invalid,
+                edge_size
+            );
         }
+        Ok(graph)
     }
 
-    positions
+    /// Load a whitespace-separated edge list (`src dst` per line; further
+    /// columns such as weights are ignored). Blank lines and lines starting
+    /// with `#` are skipped. Indices are 1-based when `one_indexed` is set,
+    /// 0-based otherwise; `node_size` is the largest index plus one. As with
+    /// [`from_mtx`](Self::from_mtx), self-loops are dropped and each node pair
+    /// becomes one edge.
+    pub fn from_edge_list(path: &Path, one_indexed: bool) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let offset = usize::from(one_indexed);
+        let mut edge_src = Vec::new();
+        let mut edge_dst = Vec::new();
+        let mut seen = HashSet::new();
+        let mut node_size = 0;
+        for (lineno, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let (Some(src), Some(dst)) = (tokens.next(), tokens.next()) else {
+                anyhow::bail!("{}:{}: expected `src dst`, got {:?}", path.display(), lineno + 1, line);
+            };
+            let mut index = |token: &str| -> Result<usize> {
+                let raw: usize = token.parse().map_err(|e| {
+                    anyhow::anyhow!("{}:{}: invalid node index {:?}: {}", path.display(), lineno + 1, token, e)
+                })?;
+                let index = raw.checked_sub(offset).ok_or_else(|| {
+                    anyhow::anyhow!("{}:{}: node index 0 in a 1-indexed edge list", path.display(), lineno + 1)
+                })?;
+                node_size = node_size.max(index + 1);
+                Ok(index)
+            };
+            let (u, v) = (index(src)?, index(dst)?);
+            if u != v && seen.insert((u.min(v), u.max(v))) {
+                edge_src.push(u);
+                edge_dst.push(v);
+            }
+        }
+        Self::from_edges(node_size, edge_src, edge_dst)
+    }
+
+    /// Load a GraphML file (e.g. exported by Gephi or NetworkX) and return
+    /// the graph with the original node ids, `ids[i]` being node `i`.
+    ///
+    /// Nodes are numbered in the order of their `<node>` elements; edges
+    /// referencing an undeclared id add that node. Directed and undirected
+    /// edges are both read as undirected edges, with self-loops dropped and
+    /// each node pair kept once as in [`from_mtx`](Self::from_mtx). `<data>`
+    /// elements, keys, ports and other attributes are ignored.
+    pub fn from_graphml(path: &Path) -> Result<(Self, Vec<String>)> {
+        Self::from_graphml_str(&fs::read_to_string(path)?, &path.display().to_string())
+    }
+
+    /// Parse GraphML text as [`from_graphml`](Self::from_graphml) does;
+    /// `source` names the input in errors.
+    pub fn from_graphml_str(text: &str, source: &str) -> Result<(Self, Vec<String>)> {
+        let mut ids: Vec<String> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        let mut node = |id: String, ids: &mut Vec<String>| {
+            *index.entry(id.clone()).or_insert_with(|| {
+                ids.push(id);
+                ids.len() - 1
+            })
+        };
+        let mut edge_src = Vec::new();
+        let mut edge_dst = Vec::new();
+        let mut seen = HashSet::new();
+        for (name, attributes) in xml_start_tags(text) {
+            match name {
+                "node" => {
+                    let id = xml_attribute(attributes, "id")
+                        .ok_or_else(|| anyhow::anyhow!("{}: <node> without an id", source))?;
+                    node(id, &mut ids);
+                }
+                "edge" => {
+                    let (Some(source), Some(target)) =
+                        (xml_attribute(attributes, "source"), xml_attribute(attributes, "target"))
+                    else {
+                        anyhow::bail!("{}: <edge> without source and target", source);
+                    };
+                    let (u, v) = (node(source, &mut ids), node(target, &mut ids));
+                    if u != v && seen.insert((u.min(v), u.max(v))) {
+                        edge_src.push(u);
+                        edge_dst.push(v);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if ids.is_empty() {
+            anyhow::bail!("{}: no <node> elements found", source);
+        }
+        let graph = Self::from_edges(ids.len(), edge_src, edge_dst)?;
+        Ok((graph, ids))
+    }
+
+    /// Build a graph from in-memory edge arrays (`edge_src[i]`-`edge_dst[i]`
+    /// is edge `i`, all in layer 0).
+    ///
+    /// Fails if the arrays differ in length or an endpoint is not below
+    /// `node_size`.
+    pub fn from_edges(node_size: usize, edge_src: Vec<usize>, edge_dst: Vec<usize>) -> Result<Self> {
+        if edge_src.len() != edge_dst.len() {
+            anyhow::bail!(
+                "edge_src has {} entries but edge_dst has {}",
+                edge_src.len(),
+                edge_dst.len()
+            );
+        }
+        if let Some((i, (u, v))) = edge_src
+            .iter()
+            .zip(&edge_dst)
+            .enumerate()
+            .find(|(_, (&u, &v))| u >= node_size || v >= node_size)
+        {
+            anyhow::bail!("edge {} ({}, {}) out of range for {} nodes", i, u, v, node_size);
+        }
+
+        let edge_size = edge_src.len();
+        Ok(Graph {
+            node_size,
+            edge_size,
+            edge_src,
+            edge_dst,
+            edge_layer: vec![0; edge_size],
+            edge_weight: vec![1.0; edge_size],
+        })
+    }
+
+    /// Rebuild the graph edges from precomputed pairs.
+    ///
+    /// For hop distances the adjacent node pairs are exactly the pairs with
+    /// `dij == 1`, so this recovers the (deduplicated) edge list when only an
+    /// `SgdParams` file is available.
+    pub fn from_unit_pairs(node_size: usize, pairs: &[EdgeInfo]) -> Self {
+        let (edge_src, edge_dst): (Vec<usize>, Vec<usize>) = pairs
+            .iter()
+            .filter(|p| p.dij == 1.0)
+            .map(|p| (p.u, p.v))
+            .unzip();
+
+        Graph {
+            node_size,
+            edge_size: edge_src.len(),
+            edge_layer: vec![0; edge_src.len()],
+            edge_weight: vec![1.0; edge_src.len()],
+            edge_src,
+            edge_dst,
+        }
+    }
+
+    /// Merge graphs over the same node ids into one multiplex graph.
+    ///
+    /// Edges of `layers[k]` get layer id `k`; the node count is the largest
+    /// of the layers. Distances are computed on the union of all layers, and
+    /// `layer_pair_weights` turns per-layer weights into pair multipliers.
+    pub fn from_layers(layers: Vec<Graph>) -> Self {
+        let mut graph = Graph {
+            node_size: 0,
+            edge_size: 0,
+            edge_src: Vec::new(),
+            edge_dst: Vec::new(),
+            edge_layer: Vec::new(),
+            edge_weight: Vec::new(),
+        };
+        for (layer, g) in layers.into_iter().enumerate() {
+            graph.node_size = graph.node_size.max(g.node_size);
+            graph.edge_layer.extend(std::iter::repeat_n(layer, g.edge_size));
+            graph.edge_weight.extend(g.edge_weight);
+            graph.edge_src.extend(g.edge_src);
+            graph.edge_dst.extend(g.edge_dst);
+        }
+        graph.edge_size = graph.edge_src.len();
+        graph
+    }
+
+    /// Pair weight multipliers from per-layer weights, for
+    /// `prepare_sgd_params_weighted`.
+    ///
+    /// An edge gets the sum of the weights of the layers it appears in, so a
+    /// relation present in several layers pulls harder. Each layer counts an
+    /// undirected edge once even if both directions are stored.
+    pub fn layer_pair_weights(&self, layer_weights: &[f64]) -> HashMap<(usize, usize), f64> {
+        let mut seen = HashSet::new();
+        let mut weights = HashMap::new();
+        for i in 0..self.edge_size {
+            let (u, v) = (self.edge_src[i], self.edge_dst[i]);
+            let layer = self.edge_layer[i];
+            let key = (u.min(v), u.max(v));
+            if !seen.insert((layer, key)) {
+                continue;
+            }
+            let w = layer_weights.get(layer).copied().unwrap_or(1.0);
+            *weights.entry(key).or_insert(0.0) += w;
+        }
+        weights
+    }
+
+    fn calc_adj_matrix(&self) -> Vec<Vec<usize>> {
+        let mut adj = vec![Vec::new(); self.node_size];
+        for i in 0..self.edge_size {
+            adj[self.edge_src[i]].push(self.edge_dst[i]);
+            adj[self.edge_dst[i]].push(self.edge_src[i]);
+        }
+        adj
+    }
+
+    /// All-pairs hop distances (`usize::MAX` for unreachable pairs), one BFS
+    /// per source. With the `parallel` feature the sources are spread over
+    /// rayon's thread pool; every row is independent, so the result is the
+    /// same either way.
+    pub fn calc_dist_matrix(&self) -> Vec<Vec<usize>> {
+        let adj = Self::calc_adj_matrix(self);
+        let n = adj.len();
+        let mut dist_matrix = vec![vec![usize::MAX; n]; n];
+
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            dist_matrix
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, row)| bfs_row(&adj, i, row));
+        }
+        #[cfg(not(feature = "parallel"))]
+        for (i, row) in dist_matrix.iter_mut().enumerate() {
+            bfs_row(&adj, i, row);
+        }
+        dist_matrix
+    }
+
+    /// `(neighbour, edge_weight)` lists per node, both directions.
+    fn calc_weighted_adj(&self) -> Vec<Vec<(usize, f64)>> {
+        let mut adj = vec![Vec::new(); self.node_size];
+        for i in 0..self.edge_size {
+            let (u, v, w) = (self.edge_src[i], self.edge_dst[i], self.edge_weight[i]);
+            adj[u].push((v, w));
+            adj[v].push((u, w));
+        }
+        adj
+    }
+
+    /// Whether the edges carry target lengths worth a weighted shortest-path
+    /// search: some `edge_weight` differs from 1 and all are positive and
+    /// finite (Dijkstra cannot use the others, so such graphs keep hop
+    /// distances).
+    pub fn has_edge_weights(&self) -> bool {
+        self.edge_weight.iter().any(|&w| w != 1.0) && self.invalid_edge_weights() == 0
+    }
+
+    /// Number of edge weights Dijkstra cannot use: zero, negative or not
+    /// finite.
+    pub fn invalid_edge_weights(&self) -> usize {
+        self.edge_weight.iter().filter(|&&w| !(w > 0.0 && w.is_finite())).count()
+    }
+
+    /// All-pairs shortest-path lengths over `edge_weight`
+    /// (`f64::INFINITY` for unreachable pairs), one Dijkstra run per source,
+    /// spread over rayon's thread pool with the `parallel` feature like
+    /// [`Graph::calc_dist_matrix`]. Expects positive weights (see
+    /// [`Graph::has_edge_weights`]).
+    pub fn calc_dist_matrix_weighted(&self) -> Vec<Vec<f64>> {
+        let adj = self.calc_weighted_adj();
+        let n = adj.len();
+        let mut dist_matrix = vec![vec![f64::INFINITY; n]; n];
+
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            dist_matrix
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, row)| dijkstra_row(&adj, i, row));
+        }
+        #[cfg(not(feature = "parallel"))]
+        for (i, row) in dist_matrix.iter_mut().enumerate() {
+            dijkstra_row(&adj, i, row);
+        }
+        dist_matrix
+    }
+
+    /// 64-bit FNV-1a hash of the node count and the edge structure.
+    ///
+    /// Edges are hashed as a sorted, de-duplicated list of unordered
+    /// `(min, max)` pairs, so edge order, direction and duplicate entries do
+    /// not change the hash. The hash is structural, not an isomorphism
+    /// invariant: the same graph with its nodes renumbered hashes differently,
+    /// which is what the distance cache needs since it stores distances by
+    /// node id.
+    pub fn structural_hash(&self) -> u64 {
+        let mut edges: Vec<(usize, usize)> = self
+            .edge_src
+            .iter()
+            .zip(&self.edge_dst)
+            .map(|(&u, &v)| (u.min(v), u.max(v)))
+            .collect();
+        edges.sort_unstable();
+        edges.dedup();
+
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut feed = |x: u64| {
+            for byte in x.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        feed(self.node_size as u64);
+        for (u, v) in edges {
+            feed(u as u64);
+            feed(v as u64);
+        }
+        hash
+    }
+
+    /// Copy of the graph with every edge written as `(min, max)`, sorted and
+    /// deduplicated. A pair present on several layers keeps the lowest layer.
+    /// Meant for output only, so files don't depend on input edge order.
+    pub fn with_sorted_edges(&self) -> Graph {
+        let mut edges: Vec<(usize, usize, usize, f64)> = (0..self.edge_size)
+            .map(|i| {
+                let (u, v) = (self.edge_src[i], self.edge_dst[i]);
+                (u.min(v), u.max(v), self.edge_layer[i], self.edge_weight[i])
+            })
+            .collect();
+        edges.sort_unstable_by_key(|e| (e.0, e.1, e.2));
+        edges.dedup_by_key(|e| (e.0, e.1));
+        Graph {
+            node_size: self.node_size,
+            edge_size: edges.len(),
+            edge_src: edges.iter().map(|e| e.0).collect(),
+            edge_dst: edges.iter().map(|e| e.1).collect(),
+            edge_layer: edges.iter().map(|e| e.2).collect(),
+            edge_weight: edges.iter().map(|e| e.3).collect(),
+        }
+    }
+
+    /// Distance matrix, reusing `{cache_dir}/{structural hash}.dist` when it
+    /// exists and writing it after the BFS otherwise.
+    ///
+    /// Graphs with the same [`Graph::structural_hash`] share one cache file,
+    /// so duplicate inputs in a batch only run the BFS once.
+    pub fn calc_dist_matrix_cached(&self, cache_dir: &Path) -> Result<Vec<Vec<usize>>> {
+        let path = cache_dir.join(format!("{:016x}.dist", self.structural_hash()));
+        if path.exists() {
+            let dist = load_dist_matrix(&path)?;
+            if dist.len() == self.node_size {
+                println!("Distance matrix loaded from cache {}", path.display());
+                return Ok(dist);
+            }
+            println!("Warning: ignoring cache {} (node count mismatch)", path.display());
+        }
+
+        let dist = self.calc_dist_matrix();
+        fs::create_dir_all(cache_dir)?;
+        save_dist_matrix(&path, &dist)?;
+        println!("Distance matrix cached to {}", path.display());
+        Ok(dist)
+    }
+
+    /// Detect a row-major lattice numbering: returns the row width `W` when
+    /// at least 90% of the distinct edges join `i` to `i + 1` (within a row)
+    /// or `i + W`.
+    ///
+    /// Only the numbering is checked, so a mesh whose nodes are numbered in
+    /// some other order is not detected; pass the width explicitly then.
+    pub fn detect_grid_width(&self) -> Option<usize> {
+        let edges: HashSet<(usize, usize)> = self
+            .edge_src
+            .iter()
+            .zip(&self.edge_dst)
+            .filter(|(u, v)| u != v)
+            .map(|(&u, &v)| (u.min(v), u.max(v)))
+            .collect();
+        if edges.is_empty() {
+            return None;
+        }
+        let mut counts: HashMap<usize, usize> = HashMap::new();
+        for &(u, v) in &edges {
+            if v - u > 1 {
+                *counts.entry(v - u).or_insert(0) += 1;
+            }
+        }
+        let (&width, _) = counts.iter().max_by_key(|&(&d, &c)| (c, std::cmp::Reverse(d)))?;
+        let matching = edges
+            .iter()
+            .filter(|&&(u, v)| v - u == width || (v - u == 1 && v % width != 0))
+            .count();
+        (matching * 10 >= edges.len() * 9).then_some(width)
+    }
+
+    /// Connected component id of every node.
+    ///
+    /// Components are numbered from 0 in order of their lowest node id.
+    pub fn components(&self) -> Vec<usize> {
+        let adj = self.calc_adj_matrix();
+        let mut labels = vec![usize::MAX; self.node_size];
+        let mut next = 0;
+        for root in 0..self.node_size {
+            if labels[root] != usize::MAX {
+                continue;
+            }
+            labels[root] = next;
+            let mut deq = VecDeque::from([root]);
+            while let Some(v) = deq.pop_front() {
+                for &u in &adj[v] {
+                    if labels[u] == usize::MAX {
+                        labels[u] = next;
+                        deq.push_back(u);
+                    }
+                }
+            }
+            next += 1;
+        }
+        labels
+    }
+
+    /// Largest finite hop distance between two nodes, i.e. the diameter of
+    /// the widest connected component.
+    pub fn diameter(&self) -> usize {
+        let adj = self.calc_adj_matrix();
+        fold_rows(
+            self.node_size,
+            |source, row| bfs_row(&adj, source, row),
+            || 0,
+            |widest, _, row| *widest = row.iter().copied().filter(|&d| d != usize::MAX).fold(*widest, usize::max),
+            usize::max,
+        )
+    }
+
+    /// Build the pair constraints from a distance matrix of hop counts or
+    /// weighted path lengths.
+    ///
+    /// Pairs are returned sorted by `(u, v)`. `transform` maps each distance
+    /// to `dij` before the weight and the returned `(wmin, wmax)` are derived
+    /// from it.
+    pub fn calc_edge_info<D: MatrixDistance>(
+        &self,
+        dist: &[Vec<D>],
+        transform: DistanceTransform,
+    ) -> (Vec<EdgeInfo>, f64, f64) {
+        let mut acc = PairAccumulator::new();
+        for (u, row) in dist.iter().enumerate() {
+            acc.add_row(u, row, transform);
+        }
+        acc.finish(self)
+    }
+
+    /// [`Graph::calc_edge_info`] without the distance matrix: `fill_row`
+    /// writes the distances from one source into a row pre-set to
+    /// [`MatrixDistance::UNREACHABLE`], and the row is turned into pairs
+    /// before the next source runs.
+    ///
+    /// Only one row per thread is alive at a time, so peak memory is the pair
+    /// list itself (`n² / 2` pairs of 32 bytes) instead of that plus the
+    /// `n²` matrix entries.
+    pub fn calc_edge_info_streamed<D: MatrixDistance>(
+        &self,
+        fill_row: impl Fn(usize, &mut [D]) + Sync,
+        transform: DistanceTransform,
+    ) -> (Vec<EdgeInfo>, f64, f64) {
+        fold_rows(
+            self.node_size,
+            fill_row,
+            PairAccumulator::new,
+            |acc, u, row| acc.add_row(u, row, transform),
+            PairAccumulator::merge,
+        )
+        .finish(self)
+    }
+
+    /// Precompute SGD parameters
+    pub fn prepare_sgd_params(
+        &self,
+        iterations: usize,
+        epsilon: f64,
+        center: bool,
+    ) -> SgdParams {
+        self.prepare_sgd_params_weighted(iterations, epsilon, center, &HashMap::new())
+    }
+
+    /// Precompute SGD parameters, scaling `wij` of selected pairs.
+    ///
+    /// `pair_weights` maps an unordered node pair to a multiplier applied to
+    /// that pair's `wij` after `calc_edge_info`. The learning-rate bounds are
+    /// derived from the resulting weight range, so strongly emphasised pairs
+    /// still get a stable schedule.
+    ///
+    /// Target distances are weighted shortest paths when the graph
+    /// [has edge weights](Graph::has_edge_weights), hop counts otherwise.
+    pub fn prepare_sgd_params_weighted(
+        &self,
+        iterations: usize,
+        epsilon: f64,
+        center: bool,
+        pair_weights: &HashMap<(usize, usize), f64>,
+    ) -> SgdParams {
+        self.prepare_sgd_params_streamed(iterations, epsilon, center, pair_weights, DistanceTransform::Identity)
+    }
+
+    /// Precompute SGD parameters for all pairs, turning each BFS (or
+    /// Dijkstra, when the graph [has edge weights](Graph::has_edge_weights))
+    /// row into pairs right away instead of keeping the distance matrix
+    /// (see [`Graph::calc_edge_info_streamed`]).
+    pub fn prepare_sgd_params_streamed(
+        &self,
+        iterations: usize,
+        epsilon: f64,
+        center: bool,
+        pair_weights: &HashMap<(usize, usize), f64>,
+        transform: DistanceTransform,
+    ) -> SgdParams {
+        let (pairs, wmin, wmax) = if self.has_edge_weights() {
+            let adj = self.calc_weighted_adj();
+            self.calc_edge_info_streamed(|source, row| dijkstra_row(&adj, source, row), transform)
+        } else {
+            let adj = self.calc_adj_matrix();
+            self.calc_edge_info_streamed(|source, row| bfs_row(&adj, source, row), transform)
+        };
+        self.params_from_pairs(pairs, wmin, wmax, iterations, epsilon, center, pair_weights)
+    }
+
+    /// Precompute SGD parameters from the graph edges alone, without the
+    /// all-pairs BFS.
+    ///
+    /// Every distinct edge becomes a pair with `dij = 1`. On top of that each
+    /// node gets `repulsion_samples` random non-adjacent partners whose
+    /// target distance is the mean hop distance seen from one BFS root; with
+    /// their small `1 / dij²` weight they act as a weak global spreading
+    /// force. Memory is `O(m + n * repulsion_samples)` instead of `O(n²)`,
+    /// at the cost of a layout that only roughly follows graph distances.
+    /// The same `seed` always samples the same partners.
+    pub fn prepare_sgd_params_edge_only(
+        &self,
+        iterations: usize,
+        epsilon: f64,
+        center: bool,
+        repulsion_samples: usize,
+        pair_weights: &HashMap<(usize, usize), f64>,
+        seed: Option<u64>,
+    ) -> SgdParams {
+        let edges: HashSet<(usize, usize)> = self
+            .edge_src
+            .iter()
+            .zip(&self.edge_dst)
+            .filter(|(u, v)| u != v)
+            .map(|(&u, &v)| (u.min(v), u.max(v)))
+            .collect();
+        let mut pairs: Vec<EdgeInfo> = edges
+            .iter()
+            .map(|&(u, v)| EdgeInfo { u, v, dij: 1.0, wij: 1.0 })
+            .collect();
+
+        let n = self.node_size;
+        if n > 2 && repulsion_samples > 0 {
+            let dij = self.mean_hop_distance(0).max(2.0);
+            let mut rng = seeded_rng(seed);
+            let mut sampled = HashSet::new();
+            for u in 0..n {
+                let mut added = 0;
+                // Bounded retries so dense neighbourhoods cannot loop forever
+                for _ in 0..repulsion_samples * 4 {
+                    if added == repulsion_samples {
+                        break;
+                    }
+                    let v = rng.random_range(0..n);
+                    let key = (u.min(v), u.max(v));
+                    if u == v || edges.contains(&key) || !sampled.insert(key) {
+                        continue;
+                    }
+                    pairs.push(EdgeInfo { u: key.0, v: key.1, dij, wij: 1.0 / (dij * dij) });
+                    added += 1;
+                }
+            }
+        }
+        pairs.sort_by_key(|p| (p.u, p.v));
+
+        let (wmin, wmax) = if pair_weights.is_empty() {
+            let wmin = pairs.iter().map(|p| p.wij).fold(f64::INFINITY, f64::min);
+            let wmax = pairs.iter().map(|p| p.wij).fold(0.0, f64::max);
+            (wmin, wmax)
+        } else {
+            apply_pair_weights(&mut pairs, pair_weights)
+        };
+        let etas = calc_learning_rate(iterations, wmin, wmax, epsilon);
+
+        SgdParams {
+            etas,
+            positions: init_positions_random(self.node_size, center),
+            pairs,
+            boundary: BoundaryMode::Open,
+            active_pairs: Vec::new(),
+            positions_z: Vec::new(),
+            color_offsets: Vec::new(),
+            fixed: Vec::new(),
+            anchors: Vec::new(),
+            seed: None,
+        }
+    }
+
+    /// BFS rows of up to `num_pivots` (at least one) pivots picked by max-min
+    /// sampling from node 0: each next pivot is the node farthest from all
+    /// previous ones, so every connected component gets one before any
+    /// component gets a second. Stops early once every node is a pivot.
+    /// `O(k (n + m))` time and `O(k n)` memory for `k` pivots.
+    pub fn pivot_rows(&self, num_pivots: usize) -> Vec<(usize, Vec<usize>)> {
+        let adj = self.calc_adj_matrix();
+        let n = self.node_size;
+        let mut rows: Vec<(usize, Vec<usize>)> = Vec::new();
+        let mut nearest = vec![usize::MAX; n];
+        let mut next = 0;
+        while rows.len() < num_pivots.max(1) && n > 0 {
+            let mut row = vec![usize::MAX; n];
+            bfs_row(&adj, next, &mut row);
+            for (near, &d) in nearest.iter_mut().zip(&row) {
+                *near = (*near).min(d);
+            }
+            rows.push((next, row));
+            let Some((farthest, &d)) = nearest.iter().enumerate().max_by_key(|&(_, &d)| d) else {
+                break;
+            };
+            if d == 0 {
+                break;
+            }
+            next = farthest;
+        }
+        rows
+    }
+
+    /// Precompute SGD parameters for sparse stress (Ortmann, Klimenta and
+    /// Brandes): every edge plus pairs between each node and `num_pivots`
+    /// pivot nodes, with BFS run from the pivots only.
+    ///
+    /// Pivots are picked by max-min sampling from node 0, so every connected
+    /// component gets one before any component gets a second. Each node
+    /// belongs to the region of its nearest pivot. A node at distance `d`
+    /// from a pivot stands in for the region members within `d / 2` of that
+    /// pivot, so the pair's weight is their count `s` times `1 / d²`.
+    /// Pivots are marked in `anchors`: a pivot term moves only the other
+    /// node, since the pivot would otherwise be pulled by every node it
+    /// stands in for.
+    ///
+    /// Memory and time are `O(m + k n)` for `k` pivots instead of `O(n²)`,
+    /// so graphs far too large for the full pair list fit. The price is that
+    /// distances between non-pivot nodes are only matched through the
+    /// pivots, which leaves the layout rougher locally than full stress;
+    /// more pivots trade memory back for quality. Edge values are ignored
+    /// (hop distances).
+    pub fn prepare_sgd_params_sparse(
+        &self,
+        iterations: usize,
+        epsilon: f64,
+        center: bool,
+        num_pivots: usize,
+    ) -> SgdParams {
+        let n = self.node_size;
+        let (pivots, pivot_dist): (Vec<usize>, Vec<Vec<usize>>) = self.pivot_rows(num_pivots).into_iter().unzip();
+
+        // `nearest[v]` is the hop distance from v to its closest pivot and
+        // `region[v]` that pivot's index (the first one on a tie)
+        let mut nearest = vec![usize::MAX; n];
+        let mut region = vec![0usize; n];
+        for (i, row) in pivot_dist.iter().enumerate() {
+            for v in 0..n {
+                if row[v] < nearest[v] {
+                    nearest[v] = row[v];
+                    region[v] = i;
+                }
+            }
+        }
+
+        // Hop distances of every region's members, sorted, to count the
+        // members within d / 2 of their pivot
+        let mut members: Vec<Vec<usize>> = vec![Vec::new(); pivots.len()];
+        for v in 0..n {
+            if nearest[v] != usize::MAX {
+                members[region[v]].push(nearest[v]);
+            }
+        }
+        for m in &mut members {
+            m.sort_unstable();
+        }
+
+        let edges: HashSet<(usize, usize)> = self
+            .edge_src
+            .iter()
+            .zip(&self.edge_dst)
+            .filter(|(u, v)| u != v)
+            .map(|(&u, &v)| (u.min(v), u.max(v)))
+            .collect();
+        let mut pairs: Vec<EdgeInfo> = edges
+            .iter()
+            .map(|&(u, v)| EdgeInfo { u, v, dij: 1.0, wij: 1.0 })
+            .collect();
+        let pivot_index: HashMap<usize, usize> = pivots.iter().enumerate().map(|(i, &p)| (p, i)).collect();
+        for (i, (&pivot, row)) in pivots.iter().zip(&pivot_dist).enumerate() {
+            for (v, &d) in row.iter().enumerate() {
+                // Neighbours are covered by their edge; a pair of pivots is
+                // added once, from the lower pivot index
+                if d == usize::MAX || d <= 1 || pivot_index.get(&v).is_some_and(|&j| j < i) {
+                    continue;
+                }
+                let represented = members[i].partition_point(|&m| 2 * m <= d).max(1);
+                let dij = d as f64;
+                pairs.push(EdgeInfo {
+                    u: pivot.min(v),
+                    v: pivot.max(v),
+                    dij,
+                    wij: represented as f64 / (dij * dij),
+                });
+            }
+        }
+        pairs.sort_by_key(|p| (p.u, p.v));
+        let mut anchors = vec![false; n];
+        for &p in &pivots {
+            anchors[p] = true;
+        }
+        println!(
+            "Sparse stress: {} pivots, {} pairs ({:.2}% of the {} node pairs)",
+            pivots.len(),
+            pairs.len(),
+            if n < 2 { 0.0 } else { 100.0 * pairs.len() as f64 / (n * (n - 1) / 2) as f64 },
+            n * n.saturating_sub(1) / 2
+        );
+
+        let wmin = pairs.iter().map(|p| p.wij).fold(f64::INFINITY, f64::min);
+        let wmax = pairs.iter().map(|p| p.wij).fold(0.0, f64::max);
+        let etas = calc_learning_rate(iterations, wmin, wmax, epsilon);
+
+        SgdParams {
+            etas,
+            positions: init_positions_random(self.node_size, center),
+            pairs,
+            boundary: BoundaryMode::Open,
+            active_pairs: Vec::new(),
+            positions_z: Vec::new(),
+            color_offsets: Vec::new(),
+            fixed: Vec::new(),
+            anchors,
+            seed: None,
+        }
+    }
+
+    /// Precompute SGD parameters for the pairs at most `radius` hops apart,
+    /// found with one BFS per node that stops at depth `radius`.
+    ///
+    /// Meant for `barnes_hut::execute_sgd`, which handles all farther pairs
+    /// approximately. The pair list takes `O(n * ball size)` memory instead
+    /// of `O(n²)`. The learning rates still span every pair's weight, down
+    /// to `1 / diameter²` (the diameter estimated by a double sweep), since
+    /// the far pairs join in during the run.
+    pub fn prepare_sgd_params_neighborhood(
+        &self,
+        iterations: usize,
+        epsilon: f64,
+        center: bool,
+        radius: usize,
+    ) -> SgdParams {
+        let adj = self.calc_adj_matrix();
+        let mut dist = vec![usize::MAX; self.node_size];
+        let mut visited = Vec::new();
+        let mut deq = VecDeque::new();
+        let mut pairs = Vec::new();
+        for root in 0..self.node_size {
+            dist[root] = 0;
+            visited.push(root);
+            deq.push_back(root);
+            while let Some(v) = deq.pop_front() {
+                if dist[v] == radius {
+                    continue;
+                }
+                for &u in &adj[v] {
+                    if dist[u] == usize::MAX {
+                        dist[u] = dist[v] + 1;
+                        visited.push(u);
+                        deq.push_back(u);
+                        if root < u {
+                            let dij = dist[u] as f64;
+                            pairs.push(EdgeInfo { u: root, v: u, dij, wij: 1.0 / (dij * dij) });
+                        }
+                    }
+                }
+            }
+            for v in visited.drain(..) {
+                dist[v] = usize::MAX;
+            }
+        }
+
+        let (wmin, wmax) = if pairs.is_empty() {
+            (1.0, 1.0)
+        } else {
+            // Double sweep instead of an all-pairs BFS: a lower bound on the
+            // diameter, exact on trees
+            let sweeps = self.pivot_rows(2);
+            let diameter = sweeps.iter().flat_map(|(_, row)| row).filter(|&&d| d != usize::MAX).max();
+            let diameter = diameter.copied().unwrap_or(1).max(1) as f64;
+            let wmax = pairs.iter().map(|p| p.wij).fold(0.0, f64::max);
+            (1.0 / (diameter * diameter), wmax)
+        };
+        let etas = calc_learning_rate(iterations, wmin, wmax, epsilon);
+
+        SgdParams {
+            etas,
+            positions: init_positions_random(self.node_size, center),
+            pairs,
+            boundary: BoundaryMode::Open,
+            active_pairs: Vec::new(),
+            positions_z: Vec::new(),
+            color_offsets: Vec::new(),
+            fixed: Vec::new(),
+            anchors: Vec::new(),
+            seed: None,
+        }
+    }
+
+    /// Mean finite hop distance from `root` to the nodes it reaches.
+    fn mean_hop_distance(&self, root: usize) -> f64 {
+        let adj = self.calc_adj_matrix();
+        let mut dist = vec![usize::MAX; self.node_size];
+        let mut deq = VecDeque::new();
+        dist[root] = 0;
+        deq.push_back(root);
+        let (mut total, mut count) = (0usize, 0usize);
+        while let Some(v) = deq.pop_front() {
+            for &u in &adj[v] {
+                if dist[u] == usize::MAX {
+                    dist[u] = dist[v] + 1;
+                    total += dist[u];
+                    count += 1;
+                    deq.push_back(u);
+                }
+            }
+        }
+        if count == 0 { 1.0 } else { total as f64 / count as f64 }
+    }
+
+    /// Precompute SGD parameters from an already computed distance matrix,
+    /// e.g. one loaded by [`Graph::calc_dist_matrix_cached`].
+    pub fn prepare_sgd_params_from_dist<D: MatrixDistance>(
+        &self,
+        dist: &[Vec<D>],
+        iterations: usize,
+        epsilon: f64,
+        center: bool,
+        pair_weights: &HashMap<(usize, usize), f64>,
+        transform: DistanceTransform,
+    ) -> SgdParams {
+        let (pairs, wmin, wmax) = self.calc_edge_info(dist, transform);
+        self.params_from_pairs(pairs, wmin, wmax, iterations, epsilon, center, pair_weights)
+    }
+
+    /// Shared tail of the all-pairs constructors: apply `pair_weights`, derive
+    /// the step sizes and place the nodes.
+    #[allow(clippy::too_many_arguments)]
+    fn params_from_pairs(
+        &self,
+        mut pairs: Vec<EdgeInfo>,
+        mut wmin: f64,
+        mut wmax: f64,
+        iterations: usize,
+        epsilon: f64,
+        center: bool,
+        pair_weights: &HashMap<(usize, usize), f64>,
+    ) -> SgdParams {
+        if !pair_weights.is_empty() {
+            (wmin, wmax) = apply_pair_weights(&mut pairs, pair_weights);
+        }
+
+        let etas = calc_learning_rate(iterations, wmin, wmax, epsilon);
+
+        let positions = init_positions_random(self.node_size, center);
+
+        SgdParams {
+            etas,
+            positions,
+            pairs,
+            boundary: BoundaryMode::Open,
+            active_pairs: Vec::new(),
+            positions_z: Vec::new(),
+            color_offsets: Vec::new(),
+            fixed: Vec::new(),
+            anchors: Vec::new(),
+            seed: None,
+        }
+    }
+}
+
+/// Write a distance matrix as magic `SGDD`, version `u32`, node count `u64`
+/// and then `n * n` row-major `u32` distances (`u32::MAX` = unreachable).
+fn save_dist_matrix(path: &Path, dist: &[Vec<usize>]) -> Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    w.write_all(DIST_CACHE_MAGIC)?;
+    w.write_all(&DIST_CACHE_VERSION.to_le_bytes())?;
+    w.write_all(&(dist.len() as u64).to_le_bytes())?;
+    for row in dist {
+        for &d in row {
+            let d = if d == usize::MAX { u32::MAX } else { d as u32 };
+            w.write_all(&d.to_le_bytes())?;
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Load a distance matrix written by `save_dist_matrix`.
+fn load_dist_matrix(path: &Path) -> Result<Vec<Vec<usize>>> {
+    let bytes = fs::read(path)?;
+    let mut r = ByteReader::new(&bytes);
+
+    if r.take(4)? != DIST_CACHE_MAGIC {
+        anyhow::bail!("{}: not a distance cache file", path.display());
+    }
+    let version = r.u32()?;
+    if version != DIST_CACHE_VERSION {
+        anyhow::bail!("{}: unsupported distance cache version {}", path.display(), version);
+    }
+    let n = r.u64()? as usize;
+    (0..n)
+        .map(|_| {
+            (0..n)
+                .map(|_| {
+                    let d = r.u32()?;
+                    Ok(if d == u32::MAX { usize::MAX } else { d as usize })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Greedy edge coloring of the pairs: `colors[c]` lists the indices of the
+/// pairs of color `c`, and no node appears twice within one color. Pairs are
+/// visited in order and take the lowest color free at both of their nodes.
+pub fn color_pairs(pairs: &[EdgeInfo], node_size: usize) -> Vec<Vec<usize>> {
+    // Bitset of the colors already used at each node
+    let mut used: Vec<Vec<u64>> = vec![Vec::new(); node_size];
+    let mut colors: Vec<Vec<usize>> = Vec::new();
+    for (k, pair) in pairs.iter().enumerate() {
+        let (a, b) = (&used[pair.u], &used[pair.v]);
+        let mut word = 0;
+        let color = loop {
+            let taken = a.get(word).copied().unwrap_or(0) | b.get(word).copied().unwrap_or(0);
+            if taken != u64::MAX {
+                break word * 64 + (!taken).trailing_zeros() as usize;
+            }
+            word += 1;
+        };
+        for node in [pair.u, pair.v] {
+            let bits = &mut used[node];
+            if bits.len() <= color / 64 {
+                bits.resize(color / 64 + 1, 0);
+            }
+            bits[color / 64] |= 1 << (color % 64);
+        }
+        if colors.len() <= color {
+            colors.resize_with(color + 1, Vec::new);
+        }
+        colors[color].push(k);
+    }
+    colors
+}
+
+/// Multiply `wij` of every pair found in `pair_weights` (keyed by unordered
+/// `(u, v)`) and return the new `(wmin, wmax)` over all pairs.
+pub fn apply_pair_weights(
+    pairs: &mut [EdgeInfo],
+    pair_weights: &HashMap<(usize, usize), f64>,
+) -> (f64, f64) {
+    let mut wmin = f64::INFINITY;
+    let mut wmax: f64 = 0.0;
+
+    for pair in pairs.iter_mut() {
+        let key = (pair.u.min(pair.v), pair.u.max(pair.v));
+        if let Some(&m) = pair_weights.get(&key) {
+            pair.wij *= m;
+        }
+        wmin = wmin.min(pair.wij);
+        wmax = wmax.max(pair.wij);
+    }
+
+    (wmin, wmax)
+}
+
+/// Read per-pair weight multipliers from a text file.
+///
+/// Each non-empty, non-`#` line is `u v multiplier` with 0-based node ids.
+pub fn read_pair_weights(path: &Path) -> Result<HashMap<(usize, usize), f64>> {
+    let text = fs::read_to_string(path)?;
+    let mut weights = HashMap::new();
+
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 3 {
+            anyhow::bail!("{}:{}: expected `u v multiplier`", path.display(), lineno + 1);
+        }
+        let u: usize = parts[0].parse()?;
+        let v: usize = parts[1].parse()?;
+        let m: f64 = parts[2].parse()?;
+        if !(m > 0.0 && m.is_finite()) {
+            anyhow::bail!("{}:{}: multiplier must be positive", path.display(), lineno + 1);
+        }
+        weights.insert((u.min(v), u.max(v)), m);
+    }
+
+    Ok(weights)
+}
+
+/// Read pinned node positions, one `node x y` line each (`#` comments and
+/// blank lines ignored), for [`SgdParams::pin`].
+pub fn read_pins(path: &Path) -> Result<HashMap<usize, [f64; 2]>> {
+    let text = fs::read_to_string(path)?;
+    let mut pins = HashMap::new();
+
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 3 {
+            anyhow::bail!("{}:{}: expected `node x y`", path.display(), lineno + 1);
+        }
+        let node: usize = parts[0].parse()?;
+        let x: f64 = parts[1].parse()?;
+        let y: f64 = parts[2].parse()?;
+        pins.insert(node, [x, y]);
+    }
+
+    Ok(pins)
+}
+
+/// Entry of a distance matrix: a hop count (`usize::MAX` when unreachable)
+/// or a weighted path length (`f64::INFINITY` when unreachable).
+pub trait MatrixDistance: Copy + Send + Sync {
+    /// Marker for a pair with no path, which rows start out as.
+    const UNREACHABLE: Self;
+
+    /// The distance, or `None` for an unreachable pair.
+    fn finite(self) -> Option<f64>;
+}
+
+impl MatrixDistance for usize {
+    const UNREACHABLE: Self = usize::MAX;
+
+    fn finite(self) -> Option<f64> {
+        (self != usize::MAX).then_some(self as f64)
+    }
+}
+
+impl MatrixDistance for f64 {
+    const UNREACHABLE: Self = f64::INFINITY;
+
+    fn finite(self) -> Option<f64> {
+        self.is_finite().then_some(self)
+    }
+}
+
+/// Pairs gathered row by row by [`Graph::calc_edge_info`] and
+/// [`Graph::calc_edge_info_streamed`].
+struct PairAccumulator {
+    pairs: Vec<EdgeInfo>,
+    dmin: f64,
+    dmax: f64,
+    unreachable: usize,
+}
+
+impl PairAccumulator {
+    fn new() -> Self {
+        PairAccumulator { pairs: Vec::new(), dmin: f64::INFINITY, dmax: 0.0, unreachable: 0 }
+    }
+
+    /// Add the pairs `(u, v)` with `v > u` from the distances of source `u`.
+    fn add_row<D: MatrixDistance>(&mut self, u: usize, row: &[D], transform: DistanceTransform) {
+        for (v, &d) in row.iter().enumerate().skip(u + 1) {
+            // Skip unreachable nodes (usize::MAX / f64::INFINITY)
+            let Some(d) = d.finite() else {
+                self.unreachable += 1;
+                continue;
+            };
+
+            if d == 0.0 {
+                continue;
+            }
+            let dij = transform.apply(d);
+
+            let wij = 1.0 / (dij * dij);
+            self.pairs.push(EdgeInfo { u, v, dij, wij });
+
+            self.dmin = self.dmin.min(dij);
+            self.dmax = self.dmax.max(dij);
+        }
+    }
+
+    /// Combine the pairs of two disjoint sets of rows.
+    fn merge(mut self, mut other: Self) -> Self {
+        self.pairs.append(&mut other.pairs);
+        self.dmin = self.dmin.min(other.dmin);
+        self.dmax = self.dmax.max(other.dmax);
+        self.unreachable += other.unreachable;
+        self
+    }
+
+    /// Sort the pairs, report the unreachable ones and return
+    /// `(pairs, wmin, wmax)`.
+    fn finish(self, graph: &Graph) -> (Vec<EdgeInfo>, f64, f64) {
+        let PairAccumulator { mut pairs, dmin, dmax, unreachable } = self;
+        // With the `parallel` feature the rows are folded by rayon jobs and
+        // `merge` just appends, so the order would follow how the rows were
+        // split. Sort so the pair buffer never depends on the thread count.
+        pairs.sort_by_key(|p| (p.u, p.v));
+
+        let total = pairs.len() + unreachable;
+        println!(
+            "Pairs: {} connected, {} skipped as unreachable ({:.1}% of {})",
+            pairs.len(),
+            unreachable,
+            if total == 0 { 0.0 } else { 100.0 * unreachable as f64 / total as f64 },
+            total
+        );
+        if unreachable > 0 {
+            let components = graph.components();
+            let mut sizes = vec![0usize; components.iter().max().map_or(0, |&c| c + 1)];
+            for &c in &components {
+                sizes[c] += 1;
+            }
+            sizes.sort_unstable_by(|a, b| b.cmp(a));
+            let isolated = sizes.iter().filter(|&&s| s == 1).count();
+            println!(
+                "  {} connected components (largest {} nodes, {} isolated nodes)",
+                sizes.len(),
+                sizes[0],
+                isolated
+            );
+        }
+
+        let wmin = 1.0 / (dmax * dmax);
+        let wmax = 1.0 / (dmin * dmin);
+
+        (pairs, wmin, wmax)
+    }
+}
+
+/// Fold the distance rows of all `n` sources into one accumulator without
+/// keeping more than one row per thread: `fill_row` writes the row of a
+/// source into a buffer reset to [`MatrixDistance::UNREACHABLE`] and `fold`
+/// consumes it. With the `parallel` feature each rayon job folds into its
+/// own `init()` value and the results are combined in source order with
+/// `merge`.
+#[cfg_attr(not(feature = "parallel"), allow(unused_variables))]
+fn fold_rows<D: MatrixDistance, A: Send>(
+    n: usize,
+    fill_row: impl Fn(usize, &mut [D]) + Sync,
+    init: impl Fn() -> A + Sync + Send,
+    fold: impl Fn(&mut A, usize, &[D]) + Sync + Send,
+    merge: impl Fn(A, A) -> A + Sync + Send,
+) -> A {
+    let step = |(mut acc, mut row): (A, Vec<D>), source: usize| {
+        row.fill(D::UNREACHABLE);
+        fill_row(source, &mut row);
+        fold(&mut acc, source, &row);
+        (acc, row)
+    };
+
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        (0..n)
+            .into_par_iter()
+            .fold(|| (init(), vec![D::UNREACHABLE; n]), step)
+            .map(|(acc, _)| acc)
+            .reduce(&init, &merge)
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        (0..n).fold((init(), vec![D::UNREACHABLE; n]), step).0
+    }
+}
+
+/// Dijkstra queue entry, ordered so that `BinaryHeap` pops the smallest
+/// distance first.
+#[derive(PartialEq)]
+struct Frontier(f64, usize);
+
+impl Eq for Frontier {}
+
+impl Ord for Frontier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0).then_with(|| other.1.cmp(&self.1))
+    }
+}
+
+impl PartialOrd for Frontier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Fill `row` (pre-set to `f64::INFINITY`) with the weighted path lengths
+/// from `source` over `adj` (`(neighbour, weight)` lists).
+fn dijkstra_row(adj: &[Vec<(usize, f64)>], source: usize, row: &mut [f64]) {
+    let mut heap = BinaryHeap::new();
+    row[source] = 0.0;
+    heap.push(Frontier(0.0, source));
+
+    while let Some(Frontier(d, v)) = heap.pop() {
+        if d > row[v] {
+            continue;
+        }
+        for &(u, w) in &adj[v] {
+            let next = d + w;
+            if next < row[u] {
+                row[u] = next;
+                heap.push(Frontier(next, u));
+            }
+        }
+    }
+}
+
+/// Fill `row` (pre-set to `usize::MAX`) with the hop distances from
+/// `source`.
+fn bfs_row(adj: &[Vec<usize>], source: usize, row: &mut [usize]) {
+    let mut deq = VecDeque::new();
+    deq.push_back(source);
+    row[source] = 0;
+
+    while let Some(v) = deq.pop_front() {
+        for &u in &adj[v] {
+            if row[u] != usize::MAX {
+                continue;
+            }
+            row[u] = row[v] + 1;
+            deq.push_back(u);
+        }
+    }
+}
+
+/// Exponentially decaying step sizes from `1 / wmin` down to `eps / wmax`.
+///
+/// `wmin == wmax` (e.g. a single-edge graph with its one `dij = 1` pair) is a
+/// valid input: the schedule then decays from `1 / w` to `eps / w`. A single
+/// iteration gets the one step `1 / wmin`. Degenerate weight bounds are
+/// clamped (see [`usable_weight_bounds`]), so the steps are always finite.
+pub fn calc_learning_rate(tmax: usize, wmin: f64, wmax: f64, eps: f64) -> Vec<f64> {
+    calc_learning_rate_with(tmax, wmin, wmax, exponential_schedule(tmax, eps))
+}
+
+/// Step sizes for `tmax` iterations from a schedule closure called as
+/// `schedule(iteration, wmin, wmax)`.
+pub fn calc_learning_rate_with(
+    tmax: usize,
+    wmin: f64,
+    wmax: f64,
+    schedule: impl Fn(usize, f64, f64) -> f64,
+) -> Vec<f64> {
+    (0..tmax).map(|t| schedule(t, wmin, wmax)).collect()
+}
+
+/// `(wmin, wmax)` with a bound that is zero, negative or not finite replaced
+/// by the other one, or by 1 if neither is usable (no pairs: the steps then
+/// never apply). Keeps `1 / wmin` and `eps / wmax` finite.
+pub fn usable_weight_bounds(wmin: f64, wmax: f64) -> (f64, f64) {
+    let usable = |w: f64| w.is_finite() && w > 0.0;
+    match (usable(wmin), usable(wmax)) {
+        (true, true) => (wmin, wmax),
+        (true, false) => (wmin, wmin),
+        (false, true) => (wmax, wmax),
+        (false, false) => (1.0, 1.0),
+    }
+}
+
+/// The default schedule: `eta_max * exp(-lambda * t)` with `eta_max = 1 / wmin`
+/// and `lambda` chosen so the last of `tmax` steps is `eps / wmax` (a single
+/// step is `eta_max`).
+pub fn exponential_schedule(tmax: usize, eps: f64) -> impl Fn(usize, f64, f64) -> f64 + Clone {
+    move |t, wmin, wmax| {
+        let (wmin, wmax) = usable_weight_bounds(wmin, wmax);
+        let eta_max = 1.0 / wmin;
+        let eta_min = eps / wmax;
+        if tmax < 2 {
+            return eta_max;
+        }
+        let lamb = (eta_max / eta_min).ln() / (tmax - 1) as f64;
+        eta_max * (-lamb * t as f64).exp()
+    }
+}
+
+/// Cosine annealing between the same bounds as [`exponential_schedule`]:
+/// starts at `1 / wmin` and ends at `eps / wmax`, but stays large for longer.
+pub fn cosine_schedule(tmax: usize, eps: f64) -> impl Fn(usize, f64, f64) -> f64 + Clone {
+    move |t, wmin, wmax| {
+        let (wmin, wmax) = usable_weight_bounds(wmin, wmax);
+        let eta_max = 1.0 / wmin;
+        let eta_min = eps / wmax;
+        let progress = t as f64 / tmax.saturating_sub(1).max(1) as f64;
+        eta_min + 0.5 * (eta_max - eta_min) * (1.0 + (std::f64::consts::PI * progress).cos())
+    }
+}
+
+/// Refinement schedule for a layout that is already close: decays
+/// exponentially from `1 / wmax` to `eps / wmax`, so only the shortest pairs
+/// ever take full steps and the existing global shape is kept (a single step
+/// is `1 / wmax`).
+pub fn refinement_schedule(tmax: usize, eps: f64) -> impl Fn(usize, f64, f64) -> f64 + Clone {
+    move |t, wmin, wmax| {
+        let (_, wmax) = usable_weight_bounds(wmin, wmax);
+        if tmax < 2 {
+            return 1.0 / wmax;
+        }
+        let lamb = -eps.ln() / (tmax - 1) as f64;
+        (-lamb * t as f64).exp() / wmax
+    }
+}
+
+/// Distribution used to draw random initial positions.
+///
+/// All variants are centred on `(0.5, 0.5)` so they cover the same region as
+/// the default unit square before optional centering.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum InitDistribution {
+    /// Uniform over `[0, 1)²`.
+    #[default]
+    UniformSquare,
+    /// Uniform over the disk of radius 0.5.
+    UniformDisk,
+    /// Isotropic 2D Gaussian with standard deviation `std`.
+    Gaussian { std: f64 },
+}
+
+pub fn init_positions_random(n_nodes: usize, center: bool) -> Vec<[f64; 2]> {
+    init_positions_random_with(n_nodes, center, InitDistribution::default())
+}
+
+/// Random initial positions drawn from `distribution`.
+pub fn init_positions_random_with(
+    n_nodes: usize,
+    center: bool,
+    distribution: InitDistribution,
+) -> Vec<[f64; 2]> {
+    init_positions_from_rng(&mut rand::rng(), n_nodes, center, distribution)
+}
+
+/// [`init_positions_random_with`] drawing from a generator seeded with
+/// `seed`, so the same seed always gives the same positions.
+pub fn init_positions_seeded(
+    n_nodes: usize,
+    center: bool,
+    distribution: InitDistribution,
+    seed: u64,
+) -> Vec<[f64; 2]> {
+    init_positions_from_rng(&mut StdRng::seed_from_u64(seed), n_nodes, center, distribution)
+}
+
+/// Generator seeded with `seed`, or from the OS when there is none.
+pub fn seeded_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    }
+}
+
+/// Random third coordinates for a 3D layout, uniform in `[0, 1)` like the
+/// default square start (centered on zero with `center`). With a `seed` the
+/// coordinates are reproducible, drawn independently of the `x`/`y` stream.
+pub fn init_positions_z(n_nodes: usize, center: bool, seed: Option<u64>) -> Vec<f64> {
+    let mut z: Vec<f64> = match seed {
+        Some(seed) => {
+            let mut rng = StdRng::seed_from_u64(seed ^ 0x5a5a_5a5a_5a5a_5a5a);
+            (0..n_nodes).map(|_| rng.random::<f64>()).collect()
+        }
+        None => {
+            let mut rng = rand::rng();
+            (0..n_nodes).map(|_| rng.random::<f64>()).collect()
+        }
+    };
+    if center && n_nodes > 0 {
+        let mean = z.iter().sum::<f64>() / n_nodes as f64;
+        z.iter_mut().for_each(|v| *v -= mean);
+    }
+    z
+}
+
+fn init_positions_from_rng(
+    rng: &mut impl Rng,
+    n_nodes: usize,
+    center: bool,
+    distribution: InitDistribution,
+) -> Vec<[f64; 2]> {
+    let mut positions: Vec<[f64; 2]> = (0..n_nodes)
+        .map(|_| match distribution {
+            // Random coordinates in the range [0, 1)
+            InitDistribution::UniformSquare => [rng.random::<f64>(), rng.random::<f64>()],
+            InitDistribution::UniformDisk => {
+                // sqrt keeps the area density uniform
+                let r = 0.5 * rng.random::<f64>().sqrt();
+                let theta = rng.random::<f64>() * std::f64::consts::TAU;
+                [0.5 + r * theta.cos(), 0.5 + r * theta.sin()]
+            }
+            InitDistribution::Gaussian { std } => {
+                // Box-Muller: one draw gives both coordinates
+                let u1 = 1.0 - rng.random::<f64>(); // (0, 1], avoids ln(0)
+                let theta = rng.random::<f64>() * std::f64::consts::TAU;
+                let r = std * (-2.0 * u1.ln()).sqrt();
+                [0.5 + r * theta.cos(), 0.5 + r * theta.sin()]
+            }
+        })
+        .collect();
+
+    // centering if center is true
+    if center {
+        center_positions(&mut positions);
+    }
+
+    positions
+}
+
+/// Lattice initial positions: node `i` at `(i % width, i / width)` with unit
+/// spacing, matching the `dij = 1` edge length of a row-major grid graph.
+pub fn init_positions_grid(n_nodes: usize, width: usize, center: bool) -> Vec<[f64; 2]> {
+    let width = width.max(1);
+    let mut positions: Vec<[f64; 2]> = (0..n_nodes)
+        .map(|i| [(i % width) as f64, (i / width) as f64])
+        .collect();
+    if center {
+        center_positions(&mut positions);
+    }
+    positions
+}
+
+/// Read initial positions from an external per-node coordinate file
+/// (e.g. 2D embedding coordinates).
+///
+/// Each non-empty, non-`#` line holds `x y` for the next node, separated by
+/// whitespace or a comma. The number of rows must equal `n_nodes`.
+pub fn init_positions_from_coords(path: &Path, n_nodes: usize, center: bool) -> Result<Vec<[f64; 2]>> {
+    let text = fs::read_to_string(path)?;
+    let mut positions = Vec::with_capacity(n_nodes);
+
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|t| !t.is_empty())
+            .collect();
+        if parts.len() < 2 {
+            anyhow::bail!("{}:{}: expected `x y`", path.display(), lineno + 1);
+        }
+        let x: f64 = parts[0].parse()?;
+        let y: f64 = parts[1].parse()?;
+        if !(x.is_finite() && y.is_finite()) {
+            anyhow::bail!("{}:{}: non-finite coordinate", path.display(), lineno + 1);
+        }
+        positions.push([x, y]);
+    }
+
+    if positions.len() != n_nodes {
+        anyhow::bail!(
+            "{}: expected {} coordinates (one per node) but found {}",
+            path.display(),
+            n_nodes,
+            positions.len()
+        );
+    }
+
+    if center {
+        center_positions(&mut positions);
+    }
+
+    Ok(positions)
+}
+
+/// Initial positions biased by per-node importance read from `path`.
+///
+/// The file holds one non-negative weight per node (one per non-empty,
+/// non-`#` line). Weights are normalized by their maximum and each node is
+/// placed at a random angle on a circle around `(0.5, 0.5)` whose radius is
+/// inversely proportional to its weight: the smallest positive weight (and
+/// any zero weight) sits on the outer radius 0.5, heavier nodes closer to
+/// the centre.
+pub fn init_positions_from_weights(path: &Path, n_nodes: usize, center: bool, seed: u64) -> Result<Vec<[f64; 2]>> {
+    let text = fs::read_to_string(path)?;
+    let mut weights = Vec::with_capacity(n_nodes);
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let w: f64 = line
+            .parse()
+            .map_err(|e| anyhow::anyhow!("{}:{}: invalid weight: {}", path.display(), lineno + 1, e))?;
+        if !(w.is_finite() && w >= 0.0) {
+            anyhow::bail!("{}:{}: weight must be finite and non-negative", path.display(), lineno + 1);
+        }
+        weights.push(w);
+    }
+    if weights.len() != n_nodes {
+        anyhow::bail!(
+            "{}: expected {} weights (one per node) but found {}",
+            path.display(),
+            n_nodes,
+            weights.len()
+        );
+    }
+
+    let max = weights.iter().copied().fold(0.0, f64::max);
+    if max == 0.0 {
+        anyhow::bail!("{}: all weights are zero", path.display());
+    }
+    let floor = weights.iter().map(|w| w / max).filter(|&w| w > 0.0).fold(1.0, f64::min);
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut positions: Vec<[f64; 2]> = weights
+        .iter()
+        .map(|w| {
+            let r = 0.5 * floor / (w / max).max(floor);
+            let theta = rng.random::<f64>() * std::f64::consts::TAU;
+            [0.5 + r * theta.cos(), 0.5 + r * theta.sin()]
+        })
+        .collect();
+    if center {
+        center_positions(&mut positions);
+    }
+    Ok(positions)
+}
+
+/// Warm-start positions from a text result written by a previous run
+/// (`output::write_result_txt`).
+///
+/// The file's `# Edges` section must describe the same undirected edge set
+/// as `graph`, and its `# Positions` section must have one row per node.
+/// Positions are returned as written, without recentering.
+pub fn init_positions_from_result(path: &Path, graph: &Graph) -> Result<Vec<[f64; 2]>> {
+    let text = fs::read_to_string(path)?;
+    let mut edges = HashSet::new();
+    let mut positions = Vec::with_capacity(graph.node_size);
+    let mut in_positions = None;
+
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with("# Edges") {
+            in_positions = Some(false);
+            continue;
+        }
+        if line.starts_with("# Positions") {
+            in_positions = Some(true);
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 2 {
+            anyhow::bail!("{}:{}: expected two columns", path.display(), lineno + 1);
+        }
+        match in_positions {
+            Some(false) => {
+                let (u, v): (usize, usize) = (parts[0].parse()?, parts[1].parse()?);
+                edges.insert((u.min(v), u.max(v)));
+            }
+            Some(true) => {
+                let (x, y): (f64, f64) = (parts[0].parse()?, parts[1].parse()?);
+                if !(x.is_finite() && y.is_finite()) {
+                    anyhow::bail!("{}:{}: non-finite coordinate", path.display(), lineno + 1);
+                }
+                positions.push([x, y]);
+            }
+            None => anyhow::bail!("{}:{}: data before the edges section", path.display(), lineno + 1),
+        }
+    }
+
+    if positions.len() != graph.node_size {
+        anyhow::bail!(
+            "{}: has {} positions but the graph has {} nodes",
+            path.display(),
+            positions.len(),
+            graph.node_size
+        );
+    }
+    let expected: HashSet<(usize, usize)> = graph
+        .edge_src
+        .iter()
+        .zip(&graph.edge_dst)
+        .map(|(&u, &v)| (u.min(v), u.max(v)))
+        .collect();
+    if edges != expected {
+        anyhow::bail!(
+            "{}: edge structure differs from the current graph ({} vs {} distinct edges, {} in common)",
+            path.display(),
+            edges.len(),
+            expected.len(),
+            edges.intersection(&expected).count()
+        );
+    }
+
+    Ok(positions)
+}
+
+/// Translate positions so their centroid is at the origin.
+pub fn center_positions(positions: &mut [[f64; 2]]) {
+    if positions.is_empty() {
+        return;
+    }
+    let n = positions.len() as f64;
+
+    // Calc the center of the positions
+    let mean_x = positions.iter().map(|p| p[0]).sum::<f64>() / n;
+    let mean_y = positions.iter().map(|p| p[1]).sum::<f64>() / n;
+
+    // move to the center
+    for pos in positions {
+        pos[0] -= mean_x;
+        pos[1] -= mean_y;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_mtx(name: &str, text: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.mtx", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn duplicate_entries_collapse_to_one_edge() {
+        let path = write_mtx(
+            "duplicate-entries",
+            "%%MatrixMarket matrix coordinate pattern general\n3 3 5\n1 2\n1 2\n2 1\n2 3\n3 3\n",
+        );
+        let graph = Graph::from_mtx(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(graph.edge_size, 2);
+    }
+
+    #[test]
+    fn mirrored_symmetric_entries_collapse_to_one_edge() {
+        let path = write_mtx(
+            "mirrored-entries",
+            "%%MatrixMarket matrix coordinate pattern symmetric\n2 2 1\n2 1\n",
+        );
+        let graph = Graph::from_mtx(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(graph.edge_size, 1);
+    }
+
+    #[test]
+    fn weighted_entries_become_dijkstra_distances() {
+        // Triangle whose direct 1-3 edge is longer than the path over 2
+        for (name, field, text) in [
+            ("real-weights", "real", "1 2 2.5\n2 3 0.5\n1 3 4\n"),
+            ("integer-weights", "integer", "1 2 2\n2 3 1\n1 3 4\n"),
+        ] {
+            let header = format!("%%MatrixMarket matrix coordinate {} general\n3 3 3\n", field);
+            let path = write_mtx(name, &(header + text));
+            let graph = Graph::from_mtx_with_policy(&path, AsymmetryPolicy::Warn).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert!(graph.has_edge_weights());
+            let dist = graph.calc_dist_matrix_weighted();
+            assert_eq!(dist[0][2], 3.0);
+            let pairs = graph.prepare_sgd_params(10, 0.1, true).pairs;
+            assert_eq!(pairs.iter().find(|p| (p.u, p.v) == (0, 2)).unwrap().dij, 3.0);
+        }
+    }
+
+    #[test]
+    fn invalid_weights_fall_back_to_hop_counts() {
+        let path = write_mtx(
+            "invalid-weights",
+            "%%MatrixMarket matrix coordinate real general\n3 3 3\n1 2 2.5\n2 3 -1\n1 3 4\n",
+        );
+        let graph = Graph::from_mtx(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(graph.invalid_edge_weights(), 1);
+        assert!(!graph.has_edge_weights());
+        let pairs = graph.prepare_sgd_params(10, 0.1, true).pairs;
+        assert!(pairs.iter().all(|p| p.dij == 1.0));
+    }
+
+    #[test]
+    fn triangle_selects_the_stored_entries() {
+        // (2, 1) and (1, 2) are one edge stored twice, (3, 2) has no mirror
+        let path = write_mtx(
+            "triangle-entries",
+            "%%MatrixMarket matrix coordinate pattern general\n3 3 3\n2 1\n1 2\n3 2\n",
+        );
+        let edges = |triangle| {
+            let graph = Graph::from_mtx_with_options(&path, AsymmetryPolicy::Warn, triangle).unwrap();
+            graph.edge_src.into_iter().zip(graph.edge_dst).collect::<Vec<_>>()
+        };
+        assert_eq!(edges(Triangle::Auto), vec![(1, 0), (2, 1)]);
+        assert_eq!(edges(Triangle::Upper), vec![(0, 1)]);
+        assert_eq!(edges(Triangle::Lower), vec![(1, 0), (2, 1)]);
+        assert_eq!(edges(Triangle::Both), vec![(1, 0), (0, 1), (2, 1)]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn graphml_keeps_the_original_node_ids() {
+        let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="w" for="edge" attr.name="weight" attr.type="double"/>
+  <graph id="G" edgedefault="undirected">
+    <!-- <node id="commented-out"/> -->
+    <node id="n10"/>
+    <node id='n3'><data key="label">three</data></node>
+    <node id="a&amp;b"/>
+    <edge source="n10" target='n3'/>
+    <edge source="n3" target="n10"><data key="w">2.0</data></edge>
+    <edge source='a&amp;b' target="n10"/>
+    <edge source="n3" target="n3"/>
+    <edge source="n3" target="late"/>
+  </graph>
+</graphml>"#;
+        let (graph, ids) = Graph::from_graphml_str(text, "inline").unwrap();
+        assert_eq!(ids, vec!["n10", "n3", "a&b", "late"]);
+        assert_eq!(graph.node_size, 4);
+        let edges: Vec<_> = graph.edge_src.iter().copied().zip(graph.edge_dst.iter().copied()).collect();
+        assert_eq!(edges, vec![(0, 1), (2, 0), (1, 3)]);
+    }
+
+    #[test]
+    fn malformed_graphml_is_an_error() {
+        for text in [
+            "",
+            "<graphml><graph></graph></graphml>",
+            r#"<graph><node id="a"/><node/></graph>"#,
+            r#"<graph><node id="a"/><edge source="a"/></graph>"#,
+            r#"<graph><node id="a/></graph>"#,
+            r#"<graph><node id=a/></graph>"#,
+            r#"<graph><node id="a""#,
+            "<graph><!-- <node id=\"a\"/>",
+        ] {
+            assert!(Graph::from_graphml_str(text, "inline").is_err(), "{:?} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn single_edge_graph_validates() {
+        let graph = Graph::from_edges(2, vec![0], vec![1]).unwrap();
+        let params = graph.prepare_sgd_params(30, 0.1, true);
+        params.validate().unwrap();
+        assert_eq!(params.pairs.len(), 1);
+        assert!((params.pairs[0].dij - 1.0).abs() < 1e-12);
+        assert_eq!(params.etas.len(), 30);
+        assert!(params.etas.iter().all(|eta| eta.is_finite()));
+
+        let (_, layout) = crate::cpu::execute_sgd(params, crate::cpu::CpuSgdOptions::default());
+        let [p0, p1] = [layout.positions[0], layout.positions[1]];
+        let length = ((p0[0] - p1[0]).powi(2) + (p0[1] - p1[1]).powi(2)).sqrt();
+        assert!((length - 1.0).abs() < 1e-3, "edge length {}", length);
+    }
+
+    #[test]
+    fn empty_schedule_is_rejected() {
+        let graph = Graph::from_edges(2, vec![0], vec![1]).unwrap();
+        assert!(graph.prepare_sgd_params(0, 0.1, true).validate().is_err());
+    }
+
+    #[test]
+    fn one_iteration_gets_the_largest_step() {
+        assert_eq!(calc_learning_rate(1, 0.25, 1.0, 0.1), vec![4.0]);
+        assert_eq!(calc_learning_rate_with(1, 0.25, 2.0, refinement_schedule(1, 0.1)), vec![0.5]);
+    }
+
+    #[test]
+    fn zero_wmin_falls_back_to_wmax() {
+        let etas = calc_learning_rate(3, 0.0, 2.0, 0.1);
+        assert!((etas[0] - 0.5).abs() < 1e-12);
+        assert!((etas[2] - 0.05).abs() < 1e-12);
+    }
+
+    #[test]
+    fn schedule_decays_from_one_over_wmin_to_eps_over_wmax() {
+        let etas = calc_learning_rate(15, 0.01, 1.0, 0.1);
+        assert_eq!(etas.len(), 15);
+        assert!((etas[0] - 100.0).abs() < 1e-9);
+        assert!((etas[14] - 0.1).abs() < 1e-12);
+        assert!(etas.windows(2).all(|w| w[0] > w[1]));
+    }
+
+    fn assert_proper_coloring(graph: &Graph) {
+        let (pairs, _, _) = graph.calc_edge_info(&graph.calc_dist_matrix(), DistanceTransform::Identity);
+        let colors = color_pairs(&pairs, graph.node_size);
+        let mut seen = vec![false; pairs.len()];
+        for color in &colors {
+            let mut nodes = HashSet::new();
+            for &k in color {
+                assert!(!std::mem::replace(&mut seen[k], true), "pair {} colored twice", k);
+                assert!(nodes.insert(pairs[k].u) && nodes.insert(pairs[k].v), "node shared within a color");
+            }
+        }
+        assert!(seen.iter().all(|&s| s), "pair left uncolored");
+    }
+
+    #[test]
+    fn color_pairs_never_shares_a_node_within_a_color() {
+        let (src, dst): (Vec<usize>, Vec<usize>) = (0..5).flat_map(|u| (u + 1..5).map(move |v| (u, v))).unzip();
+        assert_proper_coloring(&Graph::from_edges(5, src, dst).unwrap());
+        assert_proper_coloring(&Graph::from_edges(8, vec![0; 7], (1..8).collect()).unwrap());
+        let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../data/bcspwr01.mtx"));
+        assert_proper_coloring(&Graph::from_mtx(path).unwrap());
+    }
+
+    #[test]
+    fn streamed_pairs_match_the_distance_matrix() {
+        // A 5-cycle with a pendant path, so distances range from 1 to 4
+        let graph = Graph::from_edges(7, vec![0, 1, 2, 3, 4, 4, 5], vec![1, 2, 3, 4, 0, 5, 6]).unwrap();
+        let (expected, wmin, wmax) = graph.calc_edge_info(&graph.calc_dist_matrix(), DistanceTransform::Identity);
+        let adj = graph.calc_adj_matrix();
+        let (pairs, smin, smax) =
+            graph.calc_edge_info_streamed(|source, row| bfs_row(&adj, source, row), DistanceTransform::Identity);
+        assert_eq!((smin, smax), (wmin, wmax));
+        let key = |p: &EdgeInfo| (p.u, p.v, p.dij, p.wij);
+        assert_eq!(pairs.iter().map(key).collect::<Vec<_>>(), expected.iter().map(key).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn disjoint_triangles_are_connected_and_kept_apart() {
+        let graph = Graph::from_edges(6, vec![0, 1, 2, 3, 4, 5], vec![1, 2, 0, 4, 5, 3]).unwrap();
+        let components = graph.components();
+        assert_eq!(components, vec![0, 0, 0, 1, 1, 1]);
+
+        let mut params = graph.prepare_sgd_params(30, 0.1, false);
+        assert_eq!(params.connect_components(&components), 9);
+        let wmin = params.pairs.iter().map(|p| p.wij).fold(f64::INFINITY, f64::min);
+        let wmax = params.pairs.iter().map(|p| p.wij).fold(0.0, f64::max);
+        params.etas = calc_learning_rate(30, wmin, wmax, 0.1);
+        let (_, layout) = crate::cpu::execute_sgd(params, crate::cpu::CpuSgdOptions::default());
+
+        let p = &layout.positions;
+        assert!(p.iter().flatten().all(|c| c.is_finite()));
+        let dist = |u: usize, v: usize| ((p[u][0] - p[v][0]).powi(2) + (p[u][1] - p[v][1]).powi(2)).sqrt();
+        for u in 0..3 {
+            for v in 3..6 {
+                assert!(dist(u, v) > 1.0, "nodes {} and {} are {} apart", u, v, dist(u, v));
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_and_serial_pairs_come_in_the_same_order() {
+        // Large enough for rayon to split the rows across jobs; the
+        // matrix path always walks the rows in order on one thread
+        let graph = grid(30, 20);
+        let (serial, _, _) = graph.calc_edge_info(&graph.calc_dist_matrix(), DistanceTransform::Identity);
+        let adj = graph.calc_adj_matrix();
+        let (folded, _, _) =
+            graph.calc_edge_info_streamed(|source, row| bfs_row(&adj, source, row), DistanceTransform::Identity);
+        let key = |p: &EdgeInfo| (p.u, p.v);
+        assert_eq!(folded.iter().map(key).collect::<Vec<_>>(), serial.iter().map(key).collect::<Vec<_>>());
+        assert!(serial.windows(2).all(|w| key(&w[0]) < key(&w[1])));
+    }
+
+    fn round_trip(params: &SgdParams) -> SgdParams {
+        let mut bytes = Vec::new();
+        params.write_to(&mut bytes).unwrap();
+        SgdParams::from_bytes(&bytes, "round trip").unwrap()
+    }
+
+    #[test]
+    fn saved_params_keep_the_third_coordinate() {
+        let graph = Graph::from_edges(4, vec![0, 1, 2], vec![1, 2, 3]).unwrap();
+        let mut params = graph.prepare_sgd_params(10, 0.1, true);
+        assert!(round_trip(&params).positions_z.is_empty());
+        params.positions_z = init_positions_z(4, true, Some(3));
+        let loaded = round_trip(&params);
+        assert_eq!(loaded.positions_z, params.positions_z);
+        assert_eq!(loaded.positions, params.positions);
+        assert_eq!(loaded.pairs.len(), params.pairs.len());
+    }
+
+    #[test]
+    fn saved_params_keep_pinned_nodes() {
+        let graph = Graph::from_edges(4, vec![0, 1, 2], vec![1, 2, 3]).unwrap();
+        let mut params = graph.prepare_sgd_params(10, 0.1, true);
+        params.pin(&HashMap::from([(0, [1.0, 2.0]), (3, [-1.0, 0.5])])).unwrap();
+        let loaded = round_trip(&params);
+        assert_eq!(loaded.fixed, vec![true, false, false, true]);
+        assert_eq!(loaded.positions[0], [1.0, 2.0]);
+        assert_eq!(loaded.positions[3], [-1.0, 0.5]);
+    }
+
+    /// `width` x `height` grid graph, row by row.
+    fn grid(width: usize, height: usize) -> Graph {
+        let (mut src, mut dst) = (Vec::new(), Vec::new());
+        for v in 0..width * height {
+            if v % width + 1 < width {
+                src.push(v);
+                dst.push(v + 1);
+            }
+            if v + width < width * height {
+                src.push(v);
+                dst.push(v + width);
+            }
+        }
+        Graph::from_edges(width * height, src, dst).unwrap()
+    }
+
+    #[test]
+    fn sparse_stress_needs_far_fewer_pairs() {
+        let graph = grid(40, 25);
+        let params = graph.prepare_sgd_params_sparse(10, 0.1, true, 20);
+        params.validate().unwrap();
+        assert_eq!(params.anchors.iter().filter(|&&a| a).count(), 20);
+        // Full stress would have 1000 * 999 / 2 = 499500 pairs
+        assert!(params.pairs.len() <= graph.edge_size + 20 * 1000, "{} pairs", params.pairs.len());
+        assert!(params.pairs.len() < 499_500 / 20);
+    }
+
+    #[test]
+    fn saved_params_keep_sparse_stress_anchors() {
+        let params = grid(6, 5).prepare_sgd_params_sparse(10, 0.1, true, 3);
+        let loaded = round_trip(&params);
+        assert_eq!(loaded.anchors, params.anchors);
+        assert_eq!(loaded.pairs.len(), params.pairs.len());
+    }
 }
@@ -0,0 +1,303 @@
+//! The [`Layout`] a backend returns, post-processing passes applied to a
+//! finished layout, plus transforms that can run between iterations.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Final positions of a 2D run together with how they were obtained; the
+/// backends return it next to the initial positions (see
+/// [`crate::graph::LayoutPair`]).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub positions: Vec<[f32; 2]>,
+    /// Number of nodes (`positions.len()`).
+    pub node_size: usize,
+    /// Iterations actually run: fewer than scheduled after an interrupt or
+    /// an exhausted time budget.
+    pub iterations: usize,
+    /// Stress of `positions`, once computed by [`Layout::with_stress`];
+    /// the backends leave it `None` since it needs all pairs.
+    pub stress: Option<f64>,
+    /// Wall-clock time of the backend run, including upload and download.
+    pub elapsed: Duration,
+}
+
+impl Layout {
+    pub fn new(positions: Vec<[f32; 2]>, iterations: usize, elapsed: Duration) -> Self {
+        Layout { node_size: positions.len(), positions, iterations, stress: None, elapsed }
+    }
+
+    /// Fill in `stress` against `pairs` (see [`crate::metrics::stress`]).
+    pub fn with_stress(mut self, pairs: &[crate::graph::EdgeInfo]) -> Self {
+        self.stress = Some(crate::metrics::stress(&self.positions, pairs));
+        self
+    }
+
+    /// `(min, max)` corners of the axis-aligned bounding box, `None` without
+    /// nodes.
+    pub fn bounding_box(&self) -> Option<([f32; 2], [f32; 2])> {
+        let first = *self.positions.first()?;
+        Some(self.positions.iter().fold((first, first), |(lo, hi), p| {
+            ([lo[0].min(p[0]), lo[1].min(p[1])], [hi[0].max(p[0]), hi[1].max(p[1])])
+        }))
+    }
+
+    /// Centroid of the positions (the origin without nodes).
+    pub fn center(&self) -> [f32; 2] {
+        let n = self.positions.len().max(1) as f64;
+        let sum = self.positions.iter().fold([0.0, 0.0], |s, p| [s[0] + p[0] as f64, s[1] + p[1] as f64]);
+        [(sum[0] / n) as f32, (sum[1] / n) as f32]
+    }
+
+    /// Translate and scale uniformly so the bounding box starts at the
+    /// origin and its longer side is 1, i.e. the layout fits the unit
+    /// square with its aspect ratio kept. A layout without extent is only
+    /// translated. Distances change, so `stress` is reset to `None`.
+    pub fn normalize_to_unit(&mut self) {
+        let Some((lo, hi)) = self.bounding_box() else {
+            return;
+        };
+        let extent = (hi[0] - lo[0]).max(hi[1] - lo[1]) as f64;
+        let scale = if extent.is_finite() && extent > 0.0 { 1.0 / extent } else { 1.0 };
+        for p in self.positions.iter_mut() {
+            p[0] = ((p[0] - lo[0]) as f64 * scale) as f32;
+            p[1] = ((p[1] - lo[1]) as f64 * scale) as f32;
+        }
+        self.stress = None;
+    }
+
+    pub fn into_positions(self) -> Vec<[f32; 2]> {
+        self.positions
+    }
+}
+
+/// Divide every coordinate by `reference`.
+///
+/// Layouts are produced in target-distance units, so dividing each layout
+/// of a batch by the same reference (e.g. the largest diameter in the
+/// batch) puts them all on one scale instead of letting every frame be
+/// rescaled to its own extent.
+pub fn scale_to_reference(positions: &mut [[f32; 2]], reference: f64) {
+    assert!(reference > 0.0, "reference must be positive");
+    for pos in positions.iter_mut() {
+        pos[0] = (pos[0] as f64 / reference) as f32;
+        pos[1] = (pos[1] as f64 / reference) as f32;
+    }
+}
+
+/// Translate positions so their centroid is at the origin.
+///
+/// Has the [`crate::graph::TransformFn`] signature so it can run between
+/// iterations (`--recenter-each-iteration`), which stops the layout from
+/// drifting on long runs.
+pub fn recenter_transform(positions: &mut [[f64; 2]], _iteration: usize) {
+    crate::graph::center_positions(positions);
+}
+
+/// Width of the positions' bounding box, `None` when it is zero or not finite.
+fn x_extent(xs: impl Iterator<Item = f64>) -> Option<f64> {
+    let (min, max) = xs.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), x| (lo.min(x), hi.max(x)));
+    let extent = max - min;
+    (extent.is_finite() && extent > 0.0).then_some(extent)
+}
+
+/// Scale positions uniformly (both axes, about the origin) so the x-extent
+/// is `width`. Layouts with no horizontal extent are left as they are.
+pub fn fit_width(positions: &mut [[f32; 2]], width: f64) {
+    if let Some(extent) = x_extent(positions.iter().map(|p| p[0] as f64)) {
+        scale_to_reference(positions, extent / width);
+    }
+}
+
+/// [`fit_width`] as a [`crate::graph::TransformFn`]. Run between iterations
+/// it keeps the optimization itself at that width (`--target-width`): stress
+/// then only shapes the layout, while the scale it would settle on is
+/// overridden after every step.
+pub fn fit_width_transform(width: f64) -> crate::graph::TransformFn {
+    Box::new(move |positions, _| {
+        if let Some(extent) = x_extent(positions.iter().map(|p| p[0])) {
+            let s = width / extent;
+            for p in positions.iter_mut() {
+                p[0] *= s;
+                p[1] *= s;
+            }
+        }
+    })
+}
+
+/// Round every coordinate to the nearest multiple of `cell_size`.
+///
+/// When `resolve_collisions` is true, nodes that land on an already occupied
+/// cell are moved to the nearest free cell (searched ring by ring around the
+/// original position), so every node ends up on its own lattice point.
+/// Nodes are processed in index order, which keeps the result deterministic.
+pub fn snap_to_grid(positions: &mut [[f32; 2]], cell_size: f64, resolve_collisions: bool) {
+    assert!(cell_size > 0.0, "cell_size must be positive");
+
+    let mut occupied: HashSet<(i64, i64)> = HashSet::new();
+
+    for pos in positions.iter_mut() {
+        let fx = pos[0] as f64 / cell_size;
+        let fy = pos[1] as f64 / cell_size;
+        let mut cell = (fx.round() as i64, fy.round() as i64);
+
+        if resolve_collisions && occupied.contains(&cell) {
+            cell = nearest_free_cell(&occupied, cell, fx, fy);
+        }
+        occupied.insert(cell);
+
+        pos[0] = (cell.0 as f64 * cell_size) as f32;
+        pos[1] = (cell.1 as f64 * cell_size) as f32;
+    }
+}
+
+/// Find the free cell closest to the continuous position `(fx, fy)`,
+/// scanning square rings of growing radius around `start`.
+fn nearest_free_cell(
+    occupied: &HashSet<(i64, i64)>,
+    start: (i64, i64),
+    fx: f64,
+    fy: f64,
+) -> (i64, i64) {
+    let mut radius = 1i64;
+    loop {
+        let mut best: Option<((i64, i64), f64)> = None;
+        for dx in -radius..=radius {
+            for dy in -radius..=radius {
+                // Only the border of the ring; the interior was scanned before.
+                if dx.abs() != radius && dy.abs() != radius {
+                    continue;
+                }
+                let cell = (start.0 + dx, start.1 + dy);
+                if occupied.contains(&cell) {
+                    continue;
+                }
+                let ex = cell.0 as f64 - fx;
+                let ey = cell.1 as f64 - fy;
+                let d2 = ex * ex + ey * ey;
+                if best.is_none_or(|(_, b)| d2 < b) {
+                    best = Some((cell, d2));
+                }
+            }
+        }
+        if let Some((cell, _)) = best {
+            return cell;
+        }
+        radius += 1;
+    }
+}
+
+/// Spread nodes that share exactly the same coordinates.
+///
+/// The first node at a position stays put; the k-th duplicate is moved by
+/// `amount` along a golden-angle spiral (radius `amount * sqrt(k)`), so the
+/// result is deterministic and duplicates do not land on each other.
+/// Nodes at distinct positions are untouched. Returns how many nodes moved.
+pub fn jitter_duplicates(positions: &mut [[f32; 2]], amount: f64) -> usize {
+    const GOLDEN_ANGLE: f64 = 2.399_963_229_728_653;
+
+    let mut seen: HashMap<(u32, u32), usize> = HashMap::new();
+    let mut moved = 0;
+    for pos in positions.iter_mut() {
+        let key = (pos[0].to_bits(), pos[1].to_bits());
+        let k = seen.entry(key).or_insert(0);
+        if *k > 0 {
+            let r = amount * (*k as f64).sqrt();
+            let theta = *k as f64 * GOLDEN_ANGLE;
+            pos[0] = (pos[0] as f64 + r * theta.cos()) as f32;
+            pos[1] = (pos[1] as f64 + r * theta.sin()) as f32;
+            moved += 1;
+        }
+        *k += 1;
+    }
+    moved
+}
+
+/// Rigidly align `positions` onto `reference` (translation, rotation and,
+/// if it fits better, a reflection; no scaling) by least squares.
+///
+/// Stress layouts are only defined up to these motions, so two runs must be
+/// aligned before their coordinates can be compared or averaged. Returns the
+/// remaining sum of squared distances to the reference.
+pub fn procrustes_align(reference: &[[f32; 2]], positions: &mut [[f32; 2]]) -> f64 {
+    let centroid = |p: &[[f32; 2]]| {
+        let n = p.len().max(1) as f64;
+        let sum = p.iter().fold([0.0, 0.0], |s, q| [s[0] + q[0] as f64, s[1] + q[1] as f64]);
+        [sum[0] / n, sum[1] / n]
+    };
+    let (cr, cp) = (centroid(reference), centroid(positions));
+    let centered: Vec<[f64; 2]> = positions.iter().map(|p| [p[0] as f64 - cp[0], p[1] as f64 - cp[1]]).collect();
+
+    // Best rotation of `centered` (optionally mirrored in y) onto the
+    // reference: angle = atan2(Σ p × q, Σ p · q)
+    let fit = |mirror: f64| {
+        let (mut dot, mut cross) = (0.0, 0.0);
+        for (p, q) in centered.iter().zip(reference) {
+            let (px, py) = (p[0], mirror * p[1]);
+            let (qx, qy) = (q[0] as f64 - cr[0], q[1] as f64 - cr[1]);
+            dot += px * qx + py * qy;
+            cross += px * qy - py * qx;
+        }
+        (mirror, cross.atan2(dot), dot.hypot(cross))
+    };
+    let (plain, mirrored) = (fit(1.0), fit(-1.0));
+    let (mirror, angle, _) = if mirrored.2 > plain.2 { mirrored } else { plain };
+
+    let (sin, cos) = angle.sin_cos();
+    let mut residual = 0.0;
+    for ((pos, p), q) in positions.iter_mut().zip(&centered).zip(reference) {
+        let (px, py) = (p[0], mirror * p[1]);
+        let x = cos * px - sin * py + cr[0];
+        let y = sin * px + cos * py + cr[1];
+        residual += (x - q[0] as f64).powi(2) + (y - q[1] as f64).powi(2);
+        *pos = [x as f32, y as f32];
+    }
+    residual
+}
+
+/// Generalized Procrustes analysis: align every layout to their common mean
+/// and return `(consensus, residual variance)`.
+///
+/// Starts from the first layout as reference and alternates aligning all
+/// layouts to the reference with replacing the reference by their mean,
+/// until the mean moves by less than 1e-9 of its spread (at most 100
+/// rounds). The residual variance is the mean squared distance of a node in
+/// one run from its consensus position; it is 0 when all runs agree up to
+/// rigid motions. `layouts` are aligned in place.
+pub fn generalized_procrustes(layouts: &mut [Vec<[f32; 2]>]) -> (Vec<[f32; 2]>, f64) {
+    let Some(first) = layouts.first() else {
+        return (Vec::new(), 0.0);
+    };
+    let n = first.len();
+    let mut consensus = first.clone();
+    for _ in 0..100 {
+        for layout in layouts.iter_mut() {
+            procrustes_align(&consensus, layout);
+        }
+        let mut mean = vec![[0.0f64; 2]; n];
+        for layout in layouts.iter() {
+            for (m, p) in mean.iter_mut().zip(layout) {
+                m[0] += p[0] as f64 / layouts.len() as f64;
+                m[1] += p[1] as f64 / layouts.len() as f64;
+            }
+        }
+        let (mut moved, mut spread) = (0.0, 0.0);
+        for (m, c) in mean.iter().zip(&consensus) {
+            moved += (m[0] - c[0] as f64).powi(2) + (m[1] - c[1] as f64).powi(2);
+            spread += m[0] * m[0] + m[1] * m[1];
+        }
+        consensus = mean.iter().map(|m| [m[0] as f32, m[1] as f32]).collect();
+        if moved <= 1e-9 * spread.max(f64::MIN_POSITIVE) {
+            break;
+        }
+    }
+
+    let mut residual = 0.0;
+    for layout in layouts.iter() {
+        for (p, c) in layout.iter().zip(&consensus) {
+            residual += (p[0] as f64 - c[0] as f64).powi(2) + (p[1] as f64 - c[1] as f64).powi(2);
+        }
+    }
+    let variance = residual / (layouts.len() * n).max(1) as f64;
+    (consensus, variance)
+}
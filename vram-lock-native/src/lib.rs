@@ -0,0 +1,49 @@
+//! Stress-based graph layout by stochastic gradient descent, on Metal,
+//! wgpu or the CPU.
+//!
+//! The `vram-lock-native` binary is a command-line wrapper over this crate.
+//! A minimal run from another project:
+//!
+//! ```ignore
+//! use vram_lock_native::Graph;
+//!
+//! let graph = Graph::from_mtx(std::path::Path::new("data/1138_bus.mtx"))?;
+//! let params = graph.prepare_sgd_params(15, 0.1, true);
+//! let (initial, layout) = vram_lock_native::execute_sgd(params, "auto")?;
+//! println!("{} iterations in {:?}", layout.iterations, layout.elapsed);
+//! let positions = layout.into_positions();
+//! ```
+//!
+//! [`LayoutBuilder`] covers the precompute options (seeds, pair weights,
+//! distance caches, ...) in one chain.
+
+pub mod archive;
+pub mod barnes_hut;
+pub mod builder;
+pub mod cpu;
+pub mod force;
+pub mod gpu;
+pub mod graph;
+pub mod layout;
+#[cfg(target_os = "macos")]
+pub mod metal;
+pub mod metrics;
+pub mod multilevel;
+pub mod online;
+pub mod output;
+#[cfg(feature = "pair-order-debug")]
+pub mod pair_order;
+
+pub use builder::{create_backend, init_backend, Backend, LayoutBuilder, SgdBackend};
+pub use graph::{EdgeInfo, Graph, LayoutPair, SgdParams};
+pub use layout::Layout;
+#[cfg(target_os = "macos")]
+pub use metal::MetalContext;
+
+/// Run precomputed `params` on backend `backend` (`metal`, `wgpu`, `cpu` or
+/// `auto`, see [`create_backend`]); returns the initial positions and the
+/// final [`Layout`].
+pub fn execute_sgd(params: SgdParams, backend: &str) -> anyhow::Result<LayoutPair> {
+    params.validate()?;
+    create_backend(backend)?.execute(params)
+}
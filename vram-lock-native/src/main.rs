@@ -1,98 +1,1468 @@
-mod graph;
-mod metal;
+#[cfg(feature = "pair-order-debug")]
+use vram_lock_native::pair_order;
+use vram_lock_native::{
+    archive, barnes_hut, builder, cpu, force, graph, layout, metrics, multilevel, online, output,
+};
 
-use std::path::Path;
-use std::time::Instant;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use anyhow::Result;
-use std::fs::File;
-use std::io::Write;
+use builder::{init_backend, Backend};
 use chrono::Local;
+use clap::{Args, Parser, Subcommand};
+
+/// Stress-minimizing graph layout by SGD on Metal, wgpu or the CPU.
+///
+/// Without a subcommand the graph is loaded, laid out and the initial and
+/// final layouts are written to ../output.
+#[derive(Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    layout: LayoutArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the CPU precompute and save the SGD parameters
+    Precompute {
+        /// Where to save the parameters
+        params_file: PathBuf,
+        #[command(flatten)]
+        layout: Box<LayoutArgs>,
+    },
+    /// Skip graph loading and the BFS and run from a saved parameter file
+    Run {
+        #[arg(long)]
+        params: PathBuf,
+        #[command(flatten)]
+        layout: Box<LayoutArgs>,
+    },
+    /// Print the common scale reference for a batch of mtx files
+    Reference {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Grow the layout batch by batch (see online.rs)
+    Online(OnlineArgs),
+    /// Summarize a bundle written by --archive
+    Inspect { archive: PathBuf },
+    /// Compare the update strategies on the same inputs
+    Bench(BenchArgs),
+    /// Average several seeded runs (generalized Procrustes)
+    Consensus(ConsensusArgs),
+}
+
+/// Options of a single layout run.
+#[derive(Args)]
+struct LayoutArgs {
+    /// Matrix Market input (default ../data/bcspwr10.mtx)
+    #[arg(long, conflicts_with_all = ["edge_list", "graphml"])]
+    input: Option<PathBuf>,
+    /// Read a `src dst` edge list instead
+    #[arg(long, conflicts_with = "graphml")]
+    edge_list: Option<PathBuf>,
+    /// Node ids in the edge list start at 1
+    #[arg(long)]
+    one_indexed: bool,
+    /// Read a GraphML file instead
+    #[arg(long)]
+    graphml: Option<PathBuf>,
+    /// Refuse Matrix Market files whose pattern is not symmetric
+    #[arg(long)]
+    strict_symmetry: bool,
+    /// Which matrix entries become edges: auto, upper, lower or both (auto
+    /// keeps one edge per node pair; see `graph::Triangle`)
+    #[arg(long, value_parser = parse_triangle, default_value = "auto")]
+    triangle: graph::Triangle,
+    /// Overlay an edge set on the base graph, as FILE[:WEIGHT] (repeatable,
+    /// WEIGHT defaults to 1)
+    #[arg(long = "layer", value_parser = parse_layer)]
+    layers: Vec<(PathBuf, f64)>,
+
+    /// Backend: metal, wgpu, cpu or auto
+    #[arg(long, env = "GPU_BACKEND", default_value = "metal")]
+    backend: String,
+    /// stress, fr[:K[:REPULSION]] or bh[:THETA[:RADIUS]] (K and REPULSION
+    /// default to 1, THETA to 0.5 and RADIUS to 3; fr and bh need --backend
+    /// cpu or auto)
+    #[arg(long, value_parser = parse_algorithm, default_value = "stress")]
+    algorithm: graph::Algorithm,
+    /// Seed of the random initial positions and of the CPU pair shuffles
+    /// (drawn at random when not given, and recorded in the output header
+    /// either way)
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Multiply the weights of the node pairs listed in this file
+    #[arg(long)]
+    pair_weights: Option<PathBuf>,
+    /// SGD iterations
+    #[arg(long, default_value_t = 15)]
+    iterations: usize,
+    /// Final step size relative to the first
+    #[arg(long, value_parser = parse_positive_f64, default_value_t = 0.1)]
+    epsilon: f64,
+    /// Center the initial positions (default)
+    #[arg(long, overrides_with = "no_center")]
+    center: bool,
+    /// Keep the initial positions in the unit square
+    #[arg(long, overrides_with = "center")]
+    no_center: bool,
+    /// Compress long graph distances: identity, sqrt or log
+    #[arg(long, value_parser = parse_distance_transform, default_value = "identity")]
+    distance_transform: graph::DistanceTransform,
+    /// Learning-rate schedule: exp or cosine
+    #[arg(long, value_parser = ["exp", "cosine"], default_value = "exp")]
+    schedule: String,
+    /// Edges plus sampled repulsion, no all-pairs BFS
+    #[arg(long)]
+    edge_only: bool,
+    /// Repulsion partners per node with --edge-only
+    #[arg(long, default_value_t = 5)]
+    repulsion_samples: usize,
+    /// Sparse stress against K max-min pivots, no all-pairs BFS (with
+    /// --algorithm bh: the pivots estimating the far-term hop distances,
+    /// default 50)
+    #[arg(long, value_name = "K")]
+    pivots: Option<usize>,
+    /// Weak pairs between the components of a disconnected graph so they
+    /// stay about a diameter apart
+    #[arg(long)]
+    connect_components: bool,
+    /// Reuse distance matrices of structurally identical graphs
+    #[arg(long, value_name = "DIR")]
+    dist_cache: Option<PathBuf>,
+
+    /// Initial distribution: square, disk or gaussian[:STD] (STD defaults
+    /// to 0.25)
+    #[arg(long, value_parser = parse_init_dist)]
+    init_dist: Option<graph::InitDistribution>,
+    /// Lattice start for grid/mesh graphs numbered row-major (W is detected
+    /// from the edges when omitted)
+    #[arg(long, value_name = "W", num_args = 0..=1, value_parser = clap::value_parser!(u64).range(1..))]
+    init_grid: Option<Option<u64>>,
+    /// One importance weight per node; heavier nodes start nearer the centre
+    #[arg(long)]
+    init_weights: Option<PathBuf>,
+    /// Text coordinates, or a binary positions file (.bin)
+    #[arg(long)]
+    init_coords: Option<PathBuf>,
+    /// Warm start from a previous run's output
+    #[arg(long, value_name = "RESULT")]
+    continue_from: Option<PathBuf>,
+    /// Toroidal boundary of WxH
+    #[arg(long, value_name = "WxH", value_parser = parse_torus)]
+    torus: Option<(f64, f64)>,
+    /// Convex polygon boundary X1,Y1:X2,Y2:X3,Y3[:...]
+    #[arg(long, value_parser = parse_polygon)]
+    polygon: Option<Vec<[f64; 2]>>,
+    /// Lay out in 2 or 3 dimensions (3D runs stress SGD on the open plane;
+    /// the x/y start gains a random z coordinate)
+    #[arg(long, value_parser = parse_dim, default_value = "2")]
+    dim: usize,
+    /// `node x y` lines; those nodes start at (x, y) and stay there
+    #[arg(long, conflicts_with_all = ["multilevel", "recenter_each_iteration", "target_width"])]
+    pin: Option<PathBuf>,
+
+    /// Start with the longest-range pairs and add shorter ones until
+    /// FULL_AT of the iterations (defaults 0.1:0.5)
+    #[arg(long, value_name = "START[:FULL_AT]", num_args = 0..=1, value_parser = parse_pair_growth)]
+    pair_growth: Option<Option<graph::PairGrowth>>,
+    /// Reorder the pairs into colors without shared nodes and run one
+    /// lock-free pass per color on the GPU
+    #[arg(long)]
+    pair_coloring: bool,
+
+    /// Write the Shepard diagram data as CSV
+    #[arg(long)]
+    shepard: Option<PathBuf>,
+    /// Write the per-node stress as CSV
+    #[arg(long)]
+    node_stress: Option<PathBuf>,
+    /// Report the full stress over all pairs
+    #[arg(long)]
+    stress: bool,
+    /// Report the stress gradient norm before and after the run
+    #[arg(long)]
+    gradient_norm: bool,
+    /// Fix the global scale stress leaves free, so distances in the result
+    /// are in target-distance units
+    #[arg(long)]
+    optimal_scale: bool,
+    /// Bundle the starting parameters with the result
+    #[arg(long)]
+    archive: Option<PathBuf>,
+
+    /// Stop early after SECS and keep the best-so-far layout
+    #[arg(long, value_name = "SECS", value_parser = parse_positive_f64)]
+    time_budget: Option<f64>,
+    /// Write every K-th intermediate layout (and the last) to --snapshot-dir
+    /// as it is produced
+    #[arg(long, value_name = "K", value_parser = clap::value_parser!(u64).range(1..))]
+    snapshot_every: Option<u64>,
+    /// Directory of the --snapshot-every frames (default a timestamped one
+    /// in ../output)
+    #[arg(long, value_name = "DIR")]
+    snapshot_dir: Option<PathBuf>,
+    /// Warn about GPU iterations slower than MS
+    #[arg(long, value_name = "MS", value_parser = parse_positive_f64)]
+    slow_iteration_ms: Option<f64>,
+    /// Stop at the first iteration slower than --slow-iteration-ms
+    #[arg(long, requires = "slow_iteration_ms")]
+    abort_on_slow_iteration: bool,
+    /// Keep the centroid at the origin between iterations
+    #[arg(long)]
+    recenter_each_iteration: bool,
+    /// Rescale after every iteration (and once more at the end) so the
+    /// layout's x-extent is W, y scaled alike
+    #[arg(long, value_name = "W", value_parser = parse_positive_f64)]
+    target_width: Option<f64>,
+    /// Threads per workgroup on the GPU backends (default 32 on Metal; on
+    /// wgpu whole subgroups up to the device limit, at most 256)
+    #[arg(long)]
+    workgroup_size: Option<u32>,
+    /// Print the updated pair count and the largest node displacement after
+    /// every Metal iteration (two position readbacks per iteration)
+    #[arg(long)]
+    progress: bool,
+    /// Poll the live positions from a second thread every MS and print
+    /// their extent while the layout runs
+    #[arg(long, value_name = "MS", value_parser = parse_positive_f64)]
+    watch: Option<f64>,
+    /// Log the CPU backend's pair order to FILE
+    #[cfg(feature = "pair-order-debug")]
+    #[arg(long, value_name = "FILE", conflicts_with = "replay_pair_order")]
+    record_pair_order: Option<PathBuf>,
+    /// Force the CPU backend's pair order from FILE
+    #[cfg(feature = "pair-order-debug")]
+    #[arg(long, value_name = "FILE")]
+    replay_pair_order: Option<PathBuf>,
+    /// Coarsen up to LEVELS times (default 8), lay out the coarsest graph
+    /// with --iterations and refine every finer level with
+    /// --level-iterations
+    #[arg(long, value_name = "LEVELS", num_args = 0..=1)]
+    multilevel: Option<Option<usize>>,
+    /// Iterations per refined level with --multilevel
+    #[arg(long, default_value_t = 10)]
+    level_iterations: usize,
+    /// Iterations of the force-directed algorithm
+    #[arg(long, default_value_t = 300)]
+    fr_iterations: usize,
+    /// Also write the phase timings as JSON
+    #[arg(long)]
+    timing_json: Option<PathBuf>,
+
+    /// Scale both layouts by a common reference (see the `reference`
+    /// subcommand)
+    #[arg(long, value_name = "D", value_parser = parse_positive_f64)]
+    scale_reference: Option<f64>,
+    /// Snap the positions to a grid of this cell size
+    #[arg(long, value_name = "CELL_SIZE", value_parser = parse_positive_f64)]
+    snap_grid: Option<f64>,
+    /// Move nodes snapped into the same cell apart
+    #[arg(long)]
+    snap_grid_unique: bool,
+    /// Output format: txt, csv, json, svg, bin or py
+    #[arg(long, value_parser = parse_format, default_value = "txt")]
+    format: output::Format,
+    /// Leave the edges section out of the text format
+    #[arg(long)]
+    positions_only: bool,
+    /// Bits per written coordinate: 32 or 64. Every backend returns f32
+    /// positions, so 64 writes those values widened to f64 (the exact binary
+    /// value) rather than extra precision; it needs --backend cpu
+    #[arg(long, value_parser = parse_output_precision, default_value = "32")]
+    output_precision_bits: output::Precision,
+    /// Write edges as sorted, deduplicated (min, max) pairs so files diff
+    /// cleanly regardless of input edge order
+    #[arg(long)]
+    sorted_edges: bool,
+    /// Write the final layout to exactly this file instead of a timestamped
+    /// name; without --output-initial the initial layout is not written
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Write the initial layout to exactly this file
+    #[arg(long)]
+    output_initial: Option<PathBuf>,
+    /// Separate exactly overlapping nodes in the written layout by AMOUNT
+    #[arg(long, value_name = "AMOUNT", value_parser = parse_positive_f64)]
+    jitter_duplicates: Option<f64>,
+    /// Report edges stacked on top of each other (nearly collinear within
+    /// TOL, default 0.01 target-distance units)
+    #[arg(long, value_name = "TOL", num_args = 0..=1)]
+    overlapping_edges: Option<Option<f64>>,
+    /// Map node indices back to the GraphML ids
+    #[arg(long, requires = "graphml")]
+    node_ids: Option<PathBuf>,
+    /// Write the positions as positions.npy into DIR
+    #[arg(long, value_name = "DIR")]
+    npy: Option<PathBuf>,
+    /// Also write the distance matrix as dist.npy
+    #[arg(long, requires = "npy")]
+    npy_dist: bool,
+}
+
+#[derive(Args)]
+struct OnlineArgs {
+    /// Edge batches (see `online::read_edge_batches`)
+    edges_file: PathBuf,
+    /// Hop radius of the local update
+    #[arg(long, default_value_t = 3)]
+    online_radius: usize,
+    /// SGD iterations per batch
+    #[arg(long, default_value_t = 10)]
+    online_iterations: usize,
+    /// Final step size of each batch relative to its first
+    #[arg(long, value_parser = parse_positive_f64, default_value_t = 0.1)]
+    epsilon: f64,
+    /// Seed of the new nodes' starting offsets and of the CPU pair shuffles
+    /// (drawn at random when not given)
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Backend: metal, wgpu, cpu or auto
+    #[arg(long, default_value = "auto")]
+    backend: String,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    /// Matrix Market inputs
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// SGD iterations per run
+    #[arg(long, default_value_t = 15)]
+    bench_iterations: usize,
+}
+
+#[derive(Args)]
+struct ConsensusArgs {
+    /// Matrix Market input
+    path: PathBuf,
+    /// Number of seeded runs
+    #[arg(long, default_value_t = 5)]
+    runs: usize,
+    /// Runs use seeds SEED..SEED+RUNS
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// SGD iterations per run
+    #[arg(long, default_value_t = 15)]
+    iterations: usize,
+    /// Backend: metal, wgpu, cpu or auto
+    #[arg(long, default_value = "auto")]
+    backend: String,
+    /// Fit every run to width W
+    #[arg(long, value_name = "W", value_parser = parse_positive_f64)]
+    target_width: Option<f64>,
+    /// Where to write the consensus (default a timestamped file in
+    /// ../output)
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
 
 fn main() -> Result<()> {
     env_logger::init();
 
-    let mtx_path = Path::new("../data/bcspwr10.mtx");
-    let graph = graph::Graph::from_mtx(mtx_path).expect("Failed to load matrix");
+    let cli = Cli::parse();
+    match cli.command {
+        None => run_layout(cli.layout, None, None),
+        Some(Command::Precompute { params_file, layout }) => run_layout(*layout, None, Some(params_file)),
+        Some(Command::Run { params, layout }) => run_layout(*layout, Some(params), None),
+        Some(Command::Reference { paths }) => print_batch_reference(&paths),
+        Some(Command::Online(args)) => run_online(&args),
+        Some(Command::Inspect { archive }) => inspect_archive(&archive),
+        Some(Command::Bench(args)) => run_update_benchmark(&args),
+        Some(Command::Consensus(args)) => run_consensus(&args),
+    }
+}
 
-    // let graph = {
-    //     graph::Graph {
-    //         node_size: 10,
-    //         edge_size: 10,
-    //         edge_src: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
-    //         edge_dst: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 0],
-    //     }
-    // };
+impl LayoutArgs {
+    /// `--center` / `--no-center`; the last one given wins, centered if
+    /// neither is.
+    fn center(&self) -> bool {
+        self.center || !self.no_center
+    }
+
+    /// `--format`, with `--positions-only` applied to the text format
+    fn output_format(&self) -> output::Format {
+        match self.format {
+            output::Format::Txt if self.positions_only => output::Format::TxtPositionsOnly,
+            format => format,
+        }
+    }
+}
+
+/// Lay out one graph. `params_path` (the `run` subcommand) skips graph
+/// loading and the BFS; with `precompute_out` (the `precompute` subcommand)
+/// the parameters are saved there instead of running.
+fn run_layout(args: LayoutArgs, params_path: Option<PathBuf>, precompute_out: Option<PathBuf>) -> Result<()> {
+    let mtx_path = args
+        .edge_list
+        .as_deref()
+        .or(args.graphml.as_deref())
+        .or(args.input.as_deref())
+        .unwrap_or(Path::new("../data/bcspwr10.mtx"));
+    let mut node_ids = None;
+    let (graph, loaded_params) = match &params_path {
+        Some(path) => {
+            let params = graph::SgdParams::load(path)?;
+            println!("Loaded SGD params from {}", path.display());
+            let graph = graph::Graph::from_unit_pairs(params.positions.len(), &params.pairs);
+            (graph, Some(params))
+        }
+        None if args.graphml.is_some() => {
+            let (graph, ids) = graph::Graph::from_graphml(mtx_path)?;
+            println!("Loaded {} nodes, {} edges from {}", graph.node_size, graph.edge_size, mtx_path.display());
+            node_ids = Some(ids);
+            (graph, None)
+        }
+        None if args.edge_list.is_some() => (graph::Graph::from_edge_list(mtx_path, args.one_indexed)?, None),
+        None => {
+            let policy = if args.strict_symmetry {
+                graph::AsymmetryPolicy::Error
+            } else {
+                graph::AsymmetryPolicy::Warn
+            };
+            (graph::Graph::from_mtx_with_options(mtx_path, policy, args.triangle)?, None)
+        }
+    };
+    let layers = &args.layers;
+    let layer_weights: Vec<f64> = std::iter::once(1.0).chain(layers.iter().map(|l| l.1)).collect();
+    let graph = if layers.is_empty() {
+        graph
+    } else {
+        let mut graphs = vec![graph];
+        for (path, weight) in layers {
+            println!("Adding layer {} from {} (weight {})", graphs.len(), path.display(), weight);
+            graphs.push(graph::Graph::from_mtx(path)?);
+        }
+        graph::Graph::from_layers(graphs)
+    };
+    let data_name = match &params_path {
+        Some(path) => path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+        None => mtx_path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+    };
+
+    // let graph = graph::Graph::from_edges(
+    //     10,
+    //     vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+    //     vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 0],
+    // )?;
 
     // LOG: Print graph information
     // println!("{:?}",graph);
 
-    // Choose backend: "metal" or "wgpu"
-    let backend = std::env::var("GPU_BACKEND").unwrap_or_else(|_| "metal".to_string());
+    // Choose backend: "metal", "wgpu", "cpu" or "auto" (--backend or GPU_BACKEND)
+    let backend = args.backend.clone();
+    builder::check_backend(&backend)?;
     // let backend = "default";
     println!("Using GPU backend: {}", backend);
     
     let start = Instant::now();
 
+    let algorithm = args.algorithm;
+    println!("Layout algorithm: {:?}", algorithm);
+    // There are no force-directed or Barnes-Hut kernels; say so instead of
+    // ignoring a GPU backend
+    let cpu_only = match algorithm {
+        graph::Algorithm::StressSgd => None,
+        graph::Algorithm::ForceDirected { .. } => Some("fr"),
+        graph::Algorithm::BarnesHut { .. } => Some("bh"),
+    };
+    if let Some(name) = cpu_only.filter(|_| !matches!(backend.as_str(), "cpu" | "auto")) {
+        anyhow::bail!("--algorithm {} runs on the CPU only; use --backend cpu or auto", name);
+    }
+    if args.output_precision_bits == output::Precision::F64 && backend != "cpu" {
+        anyhow::bail!("--output-precision-bits 64 needs --backend cpu");
+    }
+
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let mut seed_used = None;
+    let mut epsilon_used = None;
+
     // CPU precompute
-    let sgd_params = graph.prepare_sgd_params(15, 0.1, true);
-    let initial_positions;
-    let result;
+    let mut sgd_params = match loaded_params {
+        Some(params) => params,
+        None => {
+            let mut pair_weights = if layers.is_empty() {
+                HashMap::new()
+            } else {
+                graph.layer_pair_weights(&layer_weights)
+            };
+            if let Some(path) = &args.pair_weights {
+                let overrides = graph::read_pair_weights(path)?;
+                println!("Loaded {} pair weight overrides from {}", overrides.len(), path.display());
+                for (key, w) in overrides {
+                    *pair_weights.entry(key).or_insert(1.0) *= w;
+                }
+            }
+            let iterations = args.iterations;
+            let epsilon = args.epsilon;
+            let mut builder = builder::LayoutBuilder::new()
+                .iterations(iterations)
+                .epsilon(epsilon)
+                .center(args.center())
+                .seed(seed)
+                .pair_weights(pair_weights);
+            (seed_used, epsilon_used) = (Some(seed), Some(epsilon));
+            let transform = args.distance_transform;
+            if transform != graph::DistanceTransform::Identity {
+                builder = builder.distance_transform(transform);
+                println!("Distance transform: {:?}", transform);
+            }
+            if args.schedule == "cosine" {
+                builder = builder.schedule(graph::cosine_schedule(iterations, epsilon));
+                println!("Using cosine learning-rate schedule");
+            }
+            let (edge_only, samples) = (args.edge_only, args.repulsion_samples);
+            if let graph::Algorithm::BarnesHut { radius, .. } = algorithm {
+                builder = builder.neighborhood(radius);
+            } else if algorithm != graph::Algorithm::StressSgd {
+                // Force-directed runs only use the initial positions
+                builder = builder.edge_only(0);
+            } else if let Some(pivots) = args.pivots {
+                builder = builder.sparse(pivots);
+            } else if edge_only {
+                builder = builder.edge_only(samples);
+            }
+            if args.connect_components {
+                builder = builder.connect_components(true);
+            }
+            if let Some(dir) = &args.dist_cache {
+                builder = builder.dist_cache(dir.clone());
+            }
+            let params = builder.params(&graph)?;
+            if edge_only {
+                println!("Edge-only mode: {} pairs ({} repulsion samples per node)", params.pairs.len(), samples);
+            }
+            params
+        }
+    };
+    sgd_params.seed = Some(seed);
+    if let Some(distribution) = args.init_dist {
+        sgd_params.positions = graph::init_positions_seeded(graph.node_size, true, distribution, seed);
+        seed_used = Some(seed);
+        println!("Initial positions drawn from {:?}", distribution);
+    }
+    if let Some(width) = args.init_grid {
+        let width = match width {
+            Some(w) => Some(w as usize),
+            None => graph.detect_grid_width(),
+        };
+        match width {
+            Some(width) => {
+                sgd_params.positions = graph::init_positions_grid(graph.node_size, width, true);
+                seed_used = None;
+                println!("Initial positions on a lattice of width {}", width);
+            }
+            None => println!("Warning: no grid structure detected; keeping random initial positions"),
+        }
+    }
+    if let Some(path) = &args.init_weights {
+        sgd_params.positions = graph::init_positions_from_weights(path, graph.node_size, true, seed)?;
+        seed_used = Some(seed);
+        println!("Initial positions biased by node weights from {}", path.display());
+    }
+    if let Some(path) = &args.init_coords {
+        sgd_params.positions = if path.extension().is_some_and(|e| e == "bin") {
+            let mut positions = output::read_positions_bin(path, graph.node_size)?;
+            graph::center_positions(&mut positions);
+            positions
+        } else {
+            graph::init_positions_from_coords(path, graph.node_size, true)?
+        };
+        seed_used = None;
+        println!("Initial positions loaded from {}", path.display());
+    }
+    if let Some(path) = &args.continue_from {
+        sgd_params.positions = graph::init_positions_from_result(path, &graph)?;
+        seed_used = None;
+        println!("Continuing from positions in {}", path.display());
+    }
+    if let Some((width, height)) = args.torus {
+        sgd_params.boundary = graph::BoundaryMode::Toroidal { width, height };
+        println!("Using toroidal boundary {}x{}", width, height);
+    }
+    if let Some(vertices) = &args.polygon {
+        println!("Constraining layout to a {}-vertex polygon", vertices.len());
+        sgd_params.boundary = graph::BoundaryMode::Polygon(vertices.clone());
+    }
+    // Params saved from a 3D precompute already carry their third coordinate
+    let dims = if sgd_params.positions_z.is_empty() { args.dim } else { 3 };
+    if dims == 3 {
+        anyhow::ensure!(algorithm == graph::Algorithm::StressSgd, "--dim 3 only supports --algorithm stress");
+        if sgd_params.positions_z.is_empty() {
+            sgd_params.positions_z = graph::init_positions_z(graph.node_size, args.center(), seed_used);
+        }
+    }
+    if let Some(path) = &args.pin {
+        anyhow::ensure!(algorithm == graph::Algorithm::StressSgd, "--pin only supports --algorithm stress");
+        let pins = graph::read_pins(path)?;
+        sgd_params.pin(&pins)?;
+        println!("Pinned {} nodes from {}", pins.len(), path.display());
+    }
+
+    sgd_params.validate()?;
+    let precompute_duration = start.elapsed();
+
+    if let Some(out) = precompute_out {
+        sgd_params.save(&out)?;
+        println!("SGD params saved to {} ({:.3}s)", out.display(), start.elapsed().as_secs_f64());
+        return Ok(());
+    }
+
+    if let Some(growth) = args.pair_growth {
+        sgd_params.apply_pair_growth(growth.unwrap_or_default());
+        let full_from = sgd_params.active_pairs.iter().position(|&k| k == sgd_params.pairs.len());
+        println!(
+            "Pair growth: {} of {} pairs in iteration 0, all from iteration {}",
+            sgd_params.active_pairs.first().copied().unwrap_or(0),
+            sgd_params.pairs.len(),
+            full_from.map_or("-".to_string(), |t| t.to_string())
+        );
+    }
+
+    if args.pair_coloring {
+        let color_start = std::time::Instant::now();
+        let colors = sgd_params.apply_pair_coloring();
+        println!(
+            "Pair coloring: {} colors for {} pairs ({:.3}s)",
+            colors,
+            sgd_params.pairs.len(),
+            color_start.elapsed().as_secs_f64()
+        );
+        sgd_params.validate()?;
+    }
+
+    if dims == 3 {
+        let provenance = output::Provenance {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            backend: backend.clone(),
+            algorithm: format!("{:?}", algorithm),
+            iterations: sgd_params.etas.len(),
+            epsilon: epsilon_used,
+            seed: Some(seed),
+        };
+        return run_3d(&args, &graph, sgd_params, &backend, &data_name, &provenance);
+    }
+
+    // Keep the pairs around only when a post-run diagnostic needs them.
+    let shepard_path = args.shepard.clone();
+    let node_stress_path = args.node_stress.clone();
+    let full_stress = args.stress;
+    let gradient_norm = args.gradient_norm;
+    let optimal_scale = args.optimal_scale;
+    let pairs = if shepard_path.is_some()
+        || node_stress_path.is_some()
+        || full_stress
+        || gradient_norm
+        || optimal_scale
+    {
+        sgd_params.pairs.clone()
+    } else {
+        Vec::new()
+    };
+
+    let archive_path = args.archive.clone();
+    let archived_params = archive_path.as_ref().map(|_| sgd_params.clone());
+
+    let mut initial_positions;
+    let layout;
     
-    if backend == "metal" {
-        // Metal backend
-        let metal_context = metal::MetalContext::new()?;
-        let (init_pos, final_pos) = metal_context.execute_sgd(sgd_params)?;
-        initial_positions = init_pos;
-        result = final_pos;
+    // Ctrl-C stops after the current iteration; the partial layout is still saved.
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+    ctrlc::set_handler(move || flag.store(true, Ordering::SeqCst))?;
+
+    let time_budget = args.time_budget.map(Duration::from_secs_f64);
+
+    let snapshot_every = args.snapshot_every.map(|k| k as usize);
+    let snapshot_dir = args.snapshot_dir.clone().unwrap_or_else(|| {
+        PathBuf::from(format!("../output/snapshots-{}-{}", data_name, Local::now().format("%Y%m%d_%H%M%S")))
+    });
+    if let Some(every) = snapshot_every {
+        std::fs::create_dir_all(&snapshot_dir)?;
+        println!("Writing snapshots every {} iterations to {}", every, snapshot_dir.display());
+    }
+
+    let slow_iteration = args
+        .slow_iteration_ms
+        .map(|ms| (Duration::from_secs_f64(ms / 1000.0), args.abort_on_slow_iteration));
+
+    let recenter = args.recenter_each_iteration;
+    let target_width = args.target_width;
+    let workgroup_size = args.workgroup_size;
+
+    // Only publish live positions when something reads them
+    let live = graph::LivePositions::new();
+    let watching = args.watch.is_some();
+    let watch_done = Arc::new(AtomicBool::new(false));
+    let watcher = args.watch.map(|ms| {
+        let (live, done) = (live.clone(), watch_done.clone());
+        std::thread::spawn(move || {
+            while !done.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_secs_f64(ms / 1000.0));
+                let positions = live.current_positions();
+                if positions.is_empty() {
+                    continue;
+                }
+                let (mut min, mut max) = ([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]);
+                for p in &positions {
+                    for k in 0..2 {
+                        min[k] = min[k].min(p[k]);
+                        max[k] = max[k].max(p[k]);
+                    }
+                }
+                println!(
+                    "Watch: extent [{:.3}, {:.3}] x [{:.3}, {:.3}]",
+                    min[0], max[0], min[1], max[1]
+                );
+            }
+        })
+    });
+
+    #[cfg(feature = "pair-order-debug")]
+    let (mut pair_order, pair_order_recording) = parse_pair_order(&args, &sgd_params, &backend)?;
+    #[cfg(not(feature = "pair-order-debug"))]
+    let mut pair_order: Option<graph::PairOrderFn> = None;
+
+    let multilevel_levels = args.multilevel.map(|levels| levels.unwrap_or(8));
+    let level_iterations = args.level_iterations;
+
+    let timing_json = args.timing_json.clone();
+    let timings = graph::PhaseTimings::new();
+    let num_pairs = sgd_params.pairs.len();
+
+    let provenance_iterations = sgd_params.etas.len();
+    let backend_used;
+    let layout_start = Instant::now();
+
+    // Force-directed layouts run on the CPU and need neither pairs nor a GPU
+    if let graph::Algorithm::ForceDirected { k, repulsion } = algorithm {
+        (initial_positions, layout) =
+            force::execute_layout(&graph, sgd_params.positions, args.fr_iterations, k, repulsion);
+        backend_used = "cpu";
+    } else if let graph::Algorithm::BarnesHut { theta, radius } = algorithm {
+        let distances = barnes_hut::PivotDistances::new(&graph, args.pivots.unwrap_or(barnes_hut::DEFAULT_PIVOTS));
+        (initial_positions, layout) = barnes_hut::execute_sgd(sgd_params, &distances, radius, theta);
+        backend_used = "cpu";
+    } else if let Some(levels) = multilevel_levels {
+        let ctx = builder::create_backend(&backend)?;
+        let coarse_iterations = sgd_params.etas.len();
+        (initial_positions, layout) = multilevel::execute_layout(
+            ctx.as_ref(),
+            &graph,
+            sgd_params,
+            levels,
+            coarse_iterations,
+            level_iterations,
+            epsilon_used.unwrap_or(0.1),
+        )?;
+        backend_used = ctx.name();
     } else {
-        anyhow::bail!("Unsupported backend: {}. Only 'metal' is supported.", backend);
+        match init_backend(&backend)? {
+            #[cfg(target_os = "macos")]
+            Backend::Metal(mut metal_context) => {
+                if let Some(budget) = time_budget {
+                    metal_context = metal_context.with_time_budget(budget);
+                }
+                if let Some((threshold, abort)) = slow_iteration {
+                    metal_context = metal_context.with_slow_iteration_threshold(threshold, abort);
+                }
+                if let Some(every) = snapshot_every {
+                    metal_context = metal_context.with_snapshots(every, snapshot_writer(&graph, &snapshot_dir));
+                }
+                if let Some(transform) = iteration_transform(recenter, target_width) {
+                    metal_context = metal_context.with_transform(transform);
+                }
+                if let Some(size) = workgroup_size {
+                    metal_context = metal_context.with_workgroup_size(size)?;
+                }
+                if watching {
+                    metal_context = metal_context.with_live_positions(live.clone());
+                }
+                if args.progress {
+                    metal_context = metal_context.with_progress(|stats| {
+                        println!(
+                            "Iteration {} - Updated {} pairs, max displacement {:.6}",
+                            stats.iteration, stats.updated_pairs, stats.max_displacement
+                        );
+                    });
+                }
+                let metal_context = metal_context.with_interrupt(interrupted).with_timings(timings.clone());
+                (initial_positions, layout) = metal_context.execute_sgd(sgd_params)?;
+                backend_used = "metal";
+            }
+            Backend::Wgpu(mut gpu_context) => {
+                if let Some(budget) = time_budget {
+                    gpu_context = gpu_context.with_time_budget(budget);
+                }
+                if let Some((threshold, abort)) = slow_iteration {
+                    gpu_context = gpu_context.with_slow_iteration_threshold(threshold, abort);
+                }
+                if let Some(every) = snapshot_every {
+                    gpu_context = gpu_context.with_snapshots(every, snapshot_writer(&graph, &snapshot_dir));
+                }
+                if let Some(transform) = iteration_transform(recenter, target_width) {
+                    gpu_context = gpu_context.with_transform(transform);
+                }
+                if let Some(size) = workgroup_size {
+                    gpu_context = gpu_context.with_workgroup_size(size)?;
+                }
+                if watching {
+                    gpu_context = gpu_context.with_live_positions(live.clone());
+                }
+                let gpu_context = gpu_context
+                    .with_interrupt(interrupted)
+                    .with_timings(timings.clone());
+                (initial_positions, layout) = gpu_context.execute_sgd(sgd_params)?;
+                backend_used = "wgpu";
+            }
+            Backend::Cpu => {
+                let writer = snapshot_every.map(|every| (every, snapshot_writer(&graph, &snapshot_dir)));
+                let snapshots = writer.as_ref().map(|(every, f)| (*every, f));
+                let mut transform = iteration_transform(recenter, target_width);
+                let options = cpu::CpuSgdOptions {
+                    interrupt: Some(&interrupted),
+                    time_budget,
+                    snapshots,
+                    transform: transform.as_mut(),
+                    live: watching.then_some(&live),
+                    pair_order: pair_order.as_mut(),
+                };
+                (initial_positions, layout) = cpu::execute_sgd(sgd_params, options);
+                backend_used = "cpu";
+            }
+        }
+    }
+    let layout_duration = layout_start.elapsed();
+    #[cfg(feature = "pair-order-debug")]
+    if let Some(recording) = pair_order_recording {
+        recording.save()?;
+    }
+    watch_done.store(true, Ordering::SeqCst);
+    if let Some(watcher) = watcher {
+        let _ = watcher.join();
     }
     
     let duration = start.elapsed();
     println!("Total execution:  {:.3}s (includes initialization, iterations, and result download)", duration.as_secs_f64());
+    if let Some(path) = timing_json {
+        // Backends without phase timings (CPU, force-directed, multilevel)
+        // count the whole layout step as iterations
+        let mut backend_timings = timings.get();
+        if backend_timings.iterations_run == 0 {
+            backend_timings.iterations = layout_duration;
+            backend_timings.iterations_run = layout.iterations;
+        }
+        let report = output::TimingReport {
+            total: duration,
+            precompute: precompute_duration,
+            backend: backend_timings,
+            nodes: graph.node_size,
+            pairs: num_pairs,
+        };
+        output::write_timing_json(&path, &report)?;
+        println!("Timings saved to {}", path.display());
+    }
+
+    let mut result = layout.into_positions();
+
+    if optimal_scale {
+        match metrics::optimal_scale(&result, &pairs) {
+            Some(s) => {
+                let before = metrics::stress(&result, &pairs);
+                layout::scale_to_reference(&mut result, 1.0 / s);
+                println!(
+                    "Optimal scale: {:.6} (stress {:.6} -> {:.6})",
+                    s,
+                    before,
+                    metrics::stress(&result, &pairs)
+                );
+            }
+            None => println!("Optimal scale: undefined (all pair distances are zero), layout left as is"),
+        }
+    }
+
+    // Non-SGD algorithms never ran the per-iteration transform
+    if let Some(width) = target_width {
+        layout::fit_width(&mut result, width);
+        println!("Scaled layout to width {}", width);
+    }
+
+    if let Some(reference) = args.scale_reference {
+        layout::scale_to_reference(&mut initial_positions, reference);
+        layout::scale_to_reference(&mut result, reference);
+        println!("Scaled positions by common reference {}", reference);
+    }
+
+    if let Some(cell_size) = args.snap_grid {
+        layout::snap_to_grid(&mut result, cell_size, args.snap_grid_unique);
+        println!("Snapped positions to grid (cell size {})", cell_size);
+    }
 
     // LOG: Print result
     // println!("Result: {:?}", result);
     
     // Save initial positions (after randomization) to file with timestamp
+    let format = args.output_format();
+    let precision = args.output_precision_bits;
+    let output_graph = if args.sorted_edges {
+        graph.with_sorted_edges()
+    } else {
+        graph.clone()
+    };
+    let provenance = output::Provenance {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        backend: backend_used.to_string(),
+        algorithm: format!("{:?}", algorithm),
+        iterations: provenance_iterations,
+        epsilon: epsilon_used,
+        seed: Some(seed),
+    };
+    let output_path = args.output.clone();
+    let output_initial_path = args.output_initial.clone();
     let timestamp = Local::now().format("%Y%m%d_%H%M%S");
-    let data_name = mtx_path.file_stem().unwrap_or_default().to_string_lossy();
-    let filename_init = format!("../output/vram-lock-{}-{}-0.txt", data_name, timestamp);
-    let mut file = File::create(&filename_init)?;
-    writeln!(file, "# Rust GPU Result (vram-lock) - Initial (Randomized)")?;
-    writeln!(file, "# Timestamp: {}", Local::now().format("%Y-%m-%d %H:%M:%S"))?;
-    writeln!(file, "# Node count: {}", graph.node_size)?;
-    writeln!(file, "# Edge count: {}", graph.edge_size)?;
-    writeln!(file, "")?;
-    writeln!(file, "# Edges (source target)")?;
-    for i in 0..graph.edge_size {
-        writeln!(file, "{} {}", graph.edge_src[i], graph.edge_dst[i])?;
-    }
-    writeln!(file, "")?;
-    writeln!(file, "# Positions (x y)")?;
-    for pos in &initial_positions {
-        writeln!(file, "{} {}", pos[0], pos[1])?;
-    }
-    println!("Initial result saved to {}", filename_init);
+    let filename_init = match (output_initial_path, &output_path) {
+        (Some(path), _) => Some(path),
+        (None, Some(_)) => None,
+        (None, None) => Some(PathBuf::from(format!(
+            "../output/vram-lock-{}-{}-0.{}",
+            data_name,
+            timestamp,
+            format.extension()
+        ))),
+    };
+    if let Some(filename_init) = filename_init {
+        output::write_layout(&filename_init, format, precision, "Initial (Randomized)", &output_graph, &initial_positions, Some(&provenance))?;
+        println!("Initial result saved to {}", filename_init.display());
+    }
     
+    // Cosmetic: separate exactly overlapping nodes in the written layout only;
+    // the diagnostics below still see the computed positions.
+    let mut output_positions = result.clone();
+    if let Some(amount) = args.jitter_duplicates {
+        let moved = layout::jitter_duplicates(&mut output_positions, amount);
+        println!("Jittered {} duplicate positions by {}", moved, amount);
+    }
+
     // Save processed result to file with timestamp
-    let filename_processed = format!("../output/vram-lock-{}-{}-1.txt", data_name, timestamp);
-    let mut file = File::create(&filename_processed)?;
-    writeln!(file, "# Rust GPU Result (vram-lock) - Processed")?;
-    writeln!(file, "# Timestamp: {}", Local::now().format("%Y-%m-%d %H:%M:%S"))?;
-    writeln!(file, "# Node count: {}", graph.node_size)?;
-    writeln!(file, "# Edge count: {}", graph.edge_size)?;
-    writeln!(file, "")?;
-    writeln!(file, "# Edges (source target)")?;
-    for i in 0..graph.edge_size {
-        writeln!(file, "{} {}", graph.edge_src[i], graph.edge_dst[i])?;
-    }
-    writeln!(file, "")?;
-    writeln!(file, "# Positions (x y)")?;
-    for pos in &result {
-        writeln!(file, "{} {}", pos[0], pos[1])?;
-    }
-    println!("Processed result saved to {}", filename_processed);
+    let filename_processed = output_path.unwrap_or_else(|| {
+        PathBuf::from(format!("../output/vram-lock-{}-{}-1.{}", data_name, timestamp, format.extension()))
+    });
+    output::write_layout(&filename_processed, format, precision, "Processed", &output_graph, &output_positions, Some(&provenance))?;
+    println!("Processed result saved to {}", filename_processed.display());
+
+    // Edge stress is cheap enough to always report; full stress needs all pairs.
+    let edge_stress = metrics::edge_stress(&result, &graph);
+    println!("Edge stress: {:.4}", edge_stress);
+    if full_stress {
+        println!("Full stress: {:.4}", metrics::stress(&result, &pairs));
+    }
+    if let Some(tolerance) = args.overlapping_edges {
+        let tolerance = tolerance.unwrap_or(0.01);
+        let overlaps = metrics::overlapping_edges(&result, &graph, tolerance);
+        println!("Overlapping edge pairs: {} (tolerance {})", overlaps.len(), tolerance);
+        for &(i, j) in overlaps.iter().take(10) {
+            println!(
+                "  edge {} ({}-{}) overlaps edge {} ({}-{})",
+                i, graph.edge_src[i], graph.edge_dst[i], j, graph.edge_src[j], graph.edge_dst[j]
+            );
+        }
+        if overlaps.len() > 10 {
+            println!("  ... and {} more", overlaps.len() - 10);
+        }
+    }
+    if gradient_norm {
+        println!(
+            "Stress gradient norm: initial {:.6e}, final {:.6e}",
+            metrics::stress_gradient_norm(&initial_positions, &pairs),
+            metrics::stress_gradient_norm(&result, &pairs)
+        );
+    }
+
+    if let (Some(archive_path), Some(params)) = (archive_path, archived_params) {
+        let stress = metrics::stress(&result, &params.pairs);
+        archive::write_archive(
+            &archive_path,
+            &graph,
+            &output_positions,
+            &params,
+            &[("stress", stress), ("edge_stress", edge_stress)],
+        )?;
+        println!("Archive saved to {}", archive_path.display());
+    }
+
+    if let Some(shepard_path) = shepard_path {
+        let shepard = metrics::shepard_data(&result, &pairs);
+        output::write_shepard_csv(&shepard_path, &shepard)?;
+        println!(
+            "Shepard data saved to {} (correlation {:.4})",
+            shepard_path.display(),
+            metrics::pearson_correlation(&shepard)
+        );
+    }
+
+    if let Some(ids_path) = &args.node_ids {
+        let ids = node_ids
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("--node-ids needs a --graphml input"))?;
+        output::write_node_ids_csv(ids_path, ids)?;
+        println!("Node ids saved to {}", ids_path.display());
+    }
+
+    if let Some(node_stress_path) = node_stress_path {
+        let node_stress = metrics::node_stress(&result, &pairs);
+        output::write_node_values_csv(&node_stress_path, "stress", &node_stress)?;
+        println!("Per-node stress saved to {}", node_stress_path.display());
+    }
+
+    if let Some(npy_dir) = &args.npy {
+        std::fs::create_dir_all(npy_dir)?;
+        let positions_path = npy_dir.join("positions.npy");
+        output::write_positions_npy(&positions_path, &output_positions)?;
+        println!("Positions saved to {}", positions_path.display());
+
+        if args.npy_dist {
+            let dist_path = npy_dir.join("dist.npy");
+            output::write_dist_npy(&dist_path, &graph.calc_dist_matrix())?;
+            println!("Distance matrix saved to {}", dist_path.display());
+        }
+    }
+
+    Ok(())
+}
+
+
+// ── Helpers ───────────────────────────────────────────────────────────────────
+
+/// Transform to run between iterations: recentering
+/// (`--recenter-each-iteration`), then fitting to `--target-width`.
+fn iteration_transform(recenter: bool, target_width: Option<f64>) -> Option<graph::TransformFn> {
+    match (recenter, target_width.map(layout::fit_width_transform)) {
+        (false, fit) => fit,
+        (true, None) => Some(Box::new(layout::recenter_transform)),
+        (true, Some(mut fit)) => Some(Box::new(move |positions, iteration| {
+            layout::recenter_transform(positions, iteration);
+            fit(positions, iteration);
+        })),
+    }
+}
+
+/// Snapshot callback writing `{dir}/frame-{iteration}.txt` in the text result
+/// format, so each frame is on disk instead of in memory.
+fn snapshot_writer(graph: &graph::Graph, dir: &Path) -> graph::SnapshotFn {
+    let graph = graph.clone();
+    let dir = dir.to_path_buf();
+    Box::new(move |iteration, positions| {
+        let path = dir.join(format!("frame-{:05}.txt", iteration));
+        let label = format!("Snapshot (iteration {})", iteration);
+        if let Err(e) = output::write_result_txt(&path, &label, &graph, positions, output::Precision::F32, None) {
+            println!("Warning: failed to write snapshot {}: {}", path.display(), e);
+        }
+    })
+}
+
+/// Print what a `--archive` bundle contains.
+fn inspect_archive(path: &Path) -> Result<()> {
+    let archive = archive::read_archive(path)?;
+    println!("Archive: {}", path.display());
+    println!("  Nodes: {}, Edges: {}", archive.graph.node_size, archive.graph.edge_size);
+    println!(
+        "  Params: {} iterations, {} pairs, boundary {:?}",
+        archive.params.etas.len(),
+        archive.params.pairs.len(),
+        archive.params.boundary
+    );
+    println!("  Positions: {}", archive.positions.len());
+    for (name, value) in &archive.metrics {
+        println!("  {}: {:.4}", name, value);
+    }
+    Ok(())
+}
+
+/// Print the largest graph diameter over a batch of mtx files.
+///
+/// Passing this value as `--scale-reference` to every run of the batch lays
+/// all of them out on the same scale.
+fn print_batch_reference(paths: &[PathBuf]) -> Result<()> {
+    let mut reference = 0;
+    for path in paths {
+        let diameter = graph::Graph::from_mtx(path)?.diameter();
+        println!("{}: diameter {}", path.display(), diameter);
+        reference = reference.max(diameter);
+    }
+    println!("Common scale reference: {}", reference);
+    Ok(())
+}
+
+/// `--dim 3`: run `params` on `backend` and write the initial and final
+/// layouts as text with `x y z` positions. The 2D post-processing and
+/// diagnostics do not apply and are skipped.
+fn run_3d(
+    args: &LayoutArgs,
+    graph: &graph::Graph,
+    params: graph::SgdParams,
+    backend: &str,
+    data_name: &str,
+    provenance: &output::Provenance,
+) -> Result<()> {
+    let precision = args.output_precision_bits;
+    let layout_start = Instant::now();
+    let (initial_positions, result) = builder::create_backend(backend)?.execute_3d(params)?;
+    println!("3D layout: {:.3}s", layout_start.elapsed().as_secs_f64());
+
+    let timestamp = Local::now().format("%Y%m%d_%H%M%S");
+    let filename_init = match (&args.output_initial, &args.output) {
+        (Some(path), _) => Some(path.clone()),
+        (None, Some(_)) => None,
+        (None, None) => Some(PathBuf::from(format!("../output/vram-lock-{}-{}-0.txt", data_name, timestamp))),
+    };
+    if let Some(filename_init) = filename_init {
+        output::write_result_txt_3d(&filename_init, "Initial (Randomized)", graph, &initial_positions, precision, Some(provenance))?;
+        println!("Initial result saved to {}", filename_init.display());
+    }
+    let filename_processed = args
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(format!("../output/vram-lock-{}-{}-1.txt", data_name, timestamp)));
+    output::write_result_txt_3d(&filename_processed, "Processed", graph, &result, precision, Some(provenance))?;
+    println!("Processed result saved to {}", filename_processed.display());
+    Ok(())
+}
+
+/// Run every available update strategy on the same graphs and initial
+/// positions and print iteration time and final stress side by side.
+///
+/// Strategies: the lock-based and the pair-colored kernel on Metal and on
+/// wgpu (whichever initialise) and the serial CPU loop as the race-free
+/// reference. The locked kernels skip pairs whose nodes are busy, so they
+/// trade some stress for parallelism, while the colored kernel updates every
+/// pair at the cost of one pass per color; this table puts numbers on both
+/// tradeoffs. Times include the backend setup and pair precompute of each
+/// run (and the coloring for the colored kernel).
+fn run_update_benchmark(args: &BenchArgs) -> Result<()> {
+    let iterations = args.bench_iterations;
+
+    let mut strategies = Vec::new();
+    let gpu_backends: &[&str] = if cfg!(target_os = "macos") { &["metal", "wgpu"] } else { &["wgpu"] };
+    for &name in gpu_backends {
+        match init_backend(name) {
+            Ok(_) => {
+                strategies.push((format!("lock ({})", name), name, false));
+                strategies.push((format!("pair-colored ({})", name), name, true));
+            }
+            Err(e) => println!("Skipping {}: {}", name, e),
+        }
+    }
+    strategies.push(("serial (cpu)".to_string(), "cpu", false));
+
+    let mut rows = Vec::new();
+    for path in &args.paths {
+        let graph = graph::Graph::from_mtx(path)?;
+        let builder = builder::LayoutBuilder::new().iterations(iterations);
+        let params = builder.params(&graph)?;
+        let builder = builder.initial_positions(params.positions);
+        for (label, backend, pair_coloring) in &strategies {
+            let (_, layout) = builder.clone().backend(backend).pair_coloring(*pair_coloring).run(&graph)?;
+            let per_iteration = layout.elapsed.as_secs_f64() / layout.iterations.max(1) as f64;
+            rows.push((path.display().to_string(), label.clone(), per_iteration, metrics::stress(&layout.positions, &params.pairs)));
+        }
+    }
+
+    println!("\n=== Update strategy benchmark ({} iterations) ===", iterations);
+    println!("{:<32} {:<22} {:>12} {:>14}", "graph", "strategy", "ms/iter", "stress");
+    for (path, label, per_iteration, stress) in rows {
+        println!("{:<32} {:<22} {:>12.3} {:>14.4}", path, label, per_iteration * 1000.0, stress);
+    }
+    Ok(())
+}
+
+/// Lay out one graph with several seeds and write their generalized
+/// Procrustes consensus (see `layout::generalized_procrustes`).
+///
+/// Prints the residual variance across runs as a stability measure.
+fn run_consensus(args: &ConsensusArgs) -> Result<()> {
+    let path = &args.path;
+    let runs = args.runs.max(1);
+    let first_seed = args.seed;
+    let iterations = args.iterations;
+    let backend = args.backend.as_str();
+    let target_width = args.target_width;
+
+    let graph = graph::Graph::from_mtx(path)?;
+    let mut layouts = Vec::with_capacity(runs);
+    let mut stresses = Vec::with_capacity(runs);
+    let mut pairs = Vec::new();
+    for run in 0..runs {
+        let seed = first_seed + run as u64;
+        let mut builder = builder::LayoutBuilder::new().iterations(iterations).seed(seed).backend(backend);
+        if let Some(width) = target_width {
+            builder = builder.target_width(width);
+        }
+        let params = builder.params(&graph)?;
+        let positions = builder.run(&graph)?.1.into_positions();
+        stresses.push(metrics::stress(&positions, &params.pairs));
+        println!("Run {} (seed {}): stress {:.4}", run, seed, stresses[run]);
+        layouts.push(positions);
+        pairs = params.pairs;
+    }
+
+    let (consensus, variance) = layout::generalized_procrustes(&mut layouts);
+    println!("\n=== Consensus of {} runs ===", runs);
+    println!("Residual variance: {:.6} (RMS deviation {:.6})", variance, variance.sqrt());
+    println!(
+        "Stress: consensus {:.4}, runs {:.4} to {:.4}",
+        metrics::stress(&consensus, &pairs),
+        stresses.iter().copied().fold(f64::INFINITY, f64::min),
+        stresses.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+    );
+
+    let data_name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let output_path = args.output.clone().unwrap_or_else(|| {
+        PathBuf::from(format!(
+            "../output/vram-lock-{}-{}-consensus.txt",
+            data_name,
+            Local::now().format("%Y%m%d_%H%M%S")
+        ))
+    });
+    output::write_result_txt(
+        &output_path,
+        &format!("Consensus of {} runs", runs),
+        &graph,
+        &consensus,
+        output::Precision::default(),
+        None,
+    )?;
+    println!("Consensus saved to {}", output_path.display());
+    Ok(())
+}
+
+/// Lay out a graph that arrives as edge batches, writing one result per batch.
+fn run_online(args: &OnlineArgs) -> Result<()> {
+    let path = &args.edges_file;
+    let (radius, iterations) = (args.online_radius, args.online_iterations);
+
+    let batches = online::read_edge_batches(path)?;
+    println!("Loaded {} edge batches from {}", batches.len(), path.display());
+
+    let backend = builder::create_backend(&args.backend)?;
+    let seed = args.seed.unwrap_or_else(rand::random);
+    println!("Seed: {}", seed);
+    let mut layout = online::OnlineLayout::new(radius, Some(seed));
+    let data_name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let timestamp = Local::now().format("%Y%m%d_%H%M%S");
+
+    for (i, batch) in batches.iter().enumerate() {
+        let touched = layout.push_edges(batch);
+        let params = layout.local_params(&touched, iterations, args.epsilon);
+        println!(
+            "Batch {}: {} edges, {} touched nodes, {} local pairs",
+            i,
+            batch.len(),
+            touched.len(),
+            params.pairs.len()
+        );
+        let positions = if params.pairs.is_empty() {
+            layout.positions().iter().map(|p| [p[0] as f32, p[1] as f32]).collect()
+        } else {
+            let positions = backend.execute(params)?.1.into_positions();
+            layout.set_positions(&positions);
+            positions
+        };
+
+        let filename = format!("../output/vram-lock-online-{}-{}-{}.txt", data_name, timestamp, i);
+        let label = format!("Online batch {}", i);
+        output::write_result_txt(Path::new(&filename), &label, layout.graph(), &positions, output::Precision::F32, None)?;
+        println!("Batch {} saved to {}", i, filename);
+    }
 
     Ok(())
 }
+
+/// Parse a number `X > 0`
+fn parse_positive_f64(value: &str) -> Result<f64> {
+    let x: f64 = value.parse()?;
+    anyhow::ensure!(x > 0.0, "must be positive");
+    Ok(x)
+}
+
+/// Parse `square|disk|gaussian[:STD]` (STD defaults to 0.25)
+fn parse_init_dist(value: &str) -> Result<graph::InitDistribution> {
+    let (name, param) = match value.split_once(':') {
+        Some((name, param)) => (name, Some(param)),
+        None => (value, None),
+    };
+    match name {
+        "square" => Ok(graph::InitDistribution::UniformSquare),
+        "disk" => Ok(graph::InitDistribution::UniformDisk),
+        "gaussian" => {
+            let std = match param {
+                Some(p) => parse_positive_f64(p)?,
+                None => 0.25,
+            };
+            Ok(graph::InitDistribution::Gaussian { std })
+        }
+        _ => anyhow::bail!("expected square, disk or gaussian[:STD]"),
+    }
+}
+
+/// Parse `FILE[:WEIGHT]` (WEIGHT defaults to 1)
+fn parse_layer(value: &str) -> Result<(PathBuf, f64)> {
+    let layer = match value.rsplit_once(':') {
+        Some((path, weight)) if weight.parse::<f64>().is_ok() => (PathBuf::from(path), weight.parse::<f64>()?),
+        _ => (PathBuf::from(value), 1.0),
+    };
+    anyhow::ensure!(layer.1 > 0.0, "layer weight must be positive");
+    Ok(layer)
+}
+
+/// Parse `txt|csv|json|svg|bin|py`
+fn parse_format(value: &str) -> Result<output::Format> {
+    match value {
+        "txt" => Ok(output::Format::Txt),
+        "csv" => Ok(output::Format::Csv),
+        "json" => Ok(output::Format::Json),
+        "svg" => Ok(output::Format::Svg),
+        "bin" => Ok(output::Format::Bin),
+        "py" => Ok(output::Format::Py),
+        _ => anyhow::bail!("expected txt, csv, json, svg, bin or py"),
+    }
+}
+
+/// Set up `--record-pair-order FILE` / `--replay-pair-order FILE`; both
+/// need the CPU backend since the GPU kernels have no fixed pair order.
+#[cfg(feature = "pair-order-debug")]
+fn parse_pair_order(
+    args: &LayoutArgs,
+    params: &graph::SgdParams,
+    backend: &str,
+) -> Result<(Option<graph::PairOrderFn>, Option<pair_order::Recording>)> {
+    if (args.record_pair_order.is_some() || args.replay_pair_order.is_some()) && backend != "cpu" {
+        anyhow::bail!("--record-pair-order/--replay-pair-order require --backend cpu");
+    }
+    match (&args.record_pair_order, &args.replay_pair_order) {
+        (Some(path), _) => {
+            let (hook, recording) = pair_order::recorder(path.clone(), params.pairs.len(), params.seed);
+            Ok((Some(hook), Some(recording)))
+        }
+        (None, Some(path)) => Ok((
+            Some(pair_order::replayer(path, params.pairs.len(), params.etas.len())?),
+            None,
+        )),
+        (None, None) => Ok((None, None)),
+    }
+}
+
+/// Parse `START[:FULL_AT]` (fractions in `[0, 1]`, FULL_AT defaults to 0.5)
+fn parse_pair_growth(value: &str) -> Result<graph::PairGrowth> {
+    let mut growth = graph::PairGrowth::default();
+    let mut parts = value.split(':');
+    let mut fraction = |default: f64| -> Result<f64> {
+        match parts.next() {
+            Some(p) => p
+                .parse()
+                .ok()
+                .filter(|f: &f64| (0.0..=1.0).contains(f))
+                .ok_or_else(|| anyhow::anyhow!("expected START[:FULL_AT] in [0, 1]")),
+            None => Ok(default),
+        }
+    };
+    growth.start_fraction = fraction(growth.start_fraction)?;
+    growth.full_at = fraction(growth.full_at)?;
+    Ok(growth)
+}
+
+/// Parse `identity|sqrt|log`
+fn parse_distance_transform(value: &str) -> Result<graph::DistanceTransform> {
+    match value {
+        "identity" => Ok(graph::DistanceTransform::Identity),
+        "sqrt" => Ok(graph::DistanceTransform::Sqrt),
+        "log" => Ok(graph::DistanceTransform::Log),
+        _ => anyhow::bail!("expected identity, sqrt or log"),
+    }
+}
+
+/// Parse `auto|upper|lower|both`
+fn parse_triangle(value: &str) -> Result<graph::Triangle> {
+    match value {
+        "auto" => Ok(graph::Triangle::Auto),
+        "upper" => Ok(graph::Triangle::Upper),
+        "lower" => Ok(graph::Triangle::Lower),
+        "both" => Ok(graph::Triangle::Both),
+        _ => anyhow::bail!("expected auto, upper, lower or both"),
+    }
+}
+
+/// Parse `stress|fr[:K[:REPULSION]]|bh[:THETA[:RADIUS]]` (K and REPULSION
+/// default to 1, THETA to 0.5 and RADIUS to 3)
+fn parse_algorithm(value: &str) -> Result<graph::Algorithm> {
+    let mut parts = value.split(':');
+    let kind = parts.next();
+    let mut param = |default: f64, valid: fn(f64) -> bool| -> Result<f64> {
+        match parts.next() {
+            Some(p) => p
+                .parse()
+                .ok()
+                .filter(|&x| valid(x))
+                .ok_or_else(|| anyhow::anyhow!("invalid parameter {:?}", p)),
+            None => Ok(default),
+        }
+    };
+    match kind {
+        Some("stress") => Ok(graph::Algorithm::StressSgd),
+        Some("fr") => {
+            let k = param(1.0, |x| x > 0.0)?;
+            let repulsion = param(1.0, |x| x > 0.0)?;
+            Ok(graph::Algorithm::ForceDirected { k, repulsion })
+        }
+        Some("bh") => {
+            let theta = param(0.5, |x| x >= 0.0)?;
+            let radius = param(3.0, |x| x >= 1.0 && x.fract() == 0.0)? as usize;
+            Ok(graph::Algorithm::BarnesHut { theta, radius })
+        }
+        _ => anyhow::bail!("expected stress, fr[:K[:REPULSION]] or bh[:THETA[:RADIUS]]"),
+    }
+}
+
+/// Parse `32|64`
+fn parse_output_precision(value: &str) -> Result<output::Precision> {
+    match value {
+        "32" => Ok(output::Precision::F32),
+        "64" => Ok(output::Precision::F64),
+        _ => anyhow::bail!("expected 32 or 64"),
+    }
+}
+
+/// Parse `WxH`
+fn parse_torus(value: &str) -> Result<(f64, f64)> {
+    let (w, h) = value.split_once('x').ok_or_else(|| anyhow::anyhow!("expected WxH"))?;
+    Ok((parse_positive_f64(w)?, parse_positive_f64(h)?))
+}
+
+/// Parse `X1,Y1:X2,Y2:X3,Y3[:...]` (convex, at least 3 vertices)
+fn parse_polygon(value: &str) -> Result<Vec<[f64; 2]>> {
+    let vertices = value
+        .split(':')
+        .map(|v| {
+            let (x, y) = v.split_once(',')?;
+            Some([x.trim().parse().ok()?, y.trim().parse().ok()?])
+        })
+        .collect::<Option<Vec<[f64; 2]>>>()
+        .ok_or_else(|| anyhow::anyhow!("expected X1,Y1:X2,Y2:X3,Y3[:...]"))?;
+    anyhow::ensure!(vertices.len() >= 3, "a polygon needs at least 3 vertices");
+    Ok(vertices)
+}
+
+/// Parse `2|3`
+fn parse_dim(value: &str) -> Result<usize> {
+    match value {
+        "2" => Ok(2),
+        "3" => Ok(3),
+        _ => anyhow::bail!("expected 2 or 3"),
+    }
+}
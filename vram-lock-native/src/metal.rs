@@ -1,22 +1,115 @@
+use crate::gpu::GpuEdgeInfo;
 use crate::graph;
+use crate::layout::Layout;
 use anyhow::Result;
+use bytemuck::{Pod, Zeroable};
 use metal::*;
+use std::cell::RefCell;
 use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
+/// Boundary description passed to the shader (`Boundary` in shader.metal).
 #[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct GpuBoundary {
+    toroidal: u32,
+    width: f32,
+    height: f32,
+    _pad: u32,
+}
+
+// ── Shared buffer access ─────────────────────────────────────────────────────
+//
+// All buffers are created with `StorageModeShared`, so their contents are
+// directly addressable from the CPU. Every raw pointer access goes through the
+// helpers below, which check the byte length against the element count and
+// never assume the mapped pointer is aligned for `T`.
+
+/// Create a shared-storage buffer initialised with `data`.
+fn new_buffer_with_slice<T: Pod>(device: &Device, data: &[T]) -> Buffer {
+    let bytes: &[u8] = bytemuck::cast_slice(data);
+    device.new_buffer_with_data(
+        bytes.as_ptr() as *const _,
+        bytes.len() as u64,
+        MTLResourceOptions::StorageModeShared,
+    )
+}
+
+/// Borrow the CPU-visible bytes of a shared-storage buffer.
+///
+/// The caller must make sure no command buffer writing to `buffer` is in
+/// flight (i.e. `wait_until_completed` has returned).
+fn buffer_bytes(buffer: &Buffer) -> &[u8] {
+    let ptr = buffer.contents() as *const u8;
+    assert!(!ptr.is_null(), "buffer is not CPU-accessible");
+    // SAFETY: shared-storage buffers expose `length()` bytes at `contents()`
+    // for the lifetime of the buffer, and GPU work has completed.
+    unsafe { std::slice::from_raw_parts(ptr, buffer.length() as usize) }
+}
+
+/// Read `len` elements of `T` back from a shared-storage buffer.
+fn read_buffer<T: Pod>(buffer: &Buffer, len: usize) -> Vec<T> {
+    let bytes = buffer_bytes(buffer);
+    let expected = len * mem::size_of::<T>();
+    assert!(
+        bytes.len() >= expected,
+        "buffer holds {} bytes but {} elements of {} bytes were requested",
+        bytes.len(),
+        len,
+        mem::size_of::<T>()
+    );
+    bytes[..expected]
+        .chunks_exact(mem::size_of::<T>())
+        .map(bytemuck::pod_read_unaligned)
+        .collect()
+}
+
+/// Overwrite the start of a shared-storage buffer with `data`.
+fn write_buffer<T: Pod>(buffer: &Buffer, data: &[T]) {
+    let src: &[u8] = bytemuck::cast_slice(data);
+    let ptr = buffer.contents() as *mut u8;
+    assert!(!ptr.is_null(), "buffer is not CPU-accessible");
+    assert!(
+        buffer.length() as usize >= src.len(),
+        "buffer holds {} bytes but {} bytes were written",
+        buffer.length(),
+        src.len()
+    );
+    // SAFETY: the destination range was checked above and a byte copy has no
+    // alignment requirement.
+    unsafe { std::ptr::copy_nonoverlapping(src.as_ptr(), ptr, src.len()) }
+}
+
+/// Per-iteration statistics passed to the progress callback.
 #[derive(Debug, Clone, Copy)]
-pub struct GpuEdgeInfo {
-    pub u: u32,
-    pub v: u32,
-    pub dij: f32,
-    pub wij: f32,
+pub struct IterationStats {
+    pub iteration: usize,
+    pub updated_pairs: u32,
+    /// Largest distance any node moved during this iteration. Near zero
+    /// means the run has stalled; very large values indicate instability.
+    pub max_displacement: f32,
 }
 
-#[derive(Debug)]
+type ProgressFn = Box<dyn Fn(&IterationStats)>;
+
 pub struct MetalContext {
     device: Device,
     command_queue: CommandQueue,
     pipeline: ComputePipelineState,
+    /// Lock-free `sgd_colored` kernel for colored pairs
+    colored_pipeline: ComputePipelineState,
+    interrupt: Option<Arc<AtomicBool>>,
+    time_budget: Option<Duration>,
+    /// `(threshold, abort)` for the per-iteration stall check.
+    slow_iteration: Option<(Duration, bool)>,
+    snapshots: Option<(usize, graph::SnapshotFn)>,
+    transform: Option<RefCell<graph::TransformFn>>,
+    progress: Option<ProgressFn>,
+    live: Option<graph::LivePositions>,
+    timings: Option<graph::PhaseTimings>,
+    workgroup_size: u64,
 }
 
 impl MetalContext {
@@ -40,24 +133,150 @@ impl MetalContext {
         let pipeline = device.new_compute_pipeline_state_with_function(&kernel)
             .map_err(|e| anyhow::anyhow!("Failed to create pipeline: {}", e))?;
         
+        let colored_kernel = library.get_function("sgd_colored", None)
+            .map_err(|e| anyhow::anyhow!("Failed to get kernel function: {}", e))?;
+        let colored_pipeline = device.new_compute_pipeline_state_with_function(&colored_kernel)
+            .map_err(|e| anyhow::anyhow!("Failed to create pipeline: {}", e))?;
+        
         Ok(MetalContext {
             device,
             command_queue,
             pipeline,
+            colored_pipeline,
+            interrupt: None,
+            time_budget: None,
+            slow_iteration: None,
+            snapshots: None,
+            transform: None,
+            progress: None,
+            live: None,
+            timings: None,
+            workgroup_size: crate::gpu::DEFAULT_WORKGROUP_SIZE as u64,
         })
     }
+
+    /// Threads per threadgroup. Metal takes the threadgroup size at
+    /// dispatch, so unlike the wgpu backend no shader constant is needed.
+    /// Every thread updates its own pairs, so the grid shrinks as the
+    /// threadgroups grow. Fails above the pipeline's
+    /// `maxTotalThreadsPerThreadgroup`.
+    pub fn with_workgroup_size(mut self, size: u32) -> Result<Self> {
+        let max = self
+            .pipeline
+            .max_total_threads_per_threadgroup()
+            .min(self.colored_pipeline.max_total_threads_per_threadgroup());
+        if size == 0 || size as u64 > max {
+            anyhow::bail!("workgroup size {} is not supported by this pipeline (1 to {})", size, max);
+        }
+        self.workgroup_size = size as u64;
+        Ok(self)
+    }
+    
+    /// Stop iterating (after the current iteration) once `flag` is set.
+    /// The positions reached so far are returned as the result.
+    pub fn with_interrupt(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.interrupt = Some(flag);
+        self
+    }
+
+    /// Stop iterating once the iteration loop has run for `budget`; the
+    /// positions reached so far are returned as the result.
+    pub fn with_time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    /// Warn when a single iteration takes longer than `threshold` (e.g. a
+    /// driver hitch or thermal throttling); with `abort` the run also stops
+    /// there and returns the positions reached so far.
+    pub fn with_slow_iteration_threshold(mut self, threshold: Duration, abort: bool) -> Self {
+        self.slow_iteration = Some((threshold, abort));
+        self
+    }
+
+    /// Call `f` with the positions after every `every`-th iteration and after
+    /// the last one, instead of keeping per-iteration copies.
+    ///
+    /// The positions buffer uses shared storage, so a snapshot is only a CPU
+    /// copy of `node_size * dims` floats after the iteration's command buffer
+    /// has completed; there is no extra blit or map. `f` still runs before the
+    /// next iteration is encoded, so a slow callback stalls the GPU.
+    pub fn with_snapshots(mut self, every: usize, f: graph::SnapshotFn) -> Self {
+        self.snapshots = Some((every, f));
+        self
+    }
+
+    /// Apply `f` to the downloaded positions after every iteration (after
+    /// the boundary constraint) and upload the result before the next one.
+    /// Costs a full position round trip per iteration.
+    pub fn with_transform(mut self, f: graph::TransformFn) -> Self {
+        self.transform = Some(RefCell::new(f));
+        self
+    }
+    
+    /// Call `f` after every iteration with that iteration's statistics.
+    /// Costs two position readbacks per iteration, before and after the
+    /// dispatch.
+    pub fn with_progress(mut self, f: impl Fn(&IterationStats) + 'static) -> Self {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    /// Publish the positions to `live` after every iteration. The buffers
+    /// live in shared memory, so this is a plain copy once the iteration's
+    /// command buffer has completed.
+    pub fn with_live_positions(mut self, live: graph::LivePositions) -> Self {
+        self.live = Some(live);
+        self
+    }
+
+    /// Record the upload, iteration and download times of each run into
+    /// `timings`.
+    pub fn with_timings(mut self, timings: graph::PhaseTimings) -> Self {
+        self.timings = Some(timings);
+        self
+    }
     
     pub fn execute_sgd(
         &self,
         params: graph::SgdParams,
-    ) -> Result<(Vec<[f32; 2]>, Vec<[f32; 2]>)> {
-        let gpu_etas: Vec<f32> = params.etas.into_iter().map(|e| e as f32).collect();
-        let gpu_positions: Vec<[f32; 2]> = params
-            .positions
-            .into_iter()
-            .map(|p| [p[0] as f32, p[1] as f32])
+    ) -> Result<graph::LayoutPair> {
+        anyhow::ensure!(params.positions_z.is_empty(), "3D parameters given: use execute_sgd_3d");
+        let start = std::time::Instant::now();
+        let (initial, result, iterations) = self.run(params)?;
+        let layout = Layout::new(graph::unflatten(&result), iterations, start.elapsed());
+        Ok((graph::unflatten(&initial), layout))
+    }
+
+    /// [`execute_sgd`](Self::execute_sgd) for a 3D layout (`positions_z`
+    /// set). Only the open plane is supported and no transform may be set;
+    /// snapshots and live positions see the `x`/`y` projection.
+    pub fn execute_sgd_3d(
+        &self,
+        params: graph::SgdParams,
+    ) -> Result<graph::LayoutPair3> {
+        graph::check_3d(&params, self.transform.is_some())?;
+        let (initial, result, _) = self.run(params)?;
+        Ok((graph::unflatten(&initial), graph::unflatten(&result)))
+    }
+
+    /// Run the iterations on positions flattened to `dims` floats per node;
+    /// returns the flattened `(initial, final)` positions and the number of
+    /// iterations run.
+    fn run(&self, params: graph::SgdParams) -> Result<(Vec<f32>, Vec<f32>, usize)> {
+        let setup_start = std::time::Instant::now();
+        let dims = params.dims();
+        let positions_flat: Vec<f32> = params
+            .positions_3d()
+            .iter()
+            .flat_map(|p| p[..dims].iter().map(|&c| c as f32))
             .collect();
-        let initial_positions = gpu_positions.clone();
+        let gpu_levels = params.node_levels();
+        let boundary = params.boundary;
+        let active_pairs = params.active_pairs;
+        let color_offsets = params.color_offsets;
+        let gpu_etas: Vec<f32> = params.etas.into_iter().map(|e| e as f32).collect();
+        let initial_positions = positions_flat.clone();
         
         let gpu_pairs: Vec<GpuEdgeInfo> = params
             .pairs
@@ -70,162 +289,289 @@ impl MetalContext {
             })
             .collect();
         
-        let node_size = gpu_positions.len();
+        let node_size = positions_flat.len() / dims;
         let num_iterations = gpu_etas.len();
         let num_pairs = gpu_pairs.len();
+        // Same check as `SgdParams::validate`, for callers that skip it
+        anyhow::ensure!(num_iterations > 0, "no learning rates given: etas is empty, nothing to iterate");
         
         println!("Setting up Metal buffers...");
         println!("  Nodes: {}, Pairs: {}, Iterations: {}", node_size, num_pairs, num_iterations);
         
         // Create buffers
-        let etas_buffer = self.device.new_buffer_with_data(
-            gpu_etas.as_ptr() as *const _,
-            (gpu_etas.len() * mem::size_of::<f32>()) as u64,
-            MTLResourceOptions::StorageModeShared,
-        );
+        let etas_buffer = new_buffer_with_slice(&self.device, &gpu_etas);
         
         // Positions buffer - flattened to allow atomic operations
-        let mut positions_flat: Vec<f32> = gpu_positions.iter()
-            .flat_map(|p| vec![p[0], p[1]])
-            .collect();
+        let positions_buffer = new_buffer_with_slice(&self.device, &positions_flat);
         
-        let positions_buffer = self.device.new_buffer_with_data(
-            positions_flat.as_ptr() as *const _,
-            (positions_flat.len() * mem::size_of::<f32>()) as u64,
-            MTLResourceOptions::StorageModeShared,
-        );
-        
-        let pairs_buffer = self.device.new_buffer_with_data(
-            gpu_pairs.as_ptr() as *const _,
-            (gpu_pairs.len() * mem::size_of::<GpuEdgeInfo>()) as u64,
-            MTLResourceOptions::StorageModeShared,
-        );
+        let pairs_buffer = new_buffer_with_slice(&self.device, &gpu_pairs);
         
         // Lock buffer (initialized to 0)
         let locks: Vec<u32> = vec![0; node_size];
-        let lock_buffer = self.device.new_buffer_with_data(
-            locks.as_ptr() as *const _,
-            (locks.len() * mem::size_of::<u32>()) as u64,
-            MTLResourceOptions::StorageModeShared,
-        );
+        let lock_buffer = new_buffer_with_slice(&self.device, &locks);
         
         // Updated pairs tracking
         let updated_pairs: Vec<u32> = vec![0; num_pairs];
-        let updated_pairs_buffer = self.device.new_buffer_with_data(
-            updated_pairs.as_ptr() as *const _,
-            (updated_pairs.len() * mem::size_of::<u32>()) as u64,
-            MTLResourceOptions::StorageModeShared,
-        );
-        
-        let updated_count: Vec<u32> = vec![0];
-        let updated_count_buffer = self.device.new_buffer_with_data(
-            updated_count.as_ptr() as *const _,
-            mem::size_of::<u32>() as u64,
-            MTLResourceOptions::StorageModeShared,
-        );
+        let updated_pairs_buffer = new_buffer_with_slice(&self.device, &updated_pairs);
+        
+        let updated_count_buffer = new_buffer_with_slice(&self.device, &[0u32]);
         
         // Positions before buffer
         let positions_before: Vec<[f32; 4]> = vec![[0.0; 4]; num_pairs];
-        let positions_before_buffer = self.device.new_buffer_with_data(
-            positions_before.as_ptr() as *const _,
-            (positions_before.len() * mem::size_of::<[f32; 4]>()) as u64,
-            MTLResourceOptions::StorageModeShared,
-        );
+        let positions_before_buffer = new_buffer_with_slice(&self.device, &positions_before);
         
         // Num pairs buffer (for bounds checking in shader)
-        let num_pairs_buffer = self.device.new_buffer_with_data(
-            &(num_pairs as u32) as *const _ as *const _,
-            mem::size_of::<u32>() as u64,
-            MTLResourceOptions::StorageModeShared,
-        );
+        let num_pairs_buffer = new_buffer_with_slice(&self.device, &[num_pairs as u32]);
         
+        // Boundary buffer (open plane or periodic domain).
+        // Polygons only constrain positions between iterations, on the CPU.
+        let gpu_boundary = match boundary {
+            graph::BoundaryMode::Open | graph::BoundaryMode::Polygon(_) => {
+                GpuBoundary { toroidal: 0, width: 0.0, height: 0.0, _pad: 0 }
+            }
+            graph::BoundaryMode::Toroidal { width, height } => GpuBoundary {
+                toroidal: 1,
+                width: width as f32,
+                height: height as f32,
+                _pad: 0,
+            },
+        };
+        let boundary_buffer = new_buffer_with_slice(&self.device, &[gpu_boundary]);
+        let levels_buffer = new_buffer_with_slice(&self.device, &gpu_levels);
+        let dims_value = dims as u32;
+        // Hooks and the displacement report work on the x/y projection
+        let read_xy = || graph::project_xy(&read_buffer::<f32>(&positions_buffer, node_size * dims), dims);
+        
+        let threadgroups_for = |pairs: usize| (pairs as u32).div_ceil(self.workgroup_size as u32);
+        let (grid_x, grid_y) = crate::gpu::dispatch_grid(threadgroups_for(num_pairs), u32::MAX);
+        if color_offsets.is_empty() {
+            println!("  Dispatch grid: {} x {} threadgroups of {} threads", grid_x, grid_y, self.workgroup_size);
+        } else {
+            println!("  Pair coloring: {} lock-free dispatches per iteration", color_offsets.len() - 1);
+        }
         println!("Executing SGD iterations...");
         
         let iteration_start = std::time::Instant::now();
         
         // Execute iterations
+        let mut completed_iterations = 0usize;
         for iteration in 0..num_iterations {
-            // Create iteration buffer for this iteration
-            let iteration_buffer = self.device.new_buffer_with_data(
-                &(iteration as u32) as *const _ as *const _,
-                mem::size_of::<u32>() as u64,
-                MTLResourceOptions::StorageModeShared,
-            );
+            let this_iteration = std::time::Instant::now();
+            let iteration_index = iteration as u32;
             
             // Reset updated count
-            unsafe {
-                let count_ptr = updated_count_buffer.contents() as *mut u32;
-                *count_ptr = 0;
-            }
+            write_buffer(&updated_count_buffer, &[0u32]);
+            
+            // Pair growth: dispatch (and bound the kernel to) the leading pairs
+            let active = active_pairs.get(iteration).copied().unwrap_or(num_pairs);
+            write_buffer(&num_pairs_buffer, &[active as u32]);
+            
+            // Snapshot positions for the displacement report; without a
+            // progress callback nothing is read back
+            let before = self.progress.as_ref().map(|_| read_buffer::<f32>(&positions_buffer, node_size * dims));
             
             let command_buffer = self.command_queue.new_command_buffer();
             let encoder = command_buffer.new_compute_command_encoder();
             
-            encoder.set_compute_pipeline_state(&self.pipeline);
+            encoder.set_compute_pipeline_state(if color_offsets.is_empty() {
+                &self.pipeline
+            } else {
+                &self.colored_pipeline
+            });
             encoder.set_buffer(0, Some(&etas_buffer), 0);
             encoder.set_buffer(1, Some(&positions_buffer), 0);
             encoder.set_buffer(2, Some(&pairs_buffer), 0);
-            encoder.set_buffer(3, Some(&iteration_buffer), 0);
+            // `constant uint&` argument: passed inline instead of allocating
+            // a buffer every iteration
+            encoder.set_bytes(
+                3,
+                mem::size_of::<u32>() as u64,
+                &iteration_index as *const u32 as *const std::ffi::c_void,
+            );
             encoder.set_buffer(4, Some(&lock_buffer), 0);
             encoder.set_buffer(5, Some(&updated_pairs_buffer), 0);
             encoder.set_buffer(6, Some(&updated_count_buffer), 0);
             encoder.set_buffer(7, Some(&positions_before_buffer), 0);
             encoder.set_buffer(8, Some(&num_pairs_buffer), 0);
+            encoder.set_buffer(9, Some(&boundary_buffer), 0);
+            encoder.set_bytes(
+                10,
+                mem::size_of::<u32>() as u64,
+                &dims_value as *const u32 as *const std::ffi::c_void,
+            );
+            encoder.set_buffer(11, Some(&levels_buffer), 0);
             
             // Dispatch workgroups matching WGSL implementation:
-            // @workgroup_size(32,1,1): Each workgroup = 32 threads (= 1 warp)
-            // Each workgroup processes one pair (only thread 0 does work)
-            // Use 2D dispatch to handle more pairs (up to 65535 * 65535)
-            let max_x = 65535u64;
-            let workgroup_count_x = (num_pairs as u64).min(max_x);
-            let workgroup_count_y = ((num_pairs as u64) + max_x - 1) / max_x;
+            // @workgroup_size(wg_size,1,1): Each workgroup = wg_size threads (default 32 = 1 warp)
+            // Each thread processes one pair, so ceil(pairs / wg_size) workgroups
+            // Metal has no 65535 threadgroups-per-dimension limit, so this is
+            // a 1D grid
+            let (workgroup_count_x, workgroup_count_y) = crate::gpu::dispatch_grid(threadgroups_for(active), u32::MAX);
             
             let threadgroups = MTLSize {
-                width: workgroup_count_x,
-                height: workgroup_count_y,
+                width: workgroup_count_x as u64,
+                height: workgroup_count_y as u64,
                 depth: 1,
             };
             
             let threads_per_threadgroup = MTLSize {
-                width: 32,  // Match WGSL @workgroup_size(32,1,1)
+                width: self.workgroup_size,  // Match WGSL @workgroup_size(wg_size,1,1)
                 height: 1,
                 depth: 1,
             };
             
-            encoder.dispatch_thread_groups(threadgroups, threads_per_threadgroup);
+            if color_offsets.is_empty() {
+                encoder.dispatch_thread_groups(threadgroups, threads_per_threadgroup);
+            } else {
+                // One dispatch per color; dispatches of a compute encoder run
+                // in order, so each color sees the previous one's positions
+                for range in color_offsets.windows(2) {
+                    let color_range = [range[0] as u32, (range[1] - range[0]) as u32];
+                    encoder.set_bytes(
+                        8,
+                        mem::size_of::<[u32; 2]>() as u64,
+                        color_range.as_ptr() as *const std::ffi::c_void,
+                    );
+                    let (x, y) = crate::gpu::dispatch_grid(threadgroups_for(color_range[1] as usize), u32::MAX);
+                    encoder.dispatch_thread_groups(
+                        MTLSize { width: x as u64, height: y as u64, depth: 1 },
+                        threads_per_threadgroup,
+                    );
+                }
+            }
             encoder.end_encoding();
             
             command_buffer.commit();
             command_buffer.wait_until_completed();
             
-            // Read back updated count for every iteration
-            let updated_count_val = unsafe {
-                let ptr = updated_count_buffer.contents() as *const u32;
-                *ptr
-            };
+            let max_displacement = before.map(|before| {
+                graph::max_displacement(&read_buffer::<f32>(&positions_buffer, node_size * dims), &before, dims)
+            });
             
-            println!("Iteration {} - Updated {} pairs", iteration, updated_count_val);
+            // Wrap/project positions back into the domain, then apply the hook
+            if boundary != graph::BoundaryMode::Open || self.transform.is_some() {
+                let mut current: Vec<[f64; 2]> = read_buffer::<[f32; 2]>(&positions_buffer, node_size)
+                    .into_iter()
+                    .map(|p| boundary.constrain([p[0] as f64, p[1] as f64]))
+                    .collect();
+                if let Some(transform) = &self.transform {
+                    (transform.borrow_mut())(&mut current, iteration);
+                }
+                let updated: Vec<f32> = current.iter().flat_map(|p| [p[0] as f32, p[1] as f32]).collect();
+                write_buffer(&positions_buffer, &updated);
+            }
+            if let Some(live) = &self.live {
+                live.publish(read_xy());
+            }
+            
+            if let (Some(progress), Some(max_displacement)) = (&self.progress, max_displacement) {
+                progress(&IterationStats {
+                    iteration,
+                    updated_pairs: read_buffer::<u32>(&updated_count_buffer, 1)[0],
+                    max_displacement,
+                });
+            }
+            completed_iterations += 1;
+            let interrupted = self.interrupt.as_ref().is_some_and(|f| f.load(Ordering::SeqCst));
+            let out_of_time = self
+                .time_budget
+                .is_some_and(|budget| iteration_start.elapsed() >= budget);
+            let took = this_iteration.elapsed();
+            let too_slow = match self.slow_iteration {
+                Some((threshold, abort)) if took > threshold => {
+                    println!(
+                        "Warning: iteration {} took {:.1}ms (threshold {:.1}ms)",
+                        iteration,
+                        took.as_secs_f64() * 1000.0,
+                        threshold.as_secs_f64() * 1000.0
+                    );
+                    abort
+                }
+                _ => false,
+            };
+
+            // An early stop still captures the last completed iteration
+            if let Some((every, snapshot)) = &self.snapshots {
+                if graph::snapshot_due(iteration, num_iterations, *every) || interrupted || out_of_time || too_slow {
+                    snapshot(iteration, &read_xy());
+                }
+            }
+
+            if interrupted {
+                println!("Interrupted after iteration {}", iteration);
+                break;
+            }
+            if out_of_time {
+                println!("Time budget exhausted after iteration {}", iteration);
+                break;
+            }
+            if too_slow {
+                println!("Aborting after slow iteration {}", iteration);
+                break;
+            }
         }
         
         let iteration_duration = iteration_start.elapsed();
         println!("\nSGD execution completed!");
-        let per_iteration = iteration_duration.as_secs_f64() / num_iterations as f64;
+        let per_iteration = iteration_duration.as_secs_f64() / completed_iterations.max(1) as f64;
         println!("\n=== Performance Summary ===");
+        println!("Iterations run:   {}/{}", completed_iterations, num_iterations);
         println!("Iterations total: {:.3}s", iteration_duration.as_secs_f64());
         println!("Per iteration:    {:.3}s ({:.1}ms)", per_iteration, per_iteration * 1000.0);
         
         // Read back final positions
-        unsafe {
-            let ptr = positions_buffer.contents() as *const f32;
-            positions_flat = std::slice::from_raw_parts(ptr, positions_flat.len()).to_vec();
+        let download_start = std::time::Instant::now();
+        let final_positions: Vec<f32> = read_buffer(&positions_buffer, positions_flat.len());
+        if let Some(timings) = &self.timings {
+            timings.record(graph::Timings {
+                upload: iteration_start - setup_start,
+                iterations: iteration_duration,
+                download: download_start.elapsed(),
+                iterations_run: completed_iterations,
+            });
         }
         
-        let final_positions: Vec<[f32; 2]> = positions_flat
-            .chunks(2)
-            .map(|chunk| [chunk[0], chunk[1]])
-            .collect();
-        
-        Ok((initial_positions, final_positions))
+        Ok((initial_positions, final_positions, completed_iterations))
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::tests::{assert_single_pair_result, single_pair_params};
+
+    #[test]
+    fn pair_buffer_round_trip() {
+        let Some(device) = Device::system_default() else {
+            eprintln!("skipping: no Metal device");
+            return;
+        };
+        let pairs = [
+            GpuEdgeInfo { u: 3, v: 7, dij: 2.5, wij: 0.125 },
+            GpuEdgeInfo { u: 0, v: 1, dij: 1.0, wij: 1.0 },
+        ];
+        let buffer = new_buffer_with_slice(&device, &pairs);
+        assert_eq!(buffer.length(), 32);
+        let read: Vec<GpuEdgeInfo> = read_buffer(&buffer, pairs.len());
+        for (r, p) in read.iter().zip(&pairs) {
+            assert_eq!((r.u, r.v, r.dij, r.wij), (p.u, p.v, p.dij, p.wij));
+        }
+    }
+
+    #[test]
+    fn kernel_reads_the_uploaded_pair() {
+        let Ok(ctx) = MetalContext::new() else {
+            eprintln!("skipping: no Metal device");
+            return;
+        };
+        for coloring in [false, true] {
+            let mut params = single_pair_params();
+            if coloring {
+                params.apply_pair_coloring();
+            }
+            let (_, layout) = ctx.execute_sgd(params).unwrap();
+            assert_single_pair_result(&layout.positions);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Layout quality diagnostics computed from the final positions.
+
+use crate::graph::{EdgeInfo, Graph};
+use std::collections::HashSet;
+
+fn distance(a: [f32; 2], b: [f32; 2]) -> f64 {
+    let dx = a[0] as f64 - b[0] as f64;
+    let dy = a[1] as f64 - b[1] as f64;
+    (dx * dx + dy * dy).sqrt()
+}
+
+/// Full stress `Σ wij (||xi - xj|| - dij)^2` over all pairs.
+pub fn stress(positions: &[[f32; 2]], pairs: &[EdgeInfo]) -> f64 {
+    pairs
+        .iter()
+        .map(|p| {
+            let err = distance(positions[p.u], positions[p.v]) - p.dij;
+            p.wij * err * err
+        })
+        .sum()
+}
+
+/// Gradient of the full stress with respect to every node position:
+/// `∂/∂xu Σ wij (||xi - xj|| - dij)^2 = Σ 2 wuv (r - duv) (xu - xv) / r`.
+///
+/// Pairs whose nodes coincide contribute nothing (the direction is undefined).
+pub fn stress_gradient(positions: &[[f32; 2]], pairs: &[EdgeInfo]) -> Vec<[f64; 2]> {
+    let mut gradient = vec![[0.0f64; 2]; positions.len()];
+    for p in pairs {
+        let r = distance(positions[p.u], positions[p.v]);
+        if r == 0.0 {
+            continue;
+        }
+        let scale = 2.0 * p.wij * (r - p.dij) / r;
+        for k in 0..2 {
+            let g = scale * (positions[p.u][k] as f64 - positions[p.v][k] as f64);
+            gradient[p.u][k] += g;
+            gradient[p.v][k] -= g;
+        }
+    }
+    gradient
+}
+
+/// L2 norm of [`stress_gradient`]; near zero at a stationary point.
+pub fn stress_gradient_norm(positions: &[[f32; 2]], pairs: &[EdgeInfo]) -> f64 {
+    stress_gradient(positions, pairs)
+        .iter()
+        .map(|g| g[0] * g[0] + g[1] * g[1])
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Uniform scale `s` minimising the stress of `s * positions`:
+/// `s = Σ wij dij ||xi - xj|| / Σ wij ||xi - xj||²`.
+///
+/// `None` when every pair has zero length (no scale is defined).
+pub fn optimal_scale(positions: &[[f32; 2]], pairs: &[EdgeInfo]) -> Option<f64> {
+    let (mut num, mut den) = (0.0, 0.0);
+    for p in pairs {
+        let r = distance(positions[p.u], positions[p.v]);
+        num += p.wij * p.dij * r;
+        den += p.wij * r * r;
+    }
+    (den > 0.0 && (num / den).is_finite()).then(|| num / den)
+}
+
+/// Stress restricted to the graph edges (`dij = wij = 1`).
+///
+/// Much cheaper than the full stress and measures only how well adjacency is
+/// preserved. Each undirected edge counts once, even when the edge list holds
+/// both directions.
+pub fn edge_stress(positions: &[[f32; 2]], graph: &Graph) -> f64 {
+    let mut seen = HashSet::new();
+    let mut stress = 0.0;
+    for (&u, &v) in graph.edge_src.iter().zip(&graph.edge_dst) {
+        if u == v || !seen.insert((u.min(v), u.max(v))) {
+            continue;
+        }
+        let err = distance(positions[u], positions[v]) - 1.0;
+        stress += err * err;
+    }
+    stress
+}
+
+/// Shepard diagram data: `(dij, realized distance)` for every pair.
+pub fn shepard_data(positions: &[[f32; 2]], pairs: &[EdgeInfo]) -> Vec<(f64, f64)> {
+    pairs
+        .iter()
+        .map(|p| (p.dij, distance(positions[p.u], positions[p.v])))
+        .collect()
+}
+
+/// Pearson correlation of the two columns of `data`.
+///
+/// Returns `NaN` when there are fewer than two points or one column is constant.
+pub fn pearson_correlation(data: &[(f64, f64)]) -> f64 {
+    let n = data.len() as f64;
+    if data.len() < 2 {
+        return f64::NAN;
+    }
+    let mean_x = data.iter().map(|d| d.0).sum::<f64>() / n;
+    let mean_y = data.iter().map(|d| d.1).sum::<f64>() / n;
+
+    let mut cov = 0.0;
+    let mut var_x = 0.0;
+    let mut var_y = 0.0;
+    for &(x, y) in data {
+        cov += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x) * (x - mean_x);
+        var_y += (y - mean_y) * (y - mean_y);
+    }
+    cov / (var_x * var_y).sqrt()
+}
+
+/// Per-node stress contribution.
+///
+/// Every pair term `wij * (||xi - xj|| - dij)^2` is credited to both of its
+/// endpoints, so the values sum to twice the total stress. Nodes with a high
+/// value are the ones the layout failed to place well.
+pub fn node_stress(positions: &[[f32; 2]], pairs: &[EdgeInfo]) -> Vec<f64> {
+    let mut stress = vec![0.0; positions.len()];
+    for p in pairs {
+        let err = distance(positions[p.u], positions[p.v]) - p.dij;
+        let term = p.wij * err * err;
+        stress[p.u] += term;
+        stress[p.v] += term;
+    }
+    stress
+}
+
+/// Pairs of edges drawn on top of each other: nearly collinear segments
+/// whose overlap is longer than `tolerance`.
+///
+/// Two edges overlap when both endpoints of one lie within `tolerance` of
+/// the line through the other (the longer one) and their projections onto
+/// that line share more than `tolerance` of length. Edges meeting at a node
+/// only count when one runs along the other, not when they continue in
+/// opposite directions. Stress cannot tell stacked edges apart from
+/// separated ones, which is common on symmetric graphs. Returns edge index
+/// pairs `(i, j)` with `i < j`, each undirected edge taken once and
+/// self-loops and zero-length edges skipped.
+pub fn overlapping_edges(positions: &[[f32; 2]], graph: &Graph, tolerance: f64) -> Vec<(usize, usize)> {
+    let point = |v: usize| [positions[v][0] as f64, positions[v][1] as f64];
+    let mut seen = HashSet::new();
+    let mut segments = Vec::new();
+    for (i, (&u, &v)) in graph.edge_src.iter().zip(&graph.edge_dst).enumerate() {
+        if u == v || !seen.insert((u.min(v), u.max(v))) {
+            continue;
+        }
+        let (a, b) = (point(u), point(v));
+        if ((b[0] - a[0]).powi(2) + (b[1] - a[1]).powi(2)).sqrt() > tolerance {
+            segments.push((i, a, b));
+        }
+    }
+
+    // Sweep along x: only segments whose x-ranges (widened by the
+    // tolerance) intersect can overlap
+    let x_range = |a: [f64; 2], b: [f64; 2]| (a[0].min(b[0]), a[0].max(b[0]));
+    segments.sort_by(|s, t| x_range(s.1, s.2).0.total_cmp(&x_range(t.1, t.2).0));
+    let mut overlaps = Vec::new();
+    for (k, &(i, a, b)) in segments.iter().enumerate() {
+        let max_x = x_range(a, b).1 + tolerance;
+        for &(j, c, d) in &segments[k + 1..] {
+            if x_range(c, d).0 > max_x {
+                break;
+            }
+            if segments_overlap(a, b, c, d, tolerance) {
+                overlaps.push((i.min(j), i.max(j)));
+            }
+        }
+    }
+    overlaps.sort_unstable();
+    overlaps
+}
+
+/// Overlap test of [`overlapping_edges`] for segments `ab` and `cd`.
+fn segments_overlap(a: [f64; 2], b: [f64; 2], c: [f64; 2], d: [f64; 2], tolerance: f64) -> bool {
+    let length = |p: [f64; 2], q: [f64; 2]| ((q[0] - p[0]).powi(2) + (q[1] - p[1]).powi(2)).sqrt();
+    let ((a, b), (c, d)) = if length(a, b) >= length(c, d) { ((a, b), (c, d)) } else { ((c, d), (a, b)) };
+    let len = length(a, b);
+    let dir = [(b[0] - a[0]) / len, (b[1] - a[1]) / len];
+    let offset = |p: [f64; 2]| {
+        let rel = [p[0] - a[0], p[1] - a[1]];
+        // (position along ab, distance from the line through ab)
+        (rel[0] * dir[0] + rel[1] * dir[1], (rel[0] * dir[1] - rel[1] * dir[0]).abs())
+    };
+    let ((tc, dc), (td, dd)) = (offset(c), offset(d));
+    if dc > tolerance || dd > tolerance {
+        return false;
+    }
+    let shared = tc.max(td).min(len) - tc.min(td).max(0.0);
+    shared > tolerance
+}
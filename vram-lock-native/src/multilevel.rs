@@ -0,0 +1,190 @@
+//! Multilevel stress layout (coarsen, lay out, refine).
+//!
+//! The graph is coarsened by repeatedly contracting a maximal matching of
+//! its edges, which roughly halves the node count per level. The coarsest
+//! graph is laid out with the usual SGD from random positions; every finer
+//! level starts from its parent's positions (matched nodes share their
+//! parent's position plus a small offset), scaled to the finer level's
+//! target distances, and only needs a few refinement iterations with
+//! [`graph::refinement_schedule`]. The global shape is thus found on a graph
+//! small enough for many iterations to be cheap, as in FM³ and other
+//! multilevel force-directed methods.
+
+use crate::builder::SgdBackend;
+use crate::graph::{self, Graph, SgdParams};
+use crate::layout::Layout;
+use crate::metrics;
+use anyhow::Result;
+use std::collections::HashSet;
+use std::time::Instant;
+
+/// Stop coarsening once a level has at most this many nodes.
+const MIN_COARSE_NODES: usize = 16;
+
+/// One coarsening step: `parent[v]` is the node of the coarser graph that
+/// fine node `v` was contracted into.
+struct Level {
+    graph: Graph,
+    parent: Vec<usize>,
+}
+
+/// Contract a maximal matching of `graph`.
+///
+/// Nodes are visited by increasing degree and matched with their
+/// lowest-degree unmatched neighbour, which keeps hubs from absorbing their
+/// whole neighbourhood in one level. Unmatched nodes are carried over alone.
+fn coarsen(graph: &Graph) -> Level {
+    let n = graph.node_size;
+    let mut adj = vec![Vec::new(); n];
+    for (&u, &v) in graph.edge_src.iter().zip(&graph.edge_dst) {
+        if u != v {
+            adj[u].push(v);
+            adj[v].push(u);
+        }
+    }
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by_key(|&v| adj[v].len());
+
+    let mut parent = vec![usize::MAX; n];
+    let mut coarse_size = 0;
+    for &v in &order {
+        if parent[v] != usize::MAX {
+            continue;
+        }
+        let mate = adj[v]
+            .iter()
+            .copied()
+            .filter(|&u| parent[u] == usize::MAX && u != v)
+            .min_by_key(|&u| adj[u].len());
+        parent[v] = coarse_size;
+        if let Some(u) = mate {
+            parent[u] = coarse_size;
+        }
+        coarse_size += 1;
+    }
+
+    let mut edges = HashSet::new();
+    for (&u, &v) in graph.edge_src.iter().zip(&graph.edge_dst) {
+        let (a, b) = (parent[u], parent[v]);
+        if a != b {
+            edges.insert((a.min(b), a.max(b)));
+        }
+    }
+    let mut edges: Vec<(usize, usize)> = edges.into_iter().collect();
+    edges.sort_unstable();
+    let (edge_src, edge_dst) = edges.into_iter().unzip();
+    let graph = Graph::from_edges(coarse_size, edge_src, edge_dst).expect("coarse edges are in range");
+    Level { graph, parent }
+}
+
+/// Run the multilevel layout and return the initial positions and the final
+/// layout of the finest level.
+///
+/// `finest` holds the precomputed parameters of `graph` itself; its etas
+/// are replaced by a `level_iterations` refinement schedule. Up to `levels`
+/// coarser graphs are built (fewer if coarsening stops shrinking the graph
+/// or reaches a handful of nodes); the coarsest is laid out with
+/// `coarse_iterations` iterations of the default schedule. `epsilon` is the
+/// final step factor of both schedules.
+pub fn execute_layout(
+    backend: &dyn SgdBackend,
+    graph: &Graph,
+    finest: SgdParams,
+    levels: usize,
+    coarse_iterations: usize,
+    level_iterations: usize,
+    epsilon: f64,
+) -> Result<graph::LayoutPair> {
+    let start = Instant::now();
+    let mut hierarchy: Vec<Level> = Vec::new();
+    while hierarchy.len() < levels {
+        let current = hierarchy.last().map_or(graph, |l| &l.graph);
+        if current.node_size <= MIN_COARSE_NODES {
+            break;
+        }
+        let level = coarsen(current);
+        // Stop once matching no longer shrinks the graph noticeably
+        if level.graph.node_size * 10 > current.node_size * 9 {
+            break;
+        }
+        println!(
+            "Multilevel: level {} has {} nodes, {} edges",
+            hierarchy.len() + 1,
+            level.graph.node_size,
+            level.graph.edge_size
+        );
+        hierarchy.push(level);
+    }
+
+    let initial_positions: Vec<[f32; 2]> =
+        finest.positions.iter().map(|p| [p[0] as f32, p[1] as f32]).collect();
+    let Some(coarsest) = hierarchy.last() else {
+        println!("Multilevel: graph does not coarsen, running a single level");
+        return backend.execute(finest);
+    };
+
+    println!("Multilevel: laying out level {} ({} nodes)", hierarchy.len(), coarsest.graph.node_size);
+    // Coarse levels reuse the seed of the finest so a seeded run repeats
+    let seed = finest.seed;
+    let mut params = coarsest.graph.prepare_sgd_params(coarse_iterations, epsilon, true);
+    if let Some(seed) = seed {
+        params.positions = graph::init_positions_seeded(coarsest.graph.node_size, true, Default::default(), seed);
+    }
+    params.seed = seed;
+    let (_, mut layout) = backend.execute(params)?;
+    let mut iterations = layout.iterations;
+
+    // Walk back down; `hierarchy[k]` holds level k + 1 and maps level k
+    // nodes to it
+    for k in (1..hierarchy.len()).rev() {
+        let fine_graph = &hierarchy[k - 1].graph;
+        println!("Multilevel: refining level {} ({} nodes)", k, fine_graph.node_size);
+        let mut params = fine_graph.prepare_sgd_params(level_iterations, epsilon, true);
+        params.seed = seed;
+        layout = refine(backend, params, &layout.positions, &hierarchy[k].parent, level_iterations, epsilon)?;
+        iterations += layout.iterations;
+    }
+    println!("Multilevel: refining level 0 ({} nodes)", graph.node_size);
+    let layout = refine(backend, finest, &layout.positions, &hierarchy[0].parent, level_iterations, epsilon)?;
+    iterations += layout.iterations;
+
+    // Iterations of all levels, and the time including coarsening
+    Ok((initial_positions, Layout::new(layout.into_positions(), iterations, start.elapsed())))
+}
+
+/// Start `params` from the interpolated `coarse` positions, rescaled to the
+/// stress-optimal scale for its pairs (contracting edges shrinks graph
+/// distances, so the parent layout is too small), and run `iterations`
+/// refinement steps.
+fn refine(
+    backend: &dyn SgdBackend,
+    mut params: SgdParams,
+    coarse: &[[f32; 2]],
+    parent: &[usize],
+    iterations: usize,
+    epsilon: f64,
+) -> Result<Layout> {
+    let start = interpolate(coarse, parent);
+    let scale = metrics::optimal_scale(&start, &params.pairs).unwrap_or(1.0);
+    params.positions = start.iter().map(|p| [p[0] as f64 * scale, p[1] as f64 * scale]).collect();
+
+    let wmin = params.pairs.iter().map(|p| p.wij).fold(f64::INFINITY, f64::min);
+    let wmax = params.pairs.iter().map(|p| p.wij).fold(0.0, f64::max);
+    params.etas = graph::calc_learning_rate_with(iterations, wmin, wmax, graph::refinement_schedule(iterations, epsilon));
+    let (_, layout) = backend.execute(params)?;
+    Ok(layout)
+}
+
+/// Place every fine node at its parent's position, offset by a small
+/// deterministic amount so matched pairs do not coincide.
+fn interpolate(coarse: &[[f32; 2]], parent: &[usize]) -> Vec<[f32; 2]> {
+    const GOLDEN_ANGLE: f64 = 2.399_963_229_728_653;
+    parent
+        .iter()
+        .enumerate()
+        .map(|(v, &p)| {
+            let theta = v as f64 * GOLDEN_ANGLE;
+            [coarse[p][0] + 0.1 * theta.cos() as f32, coarse[p][1] + 0.1 * theta.sin() as f32]
+        })
+        .collect()
+}
@@ -0,0 +1,295 @@
+//! Incremental (online) layout for graphs that grow over time.
+//!
+//! Edges arrive in batches. Each batch places its new nodes next to their
+//! already-placed neighbours and builds SGD pairs only around the nodes the
+//! batch touched, using hop distances truncated at `radius`. Pairs outside
+//! that neighbourhood are not part of the run, so the rest of the layout
+//! stays where it was. The truncated distances are an approximation: a new
+//! edge can shorten paths further away than `radius`, which is only picked
+//! up once a later batch touches that region.
+
+use crate::graph::{calc_learning_rate, seeded_rng, BoundaryMode, EdgeInfo, Graph, SgdParams};
+use anyhow::Result;
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::Path;
+
+pub struct OnlineLayout {
+    graph: Graph,
+    adj: Vec<Vec<usize>>,
+    edges: HashSet<(usize, usize)>,
+    positions: Vec<[f64; 2]>,
+    placed: Vec<bool>,
+    radius: usize,
+    seed: Option<u64>,
+    rng: StdRng,
+}
+
+impl OnlineLayout {
+    /// Start from an empty graph. `radius` is the hop radius of the local
+    /// distance update around each touched node. With a `seed` the new
+    /// nodes' starting offsets and the CPU pair shuffles of every batch are
+    /// reproducible.
+    pub fn new(radius: usize, seed: Option<u64>) -> Self {
+        OnlineLayout {
+            graph: Graph {
+                node_size: 0,
+                edge_size: 0,
+                edge_src: Vec::new(),
+                edge_dst: Vec::new(),
+                edge_layer: Vec::new(),
+                edge_weight: Vec::new(),
+            },
+            adj: Vec::new(),
+            edges: HashSet::new(),
+            positions: Vec::new(),
+            placed: Vec::new(),
+            radius: radius.max(1),
+            seed,
+            rng: seeded_rng(seed),
+        }
+    }
+
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    pub fn positions(&self) -> &[[f64; 2]] {
+        &self.positions
+    }
+
+    /// Add a batch of undirected edges and return the nodes they touched.
+    ///
+    /// Self-loops and edges already in the graph are ignored. Node ids beyond
+    /// the current node count grow the graph; each new node starts near the
+    /// mean of its placed neighbours, or near the layout centroid when it has
+    /// none yet.
+    pub fn push_edges(&mut self, edges: &[(usize, usize)]) -> Vec<usize> {
+        let mut touched = HashSet::new();
+
+        for &(u, v) in edges {
+            if u == v {
+                continue;
+            }
+            let key = (u.min(v), u.max(v));
+            if !self.edges.insert(key) {
+                continue;
+            }
+            let needed = u.max(v) + 1;
+            if needed > self.graph.node_size {
+                self.graph.node_size = needed;
+                self.adj.resize(needed, Vec::new());
+                self.positions.resize(needed, [0.0, 0.0]);
+                self.placed.resize(needed, false);
+            }
+            self.adj[u].push(v);
+            self.adj[v].push(u);
+            self.graph.edge_src.push(key.0);
+            self.graph.edge_dst.push(key.1);
+            self.graph.edge_layer.push(0);
+            self.graph.edge_weight.push(1.0);
+            touched.insert(u);
+            touched.insert(v);
+        }
+        self.graph.edge_size = self.graph.edge_src.len();
+
+        self.place_new_nodes();
+
+        let mut touched: Vec<usize> = touched.into_iter().collect();
+        touched.sort_unstable();
+        touched
+    }
+
+    /// Give every unplaced node a starting position.
+    fn place_new_nodes(&mut self) {
+        loop {
+            let mut progress = false;
+            let mut unplaced = false;
+            for node in 0..self.graph.node_size {
+                if self.placed[node] {
+                    continue;
+                }
+                let neighbors: Vec<[f64; 2]> = self.adj[node]
+                    .iter()
+                    .filter(|&&n| self.placed[n])
+                    .map(|&n| self.positions[n])
+                    .collect();
+                if neighbors.is_empty() {
+                    unplaced = true;
+                    continue;
+                }
+                let k = neighbors.len() as f64;
+                let mean = [
+                    neighbors.iter().map(|p| p[0]).sum::<f64>() / k,
+                    neighbors.iter().map(|p| p[1]).sum::<f64>() / k,
+                ];
+                // Unit-length offset so the new edge starts near its target length
+                let angle = self.rng.random::<f64>() * std::f64::consts::TAU;
+                self.positions[node] = [mean[0] + angle.cos(), mean[1] + angle.sin()];
+                self.placed[node] = true;
+                progress = true;
+            }
+            if !unplaced {
+                return;
+            }
+            if !progress {
+                // A component with no placed node yet: seed one near the centroid
+                let centroid = self.centroid();
+                let seed = (0..self.graph.node_size).find(|&n| !self.placed[n]).unwrap();
+                self.positions[seed] = [
+                    centroid[0] + self.rng.random::<f64>() - 0.5,
+                    centroid[1] + self.rng.random::<f64>() - 0.5,
+                ];
+                self.placed[seed] = true;
+            }
+        }
+    }
+
+    fn centroid(&self) -> [f64; 2] {
+        let placed: Vec<&[f64; 2]> = self
+            .positions
+            .iter()
+            .zip(&self.placed)
+            .filter(|(_, &p)| p)
+            .map(|(pos, _)| pos)
+            .collect();
+        if placed.is_empty() {
+            return [0.0, 0.0];
+        }
+        let k = placed.len() as f64;
+        [
+            placed.iter().map(|p| p[0]).sum::<f64>() / k,
+            placed.iter().map(|p| p[1]).sum::<f64>() / k,
+        ]
+    }
+
+    /// SGD parameters for the neighbourhood of `touched`.
+    ///
+    /// Pairs are every `(touched node, node within radius)` combination with
+    /// its truncated BFS distance. Positions are the current layout for all
+    /// nodes.
+    pub fn local_params(&self, touched: &[usize], iterations: usize, epsilon: f64) -> SgdParams {
+        let mut pair_dist: HashMap<(usize, usize), usize> = HashMap::new();
+        for &src in touched {
+            for (dst, d) in self.bfs_within_radius(src) {
+                if dst == src {
+                    continue;
+                }
+                let key = (src.min(dst), src.max(dst));
+                let entry = pair_dist.entry(key).or_insert(d);
+                *entry = (*entry).min(d);
+            }
+        }
+
+        let mut pairs: Vec<EdgeInfo> = pair_dist
+            .into_iter()
+            .map(|((u, v), d)| {
+                let dij = d as f64;
+                EdgeInfo { u, v, dij, wij: 1.0 / (dij * dij) }
+            })
+            .collect();
+        pairs.sort_by_key(|p| (p.u, p.v));
+
+        let wmin = pairs.iter().map(|p| p.wij).fold(f64::INFINITY, f64::min);
+        let wmax = pairs.iter().map(|p| p.wij).fold(0.0, f64::max);
+        let etas = if pairs.is_empty() {
+            Vec::new()
+        } else {
+            calc_learning_rate(iterations, wmin, wmax, epsilon)
+        };
+
+        SgdParams {
+            etas,
+            positions: self.positions.clone(),
+            pairs,
+            boundary: BoundaryMode::Open,
+            active_pairs: Vec::new(),
+            positions_z: Vec::new(),
+            color_offsets: Vec::new(),
+            fixed: Vec::new(),
+            anchors: Vec::new(),
+            seed: self.seed,
+        }
+    }
+
+    /// Take the positions produced by running `local_params`.
+    pub fn set_positions(&mut self, positions: &[[f32; 2]]) {
+        assert_eq!(positions.len(), self.positions.len(), "node count changed");
+        for (dst, src) in self.positions.iter_mut().zip(positions) {
+            *dst = [src[0] as f64, src[1] as f64];
+        }
+    }
+
+    fn bfs_within_radius(&self, src: usize) -> Vec<(usize, usize)> {
+        let mut dist: HashMap<usize, usize> = HashMap::new();
+        let mut deq = VecDeque::new();
+        dist.insert(src, 0);
+        deq.push_back(src);
+        while let Some(v) = deq.pop_front() {
+            let d = dist[&v];
+            if d == self.radius {
+                continue;
+            }
+            for &u in &self.adj[v] {
+                if let Entry::Vacant(e) = dist.entry(u) {
+                    e.insert(d + 1);
+                    deq.push_back(u);
+                }
+            }
+        }
+        dist.into_iter().collect()
+    }
+}
+
+/// Read edge batches from a text file: one `u v` pair per line (0-based),
+/// batches separated by blank lines, `#` lines ignored.
+pub fn read_edge_batches(path: &Path) -> Result<Vec<Vec<(usize, usize)>>> {
+    let text = fs::read_to_string(path)?;
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        if line.is_empty() {
+            if !batch.is_empty() {
+                batches.push(std::mem::take(&mut batch));
+            }
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        let (Some(u), Some(v)) = (parts.next(), parts.next()) else {
+            anyhow::bail!("{}:{}: expected `u v`", path.display(), lineno + 1);
+        };
+        batch.push((u.parse()?, v.parse()?));
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+
+    Ok(batches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_layouts_place_new_nodes_identically() {
+        let batches = [vec![(0, 1), (1, 2)], vec![(5, 6), (2, 3)]];
+        let place = |seed| {
+            let mut layout = OnlineLayout::new(2, Some(seed));
+            for batch in &batches {
+                layout.push_edges(batch);
+            }
+            layout.positions().to_vec()
+        };
+        assert_eq!(place(7), place(7));
+        assert_ne!(place(7), place(8));
+    }
+}
@@ -0,0 +1,600 @@
+//! Writers for layout results.
+
+use crate::graph::{self, Graph};
+use anyhow::Result;
+use chrono::Local;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// Output format for the initial/processed layout files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Commented text dump with edges and positions.
+    #[default]
+    Txt,
+    /// The text dump without its edges section (`--positions-only`).
+    TxtPositionsOnly,
+    /// `id,x,y` CSV.
+    Csv,
+    /// Node and edge records with connected component ids.
+    Json,
+    /// Drawing with nodes filled by connected component.
+    Svg,
+    /// Self-describing binary positions (see [`write_positions_bin`]).
+    Bin,
+    /// Standalone matplotlib script (see [`write_matplotlib_script`]).
+    Py,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Txt | Format::TxtPositionsOnly => "txt",
+            Format::Csv => "csv",
+            Format::Json => "json",
+            Format::Svg => "svg",
+            Format::Bin => "bin",
+            Format::Py => "py",
+        }
+    }
+}
+
+/// Categorical colormap (Tableau 10) used for component ids; ids beyond ten
+/// wrap around.
+const CATEGORY_COLORS: [&str; 10] = [
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f",
+    "#edc948", "#b07aa1", "#ff9da7", "#9c755f", "#bab0ac",
+];
+
+pub fn category_color(id: usize) -> &'static str {
+    CATEGORY_COLORS[id % CATEGORY_COLORS.len()]
+}
+
+/// Precision coordinates are printed at in the layout files.
+///
+/// Every backend returns `f32` positions. `F32` prints the shortest decimal
+/// that round-trips to that `f32`; `F64` widens first and prints the shortest
+/// round-trip `f64`, i.e. the exact binary value. Either way the text depends
+/// only on the bits, so backends agreeing at that precision produce identical
+/// files. `F64` adds digits, not accuracy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Precision {
+    #[default]
+    F32,
+    F64,
+}
+
+impl Precision {
+    fn format(self, v: f32) -> String {
+        match self {
+            Precision::F32 => v.to_string(),
+            Precision::F64 => (v as f64).to_string(),
+        }
+    }
+}
+
+/// How a layout was produced, recorded in the output header so a saved
+/// layout can be reproduced from the file alone.
+#[derive(Debug, Clone, Default)]
+pub struct Provenance {
+    pub version: String,
+    pub backend: String,
+    pub algorithm: String,
+    pub iterations: usize,
+    /// `None` when the parameters were loaded rather than computed.
+    pub epsilon: Option<f64>,
+    /// Seed of the random initial positions and the CPU pair shuffles.
+    pub seed: Option<u64>,
+}
+
+impl Provenance {
+    fn entries(&self) -> Vec<(&'static str, String)> {
+        let or_na = |v: Option<String>| v.unwrap_or_else(|| "n/a".to_string());
+        vec![
+            ("Version", self.version.clone()),
+            ("Backend", self.backend.clone()),
+            ("Algorithm", self.algorithm.clone()),
+            ("Iterations", self.iterations.to_string()),
+            ("Epsilon", or_na(self.epsilon.map(|e| e.to_string()))),
+            ("Seed", or_na(self.seed.map(|s| s.to_string()))),
+        ]
+    }
+}
+
+/// Write a layout in `format` (`label` is only used by the text header).
+///
+/// `provenance` goes into the text header, an SVG or Python comment or a
+/// JSON `"provenance"` object; CSV and the binary format omit it.
+pub fn write_layout(
+    path: &Path,
+    format: Format,
+    precision: Precision,
+    label: &str,
+    graph: &Graph,
+    positions: &[[f32; 2]],
+    provenance: Option<&Provenance>,
+) -> Result<()> {
+    match format {
+        Format::Txt => write_result_txt(path, label, graph, positions, precision, provenance),
+        Format::TxtPositionsOnly => write_txt(path, label, graph, positions, precision, provenance, false),
+        Format::Csv => write_csv(path, positions, precision),
+        Format::Json => write_json(path, graph, positions, precision, provenance),
+        Format::Svg => write_svg(path, graph, positions, provenance),
+        Format::Bin => write_positions_bin(path, positions, precision),
+        Format::Py => write_matplotlib_script(path, graph, positions, precision, provenance),
+    }
+}
+
+/// Write the text result format read by the visualizers.
+pub fn write_result_txt(
+    path: &Path,
+    label: &str,
+    graph: &Graph,
+    positions: &[[f32; 2]],
+    precision: Precision,
+    provenance: Option<&Provenance>,
+) -> Result<()> {
+    write_txt(path, label, graph, positions, precision, provenance, true)
+}
+
+/// [`write_result_txt`] for a 3D layout: the positions section is
+/// `# Positions (x y z)` with three columns.
+pub fn write_result_txt_3d(
+    path: &Path,
+    label: &str,
+    graph: &Graph,
+    positions: &[[f32; 3]],
+    precision: Precision,
+    provenance: Option<&Provenance>,
+) -> Result<()> {
+    write_txt(path, label, graph, positions, precision, provenance, true)
+}
+
+/// [`write_result_txt`], optionally leaving out the `# Edges` section (the
+/// header still reports the edge count). Points may have 2 or 3 coordinates.
+fn write_txt<const D: usize>(
+    path: &Path,
+    label: &str,
+    graph: &Graph,
+    positions: &[[f32; D]],
+    precision: Precision,
+    provenance: Option<&Provenance>,
+    edges: bool,
+) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "# Rust GPU Result (vram-lock) - {}", label)?;
+    writeln!(file, "# Timestamp: {}", Local::now().format("%Y-%m-%d %H:%M:%S"))?;
+    writeln!(file, "# Node count: {}", graph.node_size)?;
+    writeln!(file, "# Edge count: {}", graph.edge_size)?;
+    for (key, value) in provenance.map(Provenance::entries).unwrap_or_default() {
+        writeln!(file, "# {}: {}", key, value)?;
+    }
+    writeln!(file)?;
+    if edges {
+        writeln!(file, "# Edges (source target)")?;
+        for i in 0..graph.edge_size {
+            writeln!(file, "{} {}", graph.edge_src[i], graph.edge_dst[i])?;
+        }
+        writeln!(file)?;
+    }
+    writeln!(file, "# Positions ({})", ["x", "y", "z"][..D].join(" "))?;
+    for pos in positions {
+        let coords: Vec<String> = pos.iter().map(|&c| precision.format(c)).collect();
+        writeln!(file, "{}", coords.join(" "))?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Write positions as `id,x,y` CSV, one row per node.
+pub fn write_csv(path: &Path, positions: &[[f32; 2]], precision: Precision) -> Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    writeln!(w, "id,x,y")?;
+    for (id, pos) in positions.iter().enumerate() {
+        writeln!(w, "{},{},{}", id, precision.format(pos[0]), precision.format(pos[1]))?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Write the layout as JSON:
+///
+/// `{"provenance": {...}, "nodes": [{"id", "x", "y", "component"}, ...], "edges": [[source, target], ...]}`
+///
+/// Non-finite coordinates are written as `null`.
+pub fn write_json(
+    path: &Path,
+    graph: &Graph,
+    positions: &[[f32; 2]],
+    precision: Precision,
+    provenance: Option<&Provenance>,
+) -> Result<()> {
+    let components = graph.components();
+    let coord = |v: f32| if v.is_finite() { precision.format(v) } else { "null".to_string() };
+
+    let mut w = BufWriter::new(File::create(path)?);
+    writeln!(w, "{{")?;
+    if let Some(provenance) = provenance {
+        let fields: Vec<String> = provenance
+            .entries()
+            .into_iter()
+            .map(|(key, value)| format!("\"{}\": \"{}\"", key.to_lowercase(), value))
+            .collect();
+        writeln!(w, "  \"provenance\": {{{}}},", fields.join(", "))?;
+    }
+    writeln!(w, "  \"nodes\": [")?;
+    for (id, pos) in positions.iter().enumerate() {
+        let sep = if id + 1 < positions.len() { "," } else { "" };
+        writeln!(
+            w,
+            "    {{\"id\": {}, \"x\": {}, \"y\": {}, \"component\": {}}}{}",
+            id,
+            coord(pos[0]),
+            coord(pos[1]),
+            components.get(id).copied().unwrap_or(0),
+            sep
+        )?;
+    }
+    writeln!(w, "  ],")?;
+    writeln!(w, "  \"edges\": [")?;
+    for i in 0..graph.edge_size {
+        let sep = if i + 1 < graph.edge_size { "," } else { "" };
+        writeln!(w, "    [{}, {}]{}", graph.edge_src[i], graph.edge_dst[i], sep)?;
+    }
+    writeln!(w, "  ]")?;
+    writeln!(w, "}}")?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Write the layout as SVG, edges as `<line>` and nodes as `<circle>` filled
+/// by connected component. The view box fits the bounding box with 5%
+/// padding. SVG's y axis points down, so y is negated to keep the layout
+/// upright (as in vram-lock's `--svg`).
+pub fn write_svg(path: &Path, graph: &Graph, positions: &[[f32; 2]], provenance: Option<&Provenance>) -> Result<()> {
+    let components = graph.components();
+    let finite = positions.iter().filter(|p| p[0].is_finite() && p[1].is_finite());
+    let (mut min, mut max) = ([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]);
+    for p in finite {
+        for k in 0..2 {
+            min[k] = min[k].min(p[k]);
+            max[k] = max[k].max(p[k]);
+        }
+    }
+    if min[0] > max[0] {
+        (min, max) = ([0.0; 2], [1.0; 2]);
+    }
+    let extent = (max[0] - min[0]).max(max[1] - min[1]).max(f32::EPSILON);
+    let pad = extent * 0.05;
+    let radius = extent * 0.005;
+
+    let mut w = BufWriter::new(File::create(path)?);
+    writeln!(
+        w,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">",
+        min[0] - pad,
+        -max[1] - pad,
+        max[0] - min[0] + 2.0 * pad,
+        max[1] - min[1] + 2.0 * pad
+    )?;
+    for (key, value) in provenance.map(Provenance::entries).unwrap_or_default() {
+        writeln!(w, "<!-- {}: {} -->", key, value)?;
+    }
+    writeln!(w, "<g stroke=\"#999999\" stroke-width=\"{}\">", radius * 0.4)?;
+    for i in 0..graph.edge_size {
+        let (a, b) = (positions[graph.edge_src[i]], positions[graph.edge_dst[i]]);
+        writeln!(w, "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>", a[0], -a[1], b[0], -b[1])?;
+    }
+    writeln!(w, "</g>")?;
+    writeln!(w, "<g>")?;
+    for (id, p) in positions.iter().enumerate() {
+        let component = components.get(id).copied().unwrap_or(0);
+        writeln!(
+            w,
+            "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\" data-component=\"{}\"/>",
+            p[0],
+            -p[1],
+            radius,
+            category_color(component),
+            component
+        )?;
+    }
+    writeln!(w, "</g>")?;
+    writeln!(w, "</svg>")?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Node plus edge count above which [`write_matplotlib_script`] warns that
+/// the script will be large and slow to plot.
+const MATPLOTLIB_WARN_SIZE: usize = 50_000;
+
+/// Write a self-contained Python script that plots the layout with
+/// matplotlib (`python out.py`).
+///
+/// Positions, edges and component colours are embedded as literals; edges
+/// are drawn as one `LineCollection`. Non-finite coordinates become
+/// `float("nan")` and are skipped by matplotlib.
+pub fn write_matplotlib_script(
+    path: &Path,
+    graph: &Graph,
+    positions: &[[f32; 2]],
+    precision: Precision,
+    provenance: Option<&Provenance>,
+) -> Result<()> {
+    if positions.len() + graph.edge_size > MATPLOTLIB_WARN_SIZE {
+        println!(
+            "Warning: embedding {} nodes and {} edges in {}; the script will be large and slow to plot",
+            positions.len(),
+            graph.edge_size,
+            path.display()
+        );
+    }
+    let components = graph.components();
+    let coord = |v: f32| if v.is_finite() { precision.format(v) } else { "float(\"nan\")".to_string() };
+
+    let mut w = BufWriter::new(File::create(path)?);
+    writeln!(w, "# Rust GPU Result (vram-lock) - matplotlib script")?;
+    writeln!(w, "# Timestamp: {}", Local::now().format("%Y-%m-%d %H:%M:%S"))?;
+    for (key, value) in provenance.map(Provenance::entries).unwrap_or_default() {
+        writeln!(w, "# {}: {}", key, value)?;
+    }
+    writeln!(w, "import matplotlib.pyplot as plt")?;
+    writeln!(w, "from matplotlib.collections import LineCollection")?;
+    writeln!(w)?;
+    writeln!(w, "positions = [")?;
+    for p in positions {
+        writeln!(w, "    ({}, {}),", coord(p[0]), coord(p[1]))?;
+    }
+    writeln!(w, "]")?;
+    writeln!(w, "edges = [")?;
+    for i in 0..graph.edge_size {
+        writeln!(w, "    ({}, {}),", graph.edge_src[i], graph.edge_dst[i])?;
+    }
+    writeln!(w, "]")?;
+    writeln!(w, "colors = [")?;
+    for id in 0..positions.len() {
+        writeln!(w, "    \"{}\",", category_color(components.get(id).copied().unwrap_or(0)))?;
+    }
+    writeln!(w, "]")?;
+    writeln!(w)?;
+    writeln!(w, "fig, ax = plt.subplots(figsize=(8, 8))")?;
+    writeln!(
+        w,
+        "ax.add_collection(LineCollection([(positions[s], positions[t]) for s, t in edges], colors=\"#999999\", linewidths=0.5, zorder=1))"
+    )?;
+    writeln!(w, "ax.scatter([p[0] for p in positions], [p[1] for p in positions], s=8, c=colors, zorder=2)")?;
+    writeln!(w, "ax.set_aspect(\"equal\")")?;
+    writeln!(w, "ax.autoscale()")?;
+    writeln!(w, "ax.axis(\"off\")")?;
+    writeln!(w, "plt.show()")?;
+    w.flush()?;
+    Ok(())
+}
+
+/// Magic bytes at the start of a binary positions file.
+const POSITIONS_MAGIC: &[u8; 4] = b"SGDX";
+const POSITIONS_VERSION: u8 = 1;
+const DTYPE_F32: u8 = 1;
+const DTYPE_F64: u8 = 2;
+
+/// Write positions in the self-describing binary format.
+///
+/// Header (16 bytes): magic `SGDX`, version `u8`, dimension `u8` (always 2
+/// here; 3 is reserved), dtype `u8` (1 = `f32`, 2 = `f64`), one zero byte and
+/// the node count as `u64`. Then `count * dimension` little-endian values,
+/// row-major. `precision` picks the dtype.
+pub fn write_positions_bin(path: &Path, positions: &[[f32; 2]], precision: Precision) -> Result<()> {
+    let dtype = match precision {
+        Precision::F32 => DTYPE_F32,
+        Precision::F64 => DTYPE_F64,
+    };
+    let mut w = BufWriter::new(File::create(path)?);
+    w.write_all(POSITIONS_MAGIC)?;
+    w.write_all(&[POSITIONS_VERSION, 2, dtype, 0])?;
+    w.write_all(&(positions.len() as u64).to_le_bytes())?;
+    for p in positions {
+        for &v in p {
+            match precision {
+                Precision::F32 => w.write_all(&v.to_le_bytes())?,
+                Precision::F64 => w.write_all(&(v as f64).to_le_bytes())?,
+            }
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Read a file written by [`write_positions_bin`], checking the header and
+/// that it holds 2D positions for exactly `n_nodes` nodes.
+pub fn read_positions_bin(path: &Path, n_nodes: usize) -> Result<Vec<[f64; 2]>> {
+    let bytes = std::fs::read(path)?;
+    let header = bytes
+        .get(..16)
+        .ok_or_else(|| anyhow::anyhow!("{}: truncated positions header", path.display()))?;
+    if &header[..4] != POSITIONS_MAGIC {
+        anyhow::bail!("{}: not a binary positions file", path.display());
+    }
+    let (version, dim, dtype) = (header[4], header[5], header[6]);
+    if version != POSITIONS_VERSION {
+        anyhow::bail!("{}: unsupported positions version {}", path.display(), version);
+    }
+    if dim != 2 {
+        anyhow::bail!("{}: {}D positions cannot be used for a 2D layout", path.display(), dim);
+    }
+    let width = match dtype {
+        DTYPE_F32 => 4,
+        DTYPE_F64 => 8,
+        _ => anyhow::bail!("{}: unknown dtype {}", path.display(), dtype),
+    };
+    let count = u64::from_le_bytes(header[8..16].try_into()?) as usize;
+    if count != n_nodes {
+        anyhow::bail!("{}: holds {} positions but the graph has {} nodes", path.display(), count, n_nodes);
+    }
+    let data = &bytes[16..];
+    if data.len() != count * 2 * width {
+        anyhow::bail!(
+            "{}: expected {} bytes of {} data but found {}",
+            path.display(),
+            count * 2 * width,
+            if width == 4 { "f32" } else { "f64" },
+            data.len()
+        );
+    }
+    let values: Vec<f64> = data
+        .chunks_exact(width)
+        .map(|c| match width {
+            4 => f32::from_le_bytes(c.try_into().unwrap()) as f64,
+            _ => f64::from_le_bytes(c.try_into().unwrap()),
+        })
+        .collect();
+    Ok(values.chunks_exact(2).map(|c| [c[0], c[1]]).collect())
+}
+
+/// Write a C-ordered little-endian float32 array in NumPy `.npy` (v1.0) format.
+///
+/// `data.len()` must equal the product of `shape`.
+pub fn write_npy_f32(path: &Path, shape: &[usize], data: &[f32]) -> Result<()> {
+    let expected: usize = shape.iter().product();
+    anyhow::ensure!(
+        data.len() == expected,
+        "npy shape {:?} needs {} values but {} were given",
+        shape,
+        expected,
+        data.len()
+    );
+
+    let shape_str = match shape {
+        [n] => format!("({},)", n),
+        _ => format!(
+            "({})",
+            shape.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}",
+        shape_str
+    );
+    // magic(6) + version(2) + header_len(2) + header must be a multiple of 64,
+    // with the header terminated by '\n'.
+    let unpadded = 10 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+
+    let mut w = BufWriter::new(File::create(path)?);
+    w.write_all(b"\x93NUMPY")?;
+    w.write_all(&[1, 0])?;
+    w.write_all(&(header.len() as u16).to_le_bytes())?;
+    w.write_all(header.as_bytes())?;
+    for v in data {
+        w.write_all(&v.to_le_bytes())?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Write positions as an `n × 2` float32 `.npy` array.
+pub fn write_positions_npy(path: &Path, positions: &[[f32; 2]]) -> Result<()> {
+    let flat: Vec<f32> = positions.iter().flat_map(|p| [p[0], p[1]]).collect();
+    write_npy_f32(path, &[positions.len(), 2], &flat)
+}
+
+/// Write a hop-distance matrix as an `n × n` float32 `.npy` array.
+/// Unreachable pairs (`usize::MAX`) are stored as `inf`.
+pub fn write_dist_npy(path: &Path, dist: &[Vec<usize>]) -> Result<()> {
+    let n = dist.len();
+    let flat: Vec<f32> = dist
+        .iter()
+        .flat_map(|row| {
+            row.iter()
+                .map(|&d| if d == usize::MAX { f32::INFINITY } else { d as f32 })
+        })
+        .collect();
+    write_npy_f32(path, &[n, n], &flat)
+}
+
+/// Write Shepard diagram data as `dij,realized` CSV.
+pub fn write_shepard_csv(path: &Path, data: &[(f64, f64)]) -> Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    writeln!(w, "dij,realized")?;
+    for (dij, realized) in data {
+        writeln!(w, "{},{}", dij, realized)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Write one value per node as `id,<column>` CSV.
+pub fn write_node_values_csv(path: &Path, column: &str, values: &[f64]) -> Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    writeln!(w, "id,{}", column)?;
+    for (id, value) in values.iter().enumerate() {
+        writeln!(w, "{},{}", id, value)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Write the original id of every node as `id,original_id` CSV, quoting
+/// the original ids.
+pub fn write_node_ids_csv(path: &Path, ids: &[String]) -> Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    writeln!(w, "id,original_id")?;
+    for (id, original) in ids.iter().enumerate() {
+        writeln!(w, "{},\"{}\"", id, original.replace('"', "\"\""))?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Timing of a whole run, written by [`write_timing_json`].
+#[derive(Debug, Clone, Copy)]
+pub struct TimingReport {
+    pub total: Duration,
+    pub precompute: Duration,
+    pub backend: graph::Timings,
+    pub nodes: usize,
+    pub pairs: usize,
+}
+
+/// Write `report` as a flat JSON object:
+///
+/// `{"total_s", "per_iteration_ms", "precompute_s", "upload_s", "download_s", "iterations", "nodes", "pairs"}`
+///
+/// `per_iteration_ms` is the iteration loop's time over the iterations
+/// actually run, so early stops do not skew it.
+pub fn write_timing_json(path: &Path, report: &TimingReport) -> Result<()> {
+    let backend = &report.backend;
+    let per_iteration = backend.iterations.as_secs_f64() / backend.iterations_run.max(1) as f64;
+    let mut w = BufWriter::new(File::create(path)?);
+    writeln!(w, "{{")?;
+    writeln!(w, "  \"total_s\": {:.6},", report.total.as_secs_f64())?;
+    writeln!(w, "  \"per_iteration_ms\": {:.6},", per_iteration * 1000.0)?;
+    writeln!(w, "  \"precompute_s\": {:.6},", report.precompute.as_secs_f64())?;
+    writeln!(w, "  \"upload_s\": {:.6},", backend.upload.as_secs_f64())?;
+    writeln!(w, "  \"download_s\": {:.6},", backend.download.as_secs_f64())?;
+    writeln!(w, "  \"iterations\": {},", backend.iterations_run)?;
+    writeln!(w, "  \"nodes\": {},", report.nodes)?;
+    writeln!(w, "  \"pairs\": {}", report.pairs)?;
+    writeln!(w, "}}")?;
+    w.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn svg_y_points_up() {
+        let path = std::env::temp_dir().join(format!("svg-y-{}.svg", std::process::id()));
+        let graph = Graph::from_edges(2, vec![0], vec![1]).unwrap();
+        write_svg(&path, &graph, &[[0.0, 0.0], [1.0, 2.0]], None).unwrap();
+        let svg = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(svg.contains("<circle cx=\"1\" cy=\"-2\""), "{}", svg);
+        assert!(svg.contains("viewBox=\"-0.1 -2.1 "), "{}", svg);
+    }
+}
@@ -0,0 +1,95 @@
+//! Record and replay the CPU backend's pair-processing order (debug builds
+//! with the `pair-order-debug` feature).
+//!
+//! `--record-pair-order FILE` logs the shuffled order of every iteration;
+//! `--replay-pair-order FILE` forces a later run to process the pairs in
+//! exactly that order, e.g. to rerun a diverging layout step by step or to
+//! make two runs with different code comparable. The GPU backends process
+//! pairs concurrently, so their order cannot be recorded or forced; use
+//! `--backend cpu`.
+//!
+//! File format: a `# pairs <P> iterations <T>` header, then one line per
+//! iteration with the `P` pair indices (into the `(u, v)`-sorted pair list)
+//! in processing order.
+
+use crate::graph;
+use anyhow::Result;
+use rand::seq::SliceRandom;
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Orders logged by a recording hook, written out by [`Recording::save`].
+pub struct Recording {
+    path: PathBuf,
+    num_pairs: usize,
+    orders: Rc<RefCell<Vec<Vec<usize>>>>,
+}
+
+impl Recording {
+    pub fn save(&self) -> Result<()> {
+        let orders = self.orders.borrow();
+        let mut w = BufWriter::new(File::create(&self.path)?);
+        writeln!(w, "# pairs {} iterations {}", self.num_pairs, orders.len())?;
+        for order in orders.iter() {
+            let line: Vec<String> = order.iter().map(|i| i.to_string()).collect();
+            writeln!(w, "{}", line.join(" "))?;
+        }
+        w.flush()?;
+        println!("Recorded pair order of {} iterations to {}", orders.len(), self.path.display());
+        Ok(())
+    }
+}
+
+/// Shuffle like the default CPU backend (from the same `seed`) and log
+/// every order.
+pub fn recorder(path: PathBuf, num_pairs: usize, seed: Option<u64>) -> (graph::PairOrderFn, Recording) {
+    let orders = Rc::new(RefCell::new(Vec::new()));
+    let log = orders.clone();
+    let mut rng = graph::seeded_rng(seed);
+    let hook: graph::PairOrderFn = Box::new(move |_, order| {
+        order.shuffle(&mut rng);
+        log.borrow_mut().push(order.to_vec());
+    });
+    (hook, Recording { path, num_pairs, orders })
+}
+
+/// Replay the orders in `path`, which must cover `num_pairs` pairs and at
+/// least `iterations` iterations.
+pub fn replayer(path: &Path, num_pairs: usize, iterations: usize) -> Result<graph::PairOrderFn> {
+    let text = fs::read_to_string(path)?;
+    let mut orders = Vec::new();
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let order = line
+            .split_whitespace()
+            .map(|t| t.parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow::anyhow!("{}:{}: {}", path.display(), lineno + 1, e))?;
+        let mut seen = vec![false; num_pairs];
+        for &i in &order {
+            if i >= num_pairs || std::mem::replace(&mut seen[i], true) {
+                anyhow::bail!("{}:{}: not a permutation of {} pairs", path.display(), lineno + 1, num_pairs);
+            }
+        }
+        if order.len() != num_pairs {
+            anyhow::bail!("{}:{}: {} pairs recorded, {} expected", path.display(), lineno + 1, order.len(), num_pairs);
+        }
+        orders.push(order);
+    }
+    if orders.len() < iterations {
+        anyhow::bail!(
+            "{}: {} iterations recorded, {} needed",
+            path.display(),
+            orders.len(),
+            iterations
+        );
+    }
+    println!("Replaying pair order of {} iterations from {}", orders.len(), path.display());
+    Ok(Box::new(move |iteration, order| order.copy_from_slice(&orders[iteration])))
+}
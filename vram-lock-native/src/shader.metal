@@ -8,6 +8,14 @@ struct EdgeInfo {
     float wij;
 };
 
+// Periodic domain description (toroidal == 0 means an open plane)
+struct Boundary {
+    uint toroidal;
+    float width;
+    float height;
+    uint _pad;
+};
+
 // Helper: atomic float add using native Metal 3.1+ atomic<float>
 // This is O(1) instead of O(k) for the CAS-loop approach
 inline void atomic_add_float(device atomic<float>* addr, float value) {
@@ -27,127 +35,200 @@ inline void unlock(device atomic_uint* locks, uint node) {
     atomic_store_explicit(&locks[node], 0u, memory_order_relaxed);
 }
 
-inline bool acquire_locks(device atomic_uint* locks, uint node1, uint node2) {
-    // Always lock in order: smaller index first (deadlock prevention)
-    uint first = min(node1, node2);
-    uint second = max(node1, node2);
-    
-    // Spin until both locks are acquired (with timeout for safety)
-    const uint max_retries = 1000000;
-    for (uint retry = 0; retry < max_retries; retry++) {
-        if (try_lock(locks, first)) {
-            if (try_lock(locks, second)) {
-                return true;  // Both locks acquired successfully
-            } else {
-                unlock(locks, first);  // Release first lock and retry
-            }
-        }
-        // Small yield to allow other workgroups to progress
-    }
-    
-    // Timeout (should be extremely rare with proper GPU parallelism)
-    return false;
-}
-
 inline void release_locks(device atomic_uint* locks, uint node1, uint node2) {
     unlock(locks, node1);
     unlock(locks, node2);
 }
 
-kernel void sgd(
-    constant float* etas [[buffer(0)]],
-    device atomic<float>* positions [[buffer(1)]],  // Native atomic<float> for Metal 3.1+
-    constant EdgeInfo* pairs [[buffer(2)]],
-    constant uint& iteration [[buffer(3)]],
-    device atomic_uint* locks [[buffer(4)]],
-    device uint* updated_pairs [[buffer(5)]],
-    device atomic_uint& updated_count [[buffer(6)]],
-    device float4* positions_before [[buffer(7)]],
-    constant uint& num_pairs [[buffer(8)]],  // Add num_pairs parameter
-    uint3 local_id [[thread_position_in_threadgroup]],
-    uint3 workgroup_id [[threadgroup_position_in_grid]]
+// Deterministic angle in [0, 2π) for the pair (u, v), the nudge direction
+// when two nodes coincide: the splitmix64 finaliser on the packed pair, as
+// `pair_angle` in cpu.rs, keeping the 24 top bits a float can hold
+inline float pair_angle(uint u, uint v) {
+    ulong z = ((ulong(u) << 32) ^ ulong(v)) + 0x9e3779b97f4a7c15ul;
+    z = (z ^ (z >> 30)) * 0xbf58476d1ce4e5b9ul;
+    z = (z ^ (z >> 27)) * 0x94d049bb133111ebul;
+    z ^= z >> 31;
+    return float(z >> 40) / 16777216.0f * 6.283185307179586f;
+}
+
+// SGD update of pair `pair_idx` between nodes i and j; the caller holds
+// both locks (or owns both nodes through the pair coloring)
+inline void update_pair(
+    device atomic<float>* positions,
+    device uint* updated_pairs,
+    device atomic_uint& updated_count,
+    device float4* positions_before,
+    constant Boundary& boundary,
+    uint dims,
+    constant uint* levels,
+    uint pair_idx, uint i, uint j, float dij, float wij, float eta
 ) {
-    // 1 workgroup = 32 threads (= 1 warp)
-    // each workgroup handles 1 pair
-    // use 2D dispatch to handle more pairs: pair_idx = y * 65535 + x
-    // only local_id.x == 0 does the work
-    uint pair_idx = workgroup_id.y * 65535u + workgroup_id.x;
-    
-    // only thread 0 in the workgroup does the work
-    if (local_id.x != 0u) {
-        return;
-    }
-    
-    // Check bounds - CRITICAL for performance!
-    if (pair_idx >= num_pairs) {
-        return;
-    }
-    
-    EdgeInfo pair = pairs[pair_idx];
-    uint i = pair.u;
-    uint j = pair.v;
-    
-    // Only process upper triangular matrix (i < j)
-    if (i >= j) {
-        return;
-    }
-    
-    float dij = pair.dij;
-    float wij = pair.wij;
-    float eta = etas[iteration];
-    
-    // Acquire locks for both nodes (deadlock-free with retry limit)
-    if (!acquire_locks(locks, i, j)) {
-        // Failed to acquire locks after max retries
-        // Skip this pair in this iteration
-        return;
-    }
-    
     // Record the pair index
     uint record_idx = atomic_fetch_add_explicit(&updated_count, 1u, memory_order_relaxed);
     updated_pairs[record_idx] = pair_idx;
-    
-    // Calculate position indices
-    uint i_x = i * 2;
-    uint i_y = i_x + 1;
-    uint j_x = j * 2;
-    uint j_y = j_x + 1;
-    
+
+    // Calculate position indices (node k occupies positions[k * dims ..][..dims])
+    uint i_base = i * dims;
+    uint j_base = j * dims;
+
     // Read positions AFTER acquiring locks (need atomic_load for memory visibility)
-    float2 pos_i = float2(
-        atomic_load_explicit(&positions[i_x], memory_order_relaxed),
-        atomic_load_explicit(&positions[i_y], memory_order_relaxed)
+    float3 pos_i = float3(
+        atomic_load_explicit(&positions[i_base], memory_order_relaxed),
+        atomic_load_explicit(&positions[i_base + 1], memory_order_relaxed),
+        0.0
     );
-    float2 pos_j = float2(
-        atomic_load_explicit(&positions[j_x], memory_order_relaxed),
-        atomic_load_explicit(&positions[j_y], memory_order_relaxed)
+    float3 pos_j = float3(
+        atomic_load_explicit(&positions[j_base], memory_order_relaxed),
+        atomic_load_explicit(&positions[j_base + 1], memory_order_relaxed),
+        0.0
     );
-    
+    if (dims == 3u) {
+        pos_i.z = atomic_load_explicit(&positions[i_base + 2], memory_order_relaxed);
+        pos_j.z = atomic_load_explicit(&positions[j_base + 2], memory_order_relaxed);
+    }
+
     positions_before[record_idx] = float4(pos_i.x, pos_i.y, pos_j.x, pos_j.y);
-    
+
     // SGD update (matching WGSL implementation)
     const float tiny = 1e-12;
-    float2 diff = pos_j - pos_i;
+    float3 diff = pos_j - pos_i;
+
+    // Minimum-image convention: use the shortest wrapped displacement
+    // (the periodic domain only spans x and y)
+    if (boundary.toroidal != 0u) {
+        float2 size = float2(boundary.width, boundary.height);
+        diff.xy -= size * round(diff.xy / size);
+    }
+
     float dist = length(diff);
-    
-    // Handle zero/tiny distance case
+
+    // Handle zero/tiny distance case: nudge along the pair's own direction
     if (dist < tiny) {
-        diff = float2(1e-6, 1e-6);
+        float angle = pair_angle(i, j);
+        diff = float3(cos(angle) * 1e-6, sin(angle) * 1e-6, 0.0);
         dist = length(diff);
     }
-    
-    float2 r = ((dist - dij) / 2.0) * (diff / dist);
+
+    float3 r = ((dist - dij) / 2.0) * (diff / dist);
     float mu = min(wij * eta, 1.0);
-    
-    float2 delta = mu * r;
-    
+
+    float3 delta = mu * r;
+
+    // Of two nodes at different levels the higher one stays put and the
+    // other takes the whole displacement; fixed nodes never move
+    uint level_i = levels[i];
+    uint level_j = levels[j];
+    float3 delta_i = (level_i < 2u && level_i <= level_j) ? (level_i < level_j ? 2.0 : 1.0) * delta : float3(0.0);
+    float3 delta_j = (level_j < 2u && level_j <= level_i) ? (level_j < level_i ? 2.0 : 1.0) * delta : float3(0.0);
+
     // Atomic update of positions (need atomic for memory visibility across workgroups)
-    atomic_add_float(&positions[i_x], delta.x);
-    atomic_add_float(&positions[i_y], delta.y);
-    atomic_add_float(&positions[j_x], -delta.x);
-    atomic_add_float(&positions[j_y], -delta.y);
+    atomic_add_float(&positions[i_base], delta_i.x);
+    atomic_add_float(&positions[i_base + 1], delta_i.y);
+    atomic_add_float(&positions[j_base], -delta_j.x);
+    atomic_add_float(&positions[j_base + 1], -delta_j.y);
+    if (dims == 3u) {
+        atomic_add_float(&positions[i_base + 2], delta_i.z);
+        atomic_add_float(&positions[j_base + 2], -delta_j.z);
+    }
+}
+
+kernel void sgd(
+    constant float* etas [[buffer(0)]],
+    device atomic<float>* positions [[buffer(1)]],  // Native atomic<float> for Metal 3.1+
+    constant EdgeInfo* pairs [[buffer(2)]],
+    constant uint& iteration [[buffer(3)]],
+    device atomic_uint* locks [[buffer(4)]],
+    device uint* updated_pairs [[buffer(5)]],
+    device atomic_uint& updated_count [[buffer(6)]],
+    device float4* positions_before [[buffer(7)]],
+    constant uint& num_pairs [[buffer(8)]],  // Add num_pairs parameter
+    constant Boundary& boundary [[buffer(9)]],
+    constant uint& dims [[buffer(10)]],  // coordinates per node: 2, or 3 for a 3D layout
+    constant uint* levels [[buffer(11)]],  // SgdParams::node_levels
+    uint3 local_id [[thread_position_in_threadgroup]],
+    uint3 workgroup_id [[threadgroup_position_in_grid]],
+    uint3 num_workgroups [[threadgroups_per_grid]],
+    uint3 threads_per_workgroup [[threads_per_threadgroup]]
+) {
+    // Every thread handles its own pairs: thread t of the grid takes pairs
+    // t, t + total threads, ... (grid stride). The 2D dispatch is flattened
+    // row-major: workgroup index = y * grid width + x
+    uint wg_size = threads_per_workgroup.x;
+    uint workgroup_idx = workgroup_id.y * num_workgroups.x + workgroup_id.x;
+    uint total_threads = num_workgroups.x * num_workgroups.y * wg_size;
+    for (uint pair_idx = workgroup_idx * wg_size + local_id.x; pair_idx < num_pairs; pair_idx += total_threads) {
+        EdgeInfo pair = pairs[pair_idx];
+        uint i = pair.u;
+        uint j = pair.v;
+    
+        // Only process upper triangular matrix (i < j)
+        if (i >= j) {
+            continue;
+        }
     
-    // Release locks
-    release_locks(locks, i, j);
+        float eta = etas[iteration];
+    
+        // Always lock in order: smaller index first (deadlock prevention)
+        uint first = min(i, j);
+        uint second = max(i, j);
+    
+        // Spin until both locks are acquired (with timeout for safety). The
+        // update and unlock happen inside the loop: threads of one SIMD group
+        // run the loop in lockstep, so a thread that only left the loop with
+        // the locks held would keep them until every other thread of its
+        // group gave up spinning on them.
+        const uint max_retries = 1000000;
+        for (uint retry = 0; retry < max_retries; retry++) {
+            if (!try_lock(locks, first)) {
+                continue;
+            }
+            if (!try_lock(locks, second)) {
+                unlock(locks, first);  // Release first lock and retry
+                continue;
+            }
+            
+            update_pair(positions, updated_pairs, updated_count, positions_before, boundary, dims, levels,
+                        pair_idx, i, j, pair.dij, pair.wij, eta);
+    
+            // Release locks
+            release_locks(locks, i, j);
+            break;
+        }
+        // After max_retries without both locks the pair is skipped in this
+        // iteration (should be extremely rare)
+    }
 }
 
+// Lock-free variant for pairs reordered by color (SgdParams::apply_pair_coloring):
+// no two pairs of one color share a node, so each dispatch updates its color
+// without locks. color_range is (first pair, pair count) of that color.
+kernel void sgd_colored(
+    constant float* etas [[buffer(0)]],
+    device atomic<float>* positions [[buffer(1)]],
+    constant EdgeInfo* pairs [[buffer(2)]],
+    constant uint& iteration [[buffer(3)]],
+    device uint* updated_pairs [[buffer(5)]],
+    device atomic_uint& updated_count [[buffer(6)]],
+    device float4* positions_before [[buffer(7)]],
+    constant uint2& color_range [[buffer(8)]],
+    constant Boundary& boundary [[buffer(9)]],
+    constant uint& dims [[buffer(10)]],
+    constant uint* levels [[buffer(11)]],
+    uint3 local_id [[thread_position_in_threadgroup]],
+    uint3 workgroup_id [[threadgroup_position_in_grid]],
+    uint3 num_workgroups [[threadgroups_per_grid]],
+    uint3 threads_per_workgroup [[threads_per_threadgroup]]
+) {
+    uint wg_size = threads_per_workgroup.x;
+    uint workgroup_idx = workgroup_id.y * num_workgroups.x + workgroup_id.x;
+    uint total_threads = num_workgroups.x * num_workgroups.y * wg_size;
+    float eta = etas[iteration];
+    for (uint k = workgroup_idx * wg_size + local_id.x; k < color_range.y; k += total_threads) {
+        uint pair_idx = color_range.x + k;
+        EdgeInfo pair = pairs[pair_idx];
+        if (pair.u < pair.v) {
+            update_pair(positions, updated_pairs, updated_count, positions_before, boundary, dims, levels,
+                        pair_idx, pair.u, pair.v, pair.dij, pair.wij, eta);
+        }
+    }
+}
@@ -0,0 +1,249 @@
+struct EdgeInfo {
+    u: u32,
+    v: u32,
+    dij: f32,
+    wij: f32,
+}
+
+@group(0) @binding(0)
+var<storage, read> etas: array<f32>;
+
+// Coordinates per node, set at pipeline creation: 2, or 3 for a 3D layout.
+// Node k occupies positions[k * dims ..][..dims].
+override dims: u32 = 2u;
+
+@group(0) @binding(1)
+var<storage, read_write> positions: array<f32>;
+
+@group(0) @binding(2)
+var<storage, read> pairs: array<EdgeInfo>;
+
+@group(0) @binding(3)
+var<uniform> iteration: u32;
+
+@group(0) @binding(4)
+var<storage, read_write> locks: array<atomic<u32>>;
+
+@group(0) @binding(5)
+var<storage, read_write> updated_pairs: array<u32>;
+
+// Pairs recorded so far by the dispatches of the bound chunk of pairs
+@group(0) @binding(6)
+var<storage, read_write> updated_count: atomic<u32>;
+
+@group(0) @binding(7)
+var<storage, read_write> positions_before: array<vec4<f32>>;  // Store [xi, yi, xj, yj] for each updated pair
+
+// Periodic domain description (toroidal == 0 means an open plane)
+struct Boundary {
+    toroidal: u32,
+    width: f32,
+    height: f32,
+    _pad: u32,
+}
+
+@group(0) @binding(8)
+var<uniform> boundary: Boundary;
+
+// (first pair, pair count) of the color processed by sgd_colored, relative to
+// the chunk of the pairs buffer bound with it, at a dynamic offset per pass
+@group(0) @binding(9)
+var<uniform> color_range: vec2<u32>;
+
+// SgdParams::node_levels: 2 for nodes pinned by SgdParams::pin, 1 for
+// sparse stress pivots, 0 otherwise
+@group(0) @binding(10)
+var<storage, read> levels: array<u32>;
+
+// Position of `node` as a vec3 (z = 0 in 2D)
+fn load_position(node: u32) -> vec3<f32> {
+    let base = node * dims;
+    var p = vec3<f32>(positions[base], positions[base + 1u], 0.0);
+    if (dims == 3u) {
+        p.z = positions[base + 2u];
+    }
+    return p;
+}
+
+fn store_position(node: u32, p: vec3<f32>) {
+    let base = node * dims;
+    positions[base] = p.x;
+    positions[base + 1u] = p.y;
+    if (dims == 3u) {
+        positions[base + 2u] = p.z;
+    }
+}
+
+// Atomic lock helper functions (based on WebGPU best practices)
+fn try_lock(node: u32) -> bool {
+    // Try to swap 0 -> 1. If old value was 0, we got the lock
+    let old_value = atomicExchange(&locks[node], 1u);
+    return old_value == 0u;
+}
+
+fn unlock(node: u32) {
+    atomicExchange(&locks[node], 0u);
+}
+
+fn release_locks(node1: u32, node2: u32) {
+    unlock(node1);
+    unlock(node2);
+}
+
+// 64-bit words as (high, low) u32 halves, since WGSL has no u64
+
+fn add64(a: vec2<u32>, b: vec2<u32>) -> vec2<u32> {
+    let lo = a.y + b.y;
+    return vec2<u32>(a.x + b.x + select(0u, 1u, lo < a.y), lo);
+}
+
+// Low 64 bits of a * b; a.y * b.y is formed from 16-bit halves
+fn mul64(a: vec2<u32>, b: vec2<u32>) -> vec2<u32> {
+    let p00 = (a.y & 0xffffu) * (b.y & 0xffffu);
+    let p01 = (a.y & 0xffffu) * (b.y >> 16u);
+    let p10 = (a.y >> 16u) * (b.y & 0xffffu);
+    let p11 = (a.y >> 16u) * (b.y >> 16u);
+    let mid = (p00 >> 16u) + (p01 & 0xffffu) + (p10 & 0xffffu);
+    let hi = p11 + (p01 >> 16u) + (p10 >> 16u) + (mid >> 16u);
+    return vec2<u32>(hi + a.x * b.y + a.y * b.x, (p00 & 0xffffu) | (mid << 16u));
+}
+
+// a ^ (a >> s) for 0 < s < 32
+fn xorshift64(a: vec2<u32>, s: u32) -> vec2<u32> {
+    return a ^ vec2<u32>(a.x >> s, (a.y >> s) | (a.x << (32u - s)));
+}
+
+// Deterministic angle in [0, 2π) for the pair (u, v), the nudge direction
+// when two nodes coincide: the splitmix64 finaliser on the packed pair, as
+// `pair_angle` in cpu.rs, keeping the 24 top bits an f32 can hold
+fn pair_angle(u: u32, v: u32) -> f32 {
+    var z = add64(vec2<u32>(u, v), vec2<u32>(0x9e3779b9u, 0x7f4a7c15u));
+    z = mul64(xorshift64(z, 30u), vec2<u32>(0xbf58476du, 0x1ce4e5b9u));
+    z = mul64(xorshift64(z, 27u), vec2<u32>(0x94d049bbu, 0x133111ebu));
+    z = xorshift64(z, 31u);
+    return f32(z.x >> 8u) / 16777216.0 * 6.283185307179586;
+}
+
+// SGD update of pair `pair_idx` between nodes i and j; the caller holds
+// both locks
+fn update_pair(pair_idx: u32, i: u32, j: u32, dij: f32, wij: f32, eta: f32) {
+    // Record the pair index and positions BEFORE update (right after acquiring locks)
+    let record_idx = atomicAdd(&updated_count, 1u);
+    updated_pairs[record_idx] = pair_idx;
+    let pos_i = load_position(i);
+    let pos_j = load_position(j);
+    positions_before[record_idx] = vec4<f32>(pos_i.x, pos_i.y, pos_j.x, pos_j.y);
+    
+    // SGD update (matching Python implementation)
+    let tiny = 1e-12;
+    var diff = pos_j - pos_i;
+    
+    // Minimum-image convention: use the shortest wrapped displacement
+    // (the periodic domain only spans x and y)
+    if (boundary.toroidal != 0u) {
+        let size = vec2<f32>(boundary.width, boundary.height);
+        diff = vec3<f32>(diff.xy - size * round(diff.xy / size), diff.z);
+    }
+    
+    var dist = length(diff);
+    
+    // Handle zero/tiny distance case: nudge along the pair's own direction
+    if (dist < tiny) {
+        let angle = pair_angle(i, j);
+        diff = vec3<f32>(cos(angle) * 1e-6, sin(angle) * 1e-6, 0.0);
+        dist = length(diff);
+    }
+    
+    let r = ((dist - dij) / 2.0) * (diff / dist);
+    let mu = min(wij * eta, 1.0);
+    
+    // Of two nodes at different levels the higher one stays put and the
+    // other takes the whole displacement; fixed nodes never move
+    let level_i = levels[i];
+    let level_j = levels[j];
+    if (level_i < 2u && level_i <= level_j) {
+        store_position(i, pos_i + select(1.0, 2.0, level_i < level_j) * mu * r);
+    }
+    if (level_j < 2u && level_j <= level_i) {
+        store_position(j, pos_j - select(1.0, 2.0, level_j < level_i) * mu * r);
+    }
+}
+
+// Lock both nodes of pairs[pair_idx], update them and unlock
+fn process_pair(pair_idx: u32) {
+    let pair = pairs[pair_idx];
+    let i = pair.u;
+    let j = pair.v;
+    
+    // Only process upper triangular matrix (i < j)
+    if (i >= j) {
+        return;
+    }
+    
+    // Get learning rate for this iteration
+    let eta = etas[iteration];
+    
+    // Always lock in order: smaller index first (deadlock prevention)
+    let first = min(i, j);
+    let second = max(i, j);
+    
+    // Spin until both locks are acquired (with timeout for safety). The
+    // update and unlock happen inside the loop: threads of one SIMD group
+    // run the loop in lockstep, so a thread that only left the loop with the
+    // locks held would keep them until every other thread of its group gave
+    // up spinning on them.
+    let max_retries = 1000000u;
+    for (var retry = 0u; retry < max_retries; retry++) {
+        if (try_lock(first)) {
+            if (try_lock(second)) {
+                update_pair(pair_idx, i, j, pair.dij, pair.wij, eta);
+                release_locks(i, j);
+                return;
+            }
+            unlock(first);  // Release first lock and retry
+        }
+    }
+    // Failed to acquire locks after max retries: skip this pair in this
+    // iteration (should be extremely rare)
+}
+
+// Threads per workgroup, set at pipeline creation (GpuContext::with_workgroup_size)
+override wg_size: u32 = 32u;
+
+@compute @workgroup_size(wg_size, 1, 1)
+fn sgd(
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    // Every thread handles its own pairs: thread t of the grid takes pairs
+    // t, t + total threads, ... (grid stride). The 2D dispatch is flattened
+    // row-major: workgroup index = y * grid width + x
+    let total_pairs = arrayLength(&pairs);
+    let workgroup_idx = workgroup_id.y * num_workgroups.x + workgroup_id.x;
+    let total_threads = num_workgroups.x * num_workgroups.y * wg_size;
+    for (var pair_idx = workgroup_idx * wg_size + local_id.x; pair_idx < total_pairs; pair_idx += total_threads) {
+        process_pair(pair_idx);
+    }
+}
+
+// Lock-free variant for pairs reordered by color (SgdParams::apply_pair_coloring):
+// no two pairs of one color share a node, so each pass updates its color
+// without locks and the passes are ordered by the dispatch boundaries
+@compute @workgroup_size(wg_size, 1, 1)
+fn sgd_colored(
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    let eta = etas[iteration];
+    let workgroup_idx = workgroup_id.y * num_workgroups.x + workgroup_id.x;
+    let total_threads = num_workgroups.x * num_workgroups.y * wg_size;
+    for (var k = workgroup_idx * wg_size + local_id.x; k < color_range.y; k += total_threads) {
+        let pair_idx = color_range.x + k;
+        let pair = pairs[pair_idx];
+        if (pair.u < pair.v) {
+            update_pair(pair_idx, pair.u, pair.v, pair.dij, pair.wij, eta);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! The native backends against baseline-sgd-non-gpu's `execute_sgd`, the
+//! reference implementation of the update rule.
+//!
+//! Every backend (and on the GPU both the lock-based and the pair-colored
+//! kernel) starts from the same seeded positions, pairs and learning rates
+//! as the baseline run, and its final stress must be within `TOLERANCE`
+//! (relative) of the baseline's. Pair order differs between the
+//! implementations, so positions are not compared.
+
+use vram_lock_native::{init_backend, metrics, Graph, LayoutBuilder};
+
+// baseline-sgd-non-gpu is a binary crate, so it is compiled in from source;
+// its `algorithm` refers to `crate::graph`, hence both at the root
+#[allow(dead_code)]
+#[path = "../../baseline-sgd-non-gpu/src/graph.rs"]
+mod graph;
+#[allow(dead_code)]
+#[path = "../../baseline-sgd-non-gpu/src/algorithm.rs"]
+mod algorithm;
+
+const ITERATIONS: usize = 30;
+const TOLERANCE: f64 = 0.05;
+
+fn cycle() -> Graph {
+    Graph::from_edges(6, (0..6).collect(), (1..6).chain([0]).collect()).unwrap()
+}
+
+/// 5 x 5 grid; node `y * 5 + x` sits at `(x, y)`.
+fn grid() -> Graph {
+    let side = 5;
+    let (mut src, mut dst) = (Vec::new(), Vec::new());
+    for v in 0..side * side {
+        if v % side + 1 < side {
+            src.push(v);
+            dst.push(v + 1);
+        }
+        if v + side < side * side {
+            src.push(v);
+            dst.push(v + side);
+        }
+    }
+    Graph::from_edges(side * side, src, dst).unwrap()
+}
+
+fn builder() -> LayoutBuilder {
+    LayoutBuilder::new().iterations(ITERATIONS).seed(0)
+}
+
+/// Final stress of the baseline run on the native crate's seeded params.
+fn baseline_stress(graph: &Graph) -> f64 {
+    let params = builder().params(graph).unwrap();
+    let baseline = graph::SgdParams::<2> {
+        etas: params.etas.clone(),
+        positions: params.positions.clone(),
+        pairs: params
+            .pairs
+            .iter()
+            .map(|p| graph::EdgeInfo { u: p.u, v: p.v, dij: p.dij, wij: p.wij })
+            .collect(),
+        center: true,
+        center_mode: graph::CenterMode::Centroid,
+        fixed: Vec::new(),
+    };
+    let positions = algorithm::execute_sgd(baseline);
+    let positions: Vec<[f32; 2]> = positions.iter().map(|p| [p[0] as f32, p[1] as f32]).collect();
+    metrics::stress(&positions, &params.pairs)
+}
+
+fn final_stress(graph: &Graph, backend: &str, pair_coloring: bool) -> f64 {
+    let pairs = builder().params(graph).unwrap().pairs;
+    let (_, layout) = builder().backend(backend).pair_coloring(pair_coloring).run(graph).unwrap();
+    metrics::stress(&layout.positions, &pairs)
+}
+
+fn assert_matches(label: &str, stress: f64, reference: f64) {
+    let diff = (stress - reference).abs() / reference.max(f64::MIN_POSITIVE);
+    assert!(diff <= TOLERANCE, "{}: stress {} vs {} in the baseline", label, stress, reference);
+}
+
+#[test]
+fn cpu_stress_matches_the_baseline() {
+    for (name, graph) in [("cycle", cycle()), ("grid", grid())] {
+        let reference = baseline_stress(&graph);
+        assert_matches(&format!("cpu on the {}", name), final_stress(&graph, "cpu", false), reference);
+    }
+}
+
+#[test]
+fn gpu_stress_matches_the_baseline() {
+    let gpu_backends: &[&str] = if cfg!(target_os = "macos") { &["metal", "wgpu"] } else { &["wgpu"] };
+    for &backend in gpu_backends {
+        if init_backend(backend).is_err() {
+            eprintln!("skipping {}: no adapter", backend);
+            continue;
+        }
+        for (name, graph) in [("cycle", cycle()), ("grid", grid())] {
+            let reference = baseline_stress(&graph);
+            for pair_coloring in [false, true] {
+                let label = format!("{} (pair coloring {}) on the {}", backend, pair_coloring, name);
+                assert_matches(&label, final_stress(&graph, backend, pair_coloring), reference);
+            }
+        }
+    }
+}
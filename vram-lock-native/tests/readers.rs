@@ -0,0 +1,77 @@
+//! vram-lock and vram-lock-native must build the same graph from a Matrix
+//! Market file or an edge list: self-loops dropped and one edge per node
+//! pair.
+
+use std::path::{Path, PathBuf};
+use vram_lock_native::Graph;
+
+// vram-lock is a binary crate, so its reader is compiled in from source
+#[allow(dead_code)]
+#[path = "../../vram-lock/src/graph.rs"]
+mod vram_lock_graph;
+
+/// Undirected edges as sorted `(min, max)` pairs.
+fn edge_set(src: &[usize], dst: &[usize]) -> Vec<(usize, usize)> {
+    let mut edges: Vec<_> = src.iter().zip(dst).map(|(&u, &v)| (u.min(v), u.max(v))).collect();
+    edges.sort_unstable();
+    edges
+}
+
+#[test]
+fn both_readers_load_bcspwr01_identically() {
+    let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../data/bcspwr01.mtx"));
+    let native = Graph::from_mtx(path).unwrap();
+    let vram_lock = vram_lock_graph::Graph::from_mtx(path).unwrap();
+
+    assert_eq!(native.node_size, vram_lock.node_size);
+    assert_eq!(native.edge_size, vram_lock.edge_size);
+    let native_edges = edge_set(&native.edge_src, &native.edge_dst);
+    assert_eq!(native_edges, edge_set(&vram_lock.edge_src, &vram_lock.edge_dst));
+    // No self-loops and no pair twice
+    assert!(native_edges.iter().all(|&(u, v)| u < v));
+    assert!(native_edges.windows(2).all(|w| w[0] != w[1]));
+}
+
+/// Write `text` to a file in the temp directory, unique per test process.
+fn write_temp(name: &str, text: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-{}.txt", name, std::process::id()));
+    std::fs::write(&path, text).unwrap();
+    path
+}
+
+#[test]
+fn both_readers_load_an_edge_list_identically() {
+    let path = write_temp(
+        "edge-list",
+        "# comment\n1 2\n\n2 1\n  2 3 0.5\n3 3\n1 2\n4 1\n",
+    );
+    for (one_indexed, node_size, edges) in [
+        (true, 4, vec![(0, 1), (1, 2), (3, 0)]),
+        (false, 5, vec![(1, 2), (2, 3), (4, 1)]),
+    ] {
+        let native = Graph::from_edge_list(&path, one_indexed).unwrap();
+        let vram_lock = vram_lock_graph::Graph::from_edge_list(&path, one_indexed).unwrap();
+        assert_eq!(native.node_size, node_size);
+        assert_eq!(native.edge_src.iter().copied().zip(native.edge_dst.iter().copied()).collect::<Vec<_>>(), edges);
+        assert_eq!(vram_lock.node_size, node_size);
+        assert_eq!((vram_lock.edge_src, vram_lock.edge_dst), (native.edge_src, native.edge_dst));
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn bad_edge_list_lines_are_errors() {
+    for (text, one_indexed, message) in [
+        ("1 2\na b\n", false, "2: invalid node index \"a\""),
+        ("1 -2\n", false, "1: invalid node index \"-2\""),
+        ("1 2\n3\n", false, "2: expected `src dst`"),
+        ("0 1\n", true, "1: node index 0 in a 1-indexed edge list"),
+    ] {
+        let path = write_temp("bad-edge-list", text);
+        let native = Graph::from_edge_list(&path, one_indexed).unwrap_err().to_string();
+        let vram_lock = vram_lock_graph::Graph::from_edge_list(&path, one_indexed).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(native.contains(message), "{}", native);
+        assert_eq!(vram_lock, native);
+    }
+}
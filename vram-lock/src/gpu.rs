@@ -1,6 +1,8 @@
 use crate::graph;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::num::NonZeroU64;
+use std::path::Path;
+use std::time::{Duration, Instant};
 use wgpu::util::DeviceExt;
 use bytemuck::{Pod, Zeroable};
 
@@ -13,6 +15,14 @@ pub struct GpuEdgeInfo {
     pub wij: f32,
 }
 
+// Must match the layout of `EdgeInfo` in shader.wgsl (the pairs binding
+// declares `min_binding_size` 16).
+const _: () = assert!(std::mem::size_of::<GpuEdgeInfo>() == 16);
+const _: () = assert!(std::mem::align_of::<GpuEdgeInfo>() == 4);
+
+/// Number of storage buffers bound by the SGD pipeline (bindings 0-2, 4-7).
+const STORAGE_BUFFERS_USED: u32 = 7;
+
 #[derive(Debug)]
 pub struct GpuGraphParams {
     pub etas: Vec<f32>,
@@ -29,6 +39,9 @@ pub struct GpuPipeline {
     pub positions_buffer: wgpu::Buffer,
     pub download_buffer: wgpu::Buffer,
     pub iteration_buffer: wgpu::Buffer,
+    /// Holds `0..num_iterations`; `execute_compute_pipeline` copies one entry
+    /// into `iteration_buffer` before each pass.
+    pub iteration_indices_buffer: wgpu::Buffer,
     #[allow(dead_code)]
     pub lock_buffer: wgpu::Buffer,  // Used by GPU shader for atomic locks
     pub updated_pairs_buffer: wgpu::Buffer,
@@ -40,6 +53,10 @@ pub struct GpuPipeline {
     pub node_size: u32,
     pub num_iterations: u32,
     pub num_pairs: u32,
+    /// Next iteration to run; advanced by `GpuContext::run_n`.
+    pub next_iteration: u32,
+    /// Threads per workgroup the pipeline was compiled with (`wg_size`).
+    pub workgroup_size: u32,
 }
 
 #[derive(Debug)]
@@ -47,26 +64,71 @@ pub struct GpuContext {
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub module: wgpu::ShaderModule,
+    /// WGSL source exactly as handed to `create_shader_module`.
+    pub shader_source: String,
+    /// Nanoseconds per timestamp tick, when the device was created with
+    /// `TIMESTAMP_QUERY`.
+    pub timestamp_period: Option<f32>,
+    /// Threads per workgroup for pipelines created from now on; see
+    /// [`GpuContext::with_workgroup_size`].
+    pub workgroup_size: u32,
 }
 
 impl GpuContext {
-    // Initialize GPU context
+    // Initialize GPU context. Fails instead of panicking when no usable
+    // adapter or device exists (headless CI, unsupported GPUs).
     pub fn new() -> Result<Self> {
-        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let instance_descriptor = wgpu::InstanceDescriptor::default();
+        let backends = instance_descriptor.backends;
+        let instance = wgpu::Instance::new(&instance_descriptor);
+
+        let adapter_options = wgpu::RequestAdapterOptions::default();
+        let adapter = pollster::block_on(instance.request_adapter(&adapter_options)).with_context(|| {
+            format!(
+                "Failed to create adapter (backends {:?}, power preference {:?})",
+                backends, adapter_options.power_preference
+            )
+        })?;
 
-        let adapter =
-            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
-                .expect("Failed to create adapter");
+        // Fail early with a clear message instead of deep inside pipeline creation
+        let info = adapter.get_info();
+        let downlevel = adapter.get_downlevel_capabilities();
+        if !downlevel.flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS) {
+            anyhow::bail!(
+                "selected adapter {} ({:?}, {:?}) does not support compute shaders",
+                info.name,
+                info.device_type,
+                info.backend
+            );
+        }
+        let storage_buffers = adapter.limits().max_storage_buffers_per_shader_stage;
+        if storage_buffers < STORAGE_BUFFERS_USED {
+            anyhow::bail!(
+                "selected adapter {} ({:?}) supports only {} storage buffers per shader stage, {} are needed",
+                info.name,
+                info.device_type,
+                storage_buffers,
+                STORAGE_BUFFERS_USED
+            );
+        }
 
+        // Timestamp queries are optional; without them iteration times fall
+        // back to the wall clock
+        let required_features = adapter.features() & wgpu::Features::TIMESTAMP_QUERY;
         let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
             label: None,
-            required_features: wgpu::Features::empty(),
+            required_features,
             required_limits: adapter.limits(),
             experimental_features: wgpu::ExperimentalFeatures::disabled(),
             memory_hints: wgpu::MemoryHints::MemoryUsage,
             trace: wgpu::Trace::Off,
         }))
-        .expect("Failed to create device");
+        .with_context(|| {
+            format!(
+                "Failed to create device on {} ({:?}) with features {:?}",
+                info.name, info.backend, required_features
+            )
+        })?;
 
         // LOG: graphics card info
         // println!("Running on Adapter: {:#?}", adapter.get_info());
@@ -75,16 +137,60 @@ impl GpuContext {
         //     adapter.limits().max_compute_invocations_per_workgroup
         // );
 
-        let module = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
+        let shader_source = include_str!("shader.wgsl").to_string();
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shader.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(shader_source.as_str().into()),
+        });
+
+        let timestamp_period = device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| queue.get_timestamp_period());
+        let workgroup_size = default_workgroup_size(&device.limits());
 
         Ok(GpuContext {
             device,
             queue,
             module,
+            shader_source,
+            timestamp_period,
+            workgroup_size,
         })
     }
 
+    /// Threads per workgroup, passed to the shader's `wg_size` override when
+    /// the pipeline is created. Fails if the device cannot run workgroups of
+    /// that size.
+    pub fn with_workgroup_size(mut self, size: u32) -> Result<Self> {
+        let limits = self.device.limits();
+        let max = limits.max_compute_workgroup_size_x.min(limits.max_compute_invocations_per_workgroup);
+        if size == 0 || size > max {
+            anyhow::bail!("workgroup size {} is not supported by this device (1 to {})", size, max);
+        }
+        self.workgroup_size = size;
+        Ok(self)
+    }
+
+    /// Write the WGSL source the pipeline was compiled from to `path`.
+    ///
+    /// The `override` declarations carry the values the pipeline is created
+    /// with (see [`GpuContext::pipeline_constants`]) instead of their
+    /// declared defaults, so the file is exactly what the driver saw.
+    pub fn dump_shader(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, specialize(&self.shader_source, &self.pipeline_constants()))?;
+        println!("Shader source written to {}", path.display());
+        Ok(())
+    }
+
+    /// Values for the shader's `override` constants, passed to every
+    /// pipeline this context creates.
+    pub fn pipeline_constants(&self) -> [(&'static str, f64); 1] {
+        [("wg_size", self.workgroup_size as f64)]
+    }
+
     pub fn setup_compute_pipeline(&self, params: GpuGraphParams) -> Result<GpuPipeline> {
+        let upload_start = Instant::now();
         let etas_buffer = self
             .device
             .create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -125,6 +231,17 @@ impl GpuContext {
                 usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             });
 
+        // Iteration indices for the batched run (at least one entry, since a
+        // buffer cannot be empty)
+        let iteration_indices: Vec<u32> = (0..params.etas.len().max(1) as u32).collect();
+        let iteration_indices_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Iteration Indices Buffer"),
+                contents: bytemuck::cast_slice(&iteration_indices),
+                usage: wgpu::BufferUsages::COPY_SRC,
+            });
+
         // Lock buffer (initialized to 0 = unlocked for all nodes)
         let lock_buffer = self
             .device
@@ -181,6 +298,19 @@ impl GpuContext {
             mapped_at_creation: false,
         });
 
+        // Initialised buffers may be staged until the next submission, so
+        // flush and wait before reading the clock.
+        self.queue.submit([]);
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        let upload_bytes = etas_buffer.size()
+            + positions_buffer.size()
+            + pairs_buffer.size()
+            + iteration_buffer.size()
+            + iteration_indices_buffer.size()
+            + lock_buffer.size()
+            + updated_count_buffer.size();
+        log_transfer("Upload", upload_bytes, upload_start.elapsed());
+
         // NOTE: Bind group
         let bind_group_layout =
             self.device
@@ -333,7 +463,10 @@ impl GpuContext {
                 layout: Some(&pipeline_layout),
                 module: &self.module,
                 entry_point: None,
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &self.pipeline_constants(),
+                    ..Default::default()
+                },
                 cache: None,
             });
 
@@ -343,6 +476,7 @@ impl GpuContext {
             positions_buffer,
             download_buffer,
             iteration_buffer,
+            iteration_indices_buffer,
             lock_buffer,
             updated_pairs_buffer,
             updated_count_buffer,
@@ -353,6 +487,8 @@ impl GpuContext {
             node_size: params.positions.len() as u32,
             num_iterations: params.etas.len() as u32,
             num_pairs: params.pairs.len() as u32,
+            next_iteration: 0,
+            workgroup_size: self.workgroup_size,
         })
     }
 
@@ -393,116 +529,269 @@ impl GpuContext {
         Ok((pipeline, initial_positions, pairs_copy))
     }
 
-    pub fn execute_compute_pipeline(&self, p: GpuPipeline, pairs_info: &[GpuEdgeInfo]) -> Result<Vec<[f32; 2]>> {
-        // @workgroup_size(32,1,1): Each workgroup = 32 threads (= 1 warp)
-        // Each workgroup processes one pair (only local_id.x == 0 does work)
-        // Use 2D dispatch to handle more pairs (up to 65535 * 65535)
-        let max_x = 65535u32;
-        let workgroup_count_x = p.num_pairs.min(max_x);
-        let workgroup_count_y = (p.num_pairs + max_x - 1) / max_x;
-        
-        println!("Dispatching {}x{} workgroups (1 WG per pair, 32 threads per WG) for {} pairs on {} nodes", workgroup_count_x, workgroup_count_y, p.num_pairs, p.node_size);
-        
-        for iteration in 0..p.num_iterations {
-            // Reset updated_count to 0 at the beginning of each iteration
-            self.queue.write_buffer(&p.updated_count_buffer, 0, bytemuck::cast_slice(&[0u32]));
-            
-            // Update iteration buffer
-            self.queue.write_buffer(&p.iteration_buffer, 0, bytemuck::cast_slice(&[iteration]));
-            
-            let mut encoder =
-                self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { 
-                    label: Some(&format!("SGD Iteration {}", iteration)) 
-                });
+    /// Run every remaining iteration in a single submission, then download
+    /// the positions. Returns them with the GPU time of each iteration in
+    /// milliseconds.
+    ///
+    /// All passes go into one command encoder. Before each pass the encoder
+    /// clears the updated-pair counter and copies the iteration index from
+    /// `iteration_indices_buffer`, so the GPU never waits for the CPU between
+    /// iterations. (`queue.write_buffer` would not do: every write lands
+    /// before the submission starts.) Unlike [`run_n`](Self::run_n), the
+    /// updated pairs are not read back or logged.
+    ///
+    /// With `TIMESTAMP_QUERY` each pass writes a timestamp at its start and
+    /// end. Otherwise the wall-clock time of the whole submission is split
+    /// evenly over the iterations.
+    pub fn execute_compute_pipeline(&self, mut p: GpuPipeline) -> Result<(Vec<[f32; 2]>, Vec<f64>)> {
+        let max_per_dim = self.device.limits().max_compute_workgroups_per_dimension;
+        let (workgroup_count_x, workgroup_count_y) = workgroup_counts(p.num_pairs, p.workgroup_size, max_per_dim);
+        let iterations = p.next_iteration..p.num_iterations;
+        let count = iterations.len();
+        println!(
+            "Dispatching {}x{} workgroups (1 pair per thread, {} threads per WG) for {} pairs on {} nodes, {} iterations in one submission",
+            workgroup_count_x,
+            workgroup_count_y,
+            p.workgroup_size,
+            p.num_pairs,
+            p.node_size,
+            count
+        );
 
+        // Two queries (pass start and end) per iteration; a query set holds
+        // at most QUERY_SET_MAX_QUERIES
+        let per_set = (wgpu::QUERY_SET_MAX_QUERIES / 2) as usize;
+        let query_sets: Vec<wgpu::QuerySet> = match self.timestamp_period {
+            Some(_) => (0..count.div_ceil(per_set))
+                .map(|k| {
+                    self.device.create_query_set(&wgpu::QuerySetDescriptor {
+                        label: Some("SGD Timestamps"),
+                        ty: wgpu::QueryType::Timestamp,
+                        count: ((count - k * per_set).min(per_set) * 2) as u32,
+                    })
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("SGD Iterations"),
+        });
+        for (i, iteration) in iterations.enumerate() {
+            encoder.clear_buffer(&p.updated_count_buffer, 0, None);
+            encoder.copy_buffer_to_buffer(
+                &p.iteration_indices_buffer,
+                iteration as u64 * 4,
+                &p.iteration_buffer,
+                0,
+                4,
+            );
             let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: Some(&format!("SGD Pass {}", iteration)),
-                timestamp_writes: None,
+                timestamp_writes: query_sets.get(i / per_set).map(|query_set| {
+                    let first = (2 * (i % per_set)) as u32;
+                    wgpu::ComputePassTimestampWrites {
+                        query_set,
+                        beginning_of_pass_write_index: Some(first),
+                        end_of_pass_write_index: Some(first + 1),
+                    }
+                }),
             });
-
             compute_pass.set_pipeline(&p.pipeline);
             compute_pass.set_bind_group(0, &p.bind_group, &[]);
-            
-            // Dispatch workgroups in 2D (x, y)
             compute_pass.dispatch_workgroups(workgroup_count_x, workgroup_count_y, 1);
+        }
+        let timestamps_download_buffer = (!query_sets.is_empty()).then(|| {
+            let size = (count * 2 * std::mem::size_of::<u64>()) as u64;
+            let resolve_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Timestamp Resolve Buffer"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+            let download_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Timestamp Download Buffer"),
+                size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            });
+            for (k, query_set) in query_sets.iter().enumerate() {
+                let queries = ((count - k * per_set).min(per_set) * 2) as u32;
+                let offset = (k * per_set * 2 * std::mem::size_of::<u64>()) as u64;
+                encoder.resolve_query_set(query_set, 0..queries, &resolve_buffer, offset);
+            }
+            encoder.copy_buffer_to_buffer(&resolve_buffer, 0, &download_buffer, 0, size);
+            download_buffer
+        });
 
-            drop(compute_pass);
+        let submit_start = Instant::now();
+        self.queue.submit([encoder.finish()]);
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        let wall = submit_start.elapsed();
+        p.next_iteration = p.num_iterations;
+
+        let (iteration_ms, source) = match (&timestamps_download_buffer, self.timestamp_period) {
+            (Some(buffer), Some(period)) => {
+                let slice = buffer.slice(..);
+                slice.map_async(wgpu::MapMode::Read, |_| {});
+                self.device.poll(wgpu::PollType::wait_indefinitely())?;
+                let data = slice.get_mapped_range();
+                let ticks: Vec<u64> = data
+                    .chunks_exact(8)
+                    .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+                    .collect();
+                drop(data);
+                buffer.unmap();
+                let ms = ticks
+                    .chunks_exact(2)
+                    .map(|t| t[1].wrapping_sub(t[0]) as f64 * period as f64 / 1e6)
+                    .collect();
+                (ms, "timestamp queries")
+            }
+            _ => {
+                let per_iteration = wall.as_secs_f64() * 1000.0 / count.max(1) as f64;
+                (vec![per_iteration; count], "wall clock, timestamp queries unsupported")
+            }
+        };
+        let total: f64 = iteration_ms.iter().sum();
+        println!(
+            "GPU time: {:.3}ms total, {:.3}ms per iteration ({})",
+            total,
+            total / count.max(1) as f64,
+            source
+        );
 
-            // Copy updated_count and updated_pairs to download buffers
-            encoder.copy_buffer_to_buffer(
-                &p.updated_count_buffer,
-                0,
-                &p.updated_count_download_buffer,
-                0,
-                p.updated_count_buffer.size(),
-            );
+        Ok((self.download_positions(&p)?, iteration_ms))
+    }
 
-            encoder.copy_buffer_to_buffer(
-                &p.updated_pairs_buffer,
-                0,
-                &p.updated_pairs_download_buffer,
-                0,
-                p.updated_pairs_buffer.size(),
-            );
+    /// Run up to `n` more iterations from where the pipeline stopped, then
+    /// download and return the current positions.
+    ///
+    /// The pipeline keeps its buffers and iteration counter between calls, so
+    /// an interactive caller can step the layout a few iterations at a time.
+    /// Iterations beyond the learning-rate schedule are not run.
+    pub fn run_n(&self, p: &mut GpuPipeline, n: u32, pairs_info: &[GpuEdgeInfo]) -> Result<Vec<[f32; 2]>> {
+        let end = p.next_iteration.saturating_add(n).min(p.num_iterations);
+        if p.next_iteration == 0 && end > 0 {
+            let max_per_dim = self.device.limits().max_compute_workgroups_per_dimension;
+            let (workgroup_count_x, workgroup_count_y) = workgroup_counts(p.num_pairs, p.workgroup_size, max_per_dim);
+            println!("Dispatching {}x{} workgroups (1 pair per thread, {} threads per WG) for {} pairs on {} nodes", workgroup_count_x, workgroup_count_y, p.workgroup_size, p.num_pairs, p.node_size);
+        }
+        for iteration in p.next_iteration..end {
+            self.run_iteration(p, iteration, pairs_info)?;
+        }
+        p.next_iteration = end;
 
-            encoder.copy_buffer_to_buffer(
-                &p.positions_before_buffer,
-                0,
-                &p.positions_before_download_buffer,
-                0,
-                p.positions_before_buffer.size(),
-            );
+        self.download_positions(p)
+    }
 
-            self.queue.submit([encoder.finish()]);
-
-            // Wait for GPU to complete this iteration
-            self.device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
-            
-            // Read back the updated count
-            let count_slice = p.updated_count_download_buffer.slice(..);
-            count_slice.map_async(wgpu::MapMode::Read, |_| {});
-            self.device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
-            
-            let count_data = count_slice.get_mapped_range();
-            let count: u32 = bytemuck::cast_slice::<u8, u32>(&count_data)[0];
-            drop(count_data);
-            p.updated_count_download_buffer.unmap();
-            
-            // Read back the updated pairs
-            let pairs_slice = p.updated_pairs_download_buffer.slice(..);
-            pairs_slice.map_async(wgpu::MapMode::Read, |_| {});
-            self.device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
-            
-            let pairs_data = pairs_slice.get_mapped_range();
-            let pairs: &[u32] = bytemuck::cast_slice(&pairs_data);
-            let updated_pairs: Vec<u32> = pairs[0..count as usize].to_vec();
-            drop(pairs_data);
-            p.updated_pairs_download_buffer.unmap();
-            
-            // Read back the positions before update
-            let positions_before_slice = p.positions_before_download_buffer.slice(..);
-            positions_before_slice.map_async(wgpu::MapMode::Read, |_| {});
-            self.device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
-            
-            let positions_before_data = positions_before_slice.get_mapped_range();
-            let positions_before: &[[f32; 4]] = bytemuck::cast_slice(&positions_before_data);
-            
-            println!("Iteration {} - Updated {} pairs:", iteration, count);
-            for idx in 0..count as usize {
-                let pair_idx = updated_pairs[idx] as usize;
-                let pair = &pairs_info[pair_idx];
-                let pos_before = positions_before[idx];
-                println!("  pair[{}] (nodes {}-{}): pos[{}]=({:.4}, {:.4}), pos[{}]=({:.4}, {:.4})", 
-                    pair_idx, pair.u, pair.v, 
-                    pair.u, pos_before[0], pos_before[1], 
-                    pair.v, pos_before[2], pos_before[3]);
-            }
-            
-            drop(positions_before_data);
-            p.positions_before_download_buffer.unmap();
+    /// Dispatch one SGD iteration and log the pairs it updated.
+    fn run_iteration(&self, p: &GpuPipeline, iteration: u32, pairs_info: &[GpuEdgeInfo]) -> Result<()> {
+        let max_per_dim = self.device.limits().max_compute_workgroups_per_dimension;
+        let (workgroup_count_x, workgroup_count_y) = workgroup_counts(p.num_pairs, p.workgroup_size, max_per_dim);
+
+        // Reset updated_count to 0 at the beginning of each iteration
+        self.queue.write_buffer(&p.updated_count_buffer, 0, bytemuck::cast_slice(&[0u32]));
+        
+        // Update iteration buffer
+        self.queue.write_buffer(&p.iteration_buffer, 0, bytemuck::cast_slice(&[iteration]));
+        
+        let mut encoder =
+            self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { 
+                label: Some(&format!("SGD Iteration {}", iteration)) 
+            });
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&format!("SGD Pass {}", iteration)),
+            timestamp_writes: None,
+        });
+
+        compute_pass.set_pipeline(&p.pipeline);
+        compute_pass.set_bind_group(0, &p.bind_group, &[]);
+        
+        // Dispatch workgroups in 2D (x, y)
+        compute_pass.dispatch_workgroups(workgroup_count_x, workgroup_count_y, 1);
+
+        drop(compute_pass);
+
+        // Copy updated_count and updated_pairs to download buffers
+        encoder.copy_buffer_to_buffer(
+            &p.updated_count_buffer,
+            0,
+            &p.updated_count_download_buffer,
+            0,
+            p.updated_count_buffer.size(),
+        );
+
+        encoder.copy_buffer_to_buffer(
+            &p.updated_pairs_buffer,
+            0,
+            &p.updated_pairs_download_buffer,
+            0,
+            p.updated_pairs_buffer.size(),
+        );
+
+        encoder.copy_buffer_to_buffer(
+            &p.positions_before_buffer,
+            0,
+            &p.positions_before_download_buffer,
+            0,
+            p.positions_before_buffer.size(),
+        );
+
+        self.queue.submit([encoder.finish()]);
+
+        // Wait for GPU to complete this iteration
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        
+        // Read back the updated count
+        let count_slice = p.updated_count_download_buffer.slice(..);
+        count_slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        
+        let count_data = count_slice.get_mapped_range();
+        let count: u32 = bytemuck::cast_slice::<u8, u32>(&count_data)[0];
+        drop(count_data);
+        p.updated_count_download_buffer.unmap();
+        
+        // Read back the updated pairs
+        let pairs_slice = p.updated_pairs_download_buffer.slice(..);
+        pairs_slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        
+        let pairs_data = pairs_slice.get_mapped_range();
+        let pairs: &[u32] = bytemuck::cast_slice(&pairs_data);
+        let updated_pairs: Vec<u32> = pairs[0..count as usize].to_vec();
+        drop(pairs_data);
+        p.updated_pairs_download_buffer.unmap();
+        
+        // Read back the positions before update
+        let positions_before_slice = p.positions_before_download_buffer.slice(..);
+        positions_before_slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        
+        let positions_before_data = positions_before_slice.get_mapped_range();
+        let positions_before: &[[f32; 4]] = bytemuck::cast_slice(&positions_before_data);
+        
+        println!("Iteration {} - Updated {} pairs:", iteration, count);
+        for idx in 0..count as usize {
+            let pair_idx = updated_pairs[idx] as usize;
+            let pair = &pairs_info[pair_idx];
+            let pos_before = positions_before[idx];
+            println!("  pair[{}] (nodes {}-{}): pos[{}]=({:.4}, {:.4}), pos[{}]=({:.4}, {:.4})", 
+                pair_idx, pair.u, pair.v, 
+                pair.u, pos_before[0], pos_before[1], 
+                pair.v, pos_before[2], pos_before[3]);
         }
         
-        // NOTE: Download final results
+        drop(positions_before_data);
+        p.positions_before_download_buffer.unmap();
+        Ok(())
+    }
+
+    /// Copy the positions buffer back to the CPU.
+    fn download_positions(&self, p: &GpuPipeline) -> Result<Vec<[f32; 2]>> {
+        let download_start = Instant::now();
         let mut encoder =
             self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
@@ -524,14 +813,183 @@ impl GpuContext {
         // Wait for the GPU to finish working on the submitted work.
         // Note: poll() works on native (desktop) environments, but NOT on Web (wasm/browser).
         // On Web, you must use the callback to know when the buffer is mapped.
-        self.device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
 
         // We can now read the data from the buffer.
         let data = buffer_slice.get_mapped_range();
+        let coords = positions_from_bytes(&data, p.node_size as usize * 2);
+        drop(data);
+        p.download_buffer.unmap();
+        log_transfer("Download", p.download_buffer.size(), download_start.elapsed());
+
         // Convert the data to Vec<[f32; 2]>
-        let positions_data: &[[f32; 2]] = bytemuck::cast_slice(&data);
-        let result: Vec<[f32; 2]> = positions_data.to_vec();
+        Ok(coords?.chunks_exact(2).map(|c| [c[0], c[1]]).collect())
+    }
+}
+
+/// Decode `count` little-endian `f32` coordinates (the flattened positions)
+/// from a mapped readback buffer.
+///
+/// Reads byte-wise rather than with `bytemuck::cast_slice`, so neither the
+/// mapping's alignment nor a buffer size that is not a multiple of 4 (e.g.
+/// padding added to the buffer) can make it panic. Bytes past the last
+/// coordinate are ignored; a buffer too short for `count` coordinates is an
+/// error. Identical to the copy (and tests) in vram-lock-native's gpu.rs.
+fn positions_from_bytes(data: &[u8], count: usize) -> Result<Vec<f32>> {
+    let needed = count * 4;
+    if data.len() < needed {
+        anyhow::bail!(
+            "position readback has {} bytes, {} needed for {} coordinates",
+            data.len(),
+            needed,
+            count
+        );
+    }
+    Ok(data[..needed]
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect())
+}
+
+/// `source` with the default of each `override` named in `constants`
+/// replaced by its value. All of the shader's overrides are `u32`.
+fn specialize(source: &str, constants: &[(&str, f64)]) -> String {
+    let mut out = String::with_capacity(source.len());
+    for line in source.lines() {
+        let name = line.strip_prefix("override ").and_then(|rest| rest.split(':').next());
+        let value = constants.iter().find(|(c, _)| Some(*c) == name.map(str::trim));
+        match (value, line.split_once('=')) {
+            (Some((_, value)), Some((declaration, _))) => {
+                out.push_str(&format!("{}= {}u;", declaration, *value as u32))
+            }
+            _ => out.push_str(line),
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Workgroup grid for one iteration.
+///
+/// @workgroup_size(wg_size,1,1): Each workgroup = `workgroup_size` threads
+/// Each thread processes one pair, so ceil(num_pairs / workgroup_size) workgroups,
+/// 2D once they exceed the device's `max_per_dim` (see [`dispatch_grid`])
+fn workgroup_counts(num_pairs: u32, workgroup_size: u32, max_per_dim: u32) -> (u32, u32) {
+    dispatch_grid(num_pairs.div_ceil(workgroup_size), max_per_dim)
+}
+
+/// Most balanced `(x, y)` workgroup grid covering `count` workgroups with
+/// both dimensions at most `max_per_dim`.
+///
+/// Uses the fewest rows that fit and spreads `count` evenly over them, so at
+/// most `y - 1` workgroups fall past the end. Filling rows up to the limit
+/// instead would leave e.g. 65536 workgroups with a second row of 65534 idle
+/// ones. vram-lock-native's gpu.rs has the same function.
+fn dispatch_grid(count: u32, max_per_dim: u32) -> (u32, u32) {
+    let rows = count.div_ceil(max_per_dim.max(1)).max(1);
+    (count.div_ceil(rows), rows)
+}
+
+/// Subgroup width assumed when the adapter reports none: one NVIDIA warp,
+/// the shader's `wg_size` default.
+const DEFAULT_WORKGROUP_SIZE: u32 = 32;
+
+/// Largest default workgroup size; the kernel uses no workgroup memory or
+/// barriers, so bigger workgroups would not help it.
+const MAX_DEFAULT_WORKGROUP_SIZE: u32 = 256;
+
+/// As many threads as the device runs per workgroup
+/// (`max_compute_invocations_per_workgroup` and the x dimension limit), at
+/// most [`MAX_DEFAULT_WORKGROUP_SIZE`], rounded down to whole subgroups of
+/// `max_subgroup_size` ([`DEFAULT_WORKGROUP_SIZE`] where the adapter does not
+/// report it). A device below one subgroup gets all it allows.
+fn default_workgroup_size(limits: &wgpu::Limits) -> u32 {
+    let subgroup = if limits.max_subgroup_size > 0 { limits.max_subgroup_size } else { DEFAULT_WORKGROUP_SIZE };
+    let max = limits
+        .max_compute_invocations_per_workgroup
+        .min(limits.max_compute_workgroup_size_x)
+        .min(MAX_DEFAULT_WORKGROUP_SIZE);
+    if max < subgroup {
+        max.max(1)
+    } else {
+        max / subgroup * subgroup
+    }
+}
+
+/// Log the size and effective bandwidth of a host <-> GPU transfer.
+fn log_transfer(label: &str, bytes: u64, elapsed: Duration) {
+    let secs = elapsed.as_secs_f64();
+    let mb = bytes as f64 / 1e6;
+    let gbps = if secs > 0.0 { bytes as f64 / secs / 1e9 } else { f64::INFINITY };
+    println!("{}: {:.3} MB in {:.3}ms ({:.2} GB/s)", label, mb, secs * 1000.0, gbps);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COORDS: [f32; 6] = [0.5, -1.0, 2.0, 3.5, -0.25, 7.0];
+
+    fn coord_bytes() -> Vec<u8> {
+        COORDS.iter().flat_map(|c| c.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn decodes_an_odd_node_count() {
+        // 3 nodes: 24 bytes, not a multiple of 16
+        assert_eq!(positions_from_bytes(&coord_bytes(), 6).unwrap(), COORDS);
+    }
+
+    #[test]
+    fn ignores_a_misaligned_tail() {
+        let mut bytes = coord_bytes();
+        bytes.extend([0xaa, 0xbb, 0xcc]);
+        assert_eq!(positions_from_bytes(&bytes, 6).unwrap(), COORDS);
+    }
+
+    #[test]
+    fn decodes_from_an_unaligned_start() {
+        let mut bytes = vec![0];
+        bytes.extend(coord_bytes());
+        assert_eq!(positions_from_bytes(&bytes[1..], 6).unwrap(), COORDS);
+    }
+
+    #[test]
+    fn specialize_sets_the_workgroup_size() {
+        let source = specialize(include_str!("shader.wgsl"), &[("wg_size", 64.0)]);
+        assert!(source.contains("override wg_size: u32 = 64u;"));
+        assert!(!source.contains("= 32u;"));
+        assert_eq!(source.lines().count(), include_str!("shader.wgsl").lines().count());
+    }
+
+    #[test]
+    fn default_workgroup_size_is_whole_subgroups_up_to_the_cap() {
+        let limits = |invocations: u32, subgroup: u32| wgpu::Limits {
+            max_compute_invocations_per_workgroup: invocations,
+            max_compute_workgroup_size_x: invocations,
+            max_subgroup_size: subgroup,
+            ..wgpu::Limits::default()
+        };
+        assert_eq!(default_workgroup_size(&limits(1024, 32)), 256);
+        assert_eq!(default_workgroup_size(&limits(1024, 64)), 256);
+        assert_eq!(default_workgroup_size(&limits(200, 64)), 192);
+        assert_eq!(default_workgroup_size(&limits(200, 0)), 192);
+        assert_eq!(default_workgroup_size(&limits(16, 32)), 16);
+    }
+
+    #[test]
+    fn dispatch_grid_balances_the_rows() {
+        assert_eq!(dispatch_grid(10, 65535), (10, 1));
+        assert_eq!(dispatch_grid(65536, 65535), (32768, 2));
+        assert_eq!(dispatch_grid(0, 65535), (0, 1));
+        let (x, y) = dispatch_grid(200_000, 65535);
+        assert!(x <= 65535 && x * y >= 200_000 && x * y - 200_000 < y);
+    }
 
-        Ok(result)
+    #[test]
+    fn rejects_a_short_buffer() {
+        let bytes = coord_bytes();
+        let err = positions_from_bytes(&bytes[..22], 6).unwrap_err().to_string();
+        assert!(err.contains("22 bytes, 24 needed"), "{}", err);
     }
 }
@@ -2,7 +2,8 @@ use anyhow::Result;
 use rand::Rng;
 use sprs::io::read_matrix_market;
 use sprs::num_kinds::Pattern;
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
+use std::fs;
 use std::path::Path;
 
 #[derive(Debug)]
@@ -34,19 +35,109 @@ impl Graph {
 
         let node_size: usize = matrix.rows();
         
-        // Filter out self-loops
+        // Filter out self-loops and duplicate edges
         let mut edge_src = Vec::new();
         let mut edge_dst = Vec::new();
+        let mut seen = HashSet::new();
         
-        for (row, col) in matrix.row_inds().iter().zip(matrix.col_inds().iter()) {
-            if row != col {
-                edge_src.push(*row);
-                edge_dst.push(*col);
+        for (&row, &col) in matrix.row_inds().iter().zip(matrix.col_inds().iter()) {
+            if row != col && seen.insert((row.min(col), row.max(col))) {
+                edge_src.push(row);
+                edge_dst.push(col);
             }
         }
         
         let edge_size = edge_src.len();
 
+        // Drop trailing nodes no edge references, as vram-lock-native does;
+        // they would only float at their random initial positions
+        let referenced = edge_src
+            .iter()
+            .chain(edge_dst.iter())
+            .max()
+            .map_or(node_size, |&m| m + 1);
+        let node_size = if referenced < node_size {
+            println!(
+                "Warning: matrix declares {} nodes but edges only reference {}; ignoring {} trailing unreferenced nodes",
+                node_size,
+                referenced,
+                node_size - referenced
+            );
+            referenced
+        } else {
+            node_size
+        };
+
+        Ok(Graph {
+            node_size,
+            edge_size,
+            edge_src,
+            edge_dst,
+        })
+    }
+
+    /// Load a whitespace-separated edge list (`src dst` per line; further
+    /// columns such as weights are ignored). Blank lines and lines starting
+    /// with `#` are skipped. Indices are 1-based when `one_indexed` is set,
+    /// 0-based otherwise; `node_size` is the largest index plus one. As with
+    /// [`from_mtx`](Self::from_mtx), self-loops are dropped and each node pair
+    /// becomes one edge.
+    pub fn from_edge_list(path: &Path, one_indexed: bool) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let offset = usize::from(one_indexed);
+        let mut edge_src = Vec::new();
+        let mut edge_dst = Vec::new();
+        let mut seen = HashSet::new();
+        let mut node_size = 0;
+        for (lineno, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let (Some(src), Some(dst)) = (tokens.next(), tokens.next()) else {
+                anyhow::bail!("{}:{}: expected `src dst`, got {:?}", path.display(), lineno + 1, line);
+            };
+            let mut index = |token: &str| -> Result<usize> {
+                let raw: usize = token.parse().map_err(|e| {
+                    anyhow::anyhow!("{}:{}: invalid node index {:?}: {}", path.display(), lineno + 1, token, e)
+                })?;
+                let index = raw.checked_sub(offset).ok_or_else(|| {
+                    anyhow::anyhow!("{}:{}: node index 0 in a 1-indexed edge list", path.display(), lineno + 1)
+                })?;
+                node_size = node_size.max(index + 1);
+                Ok(index)
+            };
+            let (u, v) = (index(src)?, index(dst)?);
+            if u != v && seen.insert((u.min(v), u.max(v))) {
+                edge_src.push(u);
+                edge_dst.push(v);
+            }
+        }
+        Self::from_edges(node_size, edge_src, edge_dst)
+    }
+
+    /// Build a graph from in-memory edge arrays (`edge_src[i]`-`edge_dst[i]`
+    /// is edge `i`). Fails if the arrays differ in length or an endpoint is
+    /// not below `node_size`.
+    pub fn from_edges(node_size: usize, edge_src: Vec<usize>, edge_dst: Vec<usize>) -> Result<Self> {
+        if edge_src.len() != edge_dst.len() {
+            anyhow::bail!(
+                "edge_src has {} entries but edge_dst has {}",
+                edge_src.len(),
+                edge_dst.len()
+            );
+        }
+        if let Some((i, (u, v))) = edge_src
+            .iter()
+            .zip(&edge_dst)
+            .enumerate()
+            .find(|(_, (&u, &v))| u >= node_size || v >= node_size)
+        {
+            anyhow::bail!("edge {} ({}, {}) out of range for {} nodes", i, u, v, node_size);
+        }
+
+        let edge_size = edge_src.len();
         Ok(Graph {
             node_size,
             edge_size,
@@ -97,18 +188,14 @@ impl Graph {
         let mut dmin: f64 = f64::INFINITY;
         let mut dmax: f64 = 0.0;
 
-        for u in 0..dist.len() {
-            for v in 0..dist[u].len() {
-                if u >= v {
-                    continue;
-                }
-
+        for (u, row) in dist.iter().enumerate() {
+            for (v, &d) in row.iter().enumerate().skip(u + 1) {
                 // Skip unreachable nodes (distance == usize::MAX)
-                if dist[u][v] == usize::MAX {
+                if d == usize::MAX {
                     continue;
                 }
 
-                let dij = dist[u][v] as f64;
+                let dij = d as f64;
                 if dij <= 0.0 {
                     continue;
                 }
@@ -127,31 +214,79 @@ impl Graph {
         (pairs, wmin, wmax)
     }
 
-    /// Precompute SGD parameters
+    /// Precompute SGD parameters. `initial_positions` (e.g. from
+    /// [`init_positions_from_file`]) replaces the random start and is used
+    /// as given; it must hold one position per node.
     pub fn prepare_sgd_params(
         &self,
         iterations: usize,
         epsilon: f64,
         center: bool,
-    ) -> SgdParams {
+        initial_positions: Option<Vec<[f64; 2]>>,
+    ) -> Result<SgdParams> {
+        if let Some(positions) = &initial_positions {
+            anyhow::ensure!(
+                positions.len() == self.node_size,
+                "{} initial positions given for {} nodes",
+                positions.len(),
+                self.node_size
+            );
+        }
         let dist = self.calc_dist_matrix();
         let (pairs, wmin, wmax) = self.calc_edge_info(&dist);
 
         let etas = calc_learning_rate(iterations, wmin, wmax, epsilon);
 
-        let positions = init_positions_random(self.node_size, center);
+        let positions = initial_positions.unwrap_or_else(|| init_positions_random(self.node_size, center));
 
-        SgdParams {
+        Ok(SgdParams {
             etas,
             positions,
             pairs,
-        }
+        })
+    }
+}
+
+/// Layout stress `Σ wij (||xi - xj|| - dij)^2` over `pairs`; lower is
+/// better.
+pub fn stress(positions: &[[f64; 2]], pairs: &[EdgeInfo]) -> f64 {
+    pairs
+        .iter()
+        .map(|p| {
+            let dx = positions[p.u][0] - positions[p.v][0];
+            let dy = positions[p.u][1] - positions[p.v][1];
+            let err = (dx * dx + dy * dy).sqrt() - p.dij;
+            p.wij * err * err
+        })
+        .sum()
+}
+
+/// [`stress`] divided by `Σ wij dij^2`, the stress of placing every node at
+/// the same point, so layouts of different graphs can be compared. Zero when
+/// `pairs` is empty.
+pub fn normalized_stress(positions: &[[f64; 2]], pairs: &[EdgeInfo]) -> f64 {
+    let total: f64 = pairs.iter().map(|p| p.wij * p.dij * p.dij).sum();
+    if total > 0.0 {
+        stress(positions, pairs) / total
+    } else {
+        0.0
     }
 }
 
 pub fn calc_learning_rate(tmax: usize, wmin: f64, wmax: f64, eps: f64) -> Vec<f64> {
+    // A zero or infinite bound would make a step infinite; use the other one
+    let usable = |w: f64| w.is_finite() && w > 0.0;
+    let (wmin, wmax) = match (usable(wmin), usable(wmax)) {
+        (true, true) => (wmin, wmax),
+        (true, false) => (wmin, wmin),
+        (false, true) => (wmax, wmax),
+        (false, false) => (1.0, 1.0),
+    };
     let eta_max = 1.0 / wmin;
     let eta_min = eps / wmax;
+    if tmax < 2 {
+        return vec![eta_max; tmax];
+    }
     let lamb = (eta_max / eta_min).ln() / (tmax - 1) as f64;
 
     let etas: Vec<f64> = (0..tmax)
@@ -161,6 +296,46 @@ pub fn calc_learning_rate(tmax: usize, wmin: f64, wmax: f64, eps: f64) -> Vec<f6
     etas
 }
 
+/// Read the `# Positions (x y)` section of a result file written by this
+/// crate, to warm-start from a previous layout. Fails unless it holds
+/// exactly `n_nodes` positions.
+pub fn init_positions_from_file(path: &Path, n_nodes: usize) -> Result<Vec<[f64; 2]>> {
+    let text = fs::read_to_string(path)?;
+    let mut lines = text.lines().enumerate();
+    if !lines.any(|(_, line)| line.trim().starts_with("# Positions")) {
+        anyhow::bail!("{}: no `# Positions (x y)` section", path.display());
+    }
+
+    let mut positions = Vec::with_capacity(n_nodes);
+    for (lineno, line) in lines {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        // The next section header ends the positions
+        if line.starts_with('#') {
+            break;
+        }
+        let coords: Vec<f64> = line
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|e| anyhow::anyhow!("{}:{}: {}", path.display(), lineno + 1, e))?;
+        match coords[..] {
+            [x, y] if x.is_finite() && y.is_finite() => positions.push([x, y]),
+            _ => anyhow::bail!("{}:{}: expected two finite numbers `x y`", path.display(), lineno + 1),
+        }
+    }
+    anyhow::ensure!(
+        positions.len() == n_nodes,
+        "{}: {} positions for {} nodes",
+        path.display(),
+        positions.len(),
+        n_nodes
+    );
+    Ok(positions)
+}
+
 pub fn init_positions_random(n_nodes: usize, center: bool) -> Vec<[f64; 2]> {
     let mut rng = rand::rng();
 
@@ -186,3 +361,47 @@ pub fn init_positions_random(n_nodes: usize, center: bool) -> Vec<[f64; 2]> {
 
     positions
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path3_pairs() -> Vec<EdgeInfo> {
+        let graph = Graph::from_edges(3, vec![0, 1], vec![1, 2]).unwrap();
+        graph.calc_edge_info(&graph.calc_dist_matrix()).0
+    }
+
+    #[test]
+    fn stress_is_zero_at_graph_distances() {
+        let pairs = path3_pairs();
+        let positions = [[0.0, 0.0], [1.0, 0.0], [2.0, 0.0]];
+        assert_eq!(stress(&positions, &pairs), 0.0);
+        assert_eq!(normalized_stress(&positions, &pairs), 0.0);
+    }
+
+    #[test]
+    fn stress_of_a_stretched_path() {
+        // (1, 2) is 1 too long with weight 1, (0, 2) is 1 too long with
+        // weight 1/4; the total Σ wij dij² is 1 + 1 + 1/4 * 4 = 3
+        let pairs = path3_pairs();
+        let positions = [[0.0, 0.0], [1.0, 0.0], [3.0, 0.0]];
+        assert!((stress(&positions, &pairs) - 1.25).abs() < 1e-12);
+        assert!((normalized_stress(&positions, &pairs) - 1.25 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn learning_rates_cover_one_iteration_and_edgeless_graphs() -> Result<()> {
+        // main runs a single iteration: one step of 1 / wmin
+        let path = Graph::from_edges(3, vec![0, 1], vec![1, 2])?;
+        assert_eq!(path.prepare_sgd_params(1, 0.1, true, None)?.etas, vec![4.0]);
+        let etas = path.prepare_sgd_params(15, 0.1, true, None)?.etas;
+        assert!((etas[0] - 4.0).abs() < 1e-12);
+        assert!((etas[14] - 0.1).abs() < 1e-12);
+        assert!(etas.windows(2).all(|w| w[0] > w[1]));
+
+        let edgeless = Graph::from_edges(2, Vec::new(), Vec::new())?;
+        let etas = edgeless.prepare_sgd_params(5, 0.1, true, None)?.etas;
+        assert!(etas.iter().all(|eta| eta.is_finite() && *eta > 0.0));
+        Ok(())
+    }
+}
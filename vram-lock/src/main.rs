@@ -14,23 +14,87 @@ fn main() -> Result<()> {
     // let mtx_path = Path::new("../data/bcspwr10.mtx");
     // let graph = graph::Graph::from_mtx(mtx_path).expect("Failed to load matrix");
 
-    let graph = {
-        graph::Graph {
-            node_size: 10,
-            edge_size: 10,
-            edge_src: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
-            edge_dst: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 0],
+    let args: Vec<String> = std::env::args().collect();
+
+    // --input PATH: load a Matrix Market file instead of the built-in 10-cycle
+    let input = args.iter().position(|a| a == "--input").map(|idx| {
+        args.get(idx + 1)
+            .map(Path::new)
+            .ok_or_else(|| anyhow::anyhow!("usage: --input <path.mtx>"))
+    });
+
+    // --edge-list PATH [--one-indexed]: load a `src dst` edge list instead of
+    // the built-in 10-cycle
+    let edge_list = args.iter().position(|a| a == "--edge-list").map(|idx| {
+        args.get(idx + 1)
+            .map(Path::new)
+            .ok_or_else(|| anyhow::anyhow!("usage: --edge-list <path> [--one-indexed]"))
+    });
+    let (graph, data_name) = match (input, edge_list) {
+        (Some(path), _) => {
+            let path = path?;
+            let graph = graph::Graph::from_mtx(path)?;
+            println!("Loaded {} nodes, {} edges from {}", graph.node_size, graph.edge_size, path.display());
+            (graph, path.file_stem().unwrap_or_default().to_string_lossy().to_string())
+        }
+        (None, Some(path)) => {
+            let path = path?;
+            let graph = graph::Graph::from_edge_list(path, args.iter().any(|a| a == "--one-indexed"))?;
+            println!("Loaded {} nodes, {} edges from {}", graph.node_size, graph.edge_size, path.display());
+            (graph, path.file_stem().unwrap_or_default().to_string_lossy().to_string())
         }
+        (None, None) => (
+            graph::Graph::from_edges(
+                10,
+                vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+                vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 0],
+            )?,
+            "inline".to_string(),
+        ),
     };
 
     // LOG: Print graph information
     // println!("{:?}",graph);
 
     // GPU setup
-    let gpu_context = gpu::GpuContext::new()?;
+    let mut gpu_context = gpu::GpuContext::new()?;
+
+    // --workgroup-size N: threads per workgroup instead of the adapter's
+    // subgroup width
+    if let Some(idx) = args.iter().position(|a| a == "--workgroup-size") {
+        let size = args
+            .get(idx + 1)
+            .and_then(|s| s.parse::<u32>().ok())
+            .ok_or_else(|| anyhow::anyhow!("usage: --workgroup-size <threads>"))?;
+        gpu_context = gpu_context.with_workgroup_size(size)?;
+    }
+    println!("Workgroup size: {}", gpu_context.workgroup_size);
+
+    // --dump-shader PATH: write the compiled WGSL for inspection
+    if let Some(idx) = args.iter().position(|a| a == "--dump-shader") {
+        let path = args
+            .get(idx + 1)
+            .ok_or_else(|| anyhow::anyhow!("usage: --dump-shader <path>"))?;
+        gpu_context.dump_shader(Path::new(path))?;
+    }
+
+    // --init-positions RESULT.txt: warm start from the positions of a
+    // previous result file instead of random ones
+    let initial = match args.iter().position(|a| a == "--init-positions") {
+        Some(idx) => {
+            let path = args
+                .get(idx + 1)
+                .map(Path::new)
+                .ok_or_else(|| anyhow::anyhow!("usage: --init-positions <path>"))?;
+            let positions = graph::init_positions_from_file(path, graph.node_size)?;
+            println!("Initial positions loaded from {}", path.display());
+            Some(positions)
+        }
+        None => None,
+    };
 
     // CPU precompute
-    let sgd_params = graph.prepare_sgd_params(1, 0.1, true);
+    let sgd_params = graph.prepare_sgd_params(1, 0.1, true, initial)?;
 
     // GPU: convert + create pipeline
     let (pipeline, initial_positions, pairs_info) = gpu_context.create_pipeline_from_cpu_params(sgd_params)?;
@@ -39,53 +103,193 @@ fn main() -> Result<()> {
     // println!("Pipeline: {:?}", pipeline);
 
     let start = Instant::now();
-    let result = gpu::GpuContext::execute_compute_pipeline(&gpu_context, pipeline, &pairs_info)?;
+    // --log-updates: step one submission per iteration and print the pairs
+    // each iteration updated, instead of running all iterations at once
+    let result = if args.iter().any(|a| a == "--log-updates") {
+        let mut pipeline = pipeline;
+        gpu_context.run_n(&mut pipeline, u32::MAX, &pairs_info)?
+    } else {
+        gpu_context.execute_compute_pipeline(pipeline)?.0
+    };
     let duration = start.elapsed();
     println!("Time taken: {:?}", duration);
 
+    let pairs: Vec<graph::EdgeInfo> = pairs_info
+        .iter()
+        .map(|p| graph::EdgeInfo {
+            u: p.u as usize,
+            v: p.v as usize,
+            dij: p.dij as f64,
+            wij: p.wij as f64,
+        })
+        .collect();
+    let as_f64 = |positions: &[[f32; 2]]| -> Vec<[f64; 2]> {
+        positions.iter().map(|p| [p[0] as f64, p[1] as f64]).collect()
+    };
+    let (initial_f64, result_f64) = (as_f64(&initial_positions), as_f64(&result));
+    println!(
+        "Stress: initial {:.4}, final {:.4} (normalized {:.6})",
+        graph::stress(&initial_f64, &pairs),
+        graph::stress(&result_f64, &pairs),
+        graph::normalized_stress(&result_f64, &pairs)
+    );
+
     // LOG: Print result
     // println!("Result: {:?}", result);
     
+    // --output PATH / --output-initial PATH: write to exactly these files
+    // instead of timestamped names. With --output alone the initial layout is
+    // not written.
+    let output_path = |flag: &str| -> Result<Option<String>> {
+        match args.iter().position(|a| a == flag) {
+            Some(idx) => args
+                .get(idx + 1)
+                .cloned()
+                .map(Some)
+                .ok_or_else(|| anyhow::anyhow!("usage: {} <path>", flag)),
+            None => Ok(None),
+        }
+    };
+    let output = output_path("--output")?;
+    let output_initial = output_path("--output-initial")?;
+
     // Save initial positions (after randomization) to file with timestamp
     let timestamp = Local::now().format("%Y%m%d_%H%M%S");
-    // mtx_path が未使用のためデータ名はハードコード
-    let data_name = "inline";
-    let filename_init = format!("../output/vram-lock-{}-{}-0.txt", data_name, timestamp);
-    let mut file = File::create(&filename_init)?;
-    writeln!(file, "# Rust GPU Result (vram-lock) - Initial (Randomized)")?;
-    writeln!(file, "# Timestamp: {}", Local::now().format("%Y-%m-%d %H:%M:%S"))?;
-    writeln!(file, "# Node count: {}", graph.node_size)?;
-    writeln!(file, "# Edge count: {}", graph.edge_size)?;
-    writeln!(file, "")?;
-    writeln!(file, "# Edges (source target)")?;
-    for i in 0..graph.edge_size {
-        writeln!(file, "{} {}", graph.edge_src[i], graph.edge_dst[i])?;
-    }
-    writeln!(file, "")?;
-    writeln!(file, "# Positions (x y)")?;
-    for pos in &initial_positions {
-        writeln!(file, "{} {}", pos[0], pos[1])?;
+    let filename_init = match (&output_initial, &output) {
+        (Some(path), _) => Some(path.clone()),
+        (None, Some(_)) => None,
+        (None, None) => Some(format!("../output/vram-lock-{}-{}-0.txt", data_name, timestamp)),
+    };
+    if let Some(filename_init) = filename_init {
+        let mut file = File::create(&filename_init)?;
+        writeln!(file, "# Rust GPU Result (vram-lock) - Initial (Randomized)")?;
+        writeln!(file, "# Timestamp: {}", Local::now().format("%Y-%m-%d %H:%M:%S"))?;
+        writeln!(file, "# Node count: {}", graph.node_size)?;
+        writeln!(file, "# Edge count: {}", graph.edge_size)?;
+        writeln!(file)?;
+        writeln!(file, "# Edges (source target)")?;
+        for i in 0..graph.edge_size {
+            writeln!(file, "{} {}", graph.edge_src[i], graph.edge_dst[i])?;
+        }
+        writeln!(file)?;
+        writeln!(file, "# Positions (x y)")?;
+        for pos in &initial_positions {
+            writeln!(file, "{} {}", pos[0], pos[1])?;
+        }
+        println!("Initial result saved to {}", filename_init);
     }
-    println!("Initial result saved to {}", filename_init);
     
     // Save processed result to file with timestamp
-    let filename_processed = format!("../output/vram-lock-{}-{}-1.txt", data_name, timestamp);
+    let filename_processed =
+        output.unwrap_or_else(|| format!("../output/vram-lock-{}-{}-1.txt", data_name, timestamp));
     let mut file = File::create(&filename_processed)?;
     writeln!(file, "# Rust GPU Result (vram-lock) - Processed")?;
     writeln!(file, "# Timestamp: {}", Local::now().format("%Y-%m-%d %H:%M:%S"))?;
     writeln!(file, "# Node count: {}", graph.node_size)?;
     writeln!(file, "# Edge count: {}", graph.edge_size)?;
-    writeln!(file, "")?;
+    writeln!(file)?;
     writeln!(file, "# Edges (source target)")?;
     for i in 0..graph.edge_size {
         writeln!(file, "{} {}", graph.edge_src[i], graph.edge_dst[i])?;
     }
-    writeln!(file, "")?;
+    writeln!(file)?;
     writeln!(file, "# Positions (x y)")?;
     for pos in &result {
         writeln!(file, "{} {}", pos[0], pos[1])?;
     }
     println!("Processed result saved to {}", filename_processed);
 
+    // --svg PATH [--node-radius R] [--stroke-width W]: also draw the final
+    // layout (sizes in layout units, by default scaled to the layout)
+    if let Some(svg_path) = output_path("--svg")? {
+        let size_arg = |flag: &str| -> Result<Option<f32>> {
+            match args.iter().position(|a| a == flag) {
+                Some(idx) => args
+                    .get(idx + 1)
+                    .and_then(|s| s.parse::<f32>().ok())
+                    .filter(|&x| x > 0.0 && x.is_finite())
+                    .map(Some)
+                    .ok_or_else(|| anyhow::anyhow!("usage: {} <positive number>", flag)),
+                None => Ok(None),
+            }
+        };
+        let edges = (graph.edge_src.clone(), graph.edge_dst.clone());
+        write_svg(
+            Path::new(&svg_path),
+            &result,
+            &edges,
+            size_arg("--node-radius")?,
+            size_arg("--stroke-width")?,
+        )?;
+        println!("SVG saved to {}", svg_path);
+    }
+
     Ok(())
 }
+
+/// Write a layout as SVG: edges (`(sources, targets)`) as `<line>`, nodes as
+/// `<circle>`. The view box fits the bounding box of the finite positions
+/// with 5% padding. `node_radius` and `stroke_width` default to 0.5% and
+/// 0.2% of the larger extent. SVG's y axis points down, so y is negated to
+/// keep the layout upright.
+fn write_svg(
+    path: &Path,
+    positions: &[[f32; 2]],
+    edges: &(Vec<usize>, Vec<usize>),
+    node_radius: Option<f32>,
+    stroke_width: Option<f32>,
+) -> Result<()> {
+    let (mut min, mut max) = ([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]);
+    for p in positions.iter().filter(|p| p[0].is_finite() && p[1].is_finite()) {
+        for k in 0..2 {
+            min[k] = min[k].min(p[k]);
+            max[k] = max[k].max(p[k]);
+        }
+    }
+    if min[0] > max[0] {
+        (min, max) = ([0.0; 2], [1.0; 2]);
+    }
+    let extent = (max[0] - min[0]).max(max[1] - min[1]).max(f32::EPSILON);
+    let pad = extent * 0.05;
+    let radius = node_radius.unwrap_or(extent * 0.005);
+    let stroke = stroke_width.unwrap_or(extent * 0.002);
+
+    let mut file = std::io::BufWriter::new(File::create(path)?);
+    writeln!(
+        file,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">",
+        min[0] - pad,
+        -max[1] - pad,
+        max[0] - min[0] + 2.0 * pad,
+        max[1] - min[1] + 2.0 * pad
+    )?;
+    writeln!(file, "<g stroke=\"#999999\" stroke-width=\"{}\">", stroke)?;
+    for (&src, &dst) in edges.0.iter().zip(&edges.1) {
+        let (a, b) = (positions[src], positions[dst]);
+        writeln!(file, "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>", a[0], -a[1], b[0], -b[1])?;
+    }
+    writeln!(file, "</g>")?;
+    writeln!(file, "<g fill=\"#1f77b4\">")?;
+    for p in positions {
+        writeln!(file, "<circle cx=\"{}\" cy=\"{}\" r=\"{}\"/>", p[0], -p[1], radius)?;
+    }
+    writeln!(file, "</g>")?;
+    writeln!(file, "</svg>")?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn svg_y_points_up() {
+        let path = std::env::temp_dir().join(format!("svg-y-{}.svg", std::process::id()));
+        write_svg(&path, &[[0.0, 0.0], [1.0, 2.0]], &(vec![0], vec![1]), None, None).unwrap();
+        let svg = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(svg.contains("<circle cx=\"1\" cy=\"-2\""), "{}", svg);
+        assert!(svg.contains("viewBox=\"-0.1 -2.1 "), "{}", svg);
+    }
+}
@@ -40,75 +40,48 @@ fn unlock(node: u32) {
     atomicExchange(&locks[node], 0u);
 }
 
-fn acquire_locks(node1: u32, node2: u32) -> bool {
-    // Always lock in order: smaller index first (deadlock prevention)
-    let first = min(node1, node2);
-    let second = max(node1, node2);
-    
-    // Spin until both locks are acquired (with timeout for safety)
-    let max_retries = 1000000u;
-    for (var retry = 0u; retry < max_retries; retry++) {
-        if (try_lock(first)) {
-            if (try_lock(second)) {
-                return true;  // Both locks acquired successfully
-            } else {
-                unlock(first);  // Release first lock and retry
-            }
-        }
-        // Small yield to allow other workgroups to progress
-    }
-    
-    // Timeout (should be extremely rare with proper GPU parallelism)
-    return false;
-}
-
 fn release_locks(node1: u32, node2: u32) {
     unlock(node1);
     unlock(node2);
 }
 
-@compute @workgroup_size(32, 1, 1)
-fn sgd(@builtin(local_invocation_id) local_id: vec3<u32>,@builtin(workgroup_id) workgroup_id: vec3<u32>) {
-    let node_size = arrayLength(&positions);
-    
-    // 1 workgroup = 32 threads (= 1 warp)
-    // each workgroup handles 1 pair
-    // use 2D dispatch to handle more pairs: pair_idx = y * 65535 + x
-    // only local_id.x == 0 does the work
-    let total_pairs = arrayLength(&pairs);
-    let pair_idx = workgroup_id.y * 65535u + workgroup_id.x;
-    
-    // only thread 0 in the workgroup does the work
-    if (local_id.x != 0u) {
-        return;
-    }
-    
-    if (pair_idx >= total_pairs) {
-        return;
-    }
-    
-    let pair = pairs[pair_idx];
-    let i = pair.u;
-    let j = pair.v;
-    
-    // Only process upper triangular matrix (i < j)
-    if (i >= j) {
-        return;
-    }
-    
-    let dij = pair.dij;
-    let wij = pair.wij;
-    
-    // Get learning rate for this iteration
-    let eta = etas[iteration];
-    
-    // Acquire locks for both nodes (deadlock-free with retry limit)
-    if (!acquire_locks(i, j)) {
-        // Failed to acquire locks after max retries
-        // Skip this pair in this iteration
-        return;
-    }
-    
+// 64-bit words as (high, low) u32 halves, since WGSL has no u64
+
+fn add64(a: vec2<u32>, b: vec2<u32>) -> vec2<u32> {
+    let lo = a.y + b.y;
+    return vec2<u32>(a.x + b.x + select(0u, 1u, lo < a.y), lo);
+}
+
+// Low 64 bits of a * b; a.y * b.y is formed from 16-bit halves
+fn mul64(a: vec2<u32>, b: vec2<u32>) -> vec2<u32> {
+    let p00 = (a.y & 0xffffu) * (b.y & 0xffffu);
+    let p01 = (a.y & 0xffffu) * (b.y >> 16u);
+    let p10 = (a.y >> 16u) * (b.y & 0xffffu);
+    let p11 = (a.y >> 16u) * (b.y >> 16u);
+    let mid = (p00 >> 16u) + (p01 & 0xffffu) + (p10 & 0xffffu);
+    let hi = p11 + (p01 >> 16u) + (p10 >> 16u) + (mid >> 16u);
+    return vec2<u32>(hi + a.x * b.y + a.y * b.x, (p00 & 0xffffu) | (mid << 16u));
+}
+
+// a ^ (a >> s) for 0 < s < 32
+fn xorshift64(a: vec2<u32>, s: u32) -> vec2<u32> {
+    return a ^ vec2<u32>(a.x >> s, (a.y >> s) | (a.x << (32u - s)));
+}
+
+// Deterministic angle in [0, 2π) for the pair (u, v), the nudge direction
+// when two nodes coincide: the splitmix64 finaliser on the packed pair, as
+// `pair_angle` in baseline-sgd-non-gpu's algorithm.rs, keeping the 24 top bits an f32 can hold
+fn pair_angle(u: u32, v: u32) -> f32 {
+    var z = add64(vec2<u32>(u, v), vec2<u32>(0x9e3779b9u, 0x7f4a7c15u));
+    z = mul64(xorshift64(z, 30u), vec2<u32>(0xbf58476du, 0x1ce4e5b9u));
+    z = mul64(xorshift64(z, 27u), vec2<u32>(0x94d049bbu, 0x133111ebu));
+    z = xorshift64(z, 31u);
+    return f32(z.x >> 8u) / 16777216.0 * 6.283185307179586;
+}
+
+// SGD update of pair `pair_idx` between nodes i and j; the caller holds
+// both locks
+fn update_pair(pair_idx: u32, i: u32, j: u32, dij: f32, wij: f32, eta: f32) {
     // Record the pair index and positions BEFORE update (right after acquiring locks)
     let record_idx = atomicAdd(&updated_count, 1u);
     updated_pairs[record_idx] = pair_idx;
@@ -119,9 +92,10 @@ fn sgd(@builtin(local_invocation_id) local_id: vec3<u32>,@builtin(workgroup_id)
     var diff = positions[j] - positions[i];
     var dist = length(diff);
     
-    // Handle zero/tiny distance case
+    // Handle zero/tiny distance case: nudge along the pair's own direction
     if (dist < tiny) {
-        diff = vec2<f32>(1e-6, 1e-6);
+        let angle = pair_angle(i, j);
+        diff = vec2<f32>(cos(angle), sin(angle)) * 1e-6;
         dist = length(diff);
     }
     
@@ -130,7 +104,63 @@ fn sgd(@builtin(local_invocation_id) local_id: vec3<u32>,@builtin(workgroup_id)
     
     positions[i] += mu * r;
     positions[j] -= mu * r;
+}
+
+// Lock both nodes of pairs[pair_idx], update them and unlock
+fn process_pair(pair_idx: u32) {
+    let pair = pairs[pair_idx];
+    let i = pair.u;
+    let j = pair.v;
+    
+    // Only process upper triangular matrix (i < j)
+    if (i >= j) {
+        return;
+    }
     
-    // Release locks
-    release_locks(i, j);
-}
\ No newline at end of file
+    // Get learning rate for this iteration
+    let eta = etas[iteration];
+    
+    // Always lock in order: smaller index first (deadlock prevention)
+    let first = min(i, j);
+    let second = max(i, j);
+    
+    // Spin until both locks are acquired (with timeout for safety). The
+    // update and unlock happen inside the loop: threads of one warp run the
+    // loop in lockstep, so a thread that only left the loop with the locks
+    // held would keep them until every other thread of its warp gave up
+    // spinning on them.
+    let max_retries = 1000000u;
+    for (var retry = 0u; retry < max_retries; retry++) {
+        if (try_lock(first)) {
+            if (try_lock(second)) {
+                update_pair(pair_idx, i, j, pair.dij, pair.wij, eta);
+                release_locks(i, j);
+                return;
+            }
+            unlock(first);  // Release first lock and retry
+        }
+    }
+    // Failed to acquire locks after max retries: skip this pair in this
+    // iteration (should be extremely rare)
+}
+
+// Threads per workgroup, set at pipeline creation (GpuContext::with_workgroup_size)
+override wg_size: u32 = 32u;
+
+@compute @workgroup_size(wg_size, 1, 1)
+fn sgd(
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    // 1 workgroup = wg_size threads, every thread handles its own
+    // pairs: thread t of the grid takes pairs t, t + total threads, ...
+    // (grid stride). 2D dispatch is flattened row-major: workgroup index =
+    // y * grid width + x
+    let total_pairs = arrayLength(&pairs);
+    let workgroup_idx = workgroup_id.y * num_workgroups.x + workgroup_id.x;
+    let total_threads = num_workgroups.x * num_workgroups.y * wg_size;
+    for (var pair_idx = workgroup_idx * wg_size + local_id.x; pair_idx < total_pairs; pair_idx += total_threads) {
+        process_pair(pair_idx);
+    }
+}